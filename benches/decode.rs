@@ -0,0 +1,56 @@
+//! Decoding throughput of the instruction set
+//!
+//! Run with `cargo bench --bench decode`. The samples of the `tests`
+//! directory are decoded with [disasm], which resolves all references, and
+//! with [insn_offsets], which only walks the instruction sizes.
+
+use std::io::Cursor;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use dexrs::dalvik::{
+    dex::CodeItem,
+    file::Dex,
+    insns::{code_units, disasm, insn_offsets},
+};
+
+const SAMPLES: &[(&str, &[u8])] = &[
+    ("fib", include_bytes!("../tests/fibonacci/fib.dex")),
+    ("prime", include_bytes!("../tests/prime/prime.dex")),
+];
+
+fn decode(c: &mut Criterion) {
+    for (name, data) in SAMPLES {
+        let mut reader = Cursor::new(*data);
+        let mut dex = Dex::read(&mut reader, true).unwrap();
+        let code: Vec<CodeItem> = dex.code_items().unwrap().into_iter().map(|x| x.1).collect();
+        let bytes: usize = code.iter().map(|x| x.insns.len()).sum();
+
+        let mut group = c.benchmark_group(*name);
+        group.throughput(Throughput::Bytes(bytes as u64));
+        group.bench_function("disasm", |b| {
+            b.iter(|| {
+                for item in &code {
+                    disasm(item, &mut dex).unwrap();
+                }
+            })
+        });
+        group.bench_function("insn_offsets", |b| {
+            b.iter(|| {
+                for item in &code {
+                    insn_offsets(item).unwrap();
+                }
+            })
+        });
+        group.bench_function("code_units", |b| {
+            b.iter_batched(
+                || code.iter().map(|x| x.insns.as_slice()).collect::<Vec<_>>(),
+                |insns| insns.into_iter().map(code_units).collect::<Vec<_>>(),
+                BatchSize::SmallInput,
+            )
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
@@ -0,0 +1,6 @@
+//! Shared library of the C interface of dexrs, see `include/dexrs.h`
+//!
+//! The exported functions are defined in `dexrs::ffi`; this crate only
+//! links them into a `cdylib`.
+
+pub use dexrs::ffi::*;
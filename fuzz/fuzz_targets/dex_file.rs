@@ -0,0 +1,22 @@
+#![no_main]
+
+use std::io::{self, Cursor};
+
+use dexrs::dalvik::file::{Dex, IDex};
+use dexrs::smali::SmaliWrite;
+use libfuzzer_sys::fuzz_target;
+
+// Parses the input as an untrusted DEX file and walks every class, including
+// the disassembly of all methods. Errors are expected, panics are not.
+fuzz_target!(|data: &[u8]| {
+    let mut cursor = Cursor::new(data);
+    let Ok(mut dex) = Dex::read_untrusted(&mut cursor) else {
+        return;
+    };
+    for i in 0..dex.header.class_defs_size {
+        let Ok(class) = dex.get_class_def(i) else {
+            continue;
+        };
+        let _ = io::sink().write_class(&class, &mut dex);
+    }
+});
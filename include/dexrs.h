@@ -0,0 +1,106 @@
+#ifndef DEXRS_H
+#define DEXRS_H
+
+/* Generated by cbindgen from src/ffi, do not edit. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * The call succeeded.
+ */
+#define DEXRS_OK 0
+
+/**
+ * A required pointer argument was `NULL`.
+ */
+#define DEXRS_NULL_POINTER -1
+
+/**
+ * The file or one of its items could not be parsed.
+ */
+#define DEXRS_PARSE_ERROR -2
+
+/**
+ * An index was out of bounds or the requested item does not exist.
+ */
+#define DEXRS_NOT_FOUND -3
+
+/**
+ * The output buffer is too small; `out_len` contains the required length
+ * without the NUL terminator.
+ */
+#define DEXRS_BUFFER_TOO_SMALL -4
+
+/**
+ * The library panicked, e.g. because of a bug in the parser. The message
+ * is available through [dexrs_last_error].
+ */
+#define DEXRS_PANIC -5
+
+/**
+ * A parsed DEX file that owns its contents.
+ */
+typedef struct DexHandle DexHandle;
+
+/**
+ * Sizes of the id sections of a DEX file.
+ */
+typedef struct DexCounts {
+  uint32_t strings;
+  uint32_t types;
+  uint32_t protos;
+  uint32_t fields;
+  uint32_t methods;
+  uint32_t class_defs;
+} DexCounts;
+
+/**
+ * Parses the DEX file stored in `data`. The bytes are copied, so the
+ * buffer can be released as soon as this function returns.
+ *
+ * If `verify` is set, checksum, signature and header constraints are
+ * checked. The new handle is stored in `out` and must be released with
+ * [dexrs_free].
+ */
+int32_t dexrs_open(const uint8_t *data, size_t len, bool verify, DexHandle **out);
+
+/**
+ * Releases a handle created by [dexrs_open]. Passing `NULL` does nothing.
+ */
+void dexrs_free(DexHandle *handle);
+
+/**
+ * Stores the sizes of all id sections in `out`.
+ */
+int32_t dexrs_counts(const DexHandle *handle, DexCounts *out);
+
+/**
+ * Writes the string at `index` into `buf`.
+ */
+int32_t dexrs_get_string(DexHandle *handle,
+                         uint32_t index,
+                         char *buf,
+                         size_t buf_len,
+                         size_t *out_len);
+
+/**
+ * Writes the smali disassembly of a method into `buf`. The method is
+ * identified by the index of its class definition and its index into the
+ * `method_ids` list.
+ */
+int32_t dexrs_disasm_method(DexHandle *handle,
+                            uint32_t class_def_idx,
+                            uint32_t method_idx,
+                            char *buf,
+                            size_t buf_len,
+                            size_t *out_len);
+
+/**
+ * Writes the message of the last error that occurred on the calling
+ * thread into `buf`. Returns [DEXRS_NOT_FOUND] if there was none.
+ */
+int32_t dexrs_last_error(char *buf, size_t buf_len, size_t *out_len);
+
+#endif /* DEXRS_H */
@@ -0,0 +1,165 @@
+//! Detection of synthetic accessors and bridge methods
+//!
+//! Compilers generate methods that do nothing but forward to another
+//! member: `access$000`-style accessors let nested classes reach private
+//! members, bridge methods adapt the signature of an overridden method and
+//! other synthetic methods often wrap a single call. A method is treated as
+//! such a forwarder if it is flagged as bridge or synthetic, or named like
+//! an accessor, and its code performs exactly one field access or invoke
+//! besides moves, casts and the return.
+//!
+//! [Accessors::resolve] maps a call through a forwarder to the member it
+//! ends up at, which
+//! [CallGraph::collapse_accessors](super::callgraph::CallGraph::collapse_accessors)
+//! uses to hide them from a call graph:
+//!
+//! ```ignore
+//! let accessors = Accessors::scan(&mut dex)?;
+//! let mut graph = CallGraph::build(&mut dex, &PrettyOptions::java())?;
+//! graph.collapse_accessors(&accessors);
+//! ```
+
+use std::collections::BTreeMap;
+
+use crate::dalvik::{
+    dex::{AccessFlags, CodeItem},
+    error::Result,
+    file::{method::DexMethod, IDexRef},
+    insns::{Index, Insn, InsnFormat},
+};
+
+use super::for_each_method;
+
+/// Why a method is considered a forwarder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessorKind {
+    /// an `access$NNN` method
+    Accessor,
+
+    /// a method flagged as bridge
+    Bridge,
+
+    /// any other method flagged as synthetic
+    Synthetic,
+}
+
+/// The member a forwarder accesses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessorTarget {
+    /// reads the field with the given index
+    FieldGet(u32),
+
+    /// writes the field with the given index
+    FieldPut(u32),
+
+    /// invokes the method with the given index
+    Invoke(u32),
+}
+
+/// A method that only forwards to another member.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Accessor {
+    /// Index into the `method_ids` list.
+    pub method_idx: u32,
+
+    pub kind: AccessorKind,
+
+    pub target: AccessorTarget,
+}
+
+/// All forwarders of a DEX file.
+#[derive(Debug, Default)]
+pub struct Accessors {
+    /// All forwarders by their method index.
+    pub methods: BTreeMap<u32, Accessor>,
+}
+
+impl Accessors {
+    /// Searches all methods of the given DEX file for forwarders.
+    pub fn scan(dex: IDexRef<'_>) -> Result<Accessors> {
+        let mut accessors = Accessors::default();
+        for_each_method(dex, |_, method, insns, _| {
+            if let Some(accessor) = detect(method, insns) {
+                accessors.methods.insert(accessor.method_idx, accessor);
+            }
+            Ok(())
+        })?;
+        Ok(accessors)
+    }
+
+    /// Returns the member a call to the given method ends up at, following
+    /// chains of forwarders such as a bridge that invokes an accessor.
+    /// Returns `None` if the method is not a forwarder.
+    pub fn resolve(&self, method_idx: u32) -> Option<AccessorTarget> {
+        let mut target = self.methods.get(&method_idx)?.target;
+        // forwarders calling each other in a cycle are not followed forever
+        for _ in 0..self.methods.len() {
+            let AccessorTarget::Invoke(next) = target else {
+                break;
+            };
+            match self.methods.get(&next) {
+                Some(accessor) => target = accessor.target,
+                None => break,
+            }
+        }
+        Some(target)
+    }
+}
+
+/// Checks whether the given method is a forwarder, using its disassembled
+/// instructions.
+pub fn detect(method: &DexMethod, insns: &[Insn]) -> Option<Accessor> {
+    let has_flag = |flag| {
+        method
+            .access_flags
+            .as_ref()
+            .is_some_and(|x| x.contains(flag))
+    };
+    let kind = if has_flag(AccessFlags::BRIDGE) {
+        AccessorKind::Bridge
+    } else if method.name.starts_with("access$") {
+        AccessorKind::Accessor
+    } else if has_flag(AccessFlags::SYNTHETIC) {
+        AccessorKind::Synthetic
+    } else {
+        return None;
+    };
+    let code = method.code.as_ref()?;
+
+    let mut target = None;
+    for insn in insns {
+        let next = match insn.opcode.opcode {
+            // nop, move*, move-result*, return* and check-cast
+            0x00..=0x0C | 0x0E..=0x11 | 0x1F => continue,
+            0x52..=0x58 | 0x60..=0x66 => AccessorTarget::FieldGet(field_idx(code, insn)?),
+            0x59..=0x5F | 0x67..=0x6D => AccessorTarget::FieldPut(field_idx(code, insn)?),
+            _ => match &insn.format {
+                InsnFormat::Format35c {
+                    b: Index::Method(idx, _),
+                    ..
+                }
+                | InsnFormat::Format3rc {
+                    b: Index::Method(idx, _),
+                    ..
+                } => AccessorTarget::Invoke(*idx),
+                _ => return None,
+            },
+        };
+        if target.replace(next).is_some() {
+            return None;
+        }
+    }
+    Some(Accessor {
+        method_idx: method.identity,
+        kind,
+        target: target?,
+    })
+}
+
+/// Reads the field index of a field access, which is not kept by
+/// [Index::Field].
+fn field_idx(code: &CodeItem, insn: &Insn) -> Option<u32> {
+    let offset = insn.range.start + 2;
+    let bytes = code.insns.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]) as u32)
+}
@@ -0,0 +1,236 @@
+//! Inventory of external API usage
+//!
+//! Every method reference whose declaring class is not defined in the
+//! analysed DEX file is considered external (framework or library API).
+//! The inventory groups these references by their declaring class and
+//! counts the invoke instructions targeting them, which is usually all a
+//! permission or API mapping needs.
+//!
+//! [find_calls_to] answers the narrower question of where a specific API is
+//! called from:
+//!
+//! ```ignore
+//! use dexrs::analysis::api::FindCalls;
+//!
+//! for call in dex.find_calls_to("Landroid/telephony/SmsManager;", Some("sendTextMessage"))? {
+//!     println!("{} {} +{:#x}", call.class, call.caller_idx, call.offset);
+//! }
+//! ```
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashSet},
+    rc::Rc,
+};
+
+use crate::{
+    dalvik::{
+        dex::DexType,
+        error::Result,
+        file::{method::DexPrototype, IDex, IDexRef},
+        insns::{Index, Insn, InsnFormat, Opcode},
+    },
+    writer::layout::compare_strings,
+};
+
+use super::for_each_method;
+
+/// A referenced method that is not defined in the DEX file.
+#[derive(Debug)]
+pub struct ApiMethod {
+    /// Index into the `method_ids` list.
+    pub method_idx: u32,
+
+    /// The name of the method
+    pub name: Rc<String>,
+
+    /// The method signature
+    pub proto: Rc<DexPrototype>,
+
+    /// Number of invoke instructions targeting this method.
+    pub call_count: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct ApiInventory {
+    /// External methods grouped by the descriptor of their declaring class.
+    /// Methods of each class are sorted by their method index.
+    pub classes: BTreeMap<String, Vec<ApiMethod>>,
+}
+
+impl ApiInventory {
+    /// Returns the total number of invoke instructions targeting external
+    /// methods.
+    pub fn total_calls(&self) -> usize {
+        self.classes
+            .values()
+            .flat_map(|methods| methods.iter())
+            .map(|m| m.call_count)
+            .sum()
+    }
+}
+
+/// Collects all method references of the given DEX file that point to
+/// classes not defined in the same file.
+pub fn inventory(dex: IDexRef<'_>) -> Result<ApiInventory> {
+    // only the descriptors are needed, so no class is loaded
+    let mut defined: HashSet<String> = HashSet::new();
+    for index in 0..dex.get_header().class_defs_size {
+        let item = dex.get_class_def_item(index)?;
+        defined.insert(dex.get_type(item.class_idx)?.to_string());
+    }
+
+    // method index -> position in the class list
+    let mut inventory = ApiInventory::default();
+    let mut external: BTreeMap<u32, (String, usize)> = BTreeMap::new();
+    for method_idx in 0..dex.get_header().method_ids_size {
+        let method_item = dex.get_method(method_idx)?;
+        let class = dex.get_type(method_item.class_idx as u32)?.to_string();
+        if defined.contains(&class) {
+            continue;
+        }
+
+        let methods = inventory.classes.entry(class.clone()).or_default();
+        external.insert(method_idx, (class, methods.len()));
+        methods.push(ApiMethod {
+            method_idx,
+            name: dex.get_string(method_item.name_idx)?,
+            proto: dex.get_proto(method_item.proto_idx as u32)?,
+            call_count: 0,
+        });
+    }
+
+    for_each_method(dex, |_, _, insns, _| {
+        for method_idx in insns.iter().filter_map(invoked_method) {
+            if let Some((class, position)) = external.get(&method_idx)
+                && let Some(methods) = inventory.classes.get_mut(class)
+            {
+                methods[*position].call_count += 1;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(inventory)
+}
+
+/// An invoke instruction found by [find_calls_to].
+#[derive(Debug)]
+pub struct ApiCall {
+    /// The class declaring the calling method.
+    pub class: Rc<DexType>,
+
+    /// Index into the `method_ids` list of the calling method.
+    pub caller_idx: u32,
+
+    /// Index into the `method_ids` list of the invoked method.
+    pub method_idx: u32,
+
+    /// Byte offset of the instruction within the caller's bytecode.
+    pub offset: usize,
+
+    pub opcode: &'static Opcode,
+}
+
+/// Finds all invoke instructions targeting a method of the given class,
+/// e.g. `Landroid/telephony/SmsManager;`, optionally restricted to methods
+/// with the given name. Calls are returned in the order of the
+/// `class_defs` list.
+///
+/// Methods are matched by the class they are referenced through, so calls
+/// to an inherited method through a subclass are not found. The class is
+/// looked up by a binary search, which requires the `type_ids` list to be
+/// sorted like the format demands.
+pub fn find_calls_to(dex: IDexRef<'_>, class: &str, name: Option<&str>) -> Result<Vec<ApiCall>> {
+    // type_ids are sorted by the UTF-16 code units of their descriptors
+    let (mut low, mut high) = (0, dex.get_header().type_ids_size);
+    let mut class_idx = None;
+    while low < high {
+        let mid = low + (high - low) / 2;
+        match compare_strings(&dex.get_type(mid)?.to_string(), class) {
+            Ordering::Less => low = mid + 1,
+            Ordering::Greater => high = mid,
+            Ordering::Equal => {
+                class_idx = Some(mid);
+                break;
+            }
+        }
+    }
+    let mut targets = HashSet::new();
+    if let Some(class_idx) = class_idx {
+        for method_idx in 0..dex.get_header().method_ids_size {
+            let method_item = dex.get_method(method_idx)?;
+            if method_item.class_idx as u32 != class_idx {
+                continue;
+            }
+            if let Some(name) = name
+                && *dex.get_string(method_item.name_idx)? != name
+            {
+                continue;
+            }
+            targets.insert(method_idx);
+        }
+    }
+
+    let mut calls = Vec::new();
+    if targets.is_empty() {
+        return Ok(calls);
+    }
+    for_each_method(dex, |class, method, insns, _| {
+        for insn in insns {
+            if let Some(method_idx) = invoked_method(insn)
+                && targets.contains(&method_idx)
+            {
+                calls.push(ApiCall {
+                    class: class.type_.clone(),
+                    caller_idx: method.identity,
+                    method_idx,
+                    offset: insn.range.start,
+                    opcode: insn.opcode,
+                });
+            }
+        }
+        Ok(())
+    })?;
+    Ok(calls)
+}
+
+/// Adds [find_calls_to] as a method to all DEX files.
+pub trait FindCalls {
+    /// See [find_calls_to].
+    fn find_calls_to(&mut self, class: &str, name: Option<&str>) -> Result<Vec<ApiCall>>;
+}
+
+impl<T: IDex> FindCalls for T {
+    fn find_calls_to(&mut self, class: &str, name: Option<&str>) -> Result<Vec<ApiCall>> {
+        find_calls_to(self, class, name)
+    }
+}
+
+impl FindCalls for dyn IDex + '_ {
+    fn find_calls_to(&mut self, class: &str, name: Option<&str>) -> Result<Vec<ApiCall>> {
+        find_calls_to(self, class, name)
+    }
+}
+
+/// Returns the index of the method targeted by an invoke instruction.
+fn invoked_method(insn: &Insn) -> Option<u32> {
+    match &insn.format {
+        InsnFormat::Format35c {
+            b: Index::Method(idx, _),
+            ..
+        }
+        | InsnFormat::Format3rc {
+            b: Index::Method(idx, _),
+            ..
+        }
+        | InsnFormat::Format45cc {
+            b: Index::Method(idx, _),
+            ..
+        }
+        | InsnFormat::Format4rcc {
+            b: Index::Method(idx, _),
+            ..
+        } => Some(*idx),
+        _ => None,
+    }
+}
@@ -0,0 +1,235 @@
+//! Call graph of a DEX file
+//!
+//! Every method that stores code or is the target of an invoke instruction
+//! becomes a node; every invoked method an edge. Targets that are not
+//! declared by the class they are referenced through (e.g. inherited
+//! methods) are resolved with the [class hierarchy](super::hierarchy) if
+//! possible. Methods of classes that are not defined in the file are
+//! marked as external.
+//!
+//! The graph can be exported in Graphviz DOT format or as JSON:
+//!
+//! ```ignore
+//! let graph = CallGraph::build(&mut dex, &PrettyOptions::java())?;
+//! graph.write_dot(&mut std::io::stdout())?;
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::Write,
+};
+
+use crate::{
+    dalvik::{
+        error::Result,
+        file::IDexRef,
+        insns::{Index, InsnFormat},
+    },
+    pretty::{pretty_method, pretty_type, PrettyOptions},
+};
+
+use super::{
+    accessors::{AccessorTarget, Accessors},
+    for_each_method,
+    hierarchy::ClassHierarchy,
+};
+
+/// A method in the call graph.
+#[derive(Debug, Clone)]
+pub struct CallNode {
+    /// Index into the `method_ids` list.
+    pub method_idx: u32,
+
+    /// The declaring class, formatted with the options passed to
+    /// [CallGraph::build].
+    pub class: String,
+
+    /// The full method signature.
+    pub signature: String,
+
+    /// Whether the declaring class is defined outside of the DEX file.
+    pub external: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct CallGraph {
+    /// All nodes by their method index.
+    pub nodes: BTreeMap<u32, CallNode>,
+
+    /// Number of invoke instructions per `(caller, callee)` pair.
+    pub edges: BTreeMap<(u32, u32), usize>,
+}
+
+impl CallGraph {
+    /// Builds the call graph of the given DEX file.
+    pub fn build(dex: IDexRef<'_>, options: &PrettyOptions) -> Result<CallGraph> {
+        let mut declared = HashSet::new();
+        for index in 0..dex.get_header().class_defs_size {
+            declared.extend(dex.get_class_def(index)?.get_methods().map(|(x, _)| *x));
+        }
+
+        let mut calls: BTreeMap<(u32, u32), usize> = BTreeMap::new();
+        let mut callers = Vec::new();
+        for_each_method(dex, |_, method, insns, _| {
+            callers.push(method.identity);
+            for insn in insns {
+                let callee = match &insn.format {
+                    InsnFormat::Format35c {
+                        b: Index::Method(idx, _),
+                        ..
+                    }
+                    | InsnFormat::Format3rc {
+                        b: Index::Method(idx, _),
+                        ..
+                    }
+                    | InsnFormat::Format45cc {
+                        b: Index::Method(idx, _),
+                        ..
+                    }
+                    | InsnFormat::Format4rcc {
+                        b: Index::Method(idx, _),
+                        ..
+                    } => *idx,
+                    _ => continue,
+                };
+                *calls.entry((method.identity, callee)).or_default() += 1;
+            }
+            Ok(())
+        })?;
+
+        // map referenced methods to their implementation where possible
+        let hierarchy = ClassHierarchy::build(&mut [&mut *dex])?;
+        let mut resolved: BTreeMap<u32, u32> = BTreeMap::new();
+        for &(_, callee) in calls.keys() {
+            if declared.contains(&callee) || resolved.contains_key(&callee) {
+                continue;
+            }
+            let method_item = dex.get_method(callee)?;
+            let class = dex.get_type(method_item.class_idx as u32)?.to_string();
+            if let Some(target) = hierarchy.resolve_virtual(&mut [&mut *dex], 0, callee, &class)? {
+                resolved.insert(callee, target.method_idx);
+            }
+        }
+
+        let mut graph = CallGraph::default();
+        for ((caller, callee), count) in calls {
+            let callee = resolved.get(&callee).copied().unwrap_or(callee);
+            *graph.edges.entry((caller, callee)).or_default() += count;
+        }
+        let methods = callers
+            .into_iter()
+            .chain(graph.edges.keys().map(|(_, x)| *x))
+            .collect::<Vec<_>>();
+        for method_idx in methods {
+            if graph.nodes.contains_key(&method_idx) {
+                continue;
+            }
+            let method_item = dex.get_method(method_idx)?;
+            let class = dex.get_type(method_item.class_idx as u32)?;
+            let node = CallNode {
+                method_idx,
+                class: pretty_type(&class, options),
+                signature: pretty_method(dex, method_idx, options)?,
+                external: hierarchy.lookup(&class.to_string()).is_none(),
+            };
+            graph.nodes.insert(method_idx, node);
+        }
+        Ok(graph)
+    }
+
+    /// Redirects all calls to forwarders that invoke another method, e.g.
+    /// `access$000` or bridge methods, to that method and removes the
+    /// forwarders from the graph. Forwarders that access a field are kept.
+    pub fn collapse_accessors(&mut self, accessors: &Accessors) {
+        let invoked = |method_idx: u32| match accessors.resolve(method_idx) {
+            Some(AccessorTarget::Invoke(target)) => Some(target),
+            _ => None,
+        };
+        let mut edges: BTreeMap<(u32, u32), usize> = BTreeMap::new();
+        for (&(caller, callee), &count) in &self.edges {
+            if invoked(caller).is_some() {
+                continue;
+            }
+            let callee = invoked(callee).unwrap_or(callee);
+            *edges.entry((caller, callee)).or_default() += count;
+        }
+        self.edges = edges;
+        self.nodes
+            .retain(|method_idx, _| invoked(*method_idx).is_none());
+    }
+
+    /// Writes the graph in Graphviz DOT format. External methods are drawn
+    /// with dashed borders.
+    pub fn write_dot<W: Write>(&self, out: &mut W) -> Result<()> {
+        writeln!(out, "digraph calls {{")?;
+        writeln!(out, "    node [shape=box];")?;
+        for node in self.nodes.values() {
+            write!(
+                out,
+                "    m{} [label=\"{}\"",
+                node.method_idx,
+                escape(&node.signature)
+            )?;
+            if node.external {
+                write!(out, ", style=dashed")?;
+            }
+            writeln!(out, "];")?;
+        }
+        for ((caller, callee), count) in &self.edges {
+            write!(out, "    m{} -> m{}", caller, callee)?;
+            if *count > 1 {
+                write!(out, " [label=\"{}\"]", count)?;
+            }
+            writeln!(out, ";")?;
+        }
+        writeln!(out, "}}")?;
+        Ok(())
+    }
+
+    /// Writes the graph as a JSON object with a `nodes` and an `edges`
+    /// array. Edges refer to nodes by their method index.
+    pub fn write_json<W: Write>(&self, out: &mut W) -> Result<()> {
+        writeln!(out, "{{")?;
+        writeln!(out, "  \"nodes\": [")?;
+        for (i, node) in self.nodes.values().enumerate() {
+            let separator = if i + 1 < self.nodes.len() { "," } else { "" };
+            writeln!(
+                out,
+                "    {{\"id\": {}, \"class\": \"{}\", \"signature\": \"{}\", \"external\": {}}}{}",
+                node.method_idx,
+                escape(&node.class),
+                escape(&node.signature),
+                node.external,
+                separator
+            )?;
+        }
+        writeln!(out, "  ],")?;
+        writeln!(out, "  \"edges\": [")?;
+        for (i, ((caller, callee), count)) in self.edges.iter().enumerate() {
+            let separator = if i + 1 < self.edges.len() { "," } else { "" };
+            writeln!(
+                out,
+                "    {{\"caller\": {}, \"callee\": {}, \"count\": {}}}{}",
+                caller, callee, count, separator
+            )?;
+        }
+        writeln!(out, "  ]")?;
+        writeln!(out, "}}")?;
+        Ok(())
+    }
+}
+
+/// Escapes a string for use inside a quoted DOT label or JSON string.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,288 @@
+//! Control flow graphs of method bodies
+//!
+//! A graph is built from the disassembled instructions of a single code
+//! item. Blocks are split at branch targets, after branches and at the
+//! bounds of try blocks. Every block covered by a try block is connected
+//! to all exception handlers of that try block, even if none of its
+//! instructions can throw.
+//!
+//! Payload pseudo-instructions (switch tables and array data) are not part
+//! of any block. Their positions are stored in [ControlFlowGraph::payloads]
+//! instead.
+
+use std::{collections::BTreeSet, ops::Range};
+
+use crate::dalvik::{
+    dex::CodeItem,
+    error::{Error, Result},
+    insns::{insn_at, Insn, InsnFormat, Payload},
+};
+
+/// A sequence of instructions that is always executed from start to end.
+#[derive(Debug)]
+pub struct BasicBlock {
+    /// Byte range of the block within the method's bytecode.
+    pub range: Range<usize>,
+
+    /// Range of the block's instructions in the list the graph was built
+    /// from.
+    pub insns: Range<usize>,
+
+    /// Indices of all blocks that may be executed after this one,
+    /// including exception handlers.
+    pub successors: Vec<usize>,
+
+    /// Indices of all blocks that may be executed before this one.
+    pub predecessors: Vec<usize>,
+
+    /// Indices of the exception handlers covering this block. They are
+    /// part of `successors` as well.
+    pub handlers: Vec<usize>,
+}
+
+/// A payload pseudo-instruction within the bytecode of a method.
+#[derive(Debug)]
+pub struct PayloadRegion {
+    /// Byte range of the payload.
+    pub range: Range<usize>,
+
+    /// Byte offsets of all instructions referencing this payload
+    /// (`packed-switch`, `sparse-switch` and `fill-array-data`).
+    pub referrers: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub struct ControlFlowGraph {
+    /// All basic blocks, sorted by their offset. The entry block is always
+    /// the first one.
+    pub blocks: Vec<BasicBlock>,
+
+    /// All payloads sorted by their offset.
+    pub payloads: Vec<PayloadRegion>,
+}
+
+/// Control flow of a single instruction.
+struct Flow {
+    targets: Vec<usize>,
+    falls_through: bool,
+    payload: Option<usize>,
+}
+
+impl ControlFlowGraph {
+    /// Builds the graph of a code item from its disassembled instructions
+    /// (see [DexMethod::disasm](crate::dalvik::file::method::DexMethod::disasm)).
+    ///
+    /// Branch targets and payload references must point to the start of an
+    /// instruction, otherwise an error is returned.
+    pub fn build(code: &CodeItem, insns: &[Insn]) -> Result<ControlFlowGraph> {
+        // code unit addresses of try blocks and their handlers
+        let mut tries = Vec::with_capacity(code.tries.len());
+        for try_item in &code.tries {
+            let handler = code.catch_handler(try_item).ok_or_else(|| {
+                Error::InvalidData(format!(
+                    "no catch handler at offset {:#x}",
+                    try_item.handler_off
+                ))
+            })?;
+            let mut handlers: Vec<usize> = handler
+                .handlers
+                .iter()
+                .map(|x| x.addr.0 as usize * 2)
+                .collect();
+            if let Some(addr) = &handler.catch_all_addr {
+                handlers.push(addr.0 as usize * 2);
+            }
+            tries.push((try_item.range(), handlers));
+        }
+
+        let mut leaders = BTreeSet::from([0]);
+        for (range, handlers) in &tries {
+            leaders.insert(range.start);
+            leaders.insert(range.end);
+            leaders.extend(handlers);
+        }
+
+        let mut flows = Vec::with_capacity(insns.len());
+        let mut payloads = Vec::new();
+        for insn in insns {
+            if insn.payload.is_some() {
+                payloads.push(PayloadRegion {
+                    range: insn.range.clone(),
+                    referrers: Vec::new(),
+                });
+                flows.push(None);
+                continue;
+            }
+            let flow = flow_of(insn, insns)?;
+            leaders.extend(&flow.targets);
+            if !flow.falls_through || !flow.targets.is_empty() {
+                leaders.insert(insn.range.end);
+            }
+            flows.push(Some(flow));
+        }
+
+        for (insn, flow) in insns.iter().zip(&flows) {
+            if let Some(payload) = flow.as_ref().and_then(|x| x.payload) {
+                match payloads.binary_search_by_key(&payload, |x| x.range.start) {
+                    Ok(index) => payloads[index].referrers.push(insn.range.start),
+                    Err(_) => {
+                        return Err(Error::InvalidData(format!(
+                            "instruction at {:#x} references no payload",
+                            insn.range.start
+                        )));
+                    }
+                }
+            }
+        }
+
+        // split the instruction list into blocks
+        let mut blocks: Vec<BasicBlock> = Vec::new();
+        let mut current: Option<usize> = None;
+        for (index, insn) in insns.iter().enumerate() {
+            if insn.payload.is_some() {
+                current = None;
+                continue;
+            }
+            let block = match current {
+                Some(block) if !leaders.contains(&insn.range.start) => block,
+                _ => {
+                    blocks.push(BasicBlock {
+                        range: insn.range.clone(),
+                        insns: index..index,
+                        successors: Vec::new(),
+                        predecessors: Vec::new(),
+                        handlers: Vec::new(),
+                    });
+                    blocks.len() - 1
+                }
+            };
+            blocks[block].range.end = insn.range.end;
+            blocks[block].insns.end = index + 1;
+            current = Some(block);
+        }
+
+        let block_at = |offset: usize, from: usize| -> Result<usize> {
+            match blocks.binary_search_by_key(&offset, |x| x.range.start) {
+                Ok(block) => Ok(block),
+                Err(_) => Err(Error::InvalidData(format!(
+                    "target {:#x} of the instruction at {:#x} is not an instruction",
+                    offset, from
+                ))),
+            }
+        };
+
+        let mut edges = Vec::new();
+        let mut handler_edges = Vec::new();
+        for (block, item) in blocks.iter().enumerate() {
+            let last = &insns[item.insns.end - 1];
+            let Some(flow) = &flows[item.insns.end - 1] else {
+                continue;
+            };
+            for target in &flow.targets {
+                edges.push((block, block_at(*target, last.range.start)?));
+            }
+            // falling into a payload or off the end of the code is rejected
+            // by the verifier, so such edges are simply dropped
+            if flow.falls_through
+                && let Ok(next) = block_at(last.range.end, last.range.start)
+            {
+                edges.push((block, next));
+            }
+            for (range, handlers) in &tries {
+                if range.contains(&item.range.start) {
+                    for handler in handlers {
+                        handler_edges.push((block, block_at(*handler, item.range.start)?));
+                    }
+                }
+            }
+        }
+
+        for (from, to) in &handler_edges {
+            if !blocks[*from].handlers.contains(to) {
+                blocks[*from].handlers.push(*to);
+            }
+        }
+        for (from, to) in edges.into_iter().chain(handler_edges) {
+            if !blocks[from].successors.contains(&to) {
+                blocks[from].successors.push(to);
+                blocks[to].predecessors.push(from);
+            }
+        }
+        Ok(ControlFlowGraph { blocks, payloads })
+    }
+
+    /// Returns the index of the block containing the given byte offset.
+    pub fn block_at(&self, offset: usize) -> Option<usize> {
+        let index = self.blocks.partition_point(|x| x.range.start <= offset);
+        index
+            .checked_sub(1)
+            .filter(|&x| self.blocks[x].range.contains(&offset))
+    }
+
+    /// Returns for every block whether it can be reached from the entry
+    /// block.
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut pending = Vec::new();
+        if !self.blocks.is_empty() {
+            pending.push(0);
+        }
+        while let Some(block) = pending.pop() {
+            if reachable[block] {
+                continue;
+            }
+            reachable[block] = true;
+            pending.extend(&self.blocks[block].successors);
+        }
+        reachable
+    }
+}
+
+fn flow_of(insn: &Insn, insns: &[Insn]) -> Result<Flow> {
+    let start = insn.range.start as i64;
+    let target = |offset: i64| (start + offset * 2) as usize;
+    let mut flow = Flow {
+        targets: Vec::new(),
+        falls_through: true,
+        payload: None,
+    };
+    match (insn.opcode.opcode, &insn.format) {
+        // return-void, return, return-wide, return-object, throw
+        (0x0E..=0x11 | 0x27, _) => flow.falls_through = false,
+        (0x28, InsnFormat::Format10t { a }) => {
+            flow.targets.push(target(*a as i64));
+            flow.falls_through = false;
+        }
+        (0x29, InsnFormat::Format20t { a }) => {
+            flow.targets.push(target(*a as i64));
+            flow.falls_through = false;
+        }
+        (0x2A, InsnFormat::Format30t { a }) => {
+            flow.targets.push(target(*a as i64));
+            flow.falls_through = false;
+        }
+        (0x26 | 0x2B | 0x2C, InsnFormat::Format31t { b, .. }) => {
+            let payload = target(*b as i64);
+            flow.payload = Some(payload);
+            if insn.opcode.opcode != 0x26 {
+                let switch = insn_at(insns, payload).map(|x| &insns[x]);
+                let targets = match switch.and_then(|x| x.payload.as_ref()) {
+                    Some(Payload::PackedSwitch(x)) => &x.targets,
+                    Some(Payload::SparseSwitch(x)) => &x.targets,
+                    _ => {
+                        return Err(Error::InvalidData(format!(
+                            "switch at {:#x} references no switch payload",
+                            start
+                        )));
+                    }
+                };
+                flow.targets
+                    .extend(targets.iter().map(|x| target(*x as i64)));
+            }
+        }
+        (0x32..=0x37, InsnFormat::Format22t { c, .. }) => flow.targets.push(target(*c as i64)),
+        (0x38..=0x3D, InsnFormat::Format21t { b, .. }) => flow.targets.push(target(*b as i64)),
+        _ => {}
+    }
+    Ok(flow)
+}
@@ -0,0 +1,367 @@
+//! Intra-procedural constant propagation
+//!
+//! Tracks constants through the registers of a single method, which is what
+//! string decryption and reflection resolution usually need to recover the
+//! arguments of a call. Supported are all `const` instructions, moves and
+//! integer and long arithmetic. Every other instruction writing a register
+//! makes its value unknown, so results of invocations, field reads and
+//! floating point arithmetic are never constant.
+//!
+//! Values are propagated along the [control flow graph](super::cfg) of the
+//! method. A register is only considered constant if it holds the same
+//! value on every path reaching an instruction.
+
+use std::rc::Rc;
+
+use crate::dalvik::{
+    dex::{CodeItem, DexType},
+    error::Result,
+    insns::{Index, Insn, InsnFormat},
+};
+
+use super::cfg::ControlFlowGraph;
+
+/// A constant held by a register.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    /// A 32-bit value, which may be an int, the bits of a float, a boolean
+    /// or `null` (zero).
+    Int(i32),
+
+    /// A 64-bit value held by a register pair. It is stored for the first
+    /// register of the pair.
+    Wide(i64),
+
+    /// A string loaded by `const-string`.
+    String(Rc<String>),
+
+    /// A class loaded by `const-class`.
+    Type(Rc<DexType>),
+}
+
+type Registers = Vec<Option<Constant>>;
+
+/// Constants of all registers within a single method.
+pub struct Constants<'a> {
+    insns: &'a [Insn],
+    cfg: ControlFlowGraph,
+
+    /// registers at the start of every block, `None` for unreachable blocks
+    entries: Vec<Option<Registers>>,
+}
+
+impl<'a> Constants<'a> {
+    /// Propagates constants through the given code item, whose
+    /// disassembled instructions are passed in `insns`.
+    pub fn compute(code: &CodeItem, insns: &'a [Insn]) -> Result<Constants<'a>> {
+        let cfg = ControlFlowGraph::build(code, insns)?;
+        let mut entries: Vec<Option<Registers>> = vec![None; cfg.blocks.len()];
+        let mut pending = Vec::new();
+        if !cfg.blocks.is_empty() {
+            entries[0] = Some(vec![None; code.registers_size as usize]);
+            pending.push(0);
+        }
+
+        while let Some(block) = pending.pop() {
+            let item = &cfg.blocks[block];
+            let mut registers = entries[block].clone().unwrap_or_default();
+            // an exception may be thrown before any instruction of the block
+            let mut thrown = registers.clone();
+            for insn in &insns[item.insns.clone()] {
+                meet(&mut thrown, &registers);
+                step(insn, &mut registers);
+            }
+            meet(&mut thrown, &registers);
+
+            for successor in &item.successors {
+                let incoming = if item.handlers.contains(successor) {
+                    &thrown
+                } else {
+                    &registers
+                };
+                let changed = match &mut entries[*successor] {
+                    Some(entry) => meet(entry, incoming),
+                    entry @ None => {
+                        *entry = Some(incoming.clone());
+                        true
+                    }
+                };
+                if changed && !pending.contains(successor) {
+                    pending.push(*successor);
+                }
+            }
+        }
+
+        Ok(Constants {
+            insns,
+            cfg,
+            entries,
+        })
+    }
+
+    /// Returns the registers right before the instruction at the given
+    /// position of the instruction list, or `None` if the instruction
+    /// can't be reached or is a payload.
+    pub fn registers_at(&self, index: usize) -> Option<Vec<Option<Constant>>> {
+        let block = self
+            .cfg
+            .blocks
+            .partition_point(|x| x.insns.start <= index)
+            .checked_sub(1)?;
+        let item = &self.cfg.blocks[block];
+        if !item.insns.contains(&index) {
+            return None;
+        }
+
+        let mut registers = self.entries[block].clone()?;
+        for insn in &self.insns[item.insns.start..index] {
+            step(insn, &mut registers);
+        }
+        Some(registers)
+    }
+
+    /// Returns the constant held by `register` right before the instruction
+    /// at the given position of the instruction list.
+    pub fn value_at(&self, index: usize, register: u16) -> Option<Constant> {
+        self.registers_at(index)?
+            .get_mut(register as usize)
+            .and_then(|x| x.take())
+    }
+}
+
+/// Merges `other` into `registers` and returns whether anything changed.
+fn meet(registers: &mut Registers, other: &Registers) -> bool {
+    let mut changed = false;
+    for (value, other) in registers.iter_mut().zip(other) {
+        if value.is_some() && value != other {
+            *value = None;
+            changed = true;
+        }
+    }
+    changed
+}
+
+fn int_of(registers: &Registers, register: usize) -> Option<i32> {
+    match registers.get(register) {
+        Some(Some(Constant::Int(x))) => Some(*x),
+        _ => None,
+    }
+}
+
+fn wide_of(registers: &Registers, register: usize) -> Option<i64> {
+    match registers.get(register) {
+        Some(Some(Constant::Wide(x))) => Some(*x),
+        _ => None,
+    }
+}
+
+fn set(registers: &mut Registers, register: usize, value: Option<Constant>) {
+    if register >= registers.len() {
+        return;
+    }
+    // writing the second half of a pair destroys the wide value
+    if register > 0 && matches!(registers[register - 1], Some(Constant::Wide(_))) {
+        registers[register - 1] = None;
+    }
+    registers[register] = value;
+}
+
+fn set_wide(registers: &mut Registers, register: usize, value: Option<i64>) {
+    set(registers, register + 1, None);
+    set(registers, register, value.map(Constant::Wide));
+}
+
+/// Applies a single instruction to the registers.
+fn step(insn: &Insn, registers: &mut Registers) {
+    let op = insn.opcode.opcode;
+    match &insn.format {
+        InsnFormat::Format11n {
+            a,
+            b: Index::Literal(x),
+        } => set(registers, *a as usize, Some(Constant::Int(*x as i32))),
+        InsnFormat::Format21s {
+            a,
+            b: Index::Literal(x),
+        }
+        | InsnFormat::Format21h {
+            a,
+            b: Index::Literal(x),
+        }
+        | InsnFormat::Format31i {
+            a,
+            b: Index::Literal(x),
+        }
+        | InsnFormat::Format51l {
+            a,
+            b: Index::Literal(x),
+        } => match op {
+            // const/16, const, const/high16
+            0x13..=0x15 => set(registers, *a as usize, Some(Constant::Int(*x as i32))),
+            _ => set_wide(registers, *a as usize, Some(*x)),
+        },
+        InsnFormat::Format21c {
+            a,
+            b: Index::String(x),
+        }
+        | InsnFormat::Format31c {
+            a,
+            b: Index::String(x),
+        } => set(registers, *a as usize, Some(Constant::String(x.clone()))),
+        InsnFormat::Format21c { a, b } => match (op, b) {
+            (0x1C, Index::Type(x)) => set(registers, *a as usize, Some(Constant::Type(x.clone()))),
+            // new-instance, sget-kind, const-method-handle, const-method-type
+            (0x22 | 0x60..=0x66 | 0xFE | 0xFF, _) => unknown(registers, *a as usize, op == 0x61),
+            _ => {}
+        },
+        // move-result-kind, move-exception
+        InsnFormat::Format11x { a } if (0x0A..=0x0D).contains(&op) => {
+            unknown(registers, *a as usize, op == 0x0B)
+        }
+        InsnFormat::Format12x { a, b } => unary(op, *a as usize, *b as usize, registers),
+        InsnFormat::Format22x { a, b } => unary(op, *a as usize, *b as usize, registers),
+        InsnFormat::Format32x { a, b } => unary(op, *a as usize, *b as usize, registers),
+        InsnFormat::Format23x { a, b, c } => {
+            let (a, b, c) = (*a as usize, *b as usize, *c as usize);
+            match op {
+                0x90..=0x9A => {
+                    let value = match (int_of(registers, b), int_of(registers, c)) {
+                        (Some(x), Some(y)) => int_op(op - 0x90, x, y),
+                        _ => None,
+                    };
+                    set(registers, a, value.map(Constant::Int));
+                }
+                0x9B..=0xA5 => {
+                    let value = long_op(op - 0x9B, registers, b, c);
+                    set_wide(registers, a, value);
+                }
+                // cmp-kind, aget-kind, floating point arithmetic
+                0x2D..=0x31 | 0x44..=0x4A | 0xA6..=0xAF => {
+                    unknown(registers, a, op == 0x45 || op >= 0xAB)
+                }
+                _ => {}
+            }
+        }
+        // instance-of, new-array, iget-kind
+        InsnFormat::Format22c { a, .. } if matches!(op, 0x20 | 0x23 | 0x52..=0x58) => {
+            unknown(registers, *a as usize, op == 0x53)
+        }
+        InsnFormat::Format22s {
+            a,
+            b,
+            c: Index::Literal(x),
+        }
+        | InsnFormat::Format22b {
+            a,
+            b,
+            c: Index::Literal(x),
+        } => {
+            // lit16 operations don't support shifts, but share the order
+            let kind = if op >= 0xD8 { op - 0xD8 } else { op - 0xD0 };
+            let value = int_of(registers, *b as usize).and_then(|y| match kind {
+                // rsub-int
+                1 => Some((*x as i32).wrapping_sub(y)),
+                _ => int_op(kind, y, *x as i32),
+            });
+            set(registers, *a as usize, value.map(Constant::Int));
+        }
+        _ => {}
+    }
+}
+
+/// Moves, unary operations and two-address arithmetic.
+fn unary(op: u8, a: usize, b: usize, registers: &mut Registers) {
+    let int = int_of(registers, b);
+    let wide = wide_of(registers, b);
+    match op {
+        // move, move-object
+        0x01..=0x03 | 0x07..=0x09 => {
+            let value = registers.get(b).cloned().flatten();
+            let value = value.filter(|x| !matches!(x, Constant::Wide(_)));
+            set(registers, a, value);
+        }
+        0x04..=0x06 => set_wide(registers, a, wide),
+        // array-length
+        0x21 => set(registers, a, None),
+        0x7B => set(registers, a, int.map(|x| Constant::Int(x.wrapping_neg()))),
+        0x7C => set(registers, a, int.map(|x| Constant::Int(!x))),
+        0x7D => set_wide(registers, a, wide.map(|x| x.wrapping_neg())),
+        0x7E => set_wide(registers, a, wide.map(|x| !x)),
+        0x81 => set_wide(registers, a, int.map(|x| x as i64)),
+        0x84 => set(registers, a, wide.map(|x| Constant::Int(x as i32))),
+        0x8D => set(registers, a, int.map(|x| Constant::Int(x as i8 as i32))),
+        0x8E => set(registers, a, int.map(|x| Constant::Int(x as u16 as i32))),
+        0x8F => set(registers, a, int.map(|x| Constant::Int(x as i16 as i32))),
+        // conversions involving floating point values
+        0x7F..=0x8C => {
+            let wide_result = matches!(op, 0x80 | 0x83 | 0x86 | 0x88 | 0x89 | 0x8B);
+            unknown(registers, a, wide_result);
+        }
+        0xB0..=0xBA => {
+            let value = match (int_of(registers, a), int) {
+                (Some(x), Some(y)) => int_op(op - 0xB0, x, y),
+                _ => None,
+            };
+            set(registers, a, value.map(Constant::Int));
+        }
+        0xBB..=0xC5 => {
+            let value = long_op(op - 0xBB, registers, a, b);
+            set_wide(registers, a, value);
+        }
+        0xC6..=0xCF => unknown(registers, a, op >= 0xCB),
+        _ => {}
+    }
+}
+
+fn unknown(registers: &mut Registers, register: usize, wide: bool) {
+    if wide {
+        set_wide(registers, register, None);
+    } else {
+        set(registers, register, None);
+    }
+}
+
+/// Evaluates an int operation in the order add, sub, mul, div, rem, and,
+/// or, xor, shl, shr and ushr. Divisions by zero throw, so they have no
+/// result.
+fn int_op(kind: u8, x: i32, y: i32) -> Option<i32> {
+    Some(match kind {
+        0 => x.wrapping_add(y),
+        1 => x.wrapping_sub(y),
+        2 => x.wrapping_mul(y),
+        3 if y != 0 => x.wrapping_div(y),
+        4 if y != 0 => x.wrapping_rem(y),
+        5 => x & y,
+        6 => x | y,
+        7 => x ^ y,
+        8 => x.wrapping_shl(y as u32),
+        9 => x.wrapping_shr(y as u32),
+        10 => (x as u32).wrapping_shr(y as u32) as i32,
+        _ => return None,
+    })
+}
+
+/// Same as [int_op] for long operands. The shift distance is an int.
+fn long_op(kind: u8, registers: &Registers, b: usize, c: usize) -> Option<i64> {
+    let x = wide_of(registers, b)?;
+    if kind >= 8 {
+        let y = int_of(registers, c)? as u32;
+        return Some(match kind {
+            8 => x.wrapping_shl(y),
+            9 => x.wrapping_shr(y),
+            _ => (x as u64).wrapping_shr(y) as i64,
+        });
+    }
+
+    let y = wide_of(registers, c)?;
+    Some(match kind {
+        0 => x.wrapping_add(y),
+        1 => x.wrapping_sub(y),
+        2 => x.wrapping_mul(y),
+        3 if y != 0 => x.wrapping_div(y),
+        4 if y != 0 => x.wrapping_rem(y),
+        5 => x & y,
+        6 => x | y,
+        7 => x ^ y,
+        _ => return None,
+    })
+}
@@ -0,0 +1,96 @@
+//! Detection of dead code inside method bodies
+//!
+//! Obfuscators commonly inject junk blocks that are never executed or leave
+//! payloads behind that no instruction uses. Both are found with the
+//! [control flow graph](super::cfg) of a method.
+//!
+//! Blocks that only consist of `nop` instructions are ignored, since
+//! compilers emit them to align payloads.
+
+use std::{ops::Range, rc::Rc};
+
+use crate::dalvik::{
+    dex::{CodeItem, DexType},
+    error::Result,
+    file::IDexRef,
+    insns::Insn,
+};
+
+use super::{cfg::ControlFlowGraph, for_each_method};
+
+/// Dead code found in a single code item.
+#[derive(Debug, Default)]
+pub struct DeadCode {
+    /// Byte ranges of all blocks that can't be reached from the start of
+    /// the method.
+    pub unreachable_blocks: Vec<Range<usize>>,
+
+    /// Byte ranges of all payloads (switch tables and array data) that are
+    /// not referenced by any reachable instruction.
+    pub unreferenced_payloads: Vec<Range<usize>>,
+}
+
+impl DeadCode {
+    pub fn is_empty(&self) -> bool {
+        self.unreachable_blocks.is_empty() && self.unreferenced_payloads.is_empty()
+    }
+}
+
+/// Dead code of a method reported by [scan].
+#[derive(Debug)]
+pub struct MethodDeadCode {
+    /// The class declaring the method.
+    pub class: Rc<DexType>,
+
+    /// Index into the `method_ids` list.
+    pub method_idx: u32,
+
+    pub dead_code: DeadCode,
+}
+
+/// Finds unreachable blocks and unreferenced payloads in the given code
+/// item, whose disassembled instructions are passed in `insns`.
+pub fn find(code: &CodeItem, insns: &[Insn]) -> Result<DeadCode> {
+    let cfg = ControlFlowGraph::build(code, insns)?;
+    let reachable = cfg.reachable();
+
+    let mut dead_code = DeadCode::default();
+    for (block, item) in cfg.blocks.iter().enumerate() {
+        let padding = insns[item.insns.clone()]
+            .iter()
+            .all(|x| x.opcode.opcode == 0x00);
+        if !reachable[block] && !padding {
+            dead_code.unreachable_blocks.push(item.range.clone());
+        }
+    }
+    for payload in &cfg.payloads {
+        let referenced = payload
+            .referrers
+            .iter()
+            .any(|x| cfg.block_at(*x).is_some_and(|block| reachable[block]));
+        if !referenced {
+            dead_code.unreferenced_payloads.push(payload.range.clone());
+        }
+    }
+    Ok(dead_code)
+}
+
+/// Searches all methods of the given DEX file for dead code. Only methods
+/// with findings are returned.
+pub fn scan(dex: IDexRef<'_>) -> Result<Vec<MethodDeadCode>> {
+    let mut methods = Vec::new();
+    for_each_method(dex, |class, method, insns, _| {
+        if let Some(code) = &method.code {
+            let dead_code = find(code, insns)?;
+            if !dead_code.is_empty() {
+                methods.push(MethodDeadCode {
+                    class: class.type_.clone(),
+                    method_idx: method.identity,
+                    dead_code,
+                });
+            }
+        }
+        Ok(())
+    })?;
+    Ok(methods)
+}
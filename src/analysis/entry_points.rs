@@ -0,0 +1,250 @@
+//! Entry points of an Android application
+//!
+//! The framework calls into an application through the components declared
+//! in its `AndroidManifest.xml`: it instantiates their classes and invokes
+//! the lifecycle callbacks they override. [EntryPoints::find] marks these
+//! methods, given the list of components (the manifest itself is not
+//! parsed), to seed a reachability analysis:
+//!
+//! ```ignore
+//! let components = [
+//!     Component::new(ComponentKind::Application, "com.example.App"),
+//!     Component::new(ComponentKind::Activity, "com.example.MainActivity"),
+//! ];
+//! let entry_points = EntryPoints::find(&mut dex, &components)?;
+//! ```
+//!
+//! Callbacks are looked up along the superclass chain of a component, as
+//! long as the superclasses are defined in the same file, so that a
+//! callback implemented by a shared base activity is found as well.
+//! Classes that declare `native` methods are treated as reachable from
+//! native code: their static methods may be called through JNI, e.g. from
+//! `JNI_OnLoad`.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::dalvik::{
+    dex::AccessFlags,
+    error::Result,
+    file::{method::DexMethod, IDexRef},
+};
+
+/// The kind of a component declared in the manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ComponentKind {
+    Application,
+    Activity,
+    Service,
+    Receiver,
+    Provider,
+}
+
+impl ComponentKind {
+    /// Returns the names of the callbacks the framework invokes on
+    /// components of this kind.
+    pub fn callbacks(&self) -> &'static [&'static str] {
+        match self {
+            ComponentKind::Application => &[
+                "attachBaseContext",
+                "onCreate",
+                "onTerminate",
+                "onConfigurationChanged",
+                "onLowMemory",
+                "onTrimMemory",
+            ],
+            ComponentKind::Activity => &[
+                "attachBaseContext",
+                "onCreate",
+                "onPostCreate",
+                "onStart",
+                "onRestart",
+                "onResume",
+                "onPostResume",
+                "onPause",
+                "onStop",
+                "onDestroy",
+                "onNewIntent",
+                "onActivityResult",
+                "onRequestPermissionsResult",
+                "onSaveInstanceState",
+                "onRestoreInstanceState",
+                "onConfigurationChanged",
+                "onWindowFocusChanged",
+                "onCreateOptionsMenu",
+                "onOptionsItemSelected",
+                "onBackPressed",
+                "onKeyDown",
+                "onTouchEvent",
+            ],
+            ComponentKind::Service => &[
+                "attachBaseContext",
+                "onCreate",
+                "onStart",
+                "onStartCommand",
+                "onBind",
+                "onUnbind",
+                "onRebind",
+                "onTaskRemoved",
+                "onDestroy",
+                // IntentService, JobService and system services
+                "onHandleIntent",
+                "onStartJob",
+                "onStopJob",
+                "onAccessibilityEvent",
+                "onInterrupt",
+                "onServiceConnected",
+                "onNotificationPosted",
+                "onNotificationRemoved",
+            ],
+            ComponentKind::Receiver => &["onReceive"],
+            ComponentKind::Provider => &[
+                "attachInfo",
+                "onCreate",
+                "query",
+                "insert",
+                "update",
+                "delete",
+                "getType",
+                "call",
+                "openFile",
+                "openAssetFile",
+            ],
+        }
+    }
+}
+
+/// A component declared in the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Component {
+    pub kind: ComponentKind,
+
+    /// descriptor of the component's class, e.g. `Lcom/example/Main;`
+    pub class: String,
+}
+
+impl Component {
+    /// Creates a component from the class name used in the manifest, which
+    /// may be a Java name (`com.example.Main`) or a descriptor. Names
+    /// relative to the package (`.Main`) have to be resolved by the caller.
+    pub fn new(kind: ComponentKind, class: &str) -> Component {
+        let class = if class.starts_with('L') && class.ends_with(';') {
+            class.to_string()
+        } else {
+            format!("L{};", class.replace('.', "/"))
+        };
+        Component { kind, class }
+    }
+}
+
+/// Why a method is an entry point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryReason {
+    /// the no-argument constructor of a component
+    Constructor,
+
+    /// the static initializer of a component or of a class with native
+    /// methods
+    StaticInitializer,
+
+    /// a lifecycle callback of the given kind of component
+    Callback(ComponentKind),
+
+    /// a static method of a class that declares native methods
+    Native,
+}
+
+/// All entry points of a DEX file.
+#[derive(Debug, Default)]
+pub struct EntryPoints {
+    /// The reason of every entry point by its method index. A method that
+    /// is an entry point for several reasons keeps the first one found.
+    pub methods: BTreeMap<u32, EntryReason>,
+}
+
+impl EntryPoints {
+    /// Marks the entry points of the given components and of all classes
+    /// with native methods. Components that aren't defined in this file
+    /// are skipped.
+    pub fn find(dex: IDexRef<'_>, components: &[Component]) -> Result<EntryPoints> {
+        let mut classes = HashMap::new();
+        let mut entry_points = EntryPoints::default();
+        for index in 0..dex.get_header().class_defs_size {
+            let class = dex.get_class_def(index)?;
+            classes.insert(class.type_.to_string(), index);
+
+            let has_native = class
+                .get_methods()
+                .any(|(_, x)| has_flag(x, AccessFlags::NATIVE));
+            if !has_native {
+                continue;
+            }
+            for (_, method) in class.get_methods() {
+                if method.code.is_some() && has_flag(method, AccessFlags::STATIC) {
+                    let reason = match method.name.as_str() {
+                        "<clinit>" => EntryReason::StaticInitializer,
+                        _ => EntryReason::Native,
+                    };
+                    entry_points.add(method.identity, reason);
+                }
+            }
+        }
+
+        for component in components {
+            // callbacks that are overridden by a subclass are not invoked
+            let mut overridden = HashSet::new();
+            let mut descriptor = component.class.clone();
+            let mut visited = HashSet::new();
+            while let Some(&index) = classes.get(&descriptor) {
+                if !visited.insert(index) {
+                    break;
+                }
+                let class = dex.get_class_def(index)?;
+                let is_component = descriptor == component.class;
+                for (_, method) in class.get_methods() {
+                    if method.code.is_none() {
+                        continue;
+                    }
+                    let reason = match method.name.as_str() {
+                        "<init>" if is_component && method.proto.parameters.is_empty() => {
+                            EntryReason::Constructor
+                        }
+                        "<clinit>" if is_component => EntryReason::StaticInitializer,
+                        name if !has_flag(method, AccessFlags::STATIC)
+                            && component.kind.callbacks().contains(&name) =>
+                        {
+                            let signature = (method.name.clone(), method.proto.to_string());
+                            if !overridden.insert(signature) {
+                                continue;
+                            }
+                            EntryReason::Callback(component.kind)
+                        }
+                        _ => continue,
+                    };
+                    entry_points.add(method.identity, reason);
+                }
+                match &class.super_class {
+                    Some(superclass) => descriptor = superclass.to_string(),
+                    None => break,
+                }
+            }
+        }
+        Ok(entry_points)
+    }
+
+    /// Marks a method as an entry point, e.g. one that is only known to be
+    /// called from native code.
+    pub fn add(&mut self, method_idx: u32, reason: EntryReason) {
+        self.methods.entry(method_idx).or_insert(reason);
+    }
+
+    pub fn contains(&self, method_idx: u32) -> bool {
+        self.methods.contains_key(&method_idx)
+    }
+}
+
+fn has_flag(method: &DexMethod, flag: AccessFlags) -> bool {
+    method
+        .access_flags
+        .as_ref()
+        .is_some_and(|x| x.contains(flag))
+}
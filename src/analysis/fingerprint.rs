@@ -0,0 +1,118 @@
+//! Content fingerprints of whole files
+//!
+//! Two files that declare the same classes with the same members and code
+//! get the same [content_fingerprint], even if a tool re-ordered their
+//! pools, re-packed their data section or changed the debug information.
+//! This is meant for deduplicating a corpus of samples, where the checksum
+//! and signature of the header differ for every re-packed copy.
+//!
+//! Instructions are hashed in their [pretty](crate::pretty) form, so that
+//! index operands are replaced by the strings, types and members they
+//! refer to.
+
+use openssl::sha::Sha256;
+
+use crate::{
+    dalvik::{
+        error::{ErrorContext, Result, ResultExt},
+        file::IDexRef,
+    },
+    pretty::{pretty_insn, pretty_method, pretty_type, PrettyOptions},
+};
+
+/// Computes a SHA-256 over the semantic content of the given file.
+///
+/// Every class is reduced to its descriptor, access flags, superclass,
+/// interfaces, the signatures and access flags of its members and the
+/// instructions, try blocks and exception handlers of its methods. Classes and members are sorted by their
+/// names before hashing. Annotations, static values, source files and debug
+/// information are not part of the fingerprint.
+pub fn content_fingerprint(dex: IDexRef<'_>) -> Result<[u8; 32]> {
+    let options = PrettyOptions::smali();
+    let mut classes = Vec::with_capacity(dex.get_header().class_defs_size as usize);
+    for index in 0..dex.get_header().class_defs_size {
+        let digest = class_digest(dex, index, &options).context(ErrorContext::ClassDef(index))?;
+        classes.push(digest);
+    }
+    // the descriptor starts every digest, so this sorts classes by name
+    classes.sort();
+
+    let mut hasher = Sha256::new();
+    for class in &classes {
+        hasher.update(class.as_bytes());
+        hasher.update(b"\n");
+    }
+    Ok(hasher.finish())
+}
+
+/// Returns the text that represents a single class in the fingerprint.
+fn class_digest(dex: IDexRef<'_>, index: u32, options: &PrettyOptions) -> Result<String> {
+    let class = dex.get_class_def(index)?;
+    let flags = class.flags.as_ref().map_or(0, |x| x.bits());
+    let mut digest = format!("{} {:#x}", pretty_type(&class.type_, options), flags);
+    if let Some(super_class) = &class.super_class {
+        digest.push_str(&format!(" extends {}", pretty_type(super_class, options)));
+    }
+    let mut interfaces: Vec<String> = class
+        .interfaces
+        .iter()
+        .map(|x| pretty_type(x, options))
+        .collect();
+    interfaces.sort();
+    for interface in interfaces {
+        digest.push_str(&format!(" implements {}", interface));
+    }
+
+    let mut members = Vec::new();
+    for (_, field) in class.get_fields() {
+        let flags = field.access_flags.as_ref().map_or(0, |x| x.bits());
+        members.push(format!(
+            "field {}:{} {:#x}",
+            field.name,
+            pretty_type(&field.type_, options),
+            flags
+        ));
+    }
+    for (index, method) in class.get_methods() {
+        let flags = method.access_flags.as_ref().map_or(0, |x| x.bits());
+        let mut member = format!(
+            "method {} {:#x}",
+            pretty_method(dex, *index, options)?,
+            flags
+        );
+        if let Some(code) = &method.code {
+            let insns = method.disasm(dex).context(ErrorContext::Method(*index))?;
+            for insn in &insns {
+                member.push_str("\n  ");
+                member.push_str(&pretty_insn(insn, dex, options)?);
+            }
+            // handlers are hashed by the descriptors of their types, as
+            // the type ids differ between files
+            for try_item in &code.tries {
+                let end = try_item.start_addr + try_item.insn_count as u32;
+                member.push_str(&format!("\n  try {:#x}..{:#x}", try_item.start_addr, end));
+                let Some(handler) = code.catch_handler(try_item) else {
+                    continue;
+                };
+                for pair in &handler.handlers {
+                    let type_ = dex.get_type(pair.type_idx.0)?;
+                    member.push_str(&format!(
+                        " catch {} {:#x}",
+                        pretty_type(&type_, options),
+                        pair.addr.0
+                    ));
+                }
+                if let Some(addr) = &handler.catch_all_addr {
+                    member.push_str(&format!(" catchall {:#x}", addr.0));
+                }
+            }
+        }
+        members.push(member);
+    }
+    members.sort();
+    for member in members {
+        digest.push_str("\n ");
+        digest.push_str(&member);
+    }
+    Ok(digest)
+}
@@ -0,0 +1,182 @@
+//! Class hierarchy of one or more DEX files
+//!
+//! The hierarchy indexes all class definitions by their descriptor, which
+//! makes it possible to walk superclass and interface chains across multiple
+//! DEX files (e.g. `classes.dex` and `classes2.dex` of the same application).
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    rc::Rc,
+};
+
+use crate::dalvik::{
+    dex::{AccessFlags, DexType},
+    error::{Error, Result},
+    file::{
+        method::{DexMethod, DexPrototype},
+        DexClassDef, IDexRef,
+    },
+};
+
+#[derive(Debug)]
+pub struct ClassHierarchy {
+    /// Maps the descriptor of every defined class to the position of its
+    /// DEX file (in the slice passed to [ClassHierarchy::build]) and its
+    /// class definition index.
+    classes: HashMap<String, (usize, u32)>,
+}
+
+/// The result of a virtual method resolution.
+#[derive(Debug)]
+pub struct ResolvedMethod {
+    /// Position of the DEX file that defines the implementation.
+    pub dex: usize,
+
+    /// The class declaring the resolved implementation.
+    pub class: Rc<DexClassDef>,
+
+    /// Index into the `method_ids` list of the defining DEX file.
+    pub method_idx: u32,
+}
+
+impl ResolvedMethod {
+    /// Returns the resolved method, including its code item.
+    pub fn method(&self) -> Option<&DexMethod> {
+        self.class.get_method(self.method_idx)
+    }
+}
+
+impl ClassHierarchy {
+    /// Indexes all class definitions of the given DEX files.
+    ///
+    /// If a class is defined more than once, the first definition wins,
+    /// just like it would when loading the files with a single class loader.
+    /// Only the raw class definition items are read, no class is loaded.
+    pub fn build(dexes: &mut [IDexRef<'_>]) -> Result<Self> {
+        let mut classes = HashMap::new();
+        for (i, dex) in dexes.iter_mut().enumerate() {
+            for index in 0..dex.get_header().class_defs_size {
+                let item = dex.get_class_def_item(index)?;
+                let type_ = dex.get_type(item.class_idx)?;
+                classes.entry(type_.to_string()).or_insert((i, index));
+            }
+        }
+        Ok(ClassHierarchy { classes })
+    }
+
+    /// Returns the position of the defining DEX file and the class definition
+    /// index for the given type descriptor.
+    pub fn lookup(&self, descriptor: &str) -> Option<(usize, u32)> {
+        self.classes.get(descriptor).copied()
+    }
+
+    /// Loads the class definition of the given type descriptor or returns
+    /// `None` if the class is not defined in any of the indexed files.
+    pub fn get_class(
+        &self,
+        dexes: &mut [IDexRef<'_>],
+        descriptor: &str,
+    ) -> Result<Option<(usize, Rc<DexClassDef>)>> {
+        match self.lookup(descriptor) {
+            Some((dex, index)) => {
+                let class = get_dex(dexes, dex)?.get_class_def(index)?;
+                Ok(Some((dex, class)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Resolves the most-derived implementation of the method referenced by
+    /// an `invoke-virtual` or `invoke-interface` instruction.
+    ///
+    /// `method_idx` must be an index into the `method_ids` list of the DEX
+    /// file at position `dex`, and `receiver` the descriptor of the concrete
+    /// receiver type. The superclass chain of the receiver is searched first;
+    /// if no implementation has been found, all implemented interfaces are
+    /// searched (breadth-first) for a default method.
+    ///
+    /// `None` is returned if the chain leaves the indexed DEX files (e.g. at
+    /// a framework class) before an implementation is found.
+    pub fn resolve_virtual(
+        &self,
+        dexes: &mut [IDexRef<'_>],
+        dex: usize,
+        method_idx: u32,
+        receiver: &str,
+    ) -> Result<Option<ResolvedMethod>> {
+        let (name, proto) = {
+            let source = get_dex(dexes, dex)?;
+            let method_item = source.get_method(method_idx)?;
+            (
+                source.get_string(method_item.name_idx)?,
+                source.get_proto(method_item.proto_idx as u32)?,
+            )
+        };
+
+        // 1. walk up the superclass chain. The visited set protects us from
+        // (malicious) circular inheritance.
+        let mut visited: HashSet<String> = HashSet::new();
+        let mut interfaces: VecDeque<Rc<DexType>> = VecDeque::new();
+        let mut current = Some(receiver.to_string());
+        while let Some(descriptor) = current.take() {
+            if !visited.insert(descriptor.clone()) {
+                break;
+            }
+            // a class outside of the indexed files may implement the method
+            // itself, so its default methods are not searched either
+            let Some((i, class)) = self.get_class(dexes, &descriptor)? else {
+                return Ok(None);
+            };
+            if let Some(method_idx) = find_implementation(&class, &name, &proto) {
+                return Ok(Some(ResolvedMethod {
+                    dex: i,
+                    class,
+                    method_idx,
+                }));
+            }
+            interfaces.extend(class.interfaces.iter().cloned());
+            current = class.super_class.as_ref().map(|x| x.to_string());
+        }
+
+        // 2. search for default methods in all implemented interfaces
+        visited.clear();
+        while let Some(interface) = interfaces.pop_front() {
+            let descriptor = interface.to_string();
+            if !visited.insert(descriptor.clone()) {
+                continue;
+            }
+            let Some((i, class)) = self.get_class(dexes, &descriptor)? else {
+                continue;
+            };
+            if let Some(method_idx) = find_implementation(&class, &name, &proto) {
+                return Ok(Some(ResolvedMethod {
+                    dex: i,
+                    class,
+                    method_idx,
+                }));
+            }
+            interfaces.extend(class.interfaces.iter().cloned());
+        }
+        Ok(None)
+    }
+}
+
+fn get_dex<'a, 'b>(dexes: &'a mut [IDexRef<'b>], dex: usize) -> Result<&'a mut IDexRef<'b>> {
+    dexes.get_mut(dex).ok_or(Error::InvalidIndex(dex))
+}
+
+/// Returns the identity of the virtual method with the given name and
+/// prototype, but only if it is not abstract. Native methods have no code,
+/// but are implementations as well.
+fn find_implementation(class: &DexClassDef, name: &str, proto: &DexPrototype) -> Option<u32> {
+    class
+        .get_virtual_methods()
+        .find(|m| {
+            let is_abstract = m
+                .access_flags
+                .as_ref()
+                .is_some_and(|x| x.contains(AccessFlags::ABSTRACT));
+            !is_abstract && m.name.as_str() == name && *m.proto == *proto
+        })
+        .map(|m| m.identity)
+}
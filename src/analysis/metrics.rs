@@ -0,0 +1,90 @@
+//! Size and complexity metrics of method bodies
+//!
+//! The metrics are cheap to compute from the disassembled instructions and
+//! help ranking methods for manual review. Unusually large methods with few
+//! branches or invokes are often padded or flattened by an obfuscator.
+
+use std::{cmp::Reverse, rc::Rc};
+
+use crate::dalvik::{
+    dex::{CodeItem, DexType},
+    error::Result,
+    file::IDexRef,
+    insns::Insn,
+};
+
+use super::for_each_method;
+
+/// Metrics of a single code item.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Metrics {
+    /// Number of instructions, including payloads.
+    pub insns: usize,
+
+    /// Size of the instructions in 16-bit code units.
+    pub code_units: u32,
+
+    /// Number of try blocks.
+    pub tries: u16,
+
+    /// Number of registers used by the method, including its parameters.
+    pub registers: u16,
+
+    /// Number of `invoke-*` instructions.
+    pub invokes: usize,
+
+    /// Number of `goto`, `if-*` and switch instructions.
+    pub branches: usize,
+}
+
+/// Metrics of a method reported by [scan].
+#[derive(Debug)]
+pub struct MethodMetrics {
+    /// The class declaring the method.
+    pub class: Rc<DexType>,
+
+    /// Index into the `method_ids` list.
+    pub method_idx: u32,
+
+    pub metrics: Metrics,
+}
+
+/// Computes the metrics of the given code item, whose disassembled
+/// instructions are passed in `insns`.
+pub fn measure(code: &CodeItem, insns: &[Insn]) -> Metrics {
+    let mut metrics = Metrics {
+        insns: insns.len(),
+        code_units: code.insns_size,
+        tries: code.tries_size,
+        registers: code.registers_size,
+        ..Metrics::default()
+    };
+    for insn in insns {
+        match insn.opcode.opcode {
+            // invoke-kind, invoke-kind/range, invoke-polymorphic, invoke-custom
+            0x6E..=0x72 | 0x74..=0x78 | 0xFA..=0xFD => metrics.invokes += 1,
+            // goto, packed-switch, sparse-switch, if-test, if-testz
+            0x28..=0x2C | 0x32..=0x3D => metrics.branches += 1,
+            _ => {}
+        }
+    }
+    metrics
+}
+
+/// Computes the metrics of all methods of the given DEX file. Methods are
+/// sorted by their code size, largest first.
+pub fn scan(dex: IDexRef<'_>) -> Result<Vec<MethodMetrics>> {
+    let mut methods = Vec::new();
+    for_each_method(dex, |class, method, insns, _| {
+        if let Some(code) = &method.code {
+            methods.push(MethodMetrics {
+                class: class.type_.clone(),
+                method_idx: method.identity,
+                metrics: measure(code, insns),
+            });
+        }
+        Ok(())
+    })?;
+    methods.sort_by_key(|x| Reverse(x.metrics.code_units));
+    Ok(methods)
+}
@@ -0,0 +1,50 @@
+//! Analysis passes built on top of the lazy DEX model
+//!
+//! All passes operate on [IDex](crate::dalvik::file::IDex) references and
+//! therefore work with any DEX file that has been opened through
+//! [Dex::read](crate::dalvik::file::Dex::read).
+
+use crate::dalvik::{
+    error::{ErrorContext, Result, ResultExt},
+    file::{method::DexMethod, DexClassDef, IDexRef},
+    insns::Insn,
+};
+
+pub mod accessors;
+pub mod api;
+pub mod callgraph;
+pub mod cfg;
+pub mod constants;
+pub mod dead_code;
+pub mod entry_points;
+pub mod fingerprint;
+pub mod hierarchy;
+pub mod metrics;
+pub mod opcodes;
+pub mod reachability;
+pub mod reflection;
+pub mod strings;
+pub mod taint;
+
+/// Disassembles every method that stores code and passes the decoded
+/// instructions to the given callback, together with the declaring class
+/// and the method itself.
+///
+/// Classes are visited in the order of the `class_defs` list; within a
+/// class, direct methods are visited before virtual methods.
+pub fn for_each_method<F>(dex: IDexRef<'_>, mut callback: F) -> Result<()>
+where
+    F: FnMut(&DexClassDef, &DexMethod, &[Insn], IDexRef<'_>) -> Result<()>,
+{
+    for index in 0..dex.get_header().class_defs_size {
+        let class = dex.get_class_def(index)?;
+        for (_, method) in class.get_methods() {
+            if method.code.is_none() {
+                continue;
+            }
+            let insns = method.disasm(dex).context(ErrorContext::ClassDef(index))?;
+            callback(&class, method, &insns, dex).context(ErrorContext::ClassDef(index))?;
+        }
+    }
+    Ok(())
+}
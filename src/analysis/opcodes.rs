@@ -0,0 +1,136 @@
+//! Opcode frequencies
+//!
+//! Compilers, optimizers and packers each leave a characteristic mix of
+//! instructions behind, so the opcode histogram of a file is a cheap
+//! fingerprint. Opcodes that `d8` only emits for specific language
+//! features (method handles, `invoke-custom` for lambdas and string
+//! concatenation) are reported with their locations as well.
+
+use std::rc::Rc;
+
+use crate::dalvik::{
+    dex::DexType,
+    error::Result,
+    file::IDexRef,
+    insns::{Insn, Opcode, OPCODES},
+};
+
+use super::for_each_method;
+
+/// `invoke-polymorphic`, `invoke-polymorphic/range`, `invoke-custom`,
+/// `invoke-custom/range`, `const-method-handle` and `const-method-type`.
+pub const RARE_OPCODES: [u8; 6] = [0xFA, 0xFB, 0xFC, 0xFD, 0xFE, 0xFF];
+
+/// Number of instructions per opcode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    /// counts indexed by the opcode value
+    pub counts: [usize; 256],
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram { counts: [0; 256] }
+    }
+}
+
+impl Histogram {
+    /// Counts the given instructions. Payloads are not counted, as they
+    /// share the opcode of `nop`.
+    pub fn add(&mut self, insns: &[Insn]) {
+        for insn in insns.iter().filter(|x| x.payload.is_none()) {
+            self.counts[insn.opcode.opcode as usize] += 1;
+        }
+    }
+
+    /// Returns the number of instructions with the given opcode.
+    pub fn get(&self, opcode: u8) -> usize {
+        self.counts[opcode as usize]
+    }
+
+    /// Returns the number of all counted instructions.
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// Returns all opcodes that were counted at least once, most frequent
+    /// first.
+    pub fn most_frequent(&self) -> Vec<(&'static Opcode, usize)> {
+        let mut opcodes: Vec<_> = (0..256)
+            .filter(|x| self.counts[*x] != 0)
+            .map(|x| (&OPCODES[x], self.counts[x]))
+            .collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.opcode.cmp(&b.0.opcode)));
+        opcodes
+    }
+}
+
+/// The histogram of a single class.
+#[derive(Debug)]
+pub struct ClassHistogram {
+    pub class: Rc<DexType>,
+
+    /// Index into the `class_defs` list.
+    pub class_def: u32,
+
+    pub histogram: Histogram,
+}
+
+/// An instruction with one of the [RARE_OPCODES].
+#[derive(Debug)]
+pub struct RareUsage {
+    /// The class declaring the method.
+    pub class: Rc<DexType>,
+
+    /// Index into the `method_ids` list.
+    pub method_idx: u32,
+
+    /// Byte offset of the instruction within the method's bytecode.
+    pub offset: usize,
+
+    pub opcode: &'static Opcode,
+}
+
+/// The result of [opcode_stats].
+#[derive(Debug, Default)]
+pub struct OpcodeStats {
+    pub overall: Histogram,
+
+    /// Histograms of all classes with code, in the order of the
+    /// `class_defs` list.
+    pub classes: Vec<ClassHistogram>,
+
+    pub rare: Vec<RareUsage>,
+}
+
+/// Counts the opcodes of all methods of the given DEX file.
+pub fn opcode_stats(dex: IDexRef<'_>) -> Result<OpcodeStats> {
+    let mut stats = OpcodeStats::default();
+    for_each_method(dex, |class, method, insns, _| {
+        stats.overall.add(insns);
+        match stats.classes.last_mut() {
+            Some(last) if last.class_def == class.identity => last.histogram.add(insns),
+            _ => {
+                let mut histogram = Histogram::default();
+                histogram.add(insns);
+                stats.classes.push(ClassHistogram {
+                    class: class.type_.clone(),
+                    class_def: class.identity,
+                    histogram,
+                });
+            }
+        }
+        for insn in insns {
+            if RARE_OPCODES.contains(&insn.opcode.opcode) {
+                stats.rare.push(RareUsage {
+                    class: class.type_.clone(),
+                    method_idx: method.identity,
+                    offset: insn.range.start,
+                    opcode: insn.opcode,
+                });
+            }
+        }
+        Ok(())
+    })?;
+    Ok(stats)
+}
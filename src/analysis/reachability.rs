@@ -0,0 +1,434 @@
+//! Reachability of classes and methods from entry points
+//!
+//! Starting at the [entry points](super::entry_points), all methods that
+//! can be called are collected. Virtual calls are dispatched to every
+//! matching implementation in a subclass that is instantiated by reachable
+//! code, and the static initializer of a class becomes reachable as soon as
+//! the class is used.
+//!
+//! Classes may also be called by the framework through methods they
+//! override, e.g. `Runnable.run` or `View.OnClickListener.onClick`. As the
+//! framework classes are not part of the file, all virtual methods of an
+//! instantiated class that don't override a method defined in the file are
+//! assumed to be such callbacks if the class extends or implements a type
+//! outside of the file. This errs on the side of reachability, so code
+//! reported as unreachable is dead unless it is called through reflection
+//! or from native code. Whether its name appears in a string constant is
+//! reported as a hint for the former:
+//!
+//! ```ignore
+//! let entry_points = EntryPoints::find(&mut dex, &components)?;
+//! let reachability = Reachability::compute(&mut dex, &entry_points)?;
+//! for class in reachability.unreachable_classes(&mut dex)? {
+//!     println!("{} (named in strings: {})", class.descriptor, class.named_in_strings);
+//! }
+//! ```
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::dalvik::{
+    dex::AccessFlags,
+    error::Result,
+    file::IDexRef,
+    insns::{Index, InsnFormat},
+};
+
+use super::{entry_points::EntryPoints, for_each_method, strings::count_references};
+
+/// Methods of `java.lang.Object` that the runtime or libraries call on any
+/// object.
+const OBJECT_CALLBACKS: [&str; 5] = ["toString", "equals", "hashCode", "finalize", "clone"];
+
+/// The name and prototype of a method.
+type Signature = (String, String);
+
+/// A class that can't be reached from any entry point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnreachableClass {
+    /// Index into the `class_defs` list.
+    pub class_idx: u32,
+    pub descriptor: String,
+
+    /// whether the name of the class appears in a string constant, so that
+    /// it may be loaded through reflection
+    pub named_in_strings: bool,
+}
+
+/// A method with code that can't be reached from any entry point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnreachableMethod {
+    /// Index into the `method_ids` list.
+    pub method_idx: u32,
+
+    /// whether the name of the method appears in a string constant
+    pub named_in_strings: bool,
+}
+
+/// The classes and methods reachable from a set of entry points.
+#[derive(Debug, Default)]
+pub struct Reachability {
+    /// All reachable methods that are defined in the file.
+    pub methods: BTreeSet<u32>,
+
+    /// Indices of all classes with a reachable method.
+    pub classes: BTreeSet<u32>,
+}
+
+/// A class definition and its relation to other classes.
+#[derive(Debug, Default)]
+struct ClassNode {
+    index: u32,
+    superclass: Option<String>,
+    interfaces: Vec<String>,
+    children: Vec<String>,
+    methods: HashMap<Signature, u32>,
+    virtual_methods: Vec<Signature>,
+    clinit: Option<u32>,
+}
+
+/// A method defined in the file and what its code refers to.
+#[derive(Debug, Default)]
+struct MethodNode {
+    class: String,
+    is_constructor: bool,
+
+    /// `(class, signature, virtual)` of every invoked method
+    calls: Vec<(String, Signature, bool)>,
+
+    /// classes whose static fields are accessed
+    initialized: Vec<String>,
+}
+
+struct Solver {
+    classes: HashMap<String, ClassNode>,
+    methods: HashMap<u32, MethodNode>,
+    reached: HashSet<u32>,
+    worklist: Vec<u32>,
+    initialized: HashSet<String>,
+    instantiated: HashSet<String>,
+
+    /// signatures of virtual calls by the class they were made on
+    dispatched: HashMap<String, HashSet<Signature>>,
+}
+
+impl Reachability {
+    /// Computes all methods reachable from the given entry points.
+    pub fn compute(dex: IDexRef<'_>, entry_points: &EntryPoints) -> Result<Reachability> {
+        let mut solver = Solver {
+            classes: HashMap::new(),
+            methods: HashMap::new(),
+            reached: HashSet::new(),
+            worklist: entry_points.methods.keys().copied().collect(),
+            initialized: HashSet::new(),
+            instantiated: HashSet::new(),
+            dispatched: HashMap::new(),
+        };
+
+        for index in 0..dex.get_header().class_defs_size {
+            let class = dex.get_class_def(index)?;
+            let mut node = ClassNode {
+                index,
+                superclass: class.super_class.as_ref().map(|x| x.to_string()),
+                interfaces: class.interfaces.iter().map(|x| x.to_string()).collect(),
+                ..Default::default()
+            };
+            for (_, method) in class.get_methods() {
+                let signature = (method.name.to_string(), method.proto.to_string());
+                let is_static = method
+                    .access_flags
+                    .as_ref()
+                    .is_some_and(|x| x.contains(AccessFlags::STATIC));
+                if method.name.as_str() == "<clinit>" {
+                    node.clinit = Some(method.identity);
+                } else if !is_static && !method.name.starts_with('<') {
+                    node.virtual_methods.push(signature.clone());
+                }
+                node.methods.insert(signature, method.identity);
+                solver.methods.insert(
+                    method.identity,
+                    MethodNode {
+                        class: class.type_.to_string(),
+                        is_constructor: method.name.as_str() == "<init>",
+                        ..Default::default()
+                    },
+                );
+            }
+            solver
+                .classes
+                .entry(class.type_.to_string())
+                .or_insert(node);
+        }
+        let children: Vec<(String, String)> = solver
+            .classes
+            .iter()
+            .flat_map(|(class, node)| {
+                let parents = node.superclass.iter().chain(&node.interfaces);
+                parents.map(|x| (x.clone(), class.clone()))
+            })
+            .collect();
+        for (parent, child) in children {
+            if let Some(node) = solver.classes.get_mut(&parent) {
+                node.children.push(child);
+            }
+        }
+
+        for_each_method(dex, |_, method, insns, dex| {
+            let mut calls = Vec::new();
+            let mut initialized = Vec::new();
+            for insn in insns {
+                let opcode = insn.opcode.opcode;
+                match &insn.format {
+                    InsnFormat::Format35c {
+                        b: Index::Method(_, item),
+                        ..
+                    }
+                    | InsnFormat::Format3rc {
+                        b: Index::Method(_, item),
+                        ..
+                    } => {
+                        let class = dex.get_type(item.class_idx as u32)?.to_string();
+                        let name = dex.get_string(item.name_idx)?.to_string();
+                        let proto = dex.get_proto(item.proto_idx as u32)?.to_string();
+                        // invoke-virtual and invoke-interface
+                        let is_virtual = matches!(opcode, 0x6E | 0x72 | 0x74 | 0x78);
+                        calls.push((class, (name, proto), is_virtual));
+                    }
+                    // sget* and sput*
+                    InsnFormat::Format21c {
+                        b: Index::Field(item),
+                        ..
+                    } if (0x60..=0x6D).contains(&opcode) => {
+                        initialized.push(dex.get_type(item.class_idx as u32)?.to_string());
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(node) = solver.methods.get_mut(&method.identity) {
+                node.calls = calls;
+                node.initialized = initialized;
+            }
+            Ok(())
+        })?;
+
+        solver.run();
+        let mut reachability = Reachability::default();
+        for method_idx in solver.reached {
+            let class = &solver.methods[&method_idx].class;
+            reachability.classes.insert(solver.classes[class].index);
+            reachability.methods.insert(method_idx);
+        }
+        Ok(reachability)
+    }
+
+    pub fn is_reachable(&self, method_idx: u32) -> bool {
+        self.methods.contains(&method_idx)
+    }
+
+    /// Returns all classes without a reachable method.
+    pub fn unreachable_classes(&self, dex: IDexRef<'_>) -> Result<Vec<UnreachableClass>> {
+        let strings = strings(dex)?;
+        let mut classes = Vec::new();
+        for class_idx in 0..dex.get_header().class_defs_size {
+            if self.classes.contains(&class_idx) {
+                continue;
+            }
+            let descriptor = dex.get_class_def(class_idx)?.type_.to_string();
+            let name = descriptor
+                .strip_prefix('L')
+                .and_then(|x| x.strip_suffix(';'))
+                .unwrap_or(&descriptor);
+            // Class.forName uses the binary name, JNI the internal one
+            let named_in_strings =
+                strings.contains(name) || strings.contains(&name.replace('/', "."));
+            classes.push(UnreachableClass {
+                class_idx,
+                descriptor,
+                named_in_strings,
+            });
+        }
+        Ok(classes)
+    }
+
+    /// Returns all methods with code that are not reachable, including the
+    /// ones of unreachable classes.
+    pub fn unreachable_methods(&self, dex: IDexRef<'_>) -> Result<Vec<UnreachableMethod>> {
+        let strings = strings(dex)?;
+        let mut methods = Vec::new();
+        for class_idx in 0..dex.get_header().class_defs_size {
+            let class = dex.get_class_def(class_idx)?;
+            for (&method_idx, method) in class.get_methods() {
+                if method.code.is_none() || self.is_reachable(method_idx) {
+                    continue;
+                }
+                methods.push(UnreachableMethod {
+                    method_idx,
+                    named_in_strings: strings.contains(method.name.as_str()),
+                });
+            }
+        }
+        Ok(methods)
+    }
+}
+
+impl Solver {
+    fn run(&mut self) {
+        while let Some(method_idx) = self.worklist.pop() {
+            if !self.methods.contains_key(&method_idx) || !self.reached.insert(method_idx) {
+                continue;
+            }
+            let node = &self.methods[&method_idx];
+            let class = node.class.clone();
+            let calls = node.calls.clone();
+            let initialized = node.initialized.clone();
+            let is_constructor = node.is_constructor;
+            self.initialize(&class);
+            if is_constructor {
+                self.instantiate(&class);
+            }
+            for class in initialized {
+                self.initialize(&class);
+            }
+            for (class, signature, is_virtual) in calls {
+                if is_virtual {
+                    self.dispatch(&class, signature);
+                } else if let Some(target) = self.resolve(&class, &signature) {
+                    self.worklist.push(target);
+                }
+            }
+        }
+    }
+
+    /// Marks the static initializers of a class and its superclasses as
+    /// reachable.
+    fn initialize(&mut self, class: &str) {
+        let mut class = class.to_string();
+        while self.initialized.insert(class.clone()) {
+            let Some(node) = self.classes.get(&class) else {
+                break;
+            };
+            self.worklist.extend(node.clinit);
+            match &node.superclass {
+                Some(parent) => class = parent.clone(),
+                None => break,
+            }
+        }
+    }
+
+    /// Adds the implementations of all calls that were dispatched on one of
+    /// the class's supertypes so far, as well as the methods that may be
+    /// called by the framework.
+    fn instantiate(&mut self, class: &str) {
+        if !self.instantiated.insert(class.to_string()) {
+            return;
+        }
+        let supertypes = self.supertypes(class);
+        let mut signatures: Vec<Signature> = supertypes
+            .iter()
+            .filter_map(|x| self.dispatched.get(x))
+            .flatten()
+            .cloned()
+            .collect();
+
+        // virtual methods that don't override a method of the file
+        let external = supertypes
+            .iter()
+            .any(|x| !self.classes.contains_key(x) && x != "Ljava/lang/Object;");
+        for supertype in &supertypes {
+            let Some(node) = self.classes.get(supertype) else {
+                continue;
+            };
+            for signature in &node.virtual_methods {
+                let overrides = self
+                    .supertypes(supertype)
+                    .iter()
+                    .filter(|x| *x != supertype)
+                    .filter_map(|x| self.classes.get(x))
+                    .any(|x| x.methods.contains_key(signature));
+                if !overrides && (external || OBJECT_CALLBACKS.contains(&signature.0.as_str())) {
+                    signatures.push(signature.clone());
+                }
+            }
+        }
+
+        for signature in signatures {
+            if let Some(target) = self.resolve(class, &signature) {
+                self.worklist.push(target);
+            }
+        }
+    }
+
+    /// Records a virtual call and adds its implementations in all
+    /// instantiated subtypes.
+    fn dispatch(&mut self, class: &str, signature: Signature) {
+        if !self
+            .dispatched
+            .entry(class.to_string())
+            .or_default()
+            .insert(signature.clone())
+        {
+            return;
+        }
+        let mut pending = vec![class.to_string()];
+        let mut visited = HashSet::new();
+        while let Some(class) = pending.pop() {
+            if !visited.insert(class.clone()) {
+                continue;
+            }
+            if self.instantiated.contains(&class) {
+                let target = self.resolve(&class, &signature);
+                self.worklist.extend(target);
+            }
+            if let Some(node) = self.classes.get(&class) {
+                pending.extend(node.children.iter().cloned());
+            }
+        }
+    }
+
+    /// Finds the implementation of a method in the given class or its
+    /// supertypes, superclasses first.
+    fn resolve(&self, class: &str, signature: &Signature) -> Option<u32> {
+        self.supertypes(class)
+            .iter()
+            .find_map(|x| self.classes.get(x)?.methods.get(signature).copied())
+    }
+
+    /// Returns the class itself, all its superclasses and then all
+    /// interfaces it implements. Types outside of the file end the search
+    /// in their direction.
+    fn supertypes(&self, class: &str) -> Vec<String> {
+        let mut supertypes = vec![class.to_string()];
+        let mut class = class;
+        while let Some(superclass) = self.classes.get(class).and_then(|x| x.superclass.as_ref()) {
+            if supertypes.contains(superclass) {
+                break;
+            }
+            supertypes.push(superclass.clone());
+            class = superclass;
+        }
+        let mut i = 0;
+        while i < supertypes.len() {
+            if let Some(node) = self.classes.get(&supertypes[i]) {
+                for interface in &node.interfaces {
+                    if !supertypes.contains(interface) {
+                        supertypes.push(interface.clone());
+                    }
+                }
+            }
+            i += 1;
+        }
+        supertypes
+    }
+}
+
+/// Returns all strings that are used as values, i.e. loaded by an
+/// instruction or stored in a static field, as opposed to the names and
+/// descriptors of items.
+fn strings(dex: IDexRef<'_>) -> Result<HashSet<String>> {
+    let mut strings = HashSet::new();
+    for (index, count) in count_references(dex)?.iter().enumerate() {
+        if count.instructions != 0 || count.static_values != 0 {
+            strings.insert(dex.get_string(index as u32)?.to_string());
+        }
+    }
+    Ok(strings)
+}
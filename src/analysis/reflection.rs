@@ -0,0 +1,263 @@
+//! Resolution of reflective lookups
+//!
+//! Code that hides what it calls looks classes and members up by name, e.g.
+//! `Class.forName("com.example.Payload").getMethod("run")`. This pass finds
+//! the calls to `Class.forName`, `ClassLoader.loadClass` and the member
+//! lookups of `java.lang.Class`, and recovers their arguments with
+//! [constant propagation](super::constants):
+//!
+//! ```ignore
+//! for call in reflection::scan(&mut dex)? {
+//!     println!("{:?} {:?} {:?} ({:?})", call.kind, call.class, call.member, call.confidence);
+//! }
+//! ```
+//!
+//! Constant propagation doesn't know the results of calls, so the class a
+//! member is looked up on is only known if it was loaded with
+//! `const-class`, or if the `Class` object was returned by a resolved
+//! lookup. The latter is tracked along the instruction list, ignoring
+//! branches, and therefore only has [Confidence::Medium].
+
+use std::collections::HashMap;
+
+use crate::dalvik::{
+    dex::CodeItem,
+    error::Result,
+    file::IDexRef,
+    insns::{Index, Insn, InsnFormat},
+};
+
+use super::{
+    constants::{Constant, Constants},
+    for_each_method,
+};
+
+/// What is looked up through reflection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReflectionKind {
+    /// `Class.forName` or `ClassLoader.loadClass`
+    Class,
+
+    /// `Class.getMethod` or `Class.getDeclaredMethod`
+    Method,
+
+    /// `Class.getField` or `Class.getDeclaredField`
+    Field,
+
+    /// `Class.getConstructor` or `Class.getDeclaredConstructor`
+    Constructor,
+}
+
+/// How reliable a resolved target is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    /// nothing could be resolved
+    Unresolved,
+
+    /// either the class or the member is unknown
+    Low,
+
+    /// the class was returned by an earlier lookup
+    Medium,
+
+    /// all arguments are constants
+    High,
+}
+
+/// A reflective lookup and its resolved target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReflectiveCall {
+    /// Index into the `method_ids` list of the method making the call.
+    pub caller: u32,
+
+    /// byte offset of the invoke instruction within the code
+    pub offset: usize,
+
+    pub kind: ReflectionKind,
+
+    /// descriptor of the class that is looked up or whose member is
+    pub class: Option<String>,
+
+    /// name of the method or field
+    pub member: Option<String>,
+
+    pub confidence: Confidence,
+}
+
+/// Finds the reflective lookups in all methods of the given DEX file.
+pub fn scan(dex: IDexRef<'_>) -> Result<Vec<ReflectiveCall>> {
+    let mut calls = Vec::new();
+    for_each_method(dex, |_, method, insns, dex| {
+        if let Some(code) = &method.code {
+            calls.extend(find(code, insns, method.identity, dex)?);
+        }
+        Ok(())
+    })?;
+    Ok(calls)
+}
+
+/// Finds the reflective lookups in a single code item, whose disassembled
+/// instructions are passed in `insns`. `caller` is stored in the results.
+pub fn find(
+    code: &CodeItem,
+    insns: &[Insn],
+    caller: u32,
+    dex: IDexRef<'_>,
+) -> Result<Vec<ReflectiveCall>> {
+    let mut calls = Vec::new();
+    let mut constants = None;
+    // registers holding the result of a resolved class lookup
+    let mut classes: HashMap<u16, String> = HashMap::new();
+    let mut result: Option<String> = None;
+
+    for (index, insn) in insns.iter().enumerate() {
+        let pending = result.take();
+        let (item, registers): (_, Vec<u16>) = match &insn.format {
+            InsnFormat::Format35c {
+                a,
+                b: Index::Method(_, item),
+                c,
+                d,
+                e,
+                f,
+                g,
+            } => {
+                let registers = [*c, *d, *e, *f, *g];
+                let count = (*a as usize).min(registers.len());
+                (item, registers[..count].iter().map(|x| *x as u16).collect())
+            }
+            InsnFormat::Format3rc {
+                b: Index::Method(_, item),
+                regs,
+                ..
+            } => (item, regs.clone().map(|x| x as u16).collect()),
+            // move-result-object
+            InsnFormat::Format11x { a } if insn.opcode.opcode == 0x0C => {
+                classes.remove(&(*a as u16));
+                classes.extend(pending.map(|x| (*a as u16, x)));
+                continue;
+            }
+            _ => {
+                for register in written(insn) {
+                    classes.remove(&register);
+                }
+                continue;
+            }
+        };
+
+        let class = dex.get_type(item.class_idx as u32)?;
+        let name = dex.get_string(item.name_idx)?;
+        let (kind, name_arg) = match (class.to_string().as_str(), name.as_str()) {
+            ("Ljava/lang/Class;", "forName") => (ReflectionKind::Class, Some(0)),
+            (class, "loadClass") if class.ends_with("ClassLoader;") => {
+                (ReflectionKind::Class, Some(1))
+            }
+            ("Ljava/lang/Class;", "getMethod" | "getDeclaredMethod") => {
+                (ReflectionKind::Method, Some(1))
+            }
+            ("Ljava/lang/Class;", "getField" | "getDeclaredField") => {
+                (ReflectionKind::Field, Some(1))
+            }
+            ("Ljava/lang/Class;", "getConstructor" | "getDeclaredConstructor") => {
+                (ReflectionKind::Constructor, None)
+            }
+            _ => continue,
+        };
+
+        let constants = match &mut constants {
+            Some(constants) => constants,
+            None => constants.insert(Constants::compute(code, insns)?),
+        };
+        let string = |arg: usize| match constants.value_at(index, *registers.get(arg)?) {
+            Some(Constant::String(x)) => Some(x.to_string()),
+            _ => None,
+        };
+        let name = name_arg.and_then(string);
+
+        let call = if kind == ReflectionKind::Class {
+            let class = name.map(|x| descriptor_of(&x));
+            result = class.clone();
+            ReflectiveCall {
+                caller,
+                offset: insn.range.start,
+                kind,
+                confidence: match class {
+                    Some(_) => Confidence::High,
+                    None => Confidence::Unresolved,
+                },
+                class,
+                member: None,
+            }
+        } else {
+            let receiver = registers.first().copied();
+            let (class, confidence) = match receiver.and_then(|x| constants.value_at(index, x)) {
+                Some(Constant::Type(x)) => (Some(x.to_string()), Confidence::High),
+                _ => match receiver.and_then(|x| classes.get(&x)) {
+                    Some(class) => (Some(class.clone()), Confidence::Medium),
+                    None => (None, Confidence::Unresolved),
+                },
+            };
+            let confidence = match (&class, &name) {
+                (Some(_), Some(_)) => confidence,
+                (Some(_), None) if kind == ReflectionKind::Constructor => confidence,
+                (None, None) => Confidence::Unresolved,
+                _ => Confidence::Low,
+            };
+            ReflectiveCall {
+                caller,
+                offset: insn.range.start,
+                kind,
+                class,
+                member: name,
+                confidence,
+            }
+        };
+        calls.push(call);
+    }
+    Ok(calls)
+}
+
+/// Converts the binary name of a class as passed to `Class.forName`, e.g.
+/// `com.example.Foo` or `[Ljava.lang.String;`, to a descriptor.
+fn descriptor_of(name: &str) -> String {
+    if name.starts_with('[') {
+        name.replace('.', "/")
+    } else {
+        format!("L{};", name.replace('.', "/"))
+    }
+}
+
+/// Returns the registers written by an instruction that is not an invoke
+/// or `move-result-object`.
+fn written(insn: &Insn) -> Vec<u16> {
+    let op = insn.opcode.opcode;
+    let a = match &insn.format {
+        InsnFormat::Format11n { a, .. }
+        | InsnFormat::Format12x { a, .. }
+        | InsnFormat::Format11x { a }
+        | InsnFormat::Format21s { a, .. }
+        | InsnFormat::Format21h { a, .. }
+        | InsnFormat::Format21c { a, .. }
+        | InsnFormat::Format22b { a, .. }
+        | InsnFormat::Format22s { a, .. }
+        | InsnFormat::Format22c { a, .. }
+        | InsnFormat::Format22x { a, .. }
+        | InsnFormat::Format23x { a, .. }
+        | InsnFormat::Format31i { a, .. }
+        | InsnFormat::Format31c { a, .. }
+        | InsnFormat::Format51l { a, .. } => *a as u16,
+        InsnFormat::Format32x { a, .. } => *a,
+        _ => return Vec::new(),
+    };
+    // stores, returns, throws, monitors, switches and branches only read
+    // their registers
+    let reads_only = matches!(
+        op,
+        0x0E..=0x11 | 0x1D | 0x1E | 0x26..=0x2C | 0x32..=0x3D | 0x4B..=0x51 | 0x59..=0x5F | 0x67..=0x6D
+    );
+    match reads_only {
+        true => Vec::new(),
+        // wide results occupy two registers
+        false => vec![a, a + 1],
+    }
+}
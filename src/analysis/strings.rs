@@ -0,0 +1,345 @@
+//! String search over the string pool of a DEX file
+//!
+//! Matching strings are reported together with all instructions that load
+//! them (`const-string` and `const-string/jumbo`), which saves users from
+//! combining string iteration with manual instruction walking.
+//!
+//! In addition, [count_references] reports how often each string is
+//! referenced throughout the file, which helps with size analysis and
+//! with deciding whether a string can be patched safely.
+
+use std::{collections::HashMap, rc::Rc};
+
+use regex::Regex;
+
+use crate::dalvik::{
+    dex::DexType,
+    error::{Error, Result},
+    file::{annotation::DexAnnotation, DexValue, IDexRef},
+    insns::{Index, InsnFormat},
+};
+
+use super::for_each_method;
+
+/// A single instruction loading a string constant.
+#[derive(Debug)]
+pub struct StringUsage {
+    /// The class declaring the method.
+    pub class: Rc<DexType>,
+
+    /// Index into the `method_ids` list for the method containing the
+    /// instruction.
+    pub method_idx: u32,
+
+    /// Byte offset of the instruction within the method's bytecode (same
+    /// as `Insn::range.start`).
+    pub offset: usize,
+
+    /// The register the string is loaded into.
+    pub register: u8,
+}
+
+/// A string matching the search pattern.
+#[derive(Debug)]
+pub struct StringMatch {
+    /// Index into the `string_ids` list.
+    pub index: u32,
+
+    /// The decoded string value.
+    pub value: Rc<String>,
+
+    /// All instructions that load this string.
+    pub usages: Vec<StringUsage>,
+}
+
+/// Searches the string pool for strings matching the given regular
+/// expression.
+///
+/// The returned matches are sorted by string index and store all
+/// `const-string` instructions referencing them.
+pub fn find(dex: IDexRef<'_>, pattern: &str) -> Result<Vec<StringMatch>> {
+    let regex = Regex::new(pattern)
+        .map_err(|e| Error::InvalidData(format!("invalid pattern: {}", e)))?;
+    find_regex(dex, &regex)
+}
+
+/// Same as [find], but takes an already compiled regular expression.
+pub fn find_regex(dex: IDexRef<'_>, regex: &Regex) -> Result<Vec<StringMatch>> {
+    let mut matches = Vec::new();
+    for index in 0..dex.get_header().string_ids_size {
+        let value = dex.get_string(index)?;
+        if regex.is_match(&value) {
+            matches.push(StringMatch {
+                index,
+                value,
+                usages: Vec::new(),
+            });
+        }
+    }
+    if matches.is_empty() {
+        return Ok(matches);
+    }
+
+    // Strings in a DEX file are unique, so the decoded value identifies the
+    // string item as well.
+    let positions: HashMap<Rc<String>, usize> = matches
+        .iter()
+        .enumerate()
+        .map(|(i, m)| (m.value.clone(), i))
+        .collect();
+
+    for_each_method(dex, |class, method, insns, _| {
+        for insn in insns {
+            let (register, value) = match &insn.format {
+                InsnFormat::Format21c {
+                    a,
+                    b: Index::String(value),
+                }
+                | InsnFormat::Format31c {
+                    a,
+                    b: Index::String(value),
+                } => (*a, value),
+                _ => continue,
+            };
+            if let Some(position) = positions.get(value) {
+                matches[*position].usages.push(StringUsage {
+                    class: class.type_.clone(),
+                    method_idx: method.identity,
+                    offset: insn.range.start,
+                    register,
+                });
+            }
+        }
+        Ok(())
+    })?;
+    Ok(matches)
+}
+
+/// Number of references to a single string, grouped by the kind of item
+/// referencing it.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StringRefCount {
+    /// Type ids using the string as their descriptor.
+    pub type_ids: usize,
+
+    /// Prototype ids using the string as their shorty descriptor.
+    pub proto_ids: usize,
+
+    /// Field ids using the string as their name.
+    pub field_ids: usize,
+
+    /// Method ids using the string as their name.
+    pub method_ids: usize,
+
+    /// Class definitions using the string as their source file.
+    pub class_defs: usize,
+
+    /// Element names and string values of annotations.
+    pub annotations: usize,
+
+    /// String values in the static initializers of classes.
+    pub static_values: usize,
+
+    /// `const-string` and `const-string/jumbo` instructions.
+    pub instructions: usize,
+
+    /// Parameter names, local variables and source files in debug info.
+    pub debug_info: usize,
+}
+
+impl StringRefCount {
+    /// Returns the number of references from all sources.
+    pub fn total(&self) -> usize {
+        self.type_ids
+            + self.proto_ids
+            + self.field_ids
+            + self.method_ids
+            + self.class_defs
+            + self.annotations
+            + self.static_values
+            + self.instructions
+            + self.debug_info
+    }
+}
+
+/// Counts the references to every string of the string pool. The returned
+/// list is indexed by string index.
+///
+/// Id items and instructions are counted by their string index. Annotations
+/// and debug info are only available in their parsed form, which is why
+/// they are attributed to the first string with the same value; this only
+/// makes a difference for files with duplicate strings (see [duplicates]).
+/// Strings referenced from call sites are not counted.
+pub fn count_references(dex: IDexRef<'_>) -> Result<Vec<StringRefCount>> {
+    let header = dex.get_header();
+    let (strings, types, protos, fields, methods, classes) = (
+        header.string_ids_size,
+        header.type_ids_size,
+        header.proto_ids_size,
+        header.field_ids_size,
+        header.method_ids_size,
+        header.class_defs_size,
+    );
+
+    let mut counts = vec![StringRefCount::default(); strings as usize];
+    let mut positions: HashMap<Rc<String>, u32> = HashMap::with_capacity(strings as usize);
+    for index in 0..strings {
+        positions.entry(dex.get_string(index)?).or_insert(index);
+    }
+
+    // Counts a reference to a string by its value, an unknown value
+    // means that the file is corrupted.
+    macro_rules! count_value {
+        ($value:expr, $kind:ident) => {
+            match positions.get($value) {
+                Some(index) => counts[*index as usize].$kind += 1,
+                None => {
+                    return Err(Error::InvalidData(format!(
+                        "string {:?} is not part of the string pool",
+                        $value
+                    )))
+                }
+            }
+        };
+    }
+
+    // Counts a reference to a string by its index.
+    macro_rules! count_index {
+        ($index:expr, $kind:ident) => {
+            match counts.get_mut($index as usize) {
+                Some(count) => count.$kind += 1,
+                None => return Err(Error::InvalidIndex($index as usize)),
+            }
+        };
+    }
+
+    for index in 0..types {
+        let type_ = dex.get_type(index)?;
+        let descriptor = format!("{}{}", "[".repeat(type_.dim), type_.descriptor);
+        count_value!(&descriptor, type_ids);
+    }
+    for index in 0..protos {
+        count_value!(&dex.get_proto(index)?.shorty, proto_ids);
+    }
+    for index in 0..fields {
+        count_index!(dex.get_field(index)?.name_idx, field_ids);
+    }
+    for index in 0..methods {
+        count_index!(dex.get_method(index)?.name_idx, method_ids);
+    }
+
+    for index in 0..classes {
+        let class = dex.get_class_def(index)?;
+        if let Some(source_file) = &class.source_file {
+            count_value!(source_file, class_defs);
+        }
+
+        let mut annotations: Vec<&DexAnnotation> = class.annotations.iter().collect();
+        for (_, field) in class.get_fields() {
+            annotations.extend(&field.annotations);
+            if let Some(value) = &field.init_value {
+                for value in string_values(value, &mut annotations) {
+                    count_value!(value, static_values);
+                }
+            }
+        }
+
+        for (_, method) in class.get_methods() {
+            annotations.extend(&method.annotations);
+            for parameter in &method.parameters {
+                annotations.extend(&parameter.annotations);
+                if let Some(name) = &parameter.name {
+                    count_value!(name, debug_info);
+                }
+            }
+
+            if let Some(debug_info) = &method.debug_info {
+                if let Some(source_file) = &debug_info.source_file {
+                    count_value!(source_file, debug_info);
+                }
+                // parameters have already been counted above
+                for var in debug_info.local_variables.iter().filter(|x| !x.parameter) {
+                    for value in var.name.iter().chain(&var.signature) {
+                        count_value!(value, debug_info);
+                    }
+                }
+            }
+
+            let Some(code) = &method.code else {
+                continue;
+            };
+            for insn in method.disasm(dex)? {
+                let bytes = &code.insns[insn.range.clone()];
+                match insn.opcode.opcode {
+                    // const-string vAA, string@BBBB
+                    0x1A => count_index!(u16::from_le_bytes([bytes[2], bytes[3]]), instructions),
+                    // const-string/jumbo vAA, string@BBBBBBBB
+                    0x1B => count_index!(
+                        u32::from_le_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]),
+                        instructions
+                    ),
+                    _ => {}
+                }
+            }
+        }
+
+        while let Some(annotation) = annotations.pop() {
+            for (name, value) in &annotation.values {
+                count_value!(name, annotations);
+                let mut nested = Vec::new();
+                for value in string_values(value, &mut nested) {
+                    count_value!(value, annotations);
+                }
+                annotations.extend(nested);
+            }
+        }
+    }
+    Ok(counts)
+}
+
+/// Returns the indices of all strings that are not referenced from any of
+/// the sources described in [count_references].
+///
+/// Note that strings only used by call sites are reported as well.
+pub fn unreferenced(dex: IDexRef<'_>) -> Result<Vec<u32>> {
+    Ok(count_references(dex)?
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| count.total() == 0)
+        .map(|(index, _)| index as u32)
+        .collect())
+}
+
+/// Returns groups of string indices that share the same value. A valid DEX
+/// file stores every string only once, so this list is usually empty.
+pub fn duplicates(dex: IDexRef<'_>) -> Result<Vec<Vec<u32>>> {
+    let mut groups: HashMap<Rc<String>, Vec<u32>> = HashMap::new();
+    for index in 0..dex.get_header().string_ids_size {
+        groups
+            .entry(dex.get_string(index)?)
+            .or_default()
+            .push(index);
+    }
+    let mut duplicates: Vec<Vec<u32>> = groups.into_values().filter(|x| x.len() > 1).collect();
+    duplicates.sort();
+    Ok(duplicates)
+}
+
+/// Collects all string values stored in the given value, descending into
+/// arrays. Nested annotations are moved to `nested` instead.
+fn string_values<'a>(
+    value: &'a DexValue,
+    nested: &mut Vec<&'a DexAnnotation>,
+) -> Vec<&'a Rc<String>> {
+    let mut strings = Vec::new();
+    let mut pending = vec![value];
+    while let Some(value) = pending.pop() {
+        match value {
+            DexValue::String(x) => strings.push(x),
+            DexValue::Array(values) => pending.extend(values),
+            DexValue::Annotation(annotation) => nested.push(annotation),
+            _ => {}
+        }
+    }
+    strings
+}
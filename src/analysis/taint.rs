@@ -0,0 +1,497 @@
+//! Intra-procedural taint tracking
+//!
+//! Follows the values returned by *source* methods, e.g. the device id,
+//! through the registers of a method and reports every call of a *sink*
+//! method, e.g. a log or network write, that receives such a value. Both
+//! are configured as smali references in a [TaintConfig]:
+//!
+//! ```ignore
+//! let config = TaintConfig {
+//!     sources: vec!["Landroid/telephony/TelephonyManager;->getDeviceId()Ljava/lang/String;".into()],
+//!     sinks: vec!["Landroid/util/Log;->*".into()],
+//! };
+//! for flow in taint::scan(&mut dex, &config)? {
+//!     println!("{} -> {} at {:#x}", flow.source_ref, flow.sink_ref, flow.sink);
+//! }
+//! ```
+//!
+//! This is a conservative first version. Taint is propagated along the
+//! [control flow graph](super::cfg) by moves, array and field accesses of
+//! tainted objects, arithmetic and calls: the result of a call with a
+//! tainted argument is tainted, and so is the receiver of an instance
+//! method, which covers builders like `StringBuilder.append`. Static fields,
+//! callees and the class hierarchy are not followed, so a sink is only
+//! matched by the class of the invoked reference.
+
+use std::{collections::HashSet, rc::Rc};
+
+use crate::{
+    dalvik::{
+        dex::CodeItem,
+        error::Result,
+        file::IDexRef,
+        insns::{insn_at, Index, Insn, InsnFormat},
+    },
+    smali::smali_method_ref,
+};
+
+use super::{cfg::ControlFlowGraph, for_each_method};
+
+/// Source and sink methods of a taint analysis.
+///
+/// Methods are given as smali references, e.g.
+/// `Landroid/util/Log;->d(Ljava/lang/String;Ljava/lang/String;)I`. A
+/// reference ending with `*` matches all references starting with the part
+/// before it, e.g. `Landroid/util/Log;->*` matches all methods of `Log`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaintConfig {
+    pub sources: Vec<String>,
+    pub sinks: Vec<String>,
+}
+
+impl TaintConfig {
+    /// Device identifiers, locations, accounts and the clipboard as sources,
+    /// logging, SMS, files, URLs and broadcasts as sinks.
+    pub fn android() -> Self {
+        let sources = [
+            "Landroid/telephony/TelephonyManager;->getDeviceId()Ljava/lang/String;",
+            "Landroid/telephony/TelephonyManager;->getImei()Ljava/lang/String;",
+            "Landroid/telephony/TelephonyManager;->getSubscriberId()Ljava/lang/String;",
+            "Landroid/telephony/TelephonyManager;->getLine1Number()Ljava/lang/String;",
+            "Landroid/telephony/TelephonyManager;->getSimSerialNumber()Ljava/lang/String;",
+            "Landroid/location/Location;->getLatitude()D",
+            "Landroid/location/Location;->getLongitude()D",
+            "Landroid/location/LocationManager;->getLastKnownLocation(Ljava/lang/String;)Landroid/location/Location;",
+            "Landroid/accounts/AccountManager;->getAccounts()[Landroid/accounts/Account;",
+            "Landroid/content/ClipboardManager;->getPrimaryClip()Landroid/content/ClipData;",
+            "Landroid/provider/Settings$Secure;->getString(Landroid/content/ContentResolver;Ljava/lang/String;)Ljava/lang/String;",
+        ];
+        let sinks = [
+            "Landroid/util/Log;->*",
+            "Landroid/telephony/SmsManager;->sendTextMessage(Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;Landroid/app/PendingIntent;Landroid/app/PendingIntent;)V",
+            "Ljava/io/OutputStream;->write([B)V",
+            "Ljava/io/FileOutputStream;->write([B)V",
+            "Ljava/io/Writer;->write(Ljava/lang/String;)V",
+            "Ljava/net/URL;-><init>(Ljava/lang/String;)V",
+            "Landroid/content/Context;->sendBroadcast(Landroid/content/Intent;)V",
+        ];
+        TaintConfig {
+            sources: sources.iter().map(|x| x.to_string()).collect(),
+            sinks: sinks.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    /// Whether the given method reference is a source.
+    pub fn is_source(&self, reference: &str) -> bool {
+        self.sources.iter().any(|x| matches(x, reference))
+    }
+
+    /// Whether the given method reference is a sink.
+    pub fn is_sink(&self, reference: &str) -> bool {
+        self.sinks.iter().any(|x| matches(x, reference))
+    }
+}
+
+fn matches(pattern: &str, reference: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => reference.starts_with(prefix),
+        None => pattern == reference,
+    }
+}
+
+/// A tainted value reaching a sink.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaintFlow {
+    /// Index into the `method_ids` list of the method containing the flow.
+    pub method: u32,
+
+    /// byte offset of the source invocation within the code
+    pub source: usize,
+
+    pub source_ref: String,
+
+    /// byte offset of the sink invocation within the code
+    pub sink: usize,
+
+    pub sink_ref: String,
+
+    /// the tainted argument register of the sink
+    pub register: u16,
+
+    /// Byte offsets of the instructions that passed the value on, from the
+    /// source to the sink.
+    pub trace: Vec<usize>,
+}
+
+/// Finds the flows from sources to sinks in all methods of the given DEX
+/// file.
+pub fn scan(dex: IDexRef<'_>, config: &TaintConfig) -> Result<Vec<TaintFlow>> {
+    let mut flows = Vec::new();
+    for_each_method(dex, |_, method, insns, dex| {
+        if let Some(code) = &method.code {
+            flows.extend(find(code, insns, method.identity, dex, config)?);
+        }
+        Ok(())
+    })?;
+    Ok(flows)
+}
+
+/// Offsets of the instructions a tainted value passed through.
+type Trace = Rc<Vec<usize>>;
+
+/// Taint of every register, followed by the taint of the result of the
+/// previous invocation.
+type Registers = Vec<Option<Trace>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CallKind {
+    Source,
+    Sink,
+    Other,
+}
+
+/// An invocation and its argument registers.
+#[derive(Debug)]
+struct Call {
+    reference: String,
+    kind: CallKind,
+    args: Vec<u16>,
+
+    /// whether the first argument is the receiver
+    instance: bool,
+}
+
+/// Finds the flows from sources to sinks in a single code item, whose
+/// disassembled instructions are passed in `insns`. `method` is stored in
+/// the results, which are sorted by the offset of the sink.
+pub fn find(
+    code: &CodeItem,
+    insns: &[Insn],
+    method: u32,
+    dex: IDexRef<'_>,
+    config: &TaintConfig,
+) -> Result<Vec<TaintFlow>> {
+    let mut calls = Vec::with_capacity(insns.len());
+    for insn in insns {
+        calls.push(call_of(insn, dex, config)?);
+    }
+    if !calls.iter().flatten().any(|x| x.kind == CallKind::Source) {
+        return Ok(Vec::new());
+    }
+
+    let cfg = ControlFlowGraph::build(code, insns)?;
+    let mut entries: Vec<Option<Registers>> = vec![None; cfg.blocks.len()];
+    let mut pending = Vec::new();
+    if !cfg.blocks.is_empty() {
+        entries[0] = Some(vec![None; code.registers_size as usize + 1]);
+        pending.push(0);
+    }
+
+    while let Some(block) = pending.pop() {
+        let item = &cfg.blocks[block];
+        let mut registers = entries[block].clone().unwrap_or_default();
+        // an exception may be thrown before any instruction of the block
+        let mut thrown = registers.clone();
+        for index in item.insns.clone() {
+            meet(&mut thrown, &registers);
+            step(&insns[index], calls[index].as_ref(), &mut registers);
+        }
+        meet(&mut thrown, &registers);
+
+        for successor in &item.successors {
+            let incoming = if item.handlers.contains(successor) {
+                &thrown
+            } else {
+                &registers
+            };
+            let changed = match &mut entries[*successor] {
+                Some(entry) => meet(entry, incoming),
+                entry @ None => {
+                    *entry = Some(incoming.clone());
+                    true
+                }
+            };
+            if changed && !pending.contains(successor) {
+                pending.push(*successor);
+            }
+        }
+    }
+
+    // the sinks are only checked once the taint is stable
+    let mut flows = Vec::new();
+    let mut seen = HashSet::new();
+    for (item, entry) in cfg.blocks.iter().zip(entries) {
+        let Some(mut registers) = entry else {
+            continue;
+        };
+        for index in item.insns.clone() {
+            let insn = &insns[index];
+            for (register, trace) in step(insn, calls[index].as_ref(), &mut registers) {
+                if !seen.insert((insn.range.start, register)) {
+                    continue;
+                }
+                let source = trace[0];
+                let source_ref = insn_at(insns, source)
+                    .and_then(|x| calls[x].as_ref())
+                    .map(|x| x.reference.clone())
+                    .unwrap_or_default();
+                let mut trace = trace.to_vec();
+                trace.push(insn.range.start);
+                flows.push(TaintFlow {
+                    method,
+                    source,
+                    source_ref,
+                    sink: insn.range.start,
+                    sink_ref: calls[index]
+                        .as_ref()
+                        .map(|x| x.reference.clone())
+                        .unwrap_or_default(),
+                    register,
+                    trace,
+                });
+            }
+        }
+    }
+    flows.sort_by_key(|x| (x.sink, x.register));
+    Ok(flows)
+}
+
+/// Resolves the method invoked by an instruction, if any.
+fn call_of(insn: &Insn, dex: IDexRef<'_>, config: &TaintConfig) -> Result<Option<Call>> {
+    let (method_idx, args): (u32, Vec<u16>) = match &insn.format {
+        InsnFormat::Format35c {
+            a,
+            b: Index::Method(index, _),
+            c,
+            d,
+            e,
+            f,
+            g,
+        } => {
+            let registers = [*c, *d, *e, *f, *g];
+            let count = (*a as usize).min(registers.len());
+            (
+                *index,
+                registers[..count].iter().map(|x| *x as u16).collect(),
+            )
+        }
+        InsnFormat::Format45cc {
+            a,
+            b: Index::Method(index, _),
+            c,
+            d,
+            e,
+            f,
+            g,
+            ..
+        } => {
+            let registers = [*c, *d, *e, *f, *g];
+            let count = (*a as usize).min(registers.len());
+            (
+                *index,
+                registers[..count].iter().map(|x| *x as u16).collect(),
+            )
+        }
+        InsnFormat::Format3rc {
+            b: Index::Method(index, _),
+            regs,
+            ..
+        }
+        | InsnFormat::Format4rcc {
+            b: Index::Method(index, _),
+            regs,
+            ..
+        } => (*index, regs.clone().map(|x| x as u16).collect()),
+        _ => return Ok(None),
+    };
+
+    let reference = smali_method_ref(dex, method_idx)?;
+    let kind = if config.is_source(&reference) {
+        CallKind::Source
+    } else if config.is_sink(&reference) {
+        CallKind::Sink
+    } else {
+        CallKind::Other
+    };
+    Ok(Some(Call {
+        reference,
+        kind,
+        args,
+        // invoke-static and invoke-static/range
+        instance: !matches!(insn.opcode.opcode, 0x71 | 0x77),
+    }))
+}
+
+/// Merges `other` into `registers` and returns whether anything changed.
+/// A register is tainted if it is tainted on any path.
+fn meet(registers: &mut Registers, other: &Registers) -> bool {
+    let mut changed = false;
+    for (value, other) in registers.iter_mut().zip(other) {
+        if value.is_none() && other.is_some() {
+            *value = other.clone();
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Returns the taint of a register.
+fn get(registers: &Registers, register: impl Into<usize>) -> Option<Trace> {
+    let register: usize = register.into();
+    let end = registers.len().saturating_sub(1);
+    registers[..end].get(register).cloned().flatten()
+}
+
+/// Sets the taint of a register, or of a register pair for wide values.
+fn set(registers: &mut Registers, register: impl Into<usize>, wide: bool, taint: Option<Trace>) {
+    let register: usize = register.into();
+    let end = registers.len().saturating_sub(1);
+    for slot in registers[..end]
+        .iter_mut()
+        .skip(register)
+        .take(1 + wide as usize)
+    {
+        *slot = taint.clone();
+    }
+}
+
+/// Appends an instruction to a trace.
+fn extend(trace: Trace, offset: usize) -> Trace {
+    let mut trace = trace.to_vec();
+    trace.push(offset);
+    Rc::new(trace)
+}
+
+/// Whether an instruction writes a wide value.
+fn is_wide(op: u8) -> bool {
+    matches!(
+        op,
+        0x04..=0x06
+            | 0x0B
+            | 0x16..=0x19
+            | 0x45
+            | 0x53
+            | 0x61
+            | 0x7D
+            | 0x7E
+            | 0x80
+            | 0x81
+            | 0x83
+            | 0x86
+            | 0x88
+            | 0x89
+            | 0x8B
+            | 0x9B..=0xA5
+            | 0xAB..=0xAF
+            | 0xBB..=0xC5
+            | 0xCB..=0xCF
+    )
+}
+
+/// Applies a single instruction to the taint of the registers and returns
+/// the tainted arguments if it invokes a sink.
+fn step(insn: &Insn, call: Option<&Call>, registers: &mut Registers) -> Vec<(u16, Trace)> {
+    let offset = insn.range.start;
+    let op = insn.opcode.opcode;
+    // the result of an invocation is only available to the next
+    // instruction
+    let result = registers.len() - 1;
+    let pending = registers[result].take();
+
+    if let Some(call) = call {
+        let tainted: Vec<(u16, Trace)> = call
+            .args
+            .iter()
+            .filter_map(|x| Some((*x, get(registers, *x)?)))
+            .collect();
+        registers[result] = match call.kind {
+            CallKind::Source => Some(Rc::new(vec![offset])),
+            _ => tainted.first().map(|(_, x)| extend(x.clone(), offset)),
+        };
+        if call.kind == CallKind::Sink {
+            return tainted;
+        }
+        // the receiver may keep its arguments, e.g. StringBuilder.append
+        if call.instance
+            && let Some(receiver) = call.args.first()
+            && let Some((_, trace)) = tainted.first()
+            && get(registers, *receiver).is_none()
+        {
+            set(
+                registers,
+                *receiver,
+                false,
+                Some(extend(trace.clone(), offset)),
+            );
+        }
+        return Vec::new();
+    }
+
+    let derive = |taint: Option<Trace>| taint.map(|x| extend(x, offset));
+    match &insn.format {
+        // move-result-kind
+        InsnFormat::Format11x { a } if (0x0A..=0x0C).contains(&op) => {
+            set(registers, *a, op == 0x0B, derive(pending));
+        }
+        // move-exception
+        InsnFormat::Format11x { a } if op == 0x0D => set(registers, *a, false, None),
+        InsnFormat::Format12x { a, b } => {
+            let mut taint = get(registers, *b);
+            // two-address operations read their destination as well
+            if (0xB0..=0xCF).contains(&op) {
+                taint = taint.or_else(|| get(registers, *a));
+            }
+            set(registers, *a, is_wide(op), derive(taint));
+        }
+        InsnFormat::Format22x { a, b } => {
+            let taint = get(registers, *b);
+            set(registers, *a, is_wide(op), derive(taint));
+        }
+        InsnFormat::Format32x { a, b } => {
+            let taint = get(registers, *b);
+            set(registers, *a, is_wide(op), derive(taint));
+        }
+        InsnFormat::Format23x { a, b, c } => match op {
+            // aput-kind taints the array
+            0x4B..=0x51 => {
+                if let (Some(taint), None) = (get(registers, *a), get(registers, *b)) {
+                    set(registers, *b, false, derive(Some(taint)));
+                }
+            }
+            _ => {
+                let taint = get(registers, *b).or_else(|| get(registers, *c));
+                set(registers, *a, is_wide(op), derive(taint));
+            }
+        },
+        InsnFormat::Format22b { a, b, .. } | InsnFormat::Format22s { a, b, .. } => {
+            let taint = get(registers, *b);
+            set(registers, *a, false, derive(taint));
+        }
+        InsnFormat::Format22c { a, b, .. } => match op {
+            // iput-kind taints the object
+            0x59..=0x5F => {
+                if let (Some(taint), None) = (get(registers, *a), get(registers, *b)) {
+                    set(registers, *b, false, derive(Some(taint)));
+                }
+            }
+            // instance-of, new-array, iget-kind
+            _ => {
+                let taint = get(registers, *b);
+                set(registers, *a, is_wide(op), derive(taint));
+            }
+        },
+        // constants, new-instance and sget-kind overwrite the register,
+        // check-cast and sput-kind only read it
+        InsnFormat::Format21c { a, .. } if !matches!(op, 0x1F | 0x67..=0x6D) => {
+            set(registers, *a, is_wide(op), None);
+        }
+        InsnFormat::Format11n { a, .. }
+        | InsnFormat::Format21s { a, .. }
+        | InsnFormat::Format21h { a, .. }
+        | InsnFormat::Format31i { a, .. }
+        | InsnFormat::Format31c { a, .. }
+        | InsnFormat::Format51l { a, .. } => set(registers, *a, is_wide(op), None),
+        _ => {}
+    }
+    Vec::new()
+}
@@ -0,0 +1,408 @@
+//! Canonical dumps for differential testing against `dexdump`
+//!
+//! [dump] walks a DEX file in file order and writes the file header, the
+//! raw class definitions and all classes with their members in the plain
+//! text layout of AOSP's `dexdump -f`, so both outputs can be compared
+//! line by line:
+//!
+//! ```ignore
+//! let mut output = Vec::new();
+//! conformance::dump(&mut dex, &mut output)?;
+//! // compare with `dexdump -f classes.dex | tail -n +3`
+//! ```
+//!
+//! The first two lines of `dexdump` (the file name and version) are not
+//! written. Local variables and static field values are not part of the
+//! dump either, since `dexdump` formats them differently depending on its
+//! version. Positions are listed once per address.
+//!
+//! Snapshots of the dumps of the test fixtures are stored next to them as
+//! `*.conformance.txt`. They were written by this module, and
+//! `tests/conformance.rs` compares the dump of every fixture with them.
+//! `tests/dexdump-baselines` replaces them by the output of `dexdump`,
+//! reduced to the parts listed above, to compare with `dexdump` instead.
+
+use std::io::{Read, Seek, Write};
+
+use crate::dalvik::{
+    dex::{AccessFlags, CodeItem, NO_INDEX},
+    error::Result,
+    file::{field::DexField, method::DexMethod, Dex, DexClassDef, IDex},
+};
+
+/// Where an access flag value is used; the same bits have different names
+/// for classes, fields and methods.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagKind {
+    Class,
+    Field,
+    Method,
+}
+
+const CLASS_FLAGS: [&str; 18] = [
+    "PUBLIC",
+    "PRIVATE",
+    "PROTECTED",
+    "STATIC",
+    "FINAL",
+    "?",
+    "?",
+    "?",
+    "?",
+    "INTERFACE",
+    "ABSTRACT",
+    "?",
+    "SYNTHETIC",
+    "ANNOTATION",
+    "ENUM",
+    "?",
+    "VERIFIED",
+    "OPTIMIZED",
+];
+
+const FIELD_FLAGS: [&str; 18] = [
+    "PUBLIC",
+    "PRIVATE",
+    "PROTECTED",
+    "STATIC",
+    "FINAL",
+    "?",
+    "VOLATILE",
+    "TRANSIENT",
+    "?",
+    "?",
+    "?",
+    "?",
+    "SYNTHETIC",
+    "?",
+    "ENUM",
+    "?",
+    "?",
+    "?",
+];
+
+const METHOD_FLAGS: [&str; 18] = [
+    "PUBLIC",
+    "PRIVATE",
+    "PROTECTED",
+    "STATIC",
+    "FINAL",
+    "SYNCHRONIZED",
+    "BRIDGE",
+    "VARARGS",
+    "NATIVE",
+    "?",
+    "ABSTRACT",
+    "STRICT",
+    "SYNTHETIC",
+    "?",
+    "?",
+    "?",
+    "CONSTRUCTOR",
+    "DECLARED_SYNCHRONIZED",
+];
+
+/// Formats access flags like `dexdump`, e.g. `PUBLIC STATIC`.
+pub fn access_flags_str(flags: u32, kind: FlagKind) -> String {
+    let names = match kind {
+        FlagKind::Class => &CLASS_FLAGS,
+        FlagKind::Field => &FIELD_FLAGS,
+        FlagKind::Method => &METHOD_FLAGS,
+    };
+    names
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| flags & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn bits(flags: &Option<AccessFlags>) -> u32 {
+    flags.as_ref().map_or(0, |x| x.bits())
+}
+
+/// Writes the canonical dump of the given DEX file.
+pub fn dump<R, W>(dex: &mut Dex<'_, R>, out: &mut W) -> Result<()>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    dump_header(dex, out)?;
+    for index in 0..dex.get_header().class_defs_size {
+        dump_class_def(dex, index, out)?;
+        let class = dex.get_class_def(index)?;
+        dump_class(dex, &class, out)?;
+    }
+    Ok(())
+}
+
+fn dump_header<R: Read + Seek, W: Write>(dex: &Dex<'_, R>, out: &mut W) -> Result<()> {
+    let header = dex.get_header();
+    let version = header.magic.version_num().unwrap_or_default();
+    let signature = &header.signature;
+
+    writeln!(out, "DEX file header:")?;
+    writeln!(out, "magic               : 'dex\\n{:03}\\0'", version)?;
+    writeln!(out, "checksum            : {:08x}", header.checksum)?;
+    writeln!(
+        out,
+        "signature           : {:02x}{:02x}...{:02x}{:02x}",
+        signature[0], signature[1], signature[18], signature[19]
+    )?;
+    writeln!(out, "file_size           : {}", header.file_size)?;
+    writeln!(out, "header_size         : {}", header.header_size)?;
+    writeln!(out, "link_size           : {}", header.link_size)?;
+    let sections = [
+        ("link_off", header.link_off, None),
+        (
+            "string_ids",
+            header.string_ids_off,
+            Some(header.string_ids_size),
+        ),
+        ("type_ids", header.type_ids_off, Some(header.type_ids_size)),
+        (
+            "proto_ids",
+            header.proto_ids_off,
+            Some(header.proto_ids_size),
+        ),
+        (
+            "field_ids",
+            header.field_ids_off,
+            Some(header.field_ids_size),
+        ),
+        (
+            "method_ids",
+            header.method_ids_off,
+            Some(header.method_ids_size),
+        ),
+        (
+            "class_defs",
+            header.class_defs_off,
+            Some(header.class_defs_size),
+        ),
+        ("data", header.data_off, Some(header.data_size)),
+    ];
+    for (name, offset, size) in sections {
+        match size {
+            Some(size) => {
+                writeln!(out, "{:<20}: {}", format!("{}_size", name), size)?;
+                writeln!(
+                    out,
+                    "{:<20}: {} (0x{:06x})",
+                    format!("{}_off", name),
+                    offset,
+                    offset
+                )?;
+            }
+            None => writeln!(out, "{:<20}: {} (0x{:06x})", name, offset, offset)?,
+        }
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+fn dump_class_def<R, W>(dex: &mut Dex<'_, R>, index: u32, out: &mut W) -> Result<()>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    let item = dex.get_class_def_item(index)?;
+    let class = dex.get_class_def(index)?;
+
+    writeln!(out, "Class #{} header:", index)?;
+    writeln!(out, "class_idx           : {}", item.class_idx)?;
+    writeln!(
+        out,
+        "access_flags        : {} (0x{:04x})",
+        item.access_flags, item.access_flags
+    )?;
+    writeln!(out, "superclass_idx      : {}", item.superclass_idx as i32)?;
+    writeln!(
+        out,
+        "interfaces_off      : {} (0x{:06x})",
+        item.interfaces_off, item.interfaces_off
+    )?;
+    writeln!(out, "source_file_idx     : {}", item.source_file_idx as i32)?;
+    writeln!(
+        out,
+        "annotations_off     : {} (0x{:06x})",
+        item.annotations_off, item.annotations_off
+    )?;
+    writeln!(
+        out,
+        "class_data_off      : {} (0x{:06x})",
+        item.class_data_off, item.class_data_off
+    )?;
+    writeln!(
+        out,
+        "static_fields_size  : {}",
+        class.get_static_fields().len()
+    )?;
+    writeln!(
+        out,
+        "instance_fields_size: {}",
+        class.get_instance_fields().len()
+    )?;
+    writeln!(
+        out,
+        "direct_methods_size : {}",
+        class.get_direct_methods().len()
+    )?;
+    writeln!(
+        out,
+        "virtual_methods_size: {}",
+        class.get_virtual_methods().len()
+    )?;
+    writeln!(out)?;
+    Ok(())
+}
+
+fn dump_class<R, W>(dex: &mut Dex<'_, R>, class: &DexClassDef, out: &mut W) -> Result<()>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    let flags = bits(&class.flags);
+    writeln!(out, "Class #{}            -", class.identity)?;
+    writeln!(out, "  Class descriptor  : '{}'", class.type_)?;
+    writeln!(
+        out,
+        "  Access flags      : 0x{:04x} ({})",
+        flags,
+        access_flags_str(flags, FlagKind::Class)
+    )?;
+    if let Some(super_class) = &class.super_class {
+        writeln!(out, "  Superclass        : '{}'", super_class)?;
+    }
+    writeln!(out, "  Interfaces        -")?;
+    for (i, interface) in class.interfaces.iter().enumerate() {
+        writeln!(out, "    #{}              : '{}'", i, interface)?;
+    }
+
+    writeln!(out, "  Static fields     -")?;
+    for (i, field) in class.get_static_fields().enumerate() {
+        dump_field(field, i, out)?;
+    }
+    writeln!(out, "  Instance fields   -")?;
+    for (i, field) in class.get_instance_fields().enumerate() {
+        dump_field(field, i, out)?;
+    }
+    writeln!(out, "  Direct methods    -")?;
+    for (i, method) in class.get_direct_methods().enumerate() {
+        dump_method(dex, method, i, out)?;
+    }
+    writeln!(out, "  Virtual methods   -")?;
+    for (i, method) in class.get_virtual_methods().enumerate() {
+        dump_method(dex, method, i, out)?;
+    }
+
+    let source_file_idx = dex.get_class_def_item(class.identity)?.source_file_idx;
+    match &class.source_file {
+        Some(name) if source_file_idx != NO_INDEX => {
+            writeln!(out, "  source_file_idx   : {} ({})", source_file_idx, name)?
+        }
+        _ => writeln!(
+            out,
+            "  source_file_idx   : {} (unknown)",
+            source_file_idx as i32
+        )?,
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+fn dump_field<W: Write>(field: &DexField, index: usize, out: &mut W) -> Result<()> {
+    let flags = bits(&field.access_flags);
+    writeln!(out, "    #{}              : (in {})", index, field.class)?;
+    writeln!(out, "      name          : '{}'", field.name)?;
+    writeln!(out, "      type          : '{}'", field.type_)?;
+    writeln!(
+        out,
+        "      access        : 0x{:04x} ({})",
+        flags,
+        access_flags_str(flags, FlagKind::Field)
+    )?;
+    Ok(())
+}
+
+fn dump_method<R, W>(
+    dex: &mut Dex<'_, R>,
+    method: &DexMethod,
+    index: usize,
+    out: &mut W,
+) -> Result<()>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    let flags = bits(&method.access_flags);
+    writeln!(out, "    #{}              : (in {})", index, method.class)?;
+    writeln!(out, "      name          : '{}'", method.name)?;
+    writeln!(out, "      type          : '{}'", method.proto)?;
+    writeln!(
+        out,
+        "      access        : 0x{:04x} ({})",
+        flags,
+        access_flags_str(flags, FlagKind::Method)
+    )?;
+    match &method.code {
+        Some(code) => {
+            writeln!(out, "      code          -")?;
+            dump_code(dex, method, code, out)?;
+        }
+        None => writeln!(out, "      code          : (none)")?,
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+fn dump_code<R, W>(
+    dex: &mut Dex<'_, R>,
+    method: &DexMethod,
+    code: &CodeItem,
+    out: &mut W,
+) -> Result<()>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    writeln!(out, "      registers     : {}", code.registers_size)?;
+    writeln!(out, "      ins           : {}", code.ins_size)?;
+    writeln!(out, "      outs          : {}", code.outs_size)?;
+    writeln!(
+        out,
+        "      insns size    : {} 16-bit code units",
+        code.insns_size
+    )?;
+
+    if code.tries.is_empty() {
+        writeln!(out, "      catches       : (none)")?;
+    } else {
+        writeln!(out, "      catches       : {}", code.tries.len())?;
+        for try_item in &code.tries {
+            let end = try_item.start_addr + try_item.insn_count as u32;
+            writeln!(out, "        0x{:04x} - 0x{:04x}", try_item.start_addr, end)?;
+            let Some(handler) = code.catch_handler(try_item) else {
+                continue;
+            };
+            for pair in &handler.handlers {
+                let type_ = dex.get_type(pair.type_idx.0)?;
+                writeln!(out, "          {} -> 0x{:04x}", type_, pair.addr.0)?;
+            }
+            if let Some(addr) = &handler.catch_all_addr {
+                writeln!(out, "          <any> -> 0x{:04x}", addr.0)?;
+            }
+        }
+    }
+
+    writeln!(out, "      positions     : ")?;
+    if let Some(debug_info) = &method.debug_info {
+        let mut lines = debug_info.lines.iter().collect::<Vec<_>>();
+        lines.sort();
+        for (pc, line) in lines {
+            writeln!(out, "        0x{:04x} line={}", pc, line)?;
+        }
+    }
+    Ok(())
+}
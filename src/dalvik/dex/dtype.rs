@@ -17,6 +17,33 @@ pub struct DexType {
 }
 
 impl DexType {
+    /// Returns the character representing this type in a shorty descriptor.
+    /// All reference types, including arrays, are shortened to `L`.
+    pub fn shorty(&self) -> char {
+        match self.descriptor.chars().next() {
+            Some(c) if self.primitive && self.dim == 0 => c,
+            _ => 'L',
+        }
+    }
+
+    /// Returns whether this type has the given descriptor, including the
+    /// `[` of array types, e.g. `[Ljava/lang/String;`.
+    pub fn is(&self, descriptor: &str) -> bool {
+        let element = descriptor.trim_start_matches('[');
+        descriptor.len() - element.len() == self.dim && element == self.descriptor
+    }
+
+    /// Returns the package of a class type in its internal form, e.g.
+    /// `com/example` for `Lcom/example/Foo;`. Classes in the default
+    /// package have an empty package, primitives and arrays have none.
+    pub fn package(&self) -> Option<&str> {
+        if self.primitive || self.dim > 0 || !self.descriptor.starts_with('L') {
+            return None;
+        }
+        let name = self.descriptor[1..].trim_end_matches(';');
+        Some(name.rfind('/').map_or("", |end| &name[..end]))
+    }
+
     /// Create a new `DexType` from a `String` removing any array
     /// dimensions
     pub fn from(descriptor: &Rc<String>) -> Option<DexType> {
@@ -63,7 +90,13 @@ impl DexType {
             }
             break;
         }
-        match *chars.peek().unwrap() {
+        let Some(&c) = chars.peek() else {
+            return Err(Error::MalformedDescriptor(format!(
+                "Invalid type descriptor: {}",
+                descriptor
+            )));
+        };
+        match c {
             // primitive types
             'V' | 'Z' | 'C' | 'B' | 'S' | 'I' | 'F' | 'J' | 'D' => {
                 Ok(DexType {
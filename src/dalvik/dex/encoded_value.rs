@@ -2,10 +2,36 @@ use super::types::*;
 use binrw::{binrw, BinRead, BinWrite, Endian};
 use byteorder::{LittleEndian, ReadBytesExt};
 use std::{
+    cell::Cell,
     ffi::{c_double, c_float},
     io,
 };
 
+/// Maximum nesting depth of arrays and annotations within an encoded value.
+/// Deeper values are rejected so that hostile input can't exhaust the stack.
+pub const MAX_VALUE_DEPTH: usize = 64;
+
+thread_local! {
+    static VALUE_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+fn read_nested<T, F>(pos: u64, read: F) -> binrw::BinResult<T>
+where
+    F: FnOnce() -> binrw::BinResult<T>,
+{
+    let depth = VALUE_DEPTH.get();
+    if depth >= MAX_VALUE_DEPTH {
+        return Err(binrw::Error::AssertFail {
+            pos,
+            message: format!("Encoded value nested deeper than {}", MAX_VALUE_DEPTH),
+        });
+    }
+    VALUE_DEPTH.set(depth + 1);
+    let value = read();
+    VALUE_DEPTH.set(depth);
+    value
+}
+
 /// ## EncodedValue
 /// ### Structure
 /// - `value_type`: byte indicating the type of the immediately subsequent value along with
@@ -169,6 +195,7 @@ impl BinRead for EncodedValue {
         _: Endian,
         _: Self::Args<'_>,
     ) -> binrw::BinResult<Self> {
+        let pos = reader.stream_position()?;
         let byte = reader.read_u8()?;
 
         let value_type = byte & 0x1F_u8 as u8;
@@ -179,51 +206,64 @@ impl BinRead for EncodedValue {
 
         let value = match value_type {
             EncodedValue::VALUE_BYTE => {
-                EncodedValue::Byte(reader.read_int::<LittleEndian>(value_size).unwrap() as i8)
+                EncodedValue::Byte(reader.read_int::<LittleEndian>(value_size)? as i8)
             }
             EncodedValue::VALUE_SHORT => {
-                EncodedValue::Short(reader.read_int::<LittleEndian>(value_size).unwrap() as i16)
+                EncodedValue::Short(reader.read_int::<LittleEndian>(value_size)? as i16)
+            }
+            EncodedValue::VALUE_CHAR => {
+                // unpaired surrogates are valid UTF-16 code units but not valid chars
+                let value = reader.read_uint::<LittleEndian>(value_size)? as u32;
+                EncodedValue::Char(char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER))
             }
-            EncodedValue::VALUE_CHAR => EncodedValue::Char(
-                char::from_u32(reader.read_uint::<LittleEndian>(value_size).unwrap() as u32)
-                    .unwrap(),
-            ),
             EncodedValue::VALUE_INT => {
-                EncodedValue::Int(reader.read_int::<LittleEndian>(value_size).unwrap() as i32)
+                EncodedValue::Int(reader.read_int::<LittleEndian>(value_size)? as i32)
             }
             EncodedValue::VALUE_LONG => {
-                EncodedValue::Long(reader.read_int::<LittleEndian>(value_size).unwrap())
+                EncodedValue::Long(reader.read_int::<LittleEndian>(value_size)?)
+            }
+            // floating point values are zero-extended to the right, i.e. the
+            // stored bytes are the most significant ones
+            EncodedValue::VALUE_FLOAT => {
+                if value_size > 4 {
+                    return Err(binrw::Error::AssertFail {
+                        pos,
+                        message: format!("float value with {} bytes", value_size),
+                    });
+                }
+                let bits = reader.read_uint::<LittleEndian>(value_size)? as u32;
+                EncodedValue::Float(c_float::from_bits(bits << ((4 - value_size) * 8)))
+            }
+            EncodedValue::VALUE_DOUBLE => {
+                let bits = reader.read_uint::<LittleEndian>(value_size)?;
+                EncodedValue::Double(c_double::from_bits(bits << ((8 - value_size) * 8)))
             }
-            EncodedValue::VALUE_FLOAT => EncodedValue::Float(c_float::from_bits(
-                reader.read_uint::<LittleEndian>(value_size).unwrap() as u32,
-            )),
-            EncodedValue::VALUE_DOUBLE => EncodedValue::Double(c_double::from_bits(
-                reader.read_uint::<LittleEndian>(value_size).unwrap(),
-            )),
             EncodedValue::VALUE_METHOD_TYPE => EncodedValue::MethodType(
-                reader.read_uint::<LittleEndian>(value_size).unwrap() as u32,
+                reader.read_uint::<LittleEndian>(value_size)? as u32,
             ),
             EncodedValue::VALUE_METHOD_HANDLE => EncodedValue::MethodHandle(
-                reader.read_uint::<LittleEndian>(value_size).unwrap() as u32,
+                reader.read_uint::<LittleEndian>(value_size)? as u32,
             ),
             EncodedValue::VALUE_STRING => {
-                EncodedValue::String(reader.read_uint::<LittleEndian>(value_size).unwrap() as u32)
+                EncodedValue::String(reader.read_uint::<LittleEndian>(value_size)? as u32)
             }
             EncodedValue::VALUE_TYPE => {
-                EncodedValue::Type(reader.read_uint::<LittleEndian>(value_size).unwrap() as u32)
+                EncodedValue::Type(reader.read_uint::<LittleEndian>(value_size)? as u32)
             }
             EncodedValue::VALUE_FIELD => {
-                EncodedValue::Field(reader.read_uint::<LittleEndian>(value_size).unwrap() as u32)
+                EncodedValue::Field(reader.read_uint::<LittleEndian>(value_size)? as u32)
             }
             EncodedValue::VALUE_METHOD => {
-                EncodedValue::Method(reader.read_uint::<LittleEndian>(value_size).unwrap() as u32)
+                EncodedValue::Method(reader.read_uint::<LittleEndian>(value_size)? as u32)
             }
             EncodedValue::VALUE_ENUM => {
-                EncodedValue::Enum(reader.read_uint::<LittleEndian>(value_size).unwrap() as u32)
+                EncodedValue::Enum(reader.read_uint::<LittleEndian>(value_size)? as u32)
+            }
+            EncodedValue::VALUE_ARRAY => {
+                EncodedValue::Array(read_nested(pos, || EncodedArray::read(reader))?)
             }
-            EncodedValue::VALUE_ARRAY => EncodedValue::Array(EncodedArray::read(reader).unwrap()),
             EncodedValue::VALUE_ANNOTATION => {
-                EncodedValue::Annotation(EncodedAnnotation::read(reader).unwrap())
+                EncodedValue::Annotation(read_nested(pos, || EncodedAnnotation::read(reader))?)
             }
             EncodedValue::VALUE_NULL => EncodedValue::Null,
             EncodedValue::VALUE_BOOLEAN => {
@@ -233,26 +273,116 @@ impl BinRead for EncodedValue {
                     EncodedValue::True
                 }
             }
-            _ => panic!(
-                "Unknown value type: {} with original byte {}",
-                value_type, byte
-            ),
+            _ => {
+                return Err(binrw::Error::AssertFail {
+                    pos,
+                    message: format!(
+                        "Unknown value type: {} with original byte {}",
+                        value_type, byte
+                    ),
+                })
+            }
         };
         return Ok(value);
     }
 }
 
-// TODO
 impl BinWrite for EncodedValue {
     type Args<'a> = ();
-    fn write_options<W: io::Write>(
+
+    /// Writes the value using the smallest possible size. Floating point
+    /// values are always written with their full width.
+    fn write_options<W: io::Write + io::Seek>(
         &self,
-        _: &mut W,
-        _: Endian,
+        writer: &mut W,
+        endian: Endian,
         _: Self::Args<'_>,
     ) -> binrw::BinResult<()> {
-        todo!();
+        // (value_type, value_arg, value bytes)
+        let (value_type, bytes) = match self {
+            EncodedValue::Byte(v) => (EncodedValue::VALUE_BYTE, signed_bytes(*v as i64)),
+            EncodedValue::Short(v) => (EncodedValue::VALUE_SHORT, signed_bytes(*v as i64)),
+            EncodedValue::Char(v) => {
+                let pos = writer.stream_position()?;
+                let Ok(value) = u16::try_from(*v as u32) else {
+                    return Err(binrw::Error::AssertFail {
+                        pos,
+                        message: format!("Char value {:?} does not fit into a UTF-16 code unit", v),
+                    });
+                };
+                (EncodedValue::VALUE_CHAR, unsigned_bytes(value as u64))
+            }
+            EncodedValue::Int(v) => (EncodedValue::VALUE_INT, signed_bytes(*v as i64)),
+            EncodedValue::Long(v) => (EncodedValue::VALUE_LONG, signed_bytes(*v)),
+            EncodedValue::Float(v) => (
+                EncodedValue::VALUE_FLOAT,
+                v.to_bits().to_le_bytes().to_vec(),
+            ),
+            EncodedValue::Double(v) => (
+                EncodedValue::VALUE_DOUBLE,
+                v.to_bits().to_le_bytes().to_vec(),
+            ),
+            EncodedValue::MethodType(v) => {
+                (EncodedValue::VALUE_METHOD_TYPE, unsigned_bytes(*v as u64))
+            }
+            EncodedValue::MethodHandle(v) => {
+                (EncodedValue::VALUE_METHOD_HANDLE, unsigned_bytes(*v as u64))
+            }
+            EncodedValue::String(v) => (EncodedValue::VALUE_STRING, unsigned_bytes(*v as u64)),
+            EncodedValue::Type(v) => (EncodedValue::VALUE_TYPE, unsigned_bytes(*v as u64)),
+            EncodedValue::Field(v) => (EncodedValue::VALUE_FIELD, unsigned_bytes(*v as u64)),
+            EncodedValue::Method(v) => (EncodedValue::VALUE_METHOD, unsigned_bytes(*v as u64)),
+            EncodedValue::Enum(v) => (EncodedValue::VALUE_ENUM, unsigned_bytes(*v as u64)),
+            EncodedValue::Array(v) => {
+                writer.write_all(&[EncodedValue::VALUE_ARRAY])?;
+                return v.write_options(writer, endian, ());
+            }
+            EncodedValue::Annotation(v) => {
+                writer.write_all(&[EncodedValue::VALUE_ANNOTATION])?;
+                return v.write_options(writer, endian, ());
+            }
+            EncodedValue::Null => {
+                writer.write_all(&[EncodedValue::VALUE_NULL])?;
+                return Ok(());
+            }
+            EncodedValue::True => {
+                writer.write_all(&[EncodedValue::VALUE_BOOLEAN | 0x20])?;
+                return Ok(());
+            }
+            EncodedValue::False => {
+                writer.write_all(&[EncodedValue::VALUE_BOOLEAN])?;
+                return Ok(());
+            }
+        };
+        writer.write_all(&[value_type | ((bytes.len() as u8 - 1) << 5)])?;
+        writer.write_all(&bytes)?;
+        Ok(())
+    }
+}
+
+/// Returns the shortest little-endian representation of a sign-extended value.
+fn signed_bytes(value: i64) -> Vec<UByte> {
+    let mut bytes = value.to_le_bytes().to_vec();
+    // drop bytes that can be restored through sign extension
+    while bytes.len() > 1 {
+        let last = bytes[bytes.len() - 1];
+        let sign = bytes[bytes.len() - 2] & 0x80;
+        if (last == 0x00 && sign == 0) || (last == 0xFF && sign != 0) {
+            bytes.pop();
+        } else {
+            break;
+        }
+    }
+    bytes
+}
+
+/// Returns the shortest little-endian representation of a zero-extended value.
+fn unsigned_bytes(value: u64) -> Vec<UByte> {
+    let mut bytes = value.to_le_bytes().to_vec();
+    while bytes.len() > 1 && bytes[bytes.len() - 1] == 0 {
+        bytes.pop();
     }
+    bytes
 }
 
 #[binrw]
@@ -283,11 +413,16 @@ pub struct EncodedTypeAddrPair {
     pub addr: ULeb128,
 }
 
-//TODO
 #[binrw]
 #[brw(little)]
 #[derive(Debug)]
 pub struct EncodedCatchHandler {
+    /// offset in bytes from the start of the [EncodedCatchHandlerList] to this
+    /// handler, which is what [TryItem::handler_off](super::TryItem::handler_off)
+    /// refers to. It is not stored in the file.
+    #[brw(ignore)]
+    pub offset: UShort,
+
     /// number of catch types in this list. If non-positive, then this is the negative
     /// of the number of catch types, and the catches are followed by a catch-all
     /// handler. For example: A size of 0 means that there is a catch-all but no
@@ -298,7 +433,7 @@ pub struct EncodedCatchHandler {
 
     /// stream of `abs(size)` encoded items, one for each caught type, in the order that
     /// the types should be tested.
-    #[br(count = if size.0 != 0 { size.0.abs() } else { 0 })]
+    #[br(count = size.0.unsigned_abs())]
     pub handlers: Vec<EncodedTypeAddrPair>,
 
     /// bytecode address of the catch-all handler. This element is only present if size
@@ -314,9 +449,33 @@ pub struct EncodedCatchHandlerList {
     /// the number of entries in this list
     pub size: ULeb128,
 
-    // elements of this list
-    // #[br(count = size.0 as usize)]
-    // pub list: Vec<EncodedCatchHandler>,
+    /// elements of this list
+    #[br(parse_with = read_catch_handlers, args(size.0))]
+    pub list: Vec<EncodedCatchHandler>,
+}
+
+impl EncodedCatchHandlerList {
+    /// Returns the handler at the given offset, see
+    /// [TryItem::handler_off](super::TryItem::handler_off).
+    pub fn handler_at(&self, offset: UShort) -> Option<&EncodedCatchHandler> {
+        self.list.iter().find(|x| x.offset == offset)
+    }
+}
+
+#[binrw::parser(reader)]
+fn read_catch_handlers(size: UInt) -> binrw::BinResult<Vec<EncodedCatchHandler>> {
+    // offsets are relative to the start of the list, i.e. the size value
+    let size_len = (UInt::BITS - size.leading_zeros()).div_ceil(7).max(1);
+    let start = reader.stream_position()? - size_len as u64;
+
+    let mut list = Vec::new();
+    for _ in 0..size {
+        let offset = reader.stream_position()? - start;
+        let mut handler = EncodedCatchHandler::read(reader)?;
+        handler.offset = offset as UShort;
+        list.push(handler);
+    }
+    Ok(list)
 }
 
 #[binrw]
@@ -352,7 +511,7 @@ pub struct EncodedMethod {
 
 #[binrw]
 #[brw(little, repr = u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AnnotationVisibility {
     /// intended only to be visible at build time (e.g., during compilation of other code)
     BUILD = 0x00,
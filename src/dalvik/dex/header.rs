@@ -1,7 +1,8 @@
 use binrw::binrw;
 use openssl::sha;
 use std::{
-    io::{self},
+    io::{self, Read},
+    ops::Range,
     result,
 };
 
@@ -25,6 +26,15 @@ pub struct Magic {
 }
 
 impl Magic {
+    /// Creates the magic for the given version, e.g. `35` for `dex\n035\0`.
+    pub fn new(version: UInt) -> Self {
+        let mut raw_version = [0; 4];
+        raw_version[..3].copy_from_slice(format!("{:03}", version % 1000).as_bytes());
+        Magic {
+            version: raw_version,
+        }
+    }
+
     /// Returns the version as a u32
     pub fn version_num(&self) -> result::Result<UInt, std::num::ParseIntError> {
         // We assume the version is always 3 bytes and ends with a '\0'
@@ -52,7 +62,27 @@ pub const SIGNATURE_SIZE: usize = 20;
 /// Header item size
 pub const HEADER_SIZE: usize = 0x70;
 
-
+/// Overview of a [HeaderItem], see [HeaderItem::summary].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderSummary {
+    /// version from the magic, `None` if it isn't a number
+    pub version: Option<UInt>,
+    pub checksum: UInt,
+    pub signature: [UByte; SIGNATURE_SIZE],
+    pub file_size: UInt,
+    pub reverse_endian: bool,
+    /// byte range of the link section, if present
+    pub link: Option<Range<UInt>>,
+    /// byte range of the data section
+    pub data: Range<UInt>,
+    pub map_off: UInt,
+    pub string_ids: UInt,
+    pub type_ids: UInt,
+    pub proto_ids: UInt,
+    pub field_ids: UInt,
+    pub method_ids: UInt,
+    pub class_defs: UInt,
+}
 
 /// Header item data structure
 #[binrw]
@@ -162,6 +192,97 @@ pub struct HeaderItem {
 }
 
 impl HeaderItem {
+    /// Computes the Adler-32 checksum of a DEX file, which covers everything
+    /// except the magic and the checksum field.
+    pub fn compute_checksum(data: &[UByte]) -> UInt {
+        adler32::RollingAdler32::from_buffer(data.get(12..).unwrap_or_default()).hash()
+    }
+
+    /// Computes the SHA-1 signature of a DEX file, which covers everything
+    /// except the magic, the checksum and the signature field.
+    pub fn compute_signature(data: &[UByte]) -> [UByte; SIGNATURE_SIZE] {
+        sha::sha1(data.get(32..).unwrap_or_default())
+    }
+
+    /// Computes the SHA-1 signature of a DEX file from a reader that is
+    /// positioned right after the signature field. Only the bytes up to
+    /// `file_size` are hashed, so trailing data after the file is ignored.
+    pub fn read_signature<R: Read>(
+        reader: R,
+        file_size: UInt,
+    ) -> io::Result<[UByte; SIGNATURE_SIZE]> {
+        let mut reader = reader.take(file_size.saturating_sub(32) as u64);
+        let mut hasher = sha::Sha1::new();
+        let mut buffer = [0u8; 1024];
+        loop {
+            let count = reader.read(&mut buffer)?;
+            if count == 0 {
+                break;
+            }
+            hasher.update(&buffer[..count]);
+        }
+        Ok(hasher.finish())
+    }
+
+    /// Returns the stored SHA-1 signature of the file.
+    pub fn signature(&self) -> &[UByte; SIGNATURE_SIZE] {
+        &self.signature
+    }
+
+    /// Returns the stored Adler-32 checksum of the file.
+    pub fn checksum(&self) -> UInt {
+        self.checksum
+    }
+
+    /// Returns the version number encoded in the magic, e.g. `35`.
+    pub fn version(&self) -> Option<UInt> {
+        self.magic.version_num().ok()
+    }
+
+    /// Returns whether the file uses [REVERSE_ENDIAN_CONSTANT].
+    pub fn is_reverse_endian(&self) -> bool {
+        self.endian_tag == REVERSE_ENDIAN_CONSTANT
+    }
+
+    /// Returns the size and offset of the link section.
+    pub fn link(&self) -> (UInt, UInt) {
+        (self.link_size, self.link_off)
+    }
+
+    /// Returns the byte range of the link section, or `None` if the file
+    /// isn't statically linked.
+    pub fn link_range(&self) -> Option<Range<UInt>> {
+        match self.link_size {
+            0 => None,
+            size => Some(self.link_off..self.link_off.saturating_add(size)),
+        }
+    }
+
+    /// Returns the byte range of the data section.
+    pub fn data_range(&self) -> Range<UInt> {
+        self.data_off..self.data_off.saturating_add(self.data_size)
+    }
+
+    /// Collects the most relevant header values into a [HeaderSummary].
+    pub fn summary(&self) -> HeaderSummary {
+        HeaderSummary {
+            version: self.version(),
+            checksum: self.checksum,
+            signature: self.signature,
+            file_size: self.file_size,
+            reverse_endian: self.is_reverse_endian(),
+            link: self.link_range(),
+            data: self.data_range(),
+            map_off: self.map_off,
+            string_ids: self.string_ids_size,
+            type_ids: self.type_ids_size,
+            proto_ids: self.proto_ids_size,
+            field_ids: self.field_ids_size,
+            method_ids: self.method_ids_size,
+            class_defs: self.class_defs_size,
+        }
+    }
+
     pub fn verify<R>(&self, mut reader: R, offset: UInt) -> result::Result<(), ConstraintError>
     where
         R: io::Read + io::Seek,
@@ -177,7 +298,8 @@ impl HeaderItem {
         //
         // G2: The checksum must be an Adler-32 checksum of the whole file contents
         //     except magic and checksum field.
-        let checksum = match adler32::adler32(&mut reader) {
+        let data = (&mut reader).take(self.file_size.saturating_sub(12) as u64);
+        let checksum = match adler32::adler32(data) {
             Ok(x) => x,
             Err(e) => {
                 return Err(ConstraintError {
@@ -203,18 +325,14 @@ impl HeaderItem {
             });
         }
 
-        let digest = {
-            let mut hasher = sha::Sha1::new();
-            let mut buffer = [0u8; 1024];
-
-            loop {
-                let count = reader.read(&mut buffer).unwrap();
-                if count == 0 {
-                    break;
-                }
-                hasher.update(&buffer[..count]);
+        let digest = match Self::read_signature(&mut reader, self.file_size) {
+            Ok(x) => x,
+            Err(e) => {
+                return Err(ConstraintError {
+                    identifier: "io",
+                    description: e.to_string(),
+                })
             }
-            hasher.finish()
         };
 
         if digest != self.signature {
@@ -276,4 +394,59 @@ impl HeaderItem {
 
         return Ok(());
     }
+
+    /// Verifies that all sections described by this header lie within the
+    /// file, whose actual length is given by `file_len`.
+    ///
+    /// Unlike [HeaderItem::verify], the checksum and signature are ignored,
+    /// which makes this check suitable for tampered files.
+    pub fn verify_bounds(&self, file_len: u64) -> result::Result<(), ConstraintError> {
+        if self.header_size != 0x70 {
+            return Err(ConstraintError {
+                identifier: "G5",
+                description: format!("expected 0x70, got {}", self.header_size),
+            });
+        }
+
+        if self.file_size as u64 > file_len {
+            return Err(ConstraintError {
+                identifier: "bounds",
+                description: format!(
+                    "file_size is {} but only {} bytes are available",
+                    self.file_size, file_len
+                ),
+            });
+        }
+
+        let sections = [
+            (self.link_size, 1, self.link_off, "link"),
+            (self.string_ids_size, 4, self.string_ids_off, "string_ids"),
+            (self.type_ids_size, 4, self.type_ids_off, "type_ids"),
+            (self.proto_ids_size, 12, self.proto_ids_off, "proto_ids"),
+            (self.field_ids_size, 8, self.field_ids_off, "field_ids"),
+            (self.method_ids_size, 8, self.method_ids_off, "method_ids"),
+            (self.class_defs_size, 32, self.class_defs_off, "class_defs"),
+            (self.data_size, 1, self.data_off, "data"),
+        ];
+        for (size, item_size, offset, name) in sections {
+            let end = offset as u64 + size as u64 * item_size;
+            if end > self.file_size as u64 {
+                return Err(ConstraintError {
+                    identifier: "bounds",
+                    description: format!(
+                        "section {} ends at {:#x}, after the end of the file ({:#x})",
+                        name, end, self.file_size
+                    ),
+                });
+            }
+        }
+
+        if self.map_off as u64 >= self.file_size as u64 {
+            return Err(ConstraintError {
+                identifier: "bounds",
+                description: format!("map_off {:#x} is outside of the file", self.map_off),
+            });
+        }
+        Ok(())
+    }
 }
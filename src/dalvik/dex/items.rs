@@ -1,5 +1,5 @@
 use super::encoded_value::{EncodedField, EncodedMethod};
-use super::{types::*, EncodedCatchHandlerList};
+use super::{types::*, EncodedCatchHandler, EncodedCatchHandlerList};
 use binrw::meta::{EndianKind, ReadEndian};
 use binrw::{binrw, BinRead, Endian};
 use std::io;
@@ -130,9 +130,12 @@ impl BinRead for StringDataItem {
         _: Endian,
         _: Self::Args<'_>,
     ) -> binrw::BinResult<Self> {
-        Ok(Self {
-            data: Some(mutf8::read(reader)?),
-        })
+        let pos = reader.stream_position()?;
+        let data = mutf8::read(reader).map_err(|e| binrw::Error::Custom {
+            pos,
+            err: Box::new(e),
+        })?;
+        Ok(Self { data: Some(data) })
     }
 }
 
@@ -161,11 +164,40 @@ pub enum MethodHandleType {
     /// Method handle is a constructor invoke
     InvokeConstructor = 0x06,
 
-    /// Method handle is an instance method invoke
+    /// Method handle is a direct method invoke
     InvokeDirect = 0x07,
 
-    /// Method handle is an instance method invoke
-    InvokeStatic = 0x08,
+    /// Method handle is an interface method invoke
+    InvokeInterface = 0x08,
+}
+
+impl MethodHandleType {
+    /// Returns the name of the kind as written by smali, e.g.
+    /// `invoke-static` or `instance-get`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MethodHandleType::StaticPut => "static-put",
+            MethodHandleType::StaticGet => "static-get",
+            MethodHandleType::InstancePut => "instance-put",
+            MethodHandleType::InstanceGet => "instance-get",
+            MethodHandleType::StaticInvoke => "invoke-static",
+            MethodHandleType::InstanceInvoke => "invoke-instance",
+            MethodHandleType::InvokeConstructor => "invoke-constructor",
+            MethodHandleType::InvokeDirect => "invoke-direct",
+            MethodHandleType::InvokeInterface => "invoke-interface",
+        }
+    }
+
+    /// Whether the handle refers to a field instead of a method.
+    pub fn is_field_accessor(&self) -> bool {
+        matches!(
+            self,
+            MethodHandleType::StaticPut
+                | MethodHandleType::StaticGet
+                | MethodHandleType::InstancePut
+                | MethodHandleType::InstanceGet
+        )
+    }
 }
 
 #[binrw]
@@ -222,6 +254,15 @@ pub struct TryItem {
     pub handler_off: UShort,
 }
 
+impl TryItem {
+    /// Returns the covered byte range within the instructions of the code
+    /// item, comparable to the range of a decoded instruction.
+    pub fn range(&self) -> std::ops::Range<usize> {
+        let start = self.start_addr as usize * 2;
+        start..start + self.insn_count as usize * 2
+    }
+}
+
 #[binrw]
 #[brw(little)]
 #[derive(Debug)]
@@ -343,6 +384,42 @@ pub struct AnnotationsDirectoryItem {
     pub parameter_annotations: Vec<ParameterAnnotation>,
 }
 
+impl AnnotationsDirectoryItem {
+    /// Returns the offset of the `annotation_set_item` of the given field,
+    /// or `None` if the field is not annotated.
+    ///
+    /// The lookup is a binary search, entries of an unsorted list may not
+    /// be found.
+    pub fn field_annotations_off(&self, field_idx: UInt) -> Option<UInt> {
+        let list = &self.field_annotations;
+        let index = list
+            .binary_search_by_key(&field_idx, |x| x.field_idx)
+            .ok()?;
+        Some(list[index].annotations_off)
+    }
+
+    /// Returns the offset of the `annotation_set_item` of the given method,
+    /// see [Self::field_annotations_off].
+    pub fn method_annotations_off(&self, method_idx: UInt) -> Option<UInt> {
+        let list = &self.method_annotations;
+        let index = list
+            .binary_search_by_key(&method_idx, |x| x.method_idx)
+            .ok()?;
+        Some(list[index].annotations_off)
+    }
+
+    /// Returns the offset of the `annotation_set_ref_list` storing the
+    /// parameter annotations of the given method, see
+    /// [Self::field_annotations_off].
+    pub fn parameter_annotations_off(&self, method_idx: UInt) -> Option<UInt> {
+        let list = &self.parameter_annotations;
+        let index = list
+            .binary_search_by_key(&method_idx, |x| x.method_idx)
+            .ok()?;
+        Some(list[index].annotations_off)
+    }
+}
+
 #[binrw]
 #[brw(little)]
 #[derive(Debug)]
@@ -369,11 +446,13 @@ pub struct CodeItem {
     pub insns_size: UInt,
 
     /// actual array of bytecode.
-    #[br(count = insns_size * 2)]
+    #[br(parse_with = read_bytes, args(insns_size as usize * 2))]
     pub insns: Vec<UByte>,
 
-    #[br(if(tries_size != 0))]
-    #[bw(if(*tries_size != 0))]
+    /// two bytes of padding to align `tries`, only present if there are
+    /// try blocks and `insns_size` is odd
+    #[br(if(tries_size != 0 && insns_size % 2 == 1))]
+    #[bw(if(*tries_size != 0 && *insns_size % 2 == 1))]
     padding: Option<UShort>,
 
     /// array indicating where in the code exceptions are caught and how
@@ -387,6 +466,51 @@ pub struct CodeItem {
     pub handlers: Option<EncodedCatchHandlerList>,
 }
 
+impl CodeItem {
+    /// Creates a code item without try blocks and debug information.
+    pub fn new(
+        registers_size: UShort,
+        ins_size: UShort,
+        outs_size: UShort,
+        insns: Vec<UByte>,
+    ) -> Self {
+        CodeItem {
+            registers_size,
+            ins_size,
+            outs_size,
+            tries_size: 0,
+            debug_info_off: 0,
+            insns_size: (insns.len() / 2) as UInt,
+            insns,
+            padding: None,
+            tries: Vec::new(),
+            handlers: None,
+        }
+    }
+
+    /// Replaces the instructions of this code item and updates their size.
+    /// Try blocks and debug information are left as they are.
+    pub fn set_insns(&mut self, insns: Vec<UByte>) {
+        self.insns_size = (insns.len() / 2) as UInt;
+        self.padding = (self.tries_size != 0 && self.insns_size % 2 == 1).then_some(0);
+        self.insns = insns;
+    }
+
+    /// Replaces the try blocks and catch handlers of this code item and
+    /// updates their size and the alignment padding before them.
+    pub fn set_tries(&mut self, tries: Vec<TryItem>, handlers: Option<EncodedCatchHandlerList>) {
+        self.tries_size = tries.len() as UShort;
+        self.padding = (self.tries_size != 0 && self.insns_size % 2 == 1).then_some(0);
+        self.tries = tries;
+        self.handlers = handlers;
+    }
+
+    /// Returns the catch handler of the given try block, if it exists.
+    pub fn catch_handler(&self, try_item: &TryItem) -> Option<&EncodedCatchHandler> {
+        self.handlers.as_ref()?.handler_at(try_item.handler_off)
+    }
+}
+
 #[binrw]
 #[brw(little)]
 #[derive(Debug)]
@@ -449,7 +573,7 @@ pub struct HiddenAPIClassDataItem {
     // concatenated arrays of hidden API flags for each class. Flags are encoded in
     // the same order as fields and methods are encoded in class data.
     // flags: Vec<ULeb128>,
-    #[br(count = size as usize)]
+    #[br(parse_with = read_bytes, args(size as usize))]
     pub data: Vec<UByte>,
 }
 
@@ -494,8 +618,8 @@ pub struct FillArrayData {
     /// number of elements in the table
     pub size: u32,
 
-    /// data values
-    #[br(count = (size * width as u32) as usize)]
+    /// data values, padded to a whole number of code units
+    #[br(parse_with = read_bytes, args(size as usize * width as usize), align_after = 2)]
     pub data: Vec<u8>,
 }
 
@@ -1,143 +1,209 @@
 use binrw::binrw;
+use std::result;
 
-use super::types::*;
+use super::{header::*, types::*};
+use crate::dalvik::error::ConstraintError;
 
-#[binrw]
-#[brw(repr(UShort), little)]
-#[derive(Debug, PartialEq, Eq)]
+/// The type of a map list entry.
+///
+/// Values not defined by the DEX format are preserved as [MapListItemType::Unknown]
+/// instead of failing to parse the whole map list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MapListItemType {
     /// header item type
     ///
     /// @size: `0x70`
     /// @type: [HeaderItem]
-    HeaderItem = 0x0000,
+    HeaderItem,
 
     /// string identifier item type
     ///
     /// @size: `0x04`
     /// @type: [StringIdItem]
-    StringIdItem = 0x0001,
+    StringIdItem,
 
     /// type identifier item type
     ///
     /// @size: `0x04`
     /// @type: [TypeIdItem]
-    TypeIdItem = 0x0002,
+    TypeIdItem,
 
     /// prototype identifier item type
     ///
     /// @size: `0x0C`
     /// @type: [ProtoIdItem]
-    ProtoIdItem = 0x0003,
+    ProtoIdItem,
 
     /// field identifier item type
     ///
     /// @size: `0x08`
     /// @type: [FieldIdItem]
-    FieldIdItem = 0x0004,
+    FieldIdItem,
 
     /// method identifier item type
     ///
     /// @size: `0x08`
     /// @type: [MethodIdItem]
-    MethodIdItem = 0x0005,
+    MethodIdItem,
 
     /// class definition item type
     ///
     /// @size: `0x0C`
     /// @type: [ClassDefItem]
-    ClassDefItem = 0x0006,
+    ClassDefItem,
 
     /// call site id item type
     ///
     /// @size: `0x08`
     /// @type: [CallSiteIdItem]
-    CallSiteIdItem = 0x0007,
+    CallSiteIdItem,
 
     /// method handle item type
     ///
     /// @size: `0x08`
     /// @type: [MethodHandleItem]
-    MethodHandleItem = 0x0008,
+    MethodHandleItem,
 
     /// map list type
     ///
     /// @size: `4 + (item.size * 12)`
-    MapList = 0x1000,
+    MapList,
 
     /// type list type
     ///
     /// @size: `4 + (item.size * 2)`
-    TypeList = 0x1001,
+    TypeList,
 
     /// annotation set ref list type
     ///
     /// @size: `4 + (item.size * 4)`
     /// @type: [AnnotationSetRefList]
-    AnnotationSetRefList = 0x1002,
+    AnnotationSetRefList,
 
     /// annotation set item type
     ///
     /// @size: `4 + (item.size * 4)`
     /// @type: [AnnotationSetItem]
-    AnnotationSetItem = 0x1003,
+    AnnotationSetItem,
 
     /// class data item type
     ///
     /// @size: `0x08`
     /// @type: [ClassDataItem]
-    ClassDataItem = 0x2000,
+    ClassDataItem,
 
     /// code item type
     ///
     /// @size: _implicit_
     /// @type: [CodeItem](CodeItem)
-    CodeItem = 0x2001,
+    CodeItem,
 
     /// string data item type
     ///
     /// @size: _implicit_
     /// @type: [StringDataItem](StringDataItem)
-    StringDataItem = 0x2002,
+    StringDataItem,
 
     /// debug info item type
     ///
     /// @size: _implicit_
     /// @type: [DebugInfoItem](DebugInfoItem)
-    DebugInfoItem = 0x2003,
+    DebugInfoItem,
 
     /// annotation item type
     ///
     /// @size: _implicit_
     /// @type: [AnnotationItem](AnnotationItem)
-    AnnotationItem = 0x2004,
+    AnnotationItem,
 
     /// encoded array item type
     ///
     /// @size: _implicit_
     /// @type: [EncodedArrayItem](EncodedArrayItem)
-    EncodedArrayItem = 0x2005,
+    EncodedArrayItem,
 
     /// annotations directory item type
     ///
     /// @size: _implicit_
     /// @type: [AnnotationsDirectoryItem](AnnotationsDirectoryItem)
-    AnnotationsDirectoryItem = 0x2006,
+    AnnotationsDirectoryItem,
 
     /// hidden api list class data item type
     ///
     /// @size: _implicit_
     /// @type: [HiddenAPIClassDataItem](HiddenAPIClassDataItem)
-    HiddenApiListClassDataItem = 0xF000,
+    HiddenApiListClassDataItem,
+
+    /// item type not defined by the DEX format
+    Unknown(UShort),
+}
+
+impl From<UShort> for MapListItemType {
+    fn from(value: UShort) -> Self {
+        match value {
+            0x0000 => MapListItemType::HeaderItem,
+            0x0001 => MapListItemType::StringIdItem,
+            0x0002 => MapListItemType::TypeIdItem,
+            0x0003 => MapListItemType::ProtoIdItem,
+            0x0004 => MapListItemType::FieldIdItem,
+            0x0005 => MapListItemType::MethodIdItem,
+            0x0006 => MapListItemType::ClassDefItem,
+            0x0007 => MapListItemType::CallSiteIdItem,
+            0x0008 => MapListItemType::MethodHandleItem,
+            0x1000 => MapListItemType::MapList,
+            0x1001 => MapListItemType::TypeList,
+            0x1002 => MapListItemType::AnnotationSetRefList,
+            0x1003 => MapListItemType::AnnotationSetItem,
+            0x2000 => MapListItemType::ClassDataItem,
+            0x2001 => MapListItemType::CodeItem,
+            0x2002 => MapListItemType::StringDataItem,
+            0x2003 => MapListItemType::DebugInfoItem,
+            0x2004 => MapListItemType::AnnotationItem,
+            0x2005 => MapListItemType::EncodedArrayItem,
+            0x2006 => MapListItemType::AnnotationsDirectoryItem,
+            0xF000 => MapListItemType::HiddenApiListClassDataItem,
+            _ => MapListItemType::Unknown(value),
+        }
+    }
+}
+
+impl From<MapListItemType> for UShort {
+    fn from(value: MapListItemType) -> Self {
+        match value {
+            MapListItemType::HeaderItem => 0x0000,
+            MapListItemType::StringIdItem => 0x0001,
+            MapListItemType::TypeIdItem => 0x0002,
+            MapListItemType::ProtoIdItem => 0x0003,
+            MapListItemType::FieldIdItem => 0x0004,
+            MapListItemType::MethodIdItem => 0x0005,
+            MapListItemType::ClassDefItem => 0x0006,
+            MapListItemType::CallSiteIdItem => 0x0007,
+            MapListItemType::MethodHandleItem => 0x0008,
+            MapListItemType::MapList => 0x1000,
+            MapListItemType::TypeList => 0x1001,
+            MapListItemType::AnnotationSetRefList => 0x1002,
+            MapListItemType::AnnotationSetItem => 0x1003,
+            MapListItemType::ClassDataItem => 0x2000,
+            MapListItemType::CodeItem => 0x2001,
+            MapListItemType::StringDataItem => 0x2002,
+            MapListItemType::DebugInfoItem => 0x2003,
+            MapListItemType::AnnotationItem => 0x2004,
+            MapListItemType::EncodedArrayItem => 0x2005,
+            MapListItemType::AnnotationsDirectoryItem => 0x2006,
+            MapListItemType::HiddenApiListClassDataItem => 0xF000,
+            MapListItemType::Unknown(x) => x,
+        }
+    }
 }
 
 #[binrw]
 #[brw(little)]
 #[derive(Debug)]
 pub struct MapListItem {
-    /// type of the item
-    #[br(align_after = 4)]
-    pub type_: MapListItemType,
+    /// raw type of the item, use [MapListItem::item_type] to get the
+    /// typed value.
+    #[brw(align_after = 4)]
+    pub type_: UShort,
 
     /// count of the number of items to be found at the indicated offset
     pub size: UInt,
@@ -146,6 +212,13 @@ pub struct MapListItem {
     pub offset: UInt,
 }
 
+impl MapListItem {
+    /// Returns the type of this item.
+    pub fn item_type(&self) -> MapListItemType {
+        MapListItemType::from(self.type_)
+    }
+}
+
 /// A map list is a list of the entire contents of a file, in order.
 #[binrw]
 #[brw(little)]
@@ -161,8 +234,22 @@ pub struct MapList {
 }
 
 impl MapList {
+    /// Creates a new map list from the given entries, which must be sorted
+    /// by their offset.
+    pub fn new(list: Vec<MapListItem>) -> Self {
+        MapList {
+            size: list.len() as UInt,
+            list,
+        }
+    }
+
+    /// Returns all entries of this map list in the order they were stored.
+    pub fn items(&self) -> &[MapListItem] {
+        &self.list
+    }
+
     pub fn get(&self, type_: MapListItemType) -> Option<&MapListItem> {
-        self.list.iter().find(|&item| item.type_ == type_)
+        self.list.iter().find(|&item| item.item_type() == type_)
     }
 
     pub fn item_size(&self, type_: MapListItemType) -> usize {
@@ -178,4 +265,101 @@ impl MapList {
             None => 0,
         }
     }
+
+    /// Validates this map list against the rules defined for `map_list` in
+    /// the DEX format and the sections declared by the given header:
+    ///
+    /// - the entries must be ordered by initial offset,
+    /// - each item type may only appear once,
+    /// - the header and the map list itself must be described,
+    /// - every id section declared in the header must be described with the
+    ///   same size and offset.
+    pub fn validate(&self, header: &HeaderItem) -> result::Result<(), ConstraintError> {
+        for pair in self.list.windows(2) {
+            let (prev, item) = (&pair[0], &pair[1]);
+            if prev.offset >= item.offset {
+                return Err(ConstraintError {
+                    identifier: "map_order",
+                    description: format!(
+                        "{:?} at {:#x} is not placed after {:?} at {:#x}",
+                        item.item_type(),
+                        item.offset,
+                        prev.item_type(),
+                        prev.offset
+                    ),
+                });
+            }
+        }
+
+        for (i, item) in self.list.iter().enumerate() {
+            if self.list[..i].iter().any(|x| x.type_ == item.type_) {
+                return Err(ConstraintError {
+                    identifier: "map_duplicate",
+                    description: format!("duplicate entry for {:?}", item.item_type()),
+                });
+            }
+        }
+
+        let sections = [
+            (MapListItemType::HeaderItem, 1, 0),
+            (MapListItemType::MapList, 1, header.map_off),
+            (
+                MapListItemType::StringIdItem,
+                header.string_ids_size,
+                header.string_ids_off,
+            ),
+            (
+                MapListItemType::TypeIdItem,
+                header.type_ids_size,
+                header.type_ids_off,
+            ),
+            (
+                MapListItemType::ProtoIdItem,
+                header.proto_ids_size,
+                header.proto_ids_off,
+            ),
+            (
+                MapListItemType::FieldIdItem,
+                header.field_ids_size,
+                header.field_ids_off,
+            ),
+            (
+                MapListItemType::MethodIdItem,
+                header.method_ids_size,
+                header.method_ids_off,
+            ),
+            (
+                MapListItemType::ClassDefItem,
+                header.class_defs_size,
+                header.class_defs_off,
+            ),
+        ];
+        for (type_, size, offset) in sections {
+            if size == 0 {
+                continue;
+            }
+            match self.get(type_) {
+                Some(item) if item.size == size && item.offset == offset => {}
+                Some(item) => {
+                    return Err(ConstraintError {
+                        identifier: "map_section",
+                        description: format!(
+                            "{:?}: expected size {} at {:#x}, got size {} at {:#x}",
+                            type_, size, offset, item.size, item.offset
+                        ),
+                    });
+                }
+                None => {
+                    return Err(ConstraintError {
+                        identifier: "map_section",
+                        description: format!(
+                            "missing entry for section with size {} at {:#x}",
+                            size, offset
+                        ),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
 }
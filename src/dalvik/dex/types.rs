@@ -2,7 +2,10 @@ use binrw::meta::ReadEndian;
 use binrw::{BinRead, BinWrite, Endian};
 use bitflags::bitflags;
 use leb128;
-use std::{io, result};
+use std::{
+    io::{self, Read},
+    result,
+};
 
 /// 8bit signed int
 pub type Byte = i8;
@@ -273,9 +276,24 @@ impl BinWrite for ULeb128p1 {
 
 
 
+/// Reads `count` raw bytes.
+///
+/// Unlike `#[br(count = ...)]`, the buffer only grows with the bytes that
+/// were actually read, so a corrupted count can't trigger a huge allocation.
+#[binrw::parser(reader)]
+pub fn read_bytes(count: usize) -> binrw::BinResult<Vec<UByte>> {
+    let mut data = Vec::new();
+    reader.by_ref().take(count as u64).read_to_end(&mut data)?;
+    if data.len() != count {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(data)
+}
+
 pub mod mutf8 {
-    use std::io::{self, Read, Seek};
+    use std::io::{self, Read};
 
+    use crate::dalvik::error::{Error, Result, StringError};
 
     /// # Modified UTF-8 encoding
     ///
@@ -293,74 +311,109 @@ pub mod mutf8 {
     ///
     /// The first two items above can be summarized as: MUTF-8 is an encoding format for
     /// UTF-16, instead of being a more direct encoding format for Unicode characters.
-    pub fn read<R>(reader: &mut R) -> io::Result<String>
-    where
-        R: Read + Seek,
-    {
-        let len = match leb128::read::unsigned(reader) {
-            Ok(x) => x as usize,
-            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+    ///
+    /// Reads a complete `string_data_item` and checks it like the ART verifier:
+    /// the data has to contain exactly `utf16_size` code units, each in its
+    /// shortest form, followed by a null byte. The reader is left after the
+    /// null byte. Pass a reader limited with [Read::take] to keep the string
+    /// within a section, leaving it is reported as [StringError::Truncated].
+    pub fn read<R: Read>(reader: &mut R) -> Result<String> {
+        let utf16_size = match leb128::read::unsigned(reader) {
+            Ok(x) => u32::try_from(x).map_err(|_| StringError::BadSize)?,
+            Err(leb128::read::Error::IoError(e)) => return Err(eof(e)),
+            Err(leb128::read::Error::Overflow) => return Err(StringError::BadSize.into()),
         };
-        let mut buf = [0];
-        let mut out: Vec<u16> = Vec::with_capacity(len);
-        let mut k: usize = len;
-        while k > 0 {
-            reader.read(&mut buf)?;
-            let byte = buf[0];
-            // (4) A plain null byte (value 0) indicates the end of a string, as is the
-            // standard C language interpretation.
-            if byte == 0 {
-                break;
-            }
-
-            let out_val: u16 = match byte >> 4 {
+        // the declared length is untrusted, so don't let it drive the allocation
+        let mut out: Vec<u16> = Vec::with_capacity((utf16_size as usize).min(0x1000));
+        while out.len() < utf16_size as usize {
+            let byte = next(reader)?;
+            let unit = match byte >> 4 {
+                // (4) A plain null byte (value 0) indicates the end of a string
+                0x00 if byte == 0 => {
+                    return Err(StringError::LengthMismatch {
+                        utf16_size,
+                        actual: out.len() as u32,
+                    }
+                    .into());
+                }
                 0x00..=0x07 => {
                     // 0xxx xxxx
                     byte as u16
                 }
                 0x0C | 0x0D => {
-                    // 110x xxxx
-                    reader.read(&mut buf)?;
-                    let next = buf[0];
-                    if (next & 0xC0) != 0x80 {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "Bad second character!",
-                        ));
+                    // 110x xxxx 10xx xxxx
+                    let b = continuation(reader)?;
+                    let unit = ((byte as u16 & 0x1F) << 6) | b;
+                    // (3) U+0000 is the only code unit in two-byte form below 0x80
+                    if unit != 0 && unit < 0x80 {
+                        return Err(StringError::Overlong(unit).into());
                     }
-                    (((byte & 0x1F) << 6) | (next & 0x3F)) as u16
+                    unit
                 }
                 0x0E => {
-                    // 1110 xxxx
-                    reader.read(&mut buf)?;
-                    let b = buf[0];
-                    if (b & 0xC0) != 0x80 {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "Bad second character!",
-                        ));
-                    }
-                    reader.read(&mut buf)?;
-                    let c = buf[0];
-                    if (c & 0xC0) != 0x80 {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "Bad third character!",
-                        ));
+                    // 1110 xxxx 10xx xxxx 10xx xxxx
+                    let b = continuation(reader)?;
+                    let c = continuation(reader)?;
+                    // surrogates are kept as separate code units
+                    let unit = ((byte as u16 & 0x0F) << 12) | (b << 6) | c;
+                    if unit < 0x800 {
+                        return Err(StringError::Overlong(unit).into());
                     }
-                    // REVISIT: rust can't handle surrogates
-                    (((byte as u16) & 0x0F) << 12) | (b as u16 & 0x3F) << 6 | (c as u16 & 0x3F)
+                    unit
+                }
+                _ => return Err(StringError::InvalidByte(byte).into()),
+            };
+            out.push(unit);
+        }
+        if next(reader)? != 0 {
+            return Err(StringError::MissingTerminator { utf16_size }.into());
+        }
+        Ok(String::from_utf16_lossy(out.as_ref()))
+    }
+
+    fn next<R: Read>(reader: &mut R) -> Result<u8> {
+        let mut buf = [0];
+        reader.read_exact(&mut buf).map_err(eof)?;
+        Ok(buf[0])
+    }
+
+    /// Reads a `10xx xxxx` byte and returns its payload.
+    fn continuation<R: Read>(reader: &mut R) -> Result<u16> {
+        match next(reader)? {
+            byte if byte & 0xC0 == 0x80 => Ok(byte as u16 & 0x3F),
+            byte => Err(StringError::InvalidByte(byte).into()),
+        }
+    }
+
+    /// Reports the end of the input as a truncated string.
+    fn eof(e: io::Error) -> Error {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => StringError::Truncated.into(),
+            _ => e.into(),
+        }
+    }
+
+    /// Encodes a string as MUTF-8 without the trailing null byte. Returns the
+    /// length of the string in UTF-16 code units along with the encoded bytes.
+    pub fn encode(value: &str) -> (u32, Vec<u8>) {
+        let mut size = 0;
+        let mut out = Vec::with_capacity(value.len());
+        for unit in value.encode_utf16() {
+            size += 1;
+            match unit {
+                0x0001..=0x007F => out.push(unit as u8),
+                // U+0000 is encoded in two-byte form
+                0x0000 | 0x0080..=0x07FF => {
+                    out.push(0xC0 | (unit >> 6) as u8);
+                    out.push(0x80 | (unit & 0x3F) as u8);
                 }
                 _ => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        format!("Bad character: {:#x}", byte),
-                    ));
+                    out.push(0xE0 | (unit >> 12) as u8);
+                    out.push(0x80 | ((unit >> 6) & 0x3F) as u8);
+                    out.push(0x80 | (unit & 0x3F) as u8);
                 }
-            };
-            out.push(out_val);
-            k -= 1;
+            }
         }
-        return Ok(String::from_utf16_lossy(out.as_ref()));
+        (size, out)
     }
 }
@@ -1,4 +1,4 @@
-use std::{io, result};
+use std::{fmt, io, result};
 
 #[derive(Debug)]
 pub struct ConstraintError {
@@ -6,6 +6,50 @@ pub struct ConstraintError {
     pub description: String,
 }
 
+/// Why a `string_data_item` could not be decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StringError {
+    /// the `utf16_size` prefix is not a valid ULEB128 value
+    BadSize,
+
+    /// The data ended, or left the data section, before the string and its
+    /// terminator were complete.
+    Truncated,
+
+    /// A null byte ended the string after `actual` of the `utf16_size`
+    /// code units.
+    LengthMismatch { utf16_size: u32, actual: u32 },
+
+    /// the `utf16_size` code units are not followed by a null byte
+    MissingTerminator { utf16_size: u32 },
+
+    /// A code unit is encoded with more bytes than necessary. Only U+0000
+    /// may use the two-byte form.
+    Overlong(u16),
+
+    /// a byte that can't start or continue a code unit
+    InvalidByte(u8),
+}
+
+impl fmt::Display for StringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StringError::BadSize => write!(f, "bad utf16_size"),
+            StringError::Truncated => write!(f, "truncated string data"),
+            StringError::LengthMismatch { utf16_size, actual } => write!(
+                f,
+                "string ends after {} of {} code units",
+                actual, utf16_size
+            ),
+            StringError::MissingTerminator { utf16_size } => {
+                write!(f, "missing null byte after {} code units", utf16_size)
+            }
+            StringError::Overlong(x) => write!(f, "overlong encoding of U+{:04X}", x),
+            StringError::InvalidByte(x) => write!(f, "invalid byte {:#04x}", x),
+        }
+    }
+}
+
 pub type Result<T> = result::Result<T, Error>;
 
 #[derive(Debug)]
@@ -15,6 +59,7 @@ pub enum Error {
     Custom(&'static str),
     Validation(ConstraintError),
     InvalidData(String),
+    MalformedString(StringError),
 
     //
     InvalidOffset(isize),
@@ -23,8 +68,146 @@ pub enum Error {
     MethodNotFound(usize),
     FieldNotFound(usize),
     ParameterNotFound(usize),
+
+    /// An error together with the items that were being read when it
+    /// occurred, outermost first.
+    WithContext {
+        context: Vec<ErrorContext>,
+        source: Box<Error>,
+    },
 }
 
+/// An item that was being read when an error occurred, see [ResultExt].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorContext {
+    /// index into the `class_defs` list
+    ClassDef(u32),
+    /// index into the `field_ids` list
+    Field(u32),
+    /// index into the `method_ids` list
+    Method(u32),
+    /// index into the `string_ids` list
+    String(u32),
+    /// index into the `type_ids` list
+    Type(u32),
+    /// index into the `proto_ids` list
+    Proto(u32),
+    /// file offset of a class data item
+    ClassData(u64),
+    /// file offset of a code item
+    CodeItem(u64),
+    /// file offset of a debug info item
+    DebugInfo(u64),
+    /// file offset of an annotations directory item
+    Annotations(u64),
+    /// file offset of the static values of a class
+    StaticValues(u64),
+    /// byte offset of an instruction within its code item
+    Insn { offset: usize, name: &'static str },
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorContext::ClassDef(x) => write!(f, "class_def #{}", x),
+            ErrorContext::Field(x) => write!(f, "field #{}", x),
+            ErrorContext::Method(x) => write!(f, "method #{}", x),
+            ErrorContext::String(x) => write!(f, "string #{}", x),
+            ErrorContext::Type(x) => write!(f, "type #{}", x),
+            ErrorContext::Proto(x) => write!(f, "proto #{}", x),
+            ErrorContext::ClassData(x) => write!(f, "class data @{:#x}", x),
+            ErrorContext::CodeItem(x) => write!(f, "code item @{:#x}", x),
+            ErrorContext::DebugInfo(x) => write!(f, "debug info @{:#x}", x),
+            ErrorContext::Annotations(x) => write!(f, "annotations @{:#x}", x),
+            ErrorContext::StaticValues(x) => write!(f, "static values @{:#x}", x),
+            ErrorContext::Insn { offset, name } => write!(f, "insn {} @+{:#x}", name, offset),
+        }
+    }
+}
+
+impl Error {
+    /// Attaches the item that was being read when this error occurred.
+    pub fn context(self, context: ErrorContext) -> Error {
+        match self {
+            Error::WithContext {
+                context: mut chain,
+                source,
+            } => {
+                // nested accessors may report the same item
+                if chain.first() != Some(&context) {
+                    chain.insert(0, context);
+                }
+                Error::WithContext {
+                    context: chain,
+                    source,
+                }
+            }
+            error => Error::WithContext {
+                context: vec![context],
+                source: Box::new(error),
+            },
+        }
+    }
+
+    /// Returns all items that were being read when this error occurred,
+    /// outermost first.
+    pub fn context_chain(&self) -> &[ErrorContext] {
+        match self {
+            Error::WithContext { context, .. } => context,
+            _ => &[],
+        }
+    }
+
+    /// Returns the error without its context.
+    pub fn root_cause(&self) -> &Error {
+        match self {
+            Error::WithContext { source, .. } => source,
+            error => error,
+        }
+    }
+}
+
+/// Attaches an [ErrorContext] to the error of a result.
+pub trait ResultExt<T> {
+    fn context(self, context: ErrorContext) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for result::Result<T, E> {
+    fn context(self, context: ErrorContext) -> Result<T> {
+        self.map_err(|e| e.into().context(context))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::IO(e) => write!(f, "I/O error: {}", e),
+            Error::Parse(e) => write!(f, "parse error: {}", e),
+            Error::Custom(e) => write!(f, "{}", e),
+            Error::Validation(e) => write!(f, "{}: {}", e.identifier, e.description),
+            Error::InvalidData(e) => write!(f, "invalid data: {}", e),
+            Error::MalformedString(e) => write!(f, "malformed string: {}", e),
+            Error::InvalidOffset(x) => write!(f, "invalid offset {:#x}", x),
+            Error::InvalidIndex(x) => write!(f, "invalid index {}", x),
+            Error::MalformedDescriptor(x) => write!(f, "malformed descriptor {:?}", x),
+            Error::MethodNotFound(x) => write!(f, "method {} not found", x),
+            Error::FieldNotFound(x) => write!(f, "field {} not found", x),
+            Error::ParameterNotFound(x) => write!(f, "parameter {} not found", x),
+            Error::WithContext { context, source } => {
+                for (i, item) in context.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " → ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, ": {}", source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Self {
         Error::IO(e)
@@ -43,6 +226,12 @@ impl From<ConstraintError> for Error {
     }
 }
 
+impl From<StringError> for Error {
+    fn from(e: StringError) -> Self {
+        Error::MalformedString(e)
+    }
+}
+
 impl From<std::fmt::Error> for Error {
     fn from(e: std::fmt::Error) -> Self {
         Error::InvalidData(e.to_string())
@@ -1,15 +1,20 @@
 use crate::dalvik::dex::{
-    AnnotationItem, AnnotationSetItem, AnnotationVisibility, DexType, EncodedAnnotation,
+    AnnotationItem, AnnotationSetItem, AnnotationSetRefList, AnnotationVisibility,
+    AnnotationsDirectoryItem, DexType, EncodedAnnotation, UInt,
 };
 use crate::dalvik::error::Result;
 
 use super::{Dex, DexValue, IDexRef};
-use binrw::{io, BinRead};
+use binrw::BinRead;
 use std::collections::HashMap;
 use std::io::{Read, Seek};
 use std::rc::Rc;
 
-#[derive(Debug)]
+/// Descriptor of the system annotation that stores the default values of
+/// an annotation class.
+pub const ANNOTATION_DEFAULT: &str = "Ldalvik/annotation/AnnotationDefault;";
+
+#[derive(Debug, Clone)]
 pub struct DexAnnotation {
     /// The referenced annotation type displayed as a shared reference
     /// to the [DexType].
@@ -86,7 +91,7 @@ impl DexAnnotation {
             .list
             .iter()
             .try_for_each(|x| {
-                dex.fd.seek(io::SeekFrom::Start(x.annotation_off as u64))?;
+                dex.seeks(x.annotation_off as u64)?;
                 target.push(DexAnnotation::read(dex)?);
                 Ok(())
             })
@@ -95,4 +100,131 @@ impl DexAnnotation {
     pub fn get(&self, name: &String) -> Option<&DexValue> {
         self.values.get(name)
     }
+
+    /// Iterates over all elements of this annotation, followed by all
+    /// elements of `defaults` that were omitted at the use site.
+    ///
+    /// The defaults of an annotation class are returned by
+    /// [DexClassDef::get_annotation_defaults](super::DexClassDef::get_annotation_defaults).
+    pub fn values_with_defaults<'a>(
+        &'a self,
+        defaults: &'a HashMap<Rc<String>, DexValue>,
+    ) -> impl Iterator<Item = (&'a Rc<String>, &'a DexValue)> {
+        self.values.iter().chain(
+            defaults
+                .iter()
+                .filter(|(name, _)| !self.values.contains_key(*name)),
+        )
+    }
+}
+
+/// Annotations of a single class definition that are decoded on demand.
+///
+/// Loading a class decodes the annotations of all of its members. This
+/// accessor only reads the `annotations_directory_item` and decodes the
+/// annotation sets of the members that are looked up:
+///
+/// ```ignore
+/// if let Some(annotations) = dex.get_class_annotations(class_def_idx)? {
+///     for annotation in annotations.annotations_for_method(method_idx, &mut dex)? {
+///         println!("{}", annotation.type_);
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ClassAnnotationsAccessor {
+    directory: AnnotationsDirectoryItem,
+}
+
+impl ClassAnnotationsAccessor {
+    /// Reads the `annotations_directory_item` at the given offset.
+    pub fn read<R>(dex: &mut Dex<'_, R>, offset: UInt) -> Result<Self>
+    where
+        R: Read + Seek,
+    {
+        dex.seeks(offset as u64)?;
+        Ok(ClassAnnotationsAccessor {
+            directory: AnnotationsDirectoryItem::read(dex.fd)?,
+        })
+    }
+
+    /// Returns the raw directory item.
+    pub fn directory(&self) -> &AnnotationsDirectoryItem {
+        &self.directory
+    }
+
+    /// Decodes the annotations made directly on the class.
+    pub fn class_annotations<R>(&self, dex: &mut Dex<'_, R>) -> Result<Vec<DexAnnotation>>
+    where
+        R: Read + Seek,
+    {
+        read_set_at(dex, self.directory.class_annotations_off)
+    }
+
+    /// Decodes the annotations of the field at the given index into the
+    /// `field_ids` list. Fields without annotations return an empty list.
+    pub fn annotations_for_field<R>(
+        &self,
+        field_idx: UInt,
+        dex: &mut Dex<'_, R>,
+    ) -> Result<Vec<DexAnnotation>>
+    where
+        R: Read + Seek,
+    {
+        let offset = self.directory.field_annotations_off(field_idx);
+        read_set_at(dex, offset.unwrap_or(0))
+    }
+
+    /// Decodes the annotations of the method at the given index into the
+    /// `method_ids` list.
+    pub fn annotations_for_method<R>(
+        &self,
+        method_idx: UInt,
+        dex: &mut Dex<'_, R>,
+    ) -> Result<Vec<DexAnnotation>>
+    where
+        R: Read + Seek,
+    {
+        let offset = self.directory.method_annotations_off(method_idx);
+        read_set_at(dex, offset.unwrap_or(0))
+    }
+
+    /// Decodes the annotations of all parameters of the given method, one
+    /// list per parameter. Methods without parameter annotations return an
+    /// empty list.
+    pub fn annotations_for_parameters<R>(
+        &self,
+        method_idx: UInt,
+        dex: &mut Dex<'_, R>,
+    ) -> Result<Vec<Vec<DexAnnotation>>>
+    where
+        R: Read + Seek,
+    {
+        let Some(offset) = self.directory.parameter_annotations_off(method_idx) else {
+            return Ok(Vec::new());
+        };
+        if offset == 0 {
+            return Ok(Vec::new());
+        }
+        dex.seeks(offset as u64)?;
+        let set_ref_list = AnnotationSetRefList::read(dex.fd)?;
+        set_ref_list
+            .list
+            .iter()
+            .map(|x| read_set_at(dex, x.annotations_off))
+            .collect()
+    }
+}
+
+/// Decodes the annotation set at the given offset, `0` denotes an empty
+/// set.
+fn read_set_at<R>(dex: &mut Dex<'_, R>, offset: UInt) -> Result<Vec<DexAnnotation>>
+where
+    R: Read + Seek,
+{
+    if offset == 0 {
+        return Ok(Vec::new());
+    }
+    dex.seeks(offset as u64)?;
+    DexAnnotation::read_set(dex)
 }
@@ -0,0 +1,102 @@
+//! Shared cache of decoded strings
+//!
+//! Every [Dex] keeps the strings it decoded, but that cache is lost with
+//! the file and can't be shared: a tool that opens the same file again, or
+//! one file per thread, decodes the same descriptors over and over. A
+//! [StringCache] is a bounded, thread-safe cache that can be attached to any
+//! number of files:
+//!
+//! ```ignore
+//! let cache = StringCache::new(100_000);
+//! let mut dex = Dex::read(&mut reader, true)?.with_cache(cache.clone())?;
+//! // decoded once, later lookups of other files with the same contents
+//! // are served from the cache
+//! let name = dex.get_string(12)?;
+//! ```
+//!
+//! Strings are identified by a digest of the contents of their file and
+//! their index, so one cache can be shared by all files of a multidex
+//! application. The digest is computed when the cache is attached, the
+//! signature stored in the header is not trusted.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use crate::dalvik::dex::UByte;
+
+type Key = ([UByte; 20], u32);
+
+/// A bounded cache of decoded strings, see the [module docs](self).
+///
+/// Clones share the same entries. When the cache is full, the string that
+/// was inserted first is evicted.
+#[derive(Debug, Clone)]
+pub struct StringCache {
+    inner: Arc<Mutex<Entries>>,
+}
+
+#[derive(Debug)]
+struct Entries {
+    capacity: usize,
+    strings: HashMap<Key, Arc<str>>,
+    order: VecDeque<Key>,
+}
+
+impl StringCache {
+    /// Creates a cache that holds at most `capacity` strings.
+    pub fn new(capacity: usize) -> StringCache {
+        StringCache {
+            inner: Arc::new(Mutex::new(Entries {
+                capacity,
+                strings: HashMap::new(),
+                order: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Returns the string at the given index of the file with the given
+    /// digest, if it is cached.
+    pub fn get(&self, digest: &[UByte; 20], index: u32) -> Option<Arc<str>> {
+        self.lock().strings.get(&(*digest, index)).cloned()
+    }
+
+    /// Stores a decoded string, evicting the oldest entry if the cache is
+    /// full.
+    pub fn insert(&self, digest: &[UByte; 20], index: u32, value: &str) {
+        let mut entries = self.lock();
+        if entries.capacity == 0 || entries.strings.contains_key(&(*digest, index)) {
+            return;
+        }
+        if entries.strings.len() >= entries.capacity
+            && let Some(oldest) = entries.order.pop_front()
+        {
+            entries.strings.remove(&oldest);
+        }
+        entries.strings.insert((*digest, index), value.into());
+        entries.order.push_back((*digest, index));
+    }
+
+    /// Returns the number of cached strings.
+    pub fn len(&self) -> usize {
+        self.lock().strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes all cached strings.
+    pub fn clear(&self) {
+        let mut entries = self.lock();
+        entries.strings.clear();
+        entries.order.clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries> {
+        // a panic while holding the lock can't leave the maps inconsistent
+        // in a way that matters for a cache
+        self.inner.lock().unwrap_or_else(|x| x.into_inner())
+    }
+}
@@ -0,0 +1,56 @@
+use std::rc::Rc;
+
+use crate::dalvik::{
+    dex::{EncodedArray, MethodHandleItem},
+    error::{Error, Result},
+};
+
+use super::{method::DexPrototype, DexValue, IDexRef};
+
+/// A decoded call site of an `invoke-custom` instruction.
+///
+/// The `call_site_item` referenced by a [CallSiteIdItem](crate::dalvik::dex::CallSiteIdItem)
+/// is an encoded array whose first three values are fixed: the bootstrap
+/// method, the name of the method to link and its type. All remaining
+/// values are passed to the bootstrap method as extra arguments.
+#[derive(Debug, Clone)]
+pub struct DexCallSite {
+    /// the method handle of the bootstrap linker method
+    pub bootstrap: Rc<MethodHandleItem>,
+
+    /// name of the method to link, e.g. `apply` for a lambda
+    pub name: Rc<String>,
+
+    /// type of the method to link
+    pub method_type: Rc<DexPrototype>,
+
+    /// additional arguments passed to the bootstrap method
+    pub arguments: Vec<DexValue>,
+}
+
+impl DexCallSite {
+    /// Resolves the values of an encoded `call_site_item` and checks the
+    /// types of its first three values.
+    pub fn from_array(array: &EncodedArray, dex: IDexRef<'_>) -> Result<Self> {
+        let mut values = Vec::with_capacity(array.values.len());
+        for value in &array.values {
+            values.push(DexValue::from(value, dex)?);
+        }
+        let mut values = values.into_iter();
+        match (values.next(), values.next(), values.next()) {
+            (
+                Some(DexValue::MethodHandle(bootstrap)),
+                Some(DexValue::String(name)),
+                Some(DexValue::MethodType(method_type)),
+            ) => Ok(DexCallSite {
+                bootstrap,
+                name,
+                method_type,
+                arguments: values.collect(),
+            }),
+            _ => Err(Error::InvalidData(
+                "call site must start with a method handle, a name and a method type".to_string(),
+            )),
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Raw view of `class_data_item` structures
+//!
+//! [DexClassDef](super::DexClassDef) resolves the members of a class into
+//! fields and methods keyed by their absolute index. Patching tools also
+//! need the encoding itself: the stored index deltas, the raw access flags,
+//! code offsets and where the item and each of its entries are located in
+//! the file. The [ClassDataAccessor] returned by [Dex::get_class_data]
+//! exposes exactly that, without resolving any reference.
+//!
+//! The entries are stored as ULEB128 values that may be longer than
+//! necessary, so their offsets are taken from the file instead of being
+//! computed from the decoded values. Together with the ordinal of an entry,
+//! they allow correlating the hidden API flags of a class with its members
+//! and patching single values in place.
+//!
+//! [Dex::find_method] and [Dex::find_field] look up a single member by name
+//! instead, e.g. `onCreate`, and stop decoding at the first match.
+
+use std::{
+    io::{Read, Seek},
+    ops::Range,
+};
+
+use binrw::BinRead;
+
+use crate::dalvik::{
+    dex::{ClassDataItem, CodeItem, EncodedField, EncodedMethod, ULeb128},
+    error::{ErrorContext, Result, ResultExt},
+};
+
+use super::{Dex, IDex};
+
+/// A field entry of a `class_data_item`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawField {
+    /// Absolute index into the `field_ids` list.
+    pub field_idx: u32,
+
+    /// The index as stored, i.e. the difference to the previous entry.
+    pub field_idx_diff: u32,
+
+    pub access_flags: u32,
+
+    /// Offset of the entry, i.e. of its `field_idx_diff`, from the start of
+    /// the file.
+    pub offset: u64,
+
+    /// Position of the entry within its list of static or instance fields.
+    pub ordinal: u32,
+}
+
+/// A method entry of a `class_data_item`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawMethod {
+    /// Absolute index into the `method_ids` list.
+    pub method_idx: u32,
+
+    /// The index as stored, i.e. the difference to the previous entry.
+    pub method_idx_diff: u32,
+
+    pub access_flags: u32,
+
+    /// Offset of the code item, `0` for abstract and native methods.
+    pub code_off: u32,
+
+    /// Offset of the entry, i.e. of its `method_idx_diff`, from the start
+    /// of the file.
+    pub offset: u64,
+
+    /// Position of the entry within its list of direct or virtual methods.
+    pub ordinal: u32,
+}
+
+impl RawMethod {
+    /// Reads the code item of this method, or returns `None` for abstract
+    /// and native methods, which have no code.
+    pub fn code_item<R: Read + Seek>(&self, dex: &mut Dex<'_, R>) -> Result<Option<CodeItem>> {
+        match self.code_off {
+            0 => Ok(None),
+            offset => dex.get_code_item(offset).map(Some),
+        }
+    }
+}
+
+/// The decoded `class_data_item` of a class definition together with its
+/// location in the file.
+#[derive(Debug)]
+pub struct ClassDataAccessor {
+    /// Byte range of the item within the file.
+    pub range: Range<u64>,
+
+    pub item: ClassDataItem,
+
+    /// Offsets of all entries from the start of the file, in the order they
+    /// are encoded: static fields, instance fields, direct methods and
+    /// virtual methods. The position of a member in this list is also the
+    /// position of its flags in the hidden API data of the class.
+    pub entries: Vec<u64>,
+}
+
+impl ClassDataAccessor {
+    pub fn static_fields_size(&self) -> u32 {
+        self.item.static_fields.len() as u32
+    }
+
+    pub fn instance_fields_size(&self) -> u32 {
+        self.item.instance_fields.len() as u32
+    }
+
+    pub fn direct_methods_size(&self) -> u32 {
+        self.item.direct_methods.len() as u32
+    }
+
+    pub fn virtual_methods_size(&self) -> u32 {
+        self.item.virtual_methods.len() as u32
+    }
+
+    /// Returns the static fields in their encoded order.
+    ///
+    /// Absolute indices are accumulated with wrapping arithmetic, so a
+    /// malformed item yields wrong indices instead of an error.
+    pub fn static_fields(&self) -> impl Iterator<Item = RawField> + '_ {
+        raw_fields(&self.item.static_fields, self.entries_of(0))
+    }
+
+    /// Returns the instance fields in their encoded order.
+    pub fn instance_fields(&self) -> impl Iterator<Item = RawField> + '_ {
+        raw_fields(&self.item.instance_fields, self.entries_of(1))
+    }
+
+    /// Returns the direct methods in their encoded order.
+    pub fn direct_methods(&self) -> impl Iterator<Item = RawMethod> + '_ {
+        raw_methods(&self.item.direct_methods, self.entries_of(2))
+    }
+
+    /// Returns the virtual methods in their encoded order.
+    pub fn virtual_methods(&self) -> impl Iterator<Item = RawMethod> + '_ {
+        raw_methods(&self.item.virtual_methods, self.entries_of(3))
+    }
+
+    /// Returns the offsets of the entries of the list at the given position
+    /// in the encoded order.
+    fn entries_of(&self, list: usize) -> &[u64] {
+        let sizes = [
+            self.static_fields_size(),
+            self.instance_fields_size(),
+            self.direct_methods_size(),
+            self.virtual_methods_size(),
+        ];
+        let start: u32 = sizes[..list].iter().sum();
+        let end = (start + sizes[list]) as usize;
+        self.entries.get(start as usize..end).unwrap_or_default()
+    }
+}
+
+fn raw_fields<'a>(
+    fields: &'a [EncodedField],
+    entries: &'a [u64],
+) -> impl Iterator<Item = RawField> + 'a {
+    fields
+        .iter()
+        .zip(entries)
+        .enumerate()
+        .scan(0u32, |index, (ordinal, (field, offset))| {
+            *index = index.wrapping_add(field.field_idx_diff.0);
+            Some(RawField {
+                field_idx: *index,
+                field_idx_diff: field.field_idx_diff.0,
+                access_flags: field.access_flags.0,
+                offset: *offset,
+                ordinal: ordinal as u32,
+            })
+        })
+}
+
+fn raw_methods<'a>(
+    methods: &'a [EncodedMethod],
+    entries: &'a [u64],
+) -> impl Iterator<Item = RawMethod> + 'a {
+    methods
+        .iter()
+        .zip(entries)
+        .enumerate()
+        .scan(0u32, |index, (ordinal, (method, offset))| {
+            *index = index.wrapping_add(method.method_idx_diff.0);
+            Some(RawMethod {
+                method_idx: *index,
+                method_idx_diff: method.method_idx_diff.0,
+                access_flags: method.access_flags.0,
+                code_off: method.code_off.0,
+                offset: *offset,
+                ordinal: ordinal as u32,
+            })
+        })
+}
+
+impl<R: Read + Seek> Dex<'_, R> {
+    /// Reads the `class_data_item` of the class definition at the given
+    /// index, or returns `None` if the class has no members.
+    pub fn get_class_data(&mut self, index: u32) -> Result<Option<ClassDataAccessor>> {
+        let offset = self.get_class_def_item(index)?.class_data_off as u64;
+        if offset == 0 {
+            return Ok(None);
+        }
+        self.seeks(offset)
+            .context(ErrorContext::ClassData(offset))?;
+        let item = ClassDataItem::read(self.fd).context(ErrorContext::ClassData(offset))?;
+        let end = self.fd.stream_position()?;
+
+        // the item is walked a second time to locate its entries
+        let mut position = offset;
+        let sizes: [u32; 4] = self
+            .read_ulebs(&mut position)
+            .context(ErrorContext::ClassData(offset))?;
+        let mut entries = Vec::with_capacity(sizes.iter().sum::<u32>() as usize);
+        for (list, size) in sizes.into_iter().enumerate() {
+            // fields consist of two values, methods of three
+            let values = if list < 2 { 2 } else { 3 };
+            for _ in 0..size {
+                entries.push(position);
+                for _ in 0..values {
+                    self.read_ulebs::<1>(&mut position)
+                        .context(ErrorContext::ClassData(offset))?;
+                }
+            }
+        }
+        Ok(Some(ClassDataAccessor {
+            range: offset..end,
+            item,
+            entries,
+        }))
+    }
+
+    /// Finds a field of the class definition at the given index by its
+    /// name.
+    ///
+    /// Fields are decoded one at a time and the search stops at the first
+    /// match, so neither the whole `class_data_item` nor the names of the
+    /// remaining fields are read.
+    pub fn find_field(&mut self, index: u32, name: &str) -> Result<Option<RawField>> {
+        let offset = self.get_class_def_item(index)?.class_data_off as u64;
+        if offset == 0 {
+            return Ok(None);
+        }
+        let mut position = offset;
+        let [static_fields, instance_fields, _, _] = self
+            .read_ulebs(&mut position)
+            .context(ErrorContext::ClassData(offset))?;
+
+        for size in [static_fields, instance_fields] {
+            let mut field_idx = 0u32;
+            for ordinal in 0..size {
+                let entry = position;
+                let [field_idx_diff, access_flags] = self
+                    .read_ulebs(&mut position)
+                    .context(ErrorContext::ClassData(offset))?;
+                field_idx = field_idx.wrapping_add(field_idx_diff);
+                let name_idx = self.get_field(field_idx)?.name_idx;
+                if *self.get_string(name_idx)? == name {
+                    return Ok(Some(RawField {
+                        field_idx,
+                        field_idx_diff,
+                        access_flags,
+                        offset: entry,
+                        ordinal,
+                    }));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Finds a method of the class definition at the given index by its
+    /// name and either its shorty, e.g. `VL`, or its full descriptor, e.g.
+    /// `(Landroid/os/Bundle;)V`.
+    ///
+    /// Like [Dex::find_field], the search stops at the first match and only
+    /// resolves the prototype of methods whose name matches.
+    pub fn find_method(
+        &mut self,
+        index: u32,
+        name: &str,
+        signature: &str,
+    ) -> Result<Option<RawMethod>> {
+        let offset = self.get_class_def_item(index)?.class_data_off as u64;
+        if offset == 0 {
+            return Ok(None);
+        }
+        let mut position = offset;
+        let [static_fields, instance_fields, direct_methods, virtual_methods] = self
+            .read_ulebs(&mut position)
+            .context(ErrorContext::ClassData(offset))?;
+        for _ in 0..static_fields + instance_fields {
+            self.read_ulebs::<2>(&mut position)
+                .context(ErrorContext::ClassData(offset))?;
+        }
+
+        for size in [direct_methods, virtual_methods] {
+            let mut method_idx = 0u32;
+            for ordinal in 0..size {
+                let entry = position;
+                let [method_idx_diff, access_flags, code_off] = self
+                    .read_ulebs(&mut position)
+                    .context(ErrorContext::ClassData(offset))?;
+                method_idx = method_idx.wrapping_add(method_idx_diff);
+                let method = self.get_method(method_idx)?;
+                if *self.get_string(method.name_idx)? != name {
+                    continue;
+                }
+                let proto = self.get_proto(method.proto_idx as u32)?;
+                let matches = if signature.starts_with('(') {
+                    proto.to_string() == signature
+                } else {
+                    *proto.shorty == signature
+                };
+                if matches {
+                    return Ok(Some(RawMethod {
+                        method_idx,
+                        method_idx_diff,
+                        access_flags,
+                        code_off,
+                        offset: entry,
+                        ordinal,
+                    }));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads `N` consecutive ULEB128 values at the given position and
+    /// advances it. Seeking again before every read allows resolving
+    /// references in between.
+    fn read_ulebs<const N: usize>(&mut self, position: &mut u64) -> Result<[u32; N]> {
+        self.seeks(*position)?;
+        let mut values = [0; N];
+        for value in &mut values {
+            *value = ULeb128::read(self.fd)?.0;
+        }
+        *position = self.fd.stream_position()?;
+        Ok(values)
+    }
+}
@@ -1,19 +1,23 @@
 use crate::dalvik::{
     dex::*,
-    error::{Error, Result},
+    error::{Error, ErrorContext, Result, ResultExt},
 };
 
 use binrw::BinRead;
 use byteorder::{LittleEndian, ReadBytesExt};
 use std::{
-    collections::{btree_map::Values, BTreeMap},
+    collections::{btree_map::Values, BTreeMap, HashMap},
     fmt::Debug,
     io::{Read, Seek},
     rc::Rc,
 };
 
 use super::{
-    annotation::DexAnnotation, field::DexField, lazy_file::Dex, method::*, DexValue, IDex,
+    annotation::{DexAnnotation, ANNOTATION_DEFAULT},
+    field::DexField,
+    lazy_file::Dex,
+    method::*,
+    DexValue, IDex,
 };
 
 #[derive(Debug)]
@@ -76,19 +80,29 @@ impl DexClassDef {
 
         class_def.process_definition(&class_def_item, dex)?;
         if class_def_item.class_data_off != 0 {
-            dex.seeks(class_def_item.class_data_off as u64)?;
-            let class_data = ClassDataItem::read(dex.fd)?;
+            let offset = class_def_item.class_data_off as u64;
+            dex.seeks(offset).context(ErrorContext::ClassData(offset))?;
+            let class_data =
+                ClassDataItem::read(dex.fd).context(ErrorContext::ClassData(offset))?;
 
             // process fields and methods
             class_def.process_fields(&class_data, dex)?;
             class_def.process_methods(&class_data, dex)?;
 
             // lastly, identify possible static values
-            class_def.process_init_values(&class_def_item, &class_data, dex)?;
+            class_def
+                .process_init_values(&class_def_item, &class_data, dex)
+                .context(ErrorContext::StaticValues(
+                    class_def_item.static_values_off as u64,
+                ))?;
         }
 
         // annotations are parsed regardless of class_data_off
-        class_def.process_annotations(&class_def_item, dex)?;
+        class_def
+            .process_annotations(&class_def_item, dex)
+            .context(ErrorContext::Annotations(
+                class_def_item.annotations_off as u64,
+            ))?;
         Ok(class_def)
     }
 
@@ -122,11 +136,8 @@ impl DexClassDef {
                                             │ data: u8[]             │
                                             └────────────────────────┘
              */
-            dex.seeks(def.interfaces_off as u64)?;
-            let types = TypeList::read(dex.fd)?;
-            for type_item in &types.list {
-                self.interfaces
-                    .push(dex.get_type(type_item.type_idx as u32)?);
+            for type_idx in dex.get_type_list(def.interfaces_off)? {
+                self.interfaces.push(dex.get_type(type_idx)?);
             }
         }
 
@@ -140,14 +151,22 @@ impl DexClassDef {
     where
         R: Read + Seek,
     {
-        // TODO: explain
+        // indices are stored as differences to the previous member of the
+        // same list, starting from zero for each list
         macro_rules! _process {
             ($attr:ident) => {
                 let mut i = 0;
                 for encoded_field in &data.$attr {
                     let field = DexField::build(dex, encoded_field, i)?;
-                    i += encoded_field.field_idx_diff.0;
-                    self.$attr.insert(field.identity, field);
+                    i = field.identity;
+                    // a difference of zero is only valid for the first member
+                    if self.$attr.insert(field.identity, field).is_some() {
+                        return Err(Error::InvalidData(format!(
+                            "duplicate field index {} in {}",
+                            i,
+                            stringify!($attr)
+                        )));
+                    }
                 }
             };
         }
@@ -160,14 +179,22 @@ impl DexClassDef {
     where
         R: Read + Seek,
     {
-        // TODO: explain
+        // indices are stored as differences to the previous member of the
+        // same list, starting from zero for each list
         macro_rules! _process {
             ($attr:ident) => {
                 let mut i = 0;
                 for encoded_method in &data.$attr {
                     let method = DexMethod::build(dex, encoded_method, i)?;
-                    i += encoded_method.method_idx_diff.0;
-                    self.$attr.insert(method.identity, method);
+                    i = method.identity;
+                    // a difference of zero is only valid for the first member
+                    if self.$attr.insert(method.identity, method).is_some() {
+                        return Err(Error::InvalidData(format!(
+                            "duplicate method index {} in {}",
+                            i,
+                            stringify!($attr)
+                        )));
+                    }
                 }
             };
         }
@@ -230,29 +257,29 @@ impl DexClassDef {
         );
         iter_annotations!(field_annotations, field_idx, get_field_mut, FieldNotFound);
 
-        // parameters are handled differently:
-        let mut param_idx = 0;
-        let mut method_idx = 0;
+        // parameters are handled differently: each method refers to a list
+        // storing one annotation set per parameter
         for param_annotation in &directory_item.parameter_annotations {
-            // if the method index differs from the previous one, the parameter index
-            // needs to be reset
-            if method_idx != param_annotation.method_idx {
-                method_idx = param_annotation.method_idx;
-                param_idx = 0;
-            }
+            let method_idx = param_annotation.method_idx;
+            dex.seeks(param_annotation.annotations_off as u64)?;
+            let set_ref_list = AnnotationSetRefList::read(dex.fd)?;
 
             let method = self.get_method_mut(method_idx);
             if method.is_none() {
                 return Err(Error::MethodNotFound(method_idx as usize));
             }
-
-            let parameter = method.unwrap().parameters.get_mut(param_idx as usize);
-            if parameter.is_none() {
-                return Err(Error::ParameterNotFound(param_idx as usize));
+            let parameters = &mut method.unwrap().parameters;
+            for (param_idx, set_ref) in set_ref_list.list.iter().enumerate() {
+                if set_ref.annotations_off == 0 {
+                    continue;
+                }
+                let parameter = parameters.get_mut(param_idx);
+                if parameter.is_none() {
+                    return Err(Error::ParameterNotFound(param_idx));
+                }
+                dex.seeks(set_ref.annotations_off as u64)?;
+                parameter.unwrap().read_annotations(dex)?;
             }
-
-            dex.seeks(param_annotation.annotations_off as u64)?;
-            parameter.unwrap().read_annotations(dex)?;
         }
 
         Ok(())
@@ -277,14 +304,18 @@ impl DexClassDef {
             return Err(Error::InvalidData("Too many static values".to_string()));
         }
 
-        let mut diff = 0;
-        for (i, value) in data.values.iter().enumerate() {
-            let field_item = &class_data.static_fields[i];
-            let idx = field_item.field_idx_diff.0 + diff;
-            let field = self.static_fields.get_mut(&idx).unwrap();
+        let mut diff: u32 = 0;
+        for (value, field_item) in data.values.iter().zip(&class_data.static_fields) {
+            let idx = diff
+                .checked_add(field_item.field_idx_diff.0)
+                .ok_or_else(|| Error::InvalidData("Field index overflow".to_string()))?;
+            let field = match self.static_fields.get_mut(&idx) {
+                Some(field) => field,
+                None => return Err(Error::FieldNotFound(idx as usize)),
+            };
             field.init_value = Some(DexValue::from(value, dex)?);
 
-            diff += field_item.field_idx_diff.0;
+            diff = idx;
         }
 
         Ok(())
@@ -293,13 +324,10 @@ impl DexClassDef {
 
 macro_rules! _at {
     ($name:ident, $attr:ident, $type:ty) => {
+        /// Returns the member at the given position within its section,
+        /// counted in the order of the class data item.
         pub fn $name(&self, index: u32) -> Option<&$type> {
-            if index >= self.$attr.len() as u32 {
-                return None;
-            }
-
-            let pos = if index == 0 { 0 } else { index - 1 } as usize;
-            self.$attr.values().skip(pos).next()
+            self.$attr.values().nth(index as usize)
         }
     };
 
@@ -321,28 +349,61 @@ impl DexClassDef {
     _at!(get_static_field, static_fields, DexField);
     _at!(get_instance_field, instance_fields, DexField);
 
-    pub fn get_direct_methods(&self) -> Values<u32, DexMethod> {
+    /// Returns the default element values of an annotation class, which are
+    /// stored in its [ANNOTATION_DEFAULT] annotation. `None` is returned if
+    /// the class declares no defaults.
+    pub fn get_annotation_defaults(&self) -> Option<&HashMap<Rc<String>, DexValue>> {
+        let annotation = self
+            .annotations
+            .iter()
+            .find(|x| x.type_.descriptor == ANNOTATION_DEFAULT)?;
+        match annotation.values.iter().find(|(name, _)| name.as_str() == "value") {
+            Some((_, DexValue::Annotation(defaults))) => Some(&defaults.values),
+            _ => None,
+        }
+    }
+
+    /// Searches both method lists for the method with the given identity
+    /// (index into the `method_ids` list).
+    pub fn get_method(&self, identity: u32) -> Option<&DexMethod> {
+        self.direct_methods
+            .get(&identity)
+            .or_else(|| self.virtual_methods.get(&identity))
+    }
+
+    /// Iterates over all direct methods (static, private and constructors)
+    /// sorted by their index into the `method_ids` list, which is the order
+    /// of the class data item.
+    pub fn get_direct_methods(&self) -> Values<'_, u32, DexMethod> {
         self.direct_methods.values()
     }
 
-    pub fn get_virtual_methods(&self) -> Values<u32, DexMethod> {
+    /// Iterates over all virtual methods sorted by their index into the
+    /// `method_ids` list.
+    pub fn get_virtual_methods(&self) -> Values<'_, u32, DexMethod> {
         self.virtual_methods.values()
     }
 
+    /// Iterates over all direct methods followed by all virtual methods.
     pub fn get_methods(&self) -> impl Iterator<Item = (&u32, &DexMethod)> {
         self.direct_methods
             .iter()
             .chain(self.virtual_methods.iter())
     }
 
-    pub fn get_static_fields(&self) -> Values<u32, DexField> {
+    /// Iterates over all static fields sorted by their index into the
+    /// `field_ids` list.
+    pub fn get_static_fields(&self) -> Values<'_, u32, DexField> {
         self.static_fields.values()
     }
 
-    pub fn get_instance_fields(&self) -> Values<u32, DexField> {
+    /// Iterates over all instance fields sorted by their index into the
+    /// `field_ids` list.
+    pub fn get_instance_fields(&self) -> Values<'_, u32, DexField> {
         self.instance_fields.values()
     }
 
+    /// Iterates over all static fields followed by all instance fields.
     pub fn get_fields(&self) -> impl Iterator<Item = (&u32, &DexField)> {
         self.static_fields
             .iter()
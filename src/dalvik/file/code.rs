@@ -0,0 +1,51 @@
+//! Code items of a file
+//!
+//! Methods refer to their code by offset, so [Dex::get_code_item] needs
+//! the `code_off` of a method, or [RawMethod::code_item](super::RawMethod::code_item)
+//! reads it for a method of a [class data item](super::ClassDataAccessor). [Dex::code_items] instead walks the code
+//! item section of the map list, which also yields code that no method
+//! refers to, e.g. bytecode hidden by an obfuscator.
+//!
+//! ```ignore
+//! for (offset, code) in dex.code_items()? {
+//!     println!("{:#x}: {} code units", offset, code.insns_size);
+//! }
+//! ```
+
+use binrw::BinRead;
+use std::io::{Read, Seek};
+
+use crate::dalvik::{
+    dex::{CodeItem, MapListItemType},
+    error::{ErrorContext, Result, ResultExt},
+};
+
+use super::Dex;
+
+impl<R: Read + Seek> Dex<'_, R> {
+    /// Reads the code item at the given file offset.
+    pub fn get_code_item(&mut self, offset: u32) -> Result<CodeItem> {
+        let context = ErrorContext::CodeItem(offset as u64);
+        self.seeks(offset as u64).context(context.clone())?;
+        CodeItem::read(self.fd).context(context)
+    }
+
+    /// Reads all code items listed by the map list together with their
+    /// offsets, in the order they are stored. Returns an empty list if the
+    /// file has no code.
+    pub fn code_items(&mut self) -> Result<Vec<(u32, CodeItem)>> {
+        let (size, mut offset) = match self.get_map_list().get(MapListItemType::CodeItem) {
+            Some(item) => (item.size, item.offset as u64),
+            None => return Ok(Vec::new()),
+        };
+        let mut items = Vec::new();
+        for _ in 0..size {
+            // code items are aligned to four bytes
+            offset = offset.next_multiple_of(4);
+            let code = self.get_code_item(offset as u32)?;
+            items.push((offset as u32, code));
+            offset = self.fd.stream_position()?;
+        }
+        Ok(items)
+    }
+}
@@ -1,6 +1,6 @@
 use std::{
     collections::HashMap,
-    io::{Read, Seek},
+    io::{Read, Seek, SeekFrom},
     rc::Rc,
 };
 
@@ -28,8 +28,9 @@ pub struct DebugInfo {
     // A table that maps each instruction offset to its line number.
     pub lines: HashMap<UInt, ULong>,
 
-    // A list of all defined local variables mapped to their instruction offsets.
-    pub local_variables: HashMap<UInt, LocalVariable>,
+    // All local variables including the named parameters, sorted by the
+    // address (in code units) at which they become live.
+    pub local_variables: Vec<LocalVariable>,
 
     // The name of the source file containing the code.
     pub source_file: Option<Rc<String>>,
@@ -47,20 +48,31 @@ impl DebugInfoItem {
     {
         let mut file: Option<Rc<String>> = None;
         let mut lines: HashMap<UInt, ULong> = HashMap::new();
-        let mut local_variables: HashMap<UInt, LocalVariable> = HashMap::new();
+        let mut local_variables: Vec<LocalVariable> = Vec::new();
         let mut buf = [0u8; 1];
 
-        let mut pc = 0;
+        let mut pc: UInt = 0;
         let mut line: i64 = self.line_start.0 as i64;
         let mut regs: Vec<Option<LocalVariable>> = Vec::with_capacity(code.registers_size as usize);
         for _ in 0..code.registers_size {
             regs.push(None);
         }
 
+        macro_rules! resolve {
+            // resolving an item that is not cached yet moves the reader, which
+            // has to continue with the next opcode afterwards
+            ($func:ident, $index:expr) => {{
+                let position = dex.fd.stream_position()?;
+                let item = dex.$func($index)?;
+                dex.fd.seek(SeekFrom::Start(position))?;
+                item
+            }};
+        }
+
         macro_rules! ulebp1_unwrap {
             ($idx:ident, $func:ident, $ty:ident) => {
                 if let ULeb128p1::Pos(pos) = $idx {
-                    Some(dex.$func(pos as $ty)?)
+                    Some(resolve!($func, pos as $ty))
                 } else {
                     None
                 }
@@ -74,8 +86,9 @@ impl DebugInfoItem {
             .zip(self.parameter_names.iter())
             .rev()
         {
-            i -= match &(p_type.descriptor)[..] {
-                "D" | "J" => 2,
+            // arrays of wide types take a single register
+            i -= match p_type.shorty() {
+                'D' | 'J' => 2,
                 _ => 1,
             };
             if i < 0 {
@@ -84,7 +97,7 @@ impl DebugInfoItem {
             regs[i as usize] = Some(LocalVariable {
                 register_num: i as u32,
                 name: if let ULeb128p1::Pos(pos) = idx {
-                    Some(dex.get_string(*pos)?)
+                    Some(resolve!(get_string, *pos))
                 } else {
                     None
                 },
@@ -96,25 +109,50 @@ impl DebugInfoItem {
             });
         }
 
+        macro_rules! reg {
+            // returns the slot of the given register or fails if the register
+            // is not within the frame of this method.
+            ($reg:expr) => {
+                match regs.get_mut($reg as usize) {
+                    Some(slot) => slot,
+                    None => {
+                        return Err(Error::InvalidData(format!(
+                            "Invalid register in debug info: v{}",
+                            $reg
+                        )))
+                    }
+                }
+            };
+        }
+
         macro_rules! start_var {
             // starts a new local variable by removing any previously defined local variable
             // from the target register.
             ($reg:ident, $var:ident) => {
-                if let Some(mut prev_var) = regs[$reg.0 as usize].take() {
+                let slot = reg!($reg.0);
+                if let Some(mut prev_var) = slot.take() {
                     prev_var.end_pc = pc;
-                    local_variables.insert(prev_var.start_pc, prev_var);
+                    local_variables.push(prev_var);
                 }
-                regs[$reg.0 as usize] = Some($var);
+                *slot = Some($var);
             };
         }
 
         macro_rules! end_var {
             ($reg:ident) => {
-                if let Some(mut var) = regs[$reg.0 as usize].take() {
+                if let Some(mut var) = reg!($reg.0).take() {
                     var.end_pc = pc;
-                    local_variables.insert(var.start_pc, var);
+                    local_variables.push(var);
+                }
+            };
+        }
+
+        macro_rules! advance_pc {
+            ($diff:expr) => {
+                pc = match pc.checked_add($diff) {
+                    Some(pc) => pc,
+                    None => return Err(Error::InvalidData("Debug info address overflow".to_string())),
                 }
-                regs[$reg.0 as usize] = None;
             };
         }
 
@@ -131,7 +169,7 @@ impl DebugInfoItem {
                 // advances the address register without emitting a positions entry
                 DebugInfoItem::DBG_ADVANCE_PC => {
                     let addr_diff = ULeb128::read(dex.fd)?;
-                    pc += addr_diff.0;
+                    advance_pc!(addr_diff.0);
                 }
 
                 // advances the line register without emitting a positions entry
@@ -189,11 +227,20 @@ impl DebugInfoItem {
                 // the same as the last local that was live in the specified register.
                 DebugInfoItem::DBG_RESTART_LOCAL => {
                     let register_num = ULeb128::read(dex.fd)?;
-                    if let Some(var) = regs[register_num.0 as usize].take() {
+                    // the register usually lost its variable through
+                    // DBG_END_LOCAL, so the last one stored is restarted
+                    let last = match reg!(register_num.0) {
+                        Some(var) => Some(&*var),
+                        None => local_variables
+                            .iter()
+                            .rev()
+                            .find(|x| x.register_num == register_num.0),
+                    };
+                    if let Some(var) = last {
                         let new_var = LocalVariable {
                             register_num: var.register_num,
                             name: var.name.clone(),
-                            type_: var.type_,
+                            type_: var.type_.clone(),
                             signature: var.signature.clone(),
                             start_pc: pc,
                             end_pc: 0,
@@ -212,7 +259,7 @@ impl DebugInfoItem {
                 // file name, instead of the default name specified in code_item
                 DebugInfoItem::DBG_SET_FILE => {
                     if let ULeb128p1::Pos(file_idx) = ULeb128p1::read(dex.fd)? {
-                        file = Some(dex.get_string(file_idx)?);
+                        file = Some(resolve!(get_string, file_idx));
                     }
                 }
 
@@ -223,17 +270,20 @@ impl DebugInfoItem {
                     line += DebugInfoItem::DBG_LINE_BASE as i64
                         + ((adjusted_opcode % DebugInfoItem::DBG_LINE_RANGE) as i64);
 
-                    pc += (adjusted_opcode / DebugInfoItem::DBG_LINE_RANGE) as u32;
+                    advance_pc!((adjusted_opcode / DebugInfoItem::DBG_LINE_RANGE) as u32);
                     lines.insert(pc, line as ULong);
                 }
             }
         }
 
+        // variables that are still live at the end of the sequence are
+        // live until the end of the code
         for mut var in regs.into_iter().flatten() {
-            var.end_pc = pc;
-            local_variables.insert(var.start_pc, var);
+            var.end_pc = code.insns_size;
+            local_variables.push(var);
         }
 
+        local_variables.sort_by_key(|x| (x.start_pc, x.register_num));
         Ok(DebugInfo {
             lines,
             local_variables,
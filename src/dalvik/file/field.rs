@@ -1,5 +1,5 @@
 use crate::dalvik::dex::{AccessFlags, DexType, EncodedField};
-use crate::dalvik::error::Result;
+use crate::dalvik::error::{Error, ErrorContext, Result, ResultExt};
 
 use super::annotation::DexAnnotation;
 use super::{DexValue, IDexRef};
@@ -34,7 +34,13 @@ pub struct DexField {
 
 impl DexField {
     pub fn build(dex: IDexRef<'_>, field: &EncodedField, prev_diff: u32) -> Result<DexField> {
-        let index = field.field_idx_diff.0 + prev_diff;
+        let index = prev_diff
+            .checked_add(field.field_idx_diff.0)
+            .ok_or_else(|| Error::InvalidData("Field index overflow".to_string()))?;
+        DexField::read(dex, field, index).context(ErrorContext::Field(index))
+    }
+
+    fn read(dex: IDexRef<'_>, field: &EncodedField, index: u32) -> Result<DexField> {
         let field_item = dex.get_field(index)?;
         Ok(DexField {
             type_: dex.get_type(field_item.type_idx as u32)?,
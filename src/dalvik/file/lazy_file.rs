@@ -1,20 +1,39 @@
 use crate::dalvik::{
     dex::*,
-    error::{Error, Result},
+    error::{ConstraintError, Error, ErrorContext, Result, ResultExt},
 };
 
 use binrw::BinRead;
+use openssl::sha::Sha1;
 use std::{
-    collections::{btree_map::Entry::Vacant, BTreeMap},
+    collections::BTreeMap,
     fmt::Debug,
     io::{self, Read, Seek},
+    ops::Range,
     rc::Rc,
+    result,
 };
 
-use super::{method::DexPrototype, DexClassDef, IDex};
+use super::{
+    annotation::ClassAnnotationsAccessor, call_site::DexCallSite, method::DexPrototype,
+    DexClassDef, IDex, StringCache, TypeListAccessor,
+};
 
 type Pool<T> = BTreeMap<u32, Rc<T>>;
 
+/// How [Dex::read_with_mode] deals with a header or map list that doesn't
+/// describe the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Fails on the first violation, like [Dex::read_untrusted].
+    Strict,
+
+    /// Records violations in [Dex::warnings] and parses as much as
+    /// possible. An unreadable map list is replaced by an empty one, so
+    /// that only the sections declared by the header are available.
+    Lenient,
+}
+
 #[derive(Debug)]
 pub struct Dex<'a, R: Read + Seek> {
     pub(super) fd: &'a mut R,
@@ -25,6 +44,12 @@ pub struct Dex<'a, R: Read + Seek> {
     /// section, although that is not recommended.
     pub header: HeaderItem,
 
+    /// ## Map List
+    /// The map list describing the contents of the whole file. It is parsed
+    /// together with the header and used to locate sections that aren't
+    /// referenced by the header (e.g. method handles and call sites).
+    map_list: MapList,
+
     // Internal fields to provide fast access to method handles and call sites
     method_handles_size: u32,
     method_handles_off: u32,
@@ -55,23 +80,49 @@ pub struct Dex<'a, R: Read + Seek> {
     // Internal fields to provide fast access to method handles and call sites
     methods_handles: Pool<MethodHandleItem>,
     call_sites: Pool<CallSiteIdItem>,
+    call_site_values: Pool<DexCallSite>,
     classes: Pool<DexClassDef>,
+
+    /// Bounds of the data section, only set for files opened through
+    /// [Dex::read_untrusted]. Every seek into the data section is checked
+    /// against this range.
+    data_range: Option<Range<u64>>,
+
+    /// Shared cache that is consulted before strings are decoded, together
+    /// with the digest identifying this file in it, see [Dex::with_cache].
+    string_cache: Option<(StringCache, [UByte; 20])>,
+
+    /// Violations that were tolerated by [ParseMode::Lenient].
+    warnings: Vec<ConstraintError>,
 }
 
 macro_rules! check_index {
     ($index: expr, item_size=$item_size: expr, $size: expr, $offset: expr) => {{
-        let _offset = $offset + $index * $item_size;
-        if _offset >= ($size * $item_size) + $offset {
+        if $index >= $size {
             return Err(Error::InvalidIndex($index as usize));
         }
-        _offset
+        // computed in 64 bits so that hostile sizes and offsets can't overflow
+        $offset as u64 + $index as u64 * $item_size
+    }};
+}
+
+macro_rules! seek_data {
+    ($self:ident, $offset:expr) => {{
+        let offset = $offset as u64;
+        if let Some(range) = &$self.data_range
+            && !range.contains(&offset)
+        {
+            return Err(Error::InvalidOffset(offset as isize));
+        }
+        $self.fd.seek(io::SeekFrom::Start(offset))?;
     }};
 }
 
 impl<'b, R: Read + Seek> Dex<'b, R> {
     // fundamental seek methods
     pub(super) fn seeks(&mut self, offset: u64) -> Result<()> {
-        self.fd.seek(io::SeekFrom::Start(offset))?;
+        // all absolute seeks outside of this file point into the data section
+        seek_data!(self, offset);
         Ok(())
     }
 
@@ -100,13 +151,18 @@ impl<'b, R: Read + Seek> Dex<'b, R> {
         // list first.
         reader.seek(io::SeekFrom::Start(header.map_off as u64))?;
         let map_list = MapList::read(&mut reader)?;
-        Ok(Dex {
+        Ok(Dex::read_sections(reader, header, map_list))
+    }
+
+    fn read_sections(reader: &mut R, header: HeaderItem, map_list: MapList) -> Dex<'_, R> {
+        Dex {
             fd: reader,
             header,
             method_handles_off: map_list.item_offset(MapListItemType::MethodHandleItem) as u32,
             call_sites_off: map_list.item_offset(MapListItemType::CallSiteIdItem) as u32,
             method_handles_size: map_list.item_size(MapListItemType::MethodHandleItem) as u32,
             call_sites_size: map_list.item_size(MapListItemType::CallSiteIdItem) as u32,
+            map_list,
             // parsing is done lazily: types, strings, and protos will be
             // populated on demand
             types: BTreeMap::new(),
@@ -116,8 +172,181 @@ impl<'b, R: Read + Seek> Dex<'b, R> {
             methods: BTreeMap::new(),
             methods_handles: BTreeMap::new(),
             call_sites: BTreeMap::new(),
+            call_site_values: BTreeMap::new(),
             classes: BTreeMap::new(),
-        })
+            data_range: None,
+            string_cache: None,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Parses a DEX file that can't be trusted, e.g. a sample taken from a
+    /// malware corpus.
+    ///
+    /// Checksum and signature are not verified, since tampered files are
+    /// expected here. Instead, the header and the map list must describe
+    /// sections that lie within the file, and all offsets that point into
+    /// the data section are checked against its bounds before they are
+    /// followed.
+    pub fn read_untrusted(reader: &mut R) -> Result<Dex<'_, R>>
+    where
+        R: Read + Seek,
+    {
+        Dex::read_with_mode(reader, ParseMode::Strict)
+    }
+
+    /// Parses a DEX file that can't be trusted with the checks of
+    /// [Dex::read_untrusted]. In [ParseMode::Lenient], violations are
+    /// collected in [Dex::warnings] instead. Offsets are only checked
+    /// against the data section if the header describes it correctly.
+    pub fn read_with_mode(mut reader: &mut R, mode: ParseMode) -> Result<Dex<'_, R>>
+    where
+        R: Read + Seek,
+    {
+        let file_len = reader.seek(io::SeekFrom::End(0))?;
+        reader.seek(io::SeekFrom::Start(0))?;
+
+        let mut warnings = Vec::new();
+        let mut check = |result: result::Result<(), ConstraintError>| match (result, mode) {
+            (Err(e), ParseMode::Strict) => Err(Error::Validation(e)),
+            (Err(e), ParseMode::Lenient) => {
+                warnings.push(e);
+                Ok(false)
+            }
+            (Ok(()), _) => Ok(true),
+        };
+
+        let header = HeaderItem::read(&mut reader)?;
+        let has_bounds = check(header.verify_bounds(file_len))?;
+
+        reader.seek(io::SeekFrom::Start(header.map_off as u64))?;
+        let map_list = match (MapList::read(&mut reader), mode) {
+            (Ok(x), _) => x,
+            (Err(e), ParseMode::Strict) => return Err(e.into()),
+            (Err(e), ParseMode::Lenient) => {
+                check(Err(ConstraintError {
+                    identifier: "map_list",
+                    description: format!("map list at {:#x} can't be read: {}", header.map_off, e),
+                }))?;
+                MapList::new(Vec::new())
+            }
+        };
+        check(map_list.validate(&header))?;
+        for item in map_list.items() {
+            if item.offset as u64 >= file_len {
+                check(Err(ConstraintError {
+                    identifier: "map_bounds",
+                    description: format!(
+                        "{:?} at {:#x} is outside of the file",
+                        item.item_type(),
+                        item.offset
+                    ),
+                }))?;
+            }
+        }
+
+        let data_off = header.data_off as u64;
+        let mut dex = Dex::read_sections(reader, header, map_list);
+        if has_bounds {
+            dex.data_range = Some(data_off..data_off + dex.header.data_size as u64);
+        }
+        dex.warnings = warnings;
+        Ok(dex)
+    }
+
+    /// Returns the violations that were tolerated when this file was
+    /// parsed with [ParseMode::Lenient].
+    pub fn warnings(&self) -> &[ConstraintError] {
+        &self.warnings
+    }
+
+    /// Attaches a shared [StringCache]. Strings that are not yet decoded by
+    /// this file are looked up in the cache first, and newly decoded strings
+    /// are added to it.
+    ///
+    /// Entries are identified by a SHA-1 digest of the whole stream that is
+    /// computed here instead of trusting the signature in the header, so
+    /// only files with the same contents share entries. Files opened
+    /// through [Dex::read_untrusted] only share entries among each other,
+    /// as their strings are checked against the bounds of the data section.
+    pub fn with_cache(mut self, cache: StringCache) -> Result<Self> {
+        self.attach_cache(cache)?;
+        Ok(self)
+    }
+
+    /// See [Dex::with_cache].
+    pub(super) fn attach_cache(&mut self, cache: StringCache) -> Result<()> {
+        let mut hasher = Sha1::new();
+        if let Some(range) = &self.data_range {
+            hasher.update(&range.start.to_le_bytes());
+            hasher.update(&range.end.to_le_bytes());
+        }
+        self.fd.seek(io::SeekFrom::Start(0))?;
+        let mut buf = [0; 0x2000];
+        loop {
+            let size = self.fd.read(&mut buf)?;
+            if size == 0 {
+                break;
+            }
+            hasher.update(&buf[..size]);
+        }
+        self.string_cache = Some((cache, hasher.finish()));
+        Ok(())
+    }
+
+    /// Returns the map list of this file.
+    pub fn get_map_list(&self) -> &MapList {
+        &self.map_list
+    }
+
+    /// Returns the length of the underlying stream, which differs from the
+    /// `file_size` of the header if data was appended or the file is
+    /// truncated.
+    pub fn stream_len(&mut self) -> Result<u64> {
+        Ok(self.fd.seek(io::SeekFrom::End(0))?)
+    }
+
+    /// Reads the bytes of the given range of the file, e.g. to copy an item
+    /// without parsing it.
+    pub fn read_raw(&mut self, range: Range<u64>) -> Result<Vec<UByte>> {
+        let size = range.end.saturating_sub(range.start) as usize;
+        let mut data = vec![0; size];
+        self.fd.seek(io::SeekFrom::Start(range.start))?;
+        self.fd.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Verifies checksum, signature and the global constraints of the
+    /// header, see [HeaderItem::verify].
+    pub fn verify_header(&mut self) -> result::Result<(), ConstraintError> {
+        self.header.verify(&mut *self.fd, 0)
+    }
+
+    /// Validates the map list against the constraints of the DEX format and
+    /// the sections described by the header.
+    pub fn validate_map_list(&self) -> result::Result<(), ConstraintError> {
+        self.map_list.validate(&self.header)
+    }
+
+    /// Validates the shorty of every prototype against its return and
+    /// parameter types, see [DexPrototype::validate_shorty].
+    ///
+    /// All prototypes are parsed and cached by this check.
+    pub fn validate_protos(&mut self) -> Result<()> {
+        for index in 0..self.header.proto_ids_size {
+            self.get_proto(index)?
+                .validate_shorty()
+                .context(ErrorContext::Proto(index))?;
+        }
+        Ok(())
+    }
+
+    /// Recomputes the SHA-1 signature over everything after the signature
+    /// field up to `file_size` and compares it to the one in the header.
+    pub fn verify_signature(&mut self) -> Result<bool> {
+        self.fd.seek(io::SeekFrom::Start(32))?;
+        let digest = HeaderItem::read_signature(&mut self.fd, self.header.file_size)?;
+        Ok(&digest == self.header.signature())
     }
 
     // pub fn string_at<'a>(&'a self, index: u32) -> Result<&'a String> {
@@ -153,15 +382,7 @@ impl<'b, R: Read + Seek> Dex<'b, R> {
     ///                                   └──────────────────────┘
     ///```
     fn parse_proto(&mut self, index: u32) -> Result<()> {
-        let offset = check_index!(
-            index,
-            item_size = 12,
-            self.header.proto_ids_size,
-            self.header.proto_ids_off
-        );
-        self.fd.seek(io::SeekFrom::Start(offset as u64))?;
-
-        let proto_item = ProtoIdItem::read(self.fd)?;
+        let proto_item = self.read_proto_item(index)?;
         let shorty = self.get_string(proto_item.shorty_idx)?;
         let return_type = self.get_type(proto_item.return_type_idx)?;
         let mut proto = DexPrototype {
@@ -170,23 +391,258 @@ impl<'b, R: Read + Seek> Dex<'b, R> {
             parameters: Vec::new(),
         };
 
-        if proto_item.parameters_off != 0 {
-            // type list only present if offset is != 0
-            self.fd
-                .seek(io::SeekFrom::Start(proto_item.parameters_off as u64))?;
-            let params = TypeList::read(self.fd)?;
-            for j in 0..params.size {
-                // the parameter item stores the type index of the parameter
-                let index = params.list[j as usize].type_idx;
-                let ty = self.get_type(index as u32)?;
-                proto.parameters.push(ty);
-            }
+        for type_idx in self.read_proto_params(&proto_item)? {
+            let ty = self.get_type(type_idx)?;
+            proto.parameters.push(ty);
         }
 
         self.protos.insert(index, Rc::new(proto));
         Ok(())
     }
 
+    fn read_proto_item(&mut self, index: u32) -> Result<ProtoIdItem> {
+        let offset = check_index!(
+            index,
+            item_size = 12,
+            self.header.proto_ids_size,
+            self.header.proto_ids_off
+        );
+        self.fd.seek(io::SeekFrom::Start(offset))?;
+        Ok(ProtoIdItem::read(self.fd)?)
+    }
+
+    fn read_proto_params(&mut self, proto_item: &ProtoIdItem) -> Result<Vec<u32>> {
+        if proto_item.parameters_off == 0 {
+            // type list only present if offset is != 0
+            return Ok(Vec::new());
+        }
+        // the parameter item stores the type index of the parameter
+        let params = self.get_type_list(proto_item.parameters_off)?;
+        Ok(params.into_iter().collect())
+    }
+
+    /// Reads the `type_list` at the given offset, where `0` denotes an
+    /// absent list.
+    ///
+    /// The offset must be aligned to four bytes and the whole list must lie
+    /// within the file, or within the data section for files opened
+    /// through [Dex::read_untrusted].
+    pub fn get_type_list(&mut self, offset: u32) -> Result<TypeListAccessor> {
+        if offset == 0 {
+            return Ok(TypeListAccessor::default());
+        }
+        if !offset.is_multiple_of(4) {
+            return Err(Error::InvalidOffset(offset as isize));
+        }
+        let limit = match &self.data_range {
+            Some(range) => range.end,
+            None => self.stream_len()?,
+        };
+        seek_data!(self, offset);
+        let size = UInt::read_le(self.fd)?;
+        if offset as u64 + 4 + size as u64 * 2 > limit {
+            return Err(Error::InvalidData(format!(
+                "type_list at {:#x} with {} entries exceeds the file",
+                offset, size
+            )));
+        }
+        let mut data = vec![0; size as usize * 2];
+        self.fd.read_exact(&mut data)?;
+        let types = data
+            .chunks_exact(2)
+            .map(|x| UShort::from_le_bytes([x[0], x[1]]))
+            .collect();
+        Ok(TypeListAccessor::new(offset, types))
+    }
+
+    /// Parses the class definition at the given index into an owned
+    /// [DexClassDef], including its fields with static values, its methods
+    /// with code and debug info, and all annotations.
+    ///
+    /// Unlike [IDex::get_class_def], the class is not cached, so the caller
+    /// owns the only copy and repeated calls parse the class again.
+    pub fn load_class(&mut self, index: u32) -> Result<DexClassDef> {
+        let offset = check_index!(
+            index,
+            item_size = 32,
+            self.header.class_defs_size,
+            self.header.class_defs_off
+        );
+
+        self.fd.seek(io::SeekFrom::Start(offset))?;
+        DexClassDef::new(self, index).context(ErrorContext::ClassDef(index))
+    }
+
+    /// Reads the raw class definition item at the given index without
+    /// resolving any of its references.
+    pub fn get_class_def_item(&mut self, index: u32) -> Result<ClassDefItem> {
+        let offset = check_index!(
+            index,
+            item_size = 32,
+            self.header.class_defs_size,
+            self.header.class_defs_off
+        );
+        self.fd.seek(io::SeekFrom::Start(offset))?;
+        Ok(ClassDefItem::read(self.fd)?)
+    }
+
+    /// Returns the index of the class definition that defines the type at
+    /// `type_idx`, or `None` if the class is not defined in this file.
+    ///
+    /// Only the raw class definition items are read, so no class is parsed.
+    pub fn find_class_def(&mut self, type_idx: u32) -> Result<Option<u32>> {
+        for index in 0..self.header.class_defs_size {
+            if self.get_class_def_item(index)?.class_idx == type_idx {
+                return Ok(Some(index));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Groups all class definition indices by the package of their class,
+    /// e.g. `com.example.payload`. Classes in the default package are
+    /// stored under an empty name.
+    ///
+    /// Only the descriptors of the classes are resolved.
+    pub fn classes_by_package(&mut self) -> Result<BTreeMap<String, Vec<u32>>> {
+        let mut packages: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+        for index in 0..self.header.class_defs_size {
+            let class_idx = self.get_class_def_item(index)?.class_idx;
+            let package = self
+                .get_type(class_idx)
+                .context(ErrorContext::ClassDef(index))?
+                .package()
+                .unwrap_or_default()
+                .replace('/', ".");
+            packages.entry(package).or_default().push(index);
+        }
+        Ok(packages)
+    }
+
+    /// Groups all class definition indices by the source file stored in
+    /// their class definition. Classes without a source file are not
+    /// included.
+    pub fn classes_by_source_file(&mut self) -> Result<BTreeMap<Rc<String>, Vec<u32>>> {
+        let mut files: BTreeMap<Rc<String>, Vec<u32>> = BTreeMap::new();
+        for index in 0..self.header.class_defs_size {
+            let source_file_idx = self.get_class_def_item(index)?.source_file_idx;
+            if source_file_idx == NO_INDEX {
+                continue;
+            }
+            let source_file = self
+                .get_string(source_file_idx)
+                .context(ErrorContext::ClassDef(index))?;
+            files.entry(source_file).or_default().push(index);
+        }
+        Ok(files)
+    }
+
+    /// Returns the indices of all methods whose prototype has the given
+    /// shorty, e.g. `VL` for `(Ljava/lang/String;)V`.
+    ///
+    /// Only the shorty strings of the prototypes are compared, no prototype
+    /// is resolved.
+    pub fn methods_with_shorty(&mut self, shorty: &str) -> Result<Vec<u32>> {
+        let protos = self.protos_with_shorty(shorty)?;
+        self.methods_with_protos(&protos)
+    }
+
+    /// Returns the indices of all methods with the given return and
+    /// parameter types, e.g. `V` and `["Ljava/lang/String;"]`.
+    ///
+    /// The shorty of the signature is used to filter the prototypes first,
+    /// so only prototypes with a matching shorty are resolved and compared.
+    pub fn methods_matching(
+        &mut self,
+        return_desc: &str,
+        param_descs: &[&str],
+    ) -> Result<Vec<u32>> {
+        let shorty: String = std::iter::once(return_desc)
+            .chain(param_descs.iter().copied())
+            .map(|x| match x.chars().next() {
+                Some('L' | '[') | None => 'L',
+                Some(c) => c,
+            })
+            .collect();
+
+        let mut protos = Vec::new();
+        for index in self.protos_with_shorty(&shorty)? {
+            let proto = self.get_proto(index)?;
+            // the descriptor of a DexType omits the `[` of array types
+            let parameters = &proto.parameters;
+            if proto.return_type.is(return_desc)
+                && parameters.len() == param_descs.len()
+                && parameters.iter().zip(param_descs).all(|(x, y)| x.is(y))
+            {
+                protos.push(index);
+            }
+        }
+        self.methods_with_protos(&protos)
+    }
+
+    /// Returns the indices of all prototypes with the given shorty, in
+    /// ascending order.
+    fn protos_with_shorty(&mut self, shorty: &str) -> Result<Vec<u32>> {
+        let mut protos = Vec::new();
+        for index in 0..self.header.proto_ids_size {
+            let shorty_idx = self.read_proto_item(index)?.shorty_idx;
+            let matches = *self
+                .get_string(shorty_idx)
+                .context(ErrorContext::Proto(index))?
+                == shorty;
+            if matches {
+                protos.push(index);
+            }
+        }
+        Ok(protos)
+    }
+
+    /// Returns the indices of all methods with one of the given prototypes,
+    /// which must be sorted.
+    fn methods_with_protos(&mut self, protos: &[u32]) -> Result<Vec<u32>> {
+        let mut methods = Vec::new();
+        if protos.is_empty() {
+            return Ok(methods);
+        }
+        for index in 0..self.header.method_ids_size {
+            let proto_idx = self.get_method(index)?.proto_idx as u32;
+            if protos.binary_search(&proto_idx).is_ok() {
+                methods.push(index);
+            }
+        }
+        Ok(methods)
+    }
+
+    /// Returns the type indices of all parameters of the prototype at the
+    /// given index, without resolving the types themselves.
+    pub fn get_proto_params(&mut self, index: u32) -> Result<impl Iterator<Item = u32>> {
+        let proto_item = self.read_proto_item(index)?;
+        Ok(self.read_proto_params(&proto_item)?.into_iter())
+    }
+
+    /// Returns the type indices of the interfaces implemented by the class
+    /// definition at the given index, without resolving the types
+    /// themselves.
+    pub fn get_class_interfaces(&mut self, index: u32) -> Result<Vec<u32>> {
+        let class_def_item = self.get_class_def_item(index)?;
+        let types = self.get_type_list(class_def_item.interfaces_off)?;
+        Ok(types.into_iter().collect())
+    }
+
+    /// Returns an accessor for the annotations of the class definition at
+    /// the given index, or `None` if the class has no annotations. The
+    /// class itself is not parsed.
+    pub fn get_class_annotations(
+        &mut self,
+        index: u32,
+    ) -> Result<Option<ClassAnnotationsAccessor>> {
+        let class_def_item = self.get_class_def_item(index)?;
+        if class_def_item.annotations_off == 0 {
+            return Ok(None);
+        }
+        ClassAnnotationsAccessor::read(self, class_def_item.annotations_off).map(Some)
+    }
+
     /* Format:
     ┌─────────────────────┐
     │ TypeIdItem          │
@@ -209,7 +665,7 @@ impl<'b, R: Read + Seek> Dex<'b, R> {
             self.header.type_ids_size,
             self.header.type_ids_off
         );
-        self.fd.seek(io::SeekFrom::Start(offset as u64))?;
+        self.fd.seek(io::SeekFrom::Start(offset))?;
         let type_item = TypeIdItem::read(self.fd)?;
 
         let string = self.get_string(type_item.descriptor_idx)?;
@@ -219,6 +675,39 @@ impl<'b, R: Read + Seek> Dex<'b, R> {
         Ok(())
     }
 
+    fn parse_string(&mut self, index: u32) -> Result<()> {
+        let offset = check_index!(
+            index,
+            item_size = 4,
+            self.header.string_ids_size,
+            self.header.string_ids_off
+        );
+
+        if let Some((cache, digest)) = &self.string_cache
+            && let Some(string) = cache.get(digest, index)
+        {
+            self.strings.insert(index, Rc::new(string.to_string()));
+            return Ok(());
+        }
+
+        self.fd.seek(io::SeekFrom::Start(offset))?;
+        let string_item = StringIdItem::read(self.fd)?;
+        seek_data!(self, string_item.offset);
+        let string = match &self.data_range {
+            // the string must not run into whatever follows the data section
+            Some(range) => {
+                let limit = range.end - string_item.offset as u64;
+                mutf8::read(&mut Read::by_ref(self.fd).take(limit))?
+            }
+            None => mutf8::read(self.fd)?,
+        };
+        if let Some((cache, digest)) = &self.string_cache {
+            cache.insert(digest, index, &string);
+        }
+        self.strings.insert(index, Rc::new(string));
+        Ok(())
+    }
+
     fn parse_field(&mut self, index: u32) -> Result<()> {
         let offset = check_index!(
             index,
@@ -226,7 +715,7 @@ impl<'b, R: Read + Seek> Dex<'b, R> {
             self.header.field_ids_size,
             self.header.field_ids_off
         );
-        self.fd.seek(io::SeekFrom::Start(offset as u64))?;
+        self.fd.seek(io::SeekFrom::Start(offset))?;
         let field_item = FieldIdItem::read(self.fd)?;
 
         self.fields.insert(index, Rc::new(field_item));
@@ -240,7 +729,7 @@ impl<'b, R: Read + Seek> Dex<'b, R> {
             self.header.method_ids_size,
             self.header.method_ids_off
         );
-        self.fd.seek(io::SeekFrom::Start(offset as u64))?;
+        self.fd.seek(io::SeekFrom::Start(offset))?;
         let method_item = MethodIdItem::read(self.fd)?;
 
         self.methods.insert(index, Rc::new(method_item));
@@ -254,7 +743,7 @@ impl<'b, R: Read + Seek> Dex<'b, R> {
             self.method_handles_size,
             self.method_handles_off
         );
-        self.fd.seek(io::SeekFrom::Start(offset as u64))?;
+        self.fd.seek(io::SeekFrom::Start(offset))?;
         let method_handle = MethodHandleItem::read(self.fd)?;
         self.methods_handles.insert(index, Rc::new(method_handle));
         Ok(())
@@ -267,14 +756,27 @@ impl<'b, R: Read + Seek> Dex<'b, R> {
             self.call_sites_size,
             self.call_sites_off
         );
-        self.fd.seek(io::SeekFrom::Start(offset as u64))?;
+        self.fd.seek(io::SeekFrom::Start(offset))?;
         let call_site = CallSiteIdItem::read(self.fd)?;
         self.call_sites.insert(index, Rc::new(call_site));
         Ok(())
     }
+
+    fn parse_call_site_values(&mut self, index: u32) -> Result<()> {
+        let call_site = self.get_call_site(index)?;
+        self.seeks(call_site.call_side_off as u64)?;
+        let array = EncodedArray::read(self.fd)?;
+        let call_site = DexCallSite::from_array(&array, self)?;
+        self.call_site_values.insert(index, Rc::new(call_site));
+        Ok(())
+    }
 }
 
 impl<'a, R: Read + Seek> IDex for Dex<'a, R> {
+    fn get_header(&self) -> &HeaderItem {
+        &self.header
+    }
+
     /* Format:
     ┌──────────────┐            ┌────────────────────┐
     │ StringIdItem │            │ StringDataItem     │
@@ -285,19 +787,9 @@ impl<'a, R: Read + Seek> IDex for Dex<'a, R> {
     fn get_string(&mut self, index: u32) -> Result<Rc<String>> {
         // first tries to find the string in the string table
         // if not found, tries to read it from the file
-        if let Vacant(e) = self.strings.entry(index) {
-            let offset = check_index!(
-                index,
-                item_size = 4,
-                self.header.string_ids_size,
-                self.header.string_ids_off
-            );
-
-            self.fd.seek(io::SeekFrom::Start(offset as u64))?;
-            let string_item = StringIdItem::read(self.fd)?;
-            self.fd
-                .seek(io::SeekFrom::Start(string_item.offset as u64))?;
-            e.insert(Rc::new(mutf8::read(self.fd)?));
+        if !self.strings.contains_key(&index) {
+            self.parse_string(index)
+                .context(ErrorContext::String(index))?;
         }
         Ok(self.strings[&index].clone())
     }
@@ -310,7 +802,8 @@ impl<'a, R: Read + Seek> IDex for Dex<'a, R> {
         // same as before: first tries to find the proto in the proto table
         // if not found, tries to read it from the file
         if !self.protos.contains_key(&index) {
-            self.parse_proto(index)?;
+            self.parse_proto(index)
+                .context(ErrorContext::Proto(index))?;
         }
         Ok(self.protos[&index].clone())
     }
@@ -319,7 +812,7 @@ impl<'a, R: Read + Seek> IDex for Dex<'a, R> {
         // same as before: first tries to find the type in the type table
         // if not found, tries to read it from the file
         if !self.types.contains_key(&index) {
-            self.parse_type(index)?;
+            self.parse_type(index).context(ErrorContext::Type(index))?;
         }
 
         Ok(self.types[&index].clone())
@@ -336,14 +829,16 @@ impl<'a, R: Read + Seek> IDex for Dex<'a, R> {
 
     fn get_field(&mut self, index: u32) -> Result<Rc<FieldIdItem>> {
         if !self.fields.contains_key(&index) {
-            self.parse_field(index)?;
+            self.parse_field(index)
+                .context(ErrorContext::Field(index))?;
         }
         Ok(self.fields[&index].clone())
     }
 
     fn get_method(&mut self, index: u32) -> Result<Rc<MethodIdItem>> {
         if !self.methods.contains_key(&index) {
-            self.parse_method(index)?;
+            self.parse_method(index)
+                .context(ErrorContext::Method(index))?;
         }
         Ok(self.methods[&index].clone())
     }
@@ -355,21 +850,24 @@ impl<'a, R: Read + Seek> IDex for Dex<'a, R> {
         Ok(self.call_sites[&index].clone())
     }
 
+    fn resolve_call_site(&mut self, index: u32) -> Result<Rc<DexCallSite>> {
+        if !self.call_site_values.contains_key(&index) {
+            self.parse_call_site_values(index)?;
+        }
+        Ok(self.call_site_values[&index].clone())
+    }
+
     fn get_class_def(&mut self, index: u32) -> Result<Rc<DexClassDef>> {
         // Note: we can't use btree_map::Entry::Vacant here as it would
         // introduce a second mutable borrow of 'self'
         if !self.classes.contains_key(&index) {
-            let offset = check_index!(
-                index,
-                item_size = 32,
-                self.header.class_defs_size,
-                self.header.class_defs_off
-            );
-
-            self.fd.seek(io::SeekFrom::Start(offset as u64))?;
-            let class_def = DexClassDef::new(self, index)?;
+            let class_def = self.load_class(index)?;
             self.classes.insert(index, Rc::new(class_def));
         }
         Ok(self.classes[&index].clone())
     }
+
+    fn get_class_def_item(&mut self, index: u32) -> Result<ClassDefItem> {
+        Dex::get_class_def_item(self, index)
+    }
 }
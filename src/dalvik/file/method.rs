@@ -1,16 +1,21 @@
 use crate::dalvik::dex::{
-    AccessFlags, AnnotationSetRefList, CodeItem, DebugInfoItem, DexType, EncodedMethod, SLeb128, ULeb128, ULeb128p1
+    AccessFlags, CodeItem, DebugInfoItem, DexType, EncodedMethod, SLeb128, ULeb128, ULeb128p1
 };
-use crate::dalvik::error::Result;
+use crate::dalvik::error::{ConstraintError, Error, ErrorContext, Result, ResultExt};
 use crate::dalvik::insns::{self, Insn};
 
 use super::annotation::DexAnnotation;
 use super::{debug::DebugInfo, Dex, IDex, IDexRef};
 use binrw::BinRead;
+use std::fmt::Display;
 use std::io::{Read, Seek};
 use std::rc::Rc;
 
-#[derive(Debug)]
+/// A method prototype, i.e. the signature of a method without its name.
+///
+/// Its [Display] implementation produces the method descriptor used by the
+/// DEX format and smali, e.g. `(ILjava/lang/String;)V`.
+#[derive(Debug, PartialEq, Eq)]
 pub struct DexPrototype {
     /// The shorty of the prototype (short type descriptor)
     pub shorty: Rc<String>,
@@ -20,6 +25,50 @@ pub struct DexPrototype {
     pub parameters: Vec<Rc<DexType>>,
 }
 
+impl Display for DexPrototype {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "(")?;
+        for param in &self.parameters {
+            write!(f, "{}", param)?;
+        }
+        write!(f, "){}", self.return_type)
+    }
+}
+
+impl DexPrototype {
+    /// Builds the shorty descriptor from the return and parameter types,
+    /// e.g. `VLI` for `(Ljava/lang/String;I)V`.
+    pub fn expected_shorty(&self) -> String {
+        std::iter::once(&self.return_type)
+            .chain(&self.parameters)
+            .map(|x| x.shorty())
+            .collect()
+    }
+
+    /// Checks the stored shorty against the return and parameter types, as
+    /// ART does when verifying a file. Parameters must not be `void`.
+    pub fn validate_shorty(&self) -> std::result::Result<(), ConstraintError> {
+        if self.parameters.iter().any(|x| x.shorty() == 'V') {
+            return Err(ConstraintError {
+                identifier: "proto_void_parameter",
+                description: format!("prototype {} has a void parameter", self),
+            });
+        }
+
+        let expected = self.expected_shorty();
+        if *self.shorty != expected {
+            return Err(ConstraintError {
+                identifier: "proto_shorty",
+                description: format!(
+                    "shorty {:?} of {} does not match {:?}",
+                    self.shorty, self, expected
+                ),
+            });
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct DexParameter {
     /// The type of this parameter
@@ -41,19 +90,15 @@ pub struct DexParameter {
 }
 
 impl DexParameter {
+    /// Reads the annotations of this parameter.
+    ///
+    /// @**Note**: This function assumes that the reader points to the
+    ///            start of an [AnnotationSetItem](crate::dalvik::dex::AnnotationSetItem).
     pub fn read_annotations<R>(&mut self, dex: &mut Dex<'_, R>) -> Result<()>
     where
         R: Read + Seek,
     {
-        let set_ref_list = AnnotationSetRefList::read(dex.fd)?;
-        for set_ref in &set_ref_list.list {
-            if set_ref.annotations_off == 0 {
-                continue;
-            }
-            dex.seeks(set_ref.annotations_off as u64)?;
-            DexAnnotation::read_set_into(dex, &mut self.annotations)?;
-        }
-        Ok(())
+        DexAnnotation::read_set_into(dex, &mut self.annotations)
     }
 }
 
@@ -103,7 +148,16 @@ impl DexMethod {
         // In subsequent items, however, this value is the difference from the index
         // of the previous item, and to calculate the method_ids index the difference
         // must be incremented to the previous method_idx_diff values.
-        let index = prev_diff + encoded_method.method_idx_diff.0;
+        let index = prev_diff
+            .checked_add(encoded_method.method_idx_diff.0)
+            .ok_or_else(|| Error::InvalidData("Method index overflow".to_string()))?;
+        DexMethod::read(dex, encoded_method, index).context(ErrorContext::Method(index))
+    }
+
+    fn read<R>(dex: &mut Dex<'_, R>, encoded_method: &EncodedMethod, index: u32) -> Result<Self>
+    where
+        R: Read + Seek,
+    {
         let method_item = dex.get_method(index)?;
 
         let proto = dex.get_proto(method_item.proto_idx as u32)?;
@@ -124,17 +178,23 @@ impl DexMethod {
         let mut debug: Option<DebugInfo> = None;
         if encoded_method.code_off.0 != 0 {
             // parse code item but don't start parsing instructions just yet
-            dex.seeks(encoded_method.code_off.0 as u64)?;
-            let code_item = CodeItem::read(dex.fd)?;
+            let offset = encoded_method.code_off.0 as u64;
+            dex.seeks(offset).context(ErrorContext::CodeItem(offset))?;
+            let code_item = CodeItem::read(dex.fd).context(ErrorContext::CodeItem(offset))?;
 
             if code_item.debug_info_off != 0 {
                 // directly parse debug information
-                dex.seeks(code_item.debug_info_off as u64)?;
-                let debug_info = DebugInfoItem::read(dex.fd)?;
+                let offset = code_item.debug_info_off as u64;
+                let debug_info = DexMethod::read_debug_info(dex, offset)
+                    .context(ErrorContext::DebugInfo(offset))?;
+                // parse additional information, the state machine directly
+                // follows the item
+                debug = Some(
+                    debug_info
+                        .parse_debug_info(&code_item, dex, &proto)
+                        .context(ErrorContext::DebugInfo(offset))?,
+                );
                 DexMethod::apply_debug_info(&mut parameters, &debug_info, dex)?;
-
-                // parse additional information
-                debug = Some(debug_info.parse_debug_info(&code_item, dex, &proto)?);
             }
             code = Some(code_item);
         }
@@ -153,14 +213,23 @@ impl DexMethod {
         })
     }
 
+    fn read_debug_info<R>(dex: &mut Dex<'_, R>, offset: u64) -> Result<DebugInfoItem>
+    where
+        R: Read + Seek,
+    {
+        dex.seeks(offset)?;
+        Ok(DebugInfoItem::read(dex.fd)?)
+    }
+
     fn apply_debug_info(
         parameters: &mut [DexParameter],
         debug_info: &DebugInfoItem,
         dex: IDexRef<'_>,
     ) -> Result<()> {
-        for (i, param_name_idx) in debug_info.parameter_names.iter().enumerate() {
+        // the debug info may declare more names than the prototype has parameters
+        for (parameter, param_name_idx) in parameters.iter_mut().zip(&debug_info.parameter_names) {
             if let ULeb128p1::Pos(index) = param_name_idx {
-                parameters[i].name = Some(dex.get_string(*index)?);
+                parameter.name = Some(dex.get_string(*index)?);
             }
         }
         Ok(())
@@ -171,7 +240,7 @@ impl DexMethod {
 impl DexMethod {
     pub fn disasm(&self, dex: IDexRef<'_>) -> Result<Vec<Insn>> {
         if let Some(code) = &self.code {
-            Ok(insns::disasm(code, dex)?)
+            insns::disasm(code, dex).context(ErrorContext::Method(self.identity))
         } else {
             Ok(Vec::new())
         }
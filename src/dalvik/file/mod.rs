@@ -1,5 +1,8 @@
 use super::{
-    dex::{CallSiteIdItem, DexType, FieldIdItem, MethodHandleItem, MethodIdItem},
+    dex::{
+        CallSiteIdItem, ClassDefItem, DexType, FieldIdItem, HeaderItem, MethodHandleItem,
+        MethodIdItem,
+    },
     error::Result,
 };
 use std::rc::Rc;
@@ -7,13 +10,30 @@ use std::rc::Rc;
 pub mod value;
 pub use value::*;
 
+pub mod cache;
+pub use cache::*;
+
 pub mod class_def;
 pub use class_def::*;
 
+pub mod class_data;
+pub use class_data::*;
+
 pub mod lazy_file;
 pub use lazy_file::*;
 
+pub mod owned;
+pub use owned::*;
+
+pub mod ranges;
+pub use ranges::*;
+
+pub mod type_list;
+pub use type_list::*;
+
 pub mod annotation;
+pub mod call_site;
+pub mod code;
 pub mod debug;
 pub mod field;
 pub mod method;
@@ -21,6 +41,9 @@ pub mod method;
 // public interfaces that define behaviour of all classes
 
 pub trait IDex {
+    /// Returns the parsed header of the underlying DEX file, which stores
+    /// the sizes of all id sections.
+    fn get_header(&self) -> &HeaderItem;
     fn get_string(&mut self, index: u32) -> Result<Rc<String>>;
     fn get_proto(&mut self, index: u32) -> Result<Rc<method::DexPrototype>>;
     fn get_type(&mut self, index: u32) -> Result<Rc<DexType>>;
@@ -28,7 +51,13 @@ pub trait IDex {
     fn get_field(&mut self, index: u32) -> Result<Rc<FieldIdItem>>;
     fn get_method(&mut self, index: u32) -> Result<Rc<MethodIdItem>>;
     fn get_call_site(&mut self, index: u32) -> Result<Rc<CallSiteIdItem>>;
+    /// Decodes the call site at the given index into the `call_site_ids`
+    /// list, i.e. its bootstrap method, name, type and extra arguments.
+    fn resolve_call_site(&mut self, index: u32) -> Result<Rc<call_site::DexCallSite>>;
     fn get_class_def(&mut self, index: u32) -> Result<Rc<DexClassDef>>;
+    /// Reads the raw class definition item at the given index without
+    /// loading the class.
+    fn get_class_def_item(&mut self, index: u32) -> Result<ClassDefItem>;
 }
 
 pub type IDexRef<'a> = &'a mut dyn IDex;
@@ -0,0 +1,287 @@
+//! A [Dex] that owns its reader
+//!
+//! [Dex] borrows the reader it parses from, which makes it hard to store a
+//! parsed file in a struct next to its source. [OwnedDex] keeps both
+//! together:
+//!
+//! ```ignore
+//! let data = std::fs::read("classes.dex")?;
+//! let mut dex = OwnedDex::from_bytes(data, true)?;
+//! // or, without a file: OwnedDex::from_reader(response, true)?
+//! let class = dex.get_class_def(0)?;
+//! let class = dex.with_dex(|dex| dex.load_class(0))?;
+//! ```
+//!
+//! The file implements [IDex] itself, so it can be passed to everything
+//! that accepts an [IDexRef](super::IDexRef). Methods that are only defined
+//! on [Dex] are available through [OwnedDex::with_dex].
+//!
+//! A DEX file stored within a larger buffer, e.g. an uncompressed entry of
+//! an APK or a region of a memory dump, is opened with [OwnedDex::open_at].
+//! The buffer is not copied and all offsets of the parsed file are relative
+//! to the start of the region:
+//!
+//! ```ignore
+//! let dump: Arc<[u8]> = std::fs::read("heap.bin")?.into();
+//! let mut dex = OwnedDex::open_at(dump.clone(), 0x1f000, 0x4c8, false)?;
+//! ```
+//!
+//! Parsed files cache their items in [Rc]s and can't be sent to other
+//! threads. A [SharedDex] shares the buffer instead, and every thread
+//! parses its own view of it:
+//!
+//! ```ignore
+//! let shared = SharedDex::new(std::fs::read("classes.dex")?, ParseMode::Strict)?;
+//! std::thread::scope(|scope| {
+//!     for _ in 0..4 {
+//!         let shared = &shared;
+//!         scope.spawn(move || shared.view()?.get_class_def(0).map(|_| ()));
+//!     }
+//! });
+//! ```
+
+use std::{
+    io::{Cursor, Read, Seek},
+    mem::ManuallyDrop,
+    ptr::NonNull,
+    rc::Rc,
+    sync::Arc,
+};
+
+use crate::dalvik::{
+    dex::{
+        CallSiteIdItem, ClassDefItem, DexType, FieldIdItem, HeaderItem, MethodHandleItem,
+        MethodIdItem,
+    },
+    error::{Error, Result},
+};
+
+use super::{
+    call_site::DexCallSite, method::DexPrototype, Dex, DexClassDef, IDex, ParseMode, StringCache,
+};
+
+/// A parsed DEX file together with the reader it was parsed from.
+pub struct OwnedDex<R: Read + Seek + 'static> {
+    // borrows from `reader`, which is why it must be dropped first and must
+    // never be handed out with its 'static lifetime
+    dex: ManuallyDrop<Dex<'static, R>>,
+    reader: NonNull<R>,
+}
+
+impl<R: Read + Seek + 'static> OwnedDex<R> {
+    fn new<F>(reader: R, parse: F) -> Result<OwnedDex<R>>
+    where
+        F: FnOnce(&'static mut R) -> Result<Dex<'static, R>>,
+    {
+        let reader = NonNull::from(Box::leak(Box::new(reader)));
+        // SAFETY: the reader is only freed when the file is dropped, after
+        // the parsed file that borrows it.
+        match parse(unsafe { &mut *reader.as_ptr() }) {
+            Ok(dex) => Ok(OwnedDex {
+                dex: ManuallyDrop::new(dex),
+                reader,
+            }),
+            Err(e) => {
+                drop(unsafe { Box::from_raw(reader.as_ptr()) });
+                Err(e)
+            }
+        }
+    }
+
+    /// Takes ownership of the given reader and parses it, see [Dex::read].
+    pub fn read(reader: R, verify: bool) -> Result<OwnedDex<R>> {
+        OwnedDex::new(reader, |reader| Dex::read(reader, verify))
+    }
+
+    /// Takes ownership of the given reader and parses it as untrusted input,
+    /// see [Dex::read_untrusted].
+    pub fn read_untrusted(reader: R) -> Result<OwnedDex<R>> {
+        OwnedDex::new(reader, |reader| Dex::read_untrusted(reader))
+    }
+
+    /// Takes ownership of the given reader and parses it in the given mode,
+    /// see [Dex::read_with_mode].
+    pub fn read_with_mode(reader: R, mode: ParseMode) -> Result<OwnedDex<R>> {
+        OwnedDex::new(reader, |reader| Dex::read_with_mode(reader, mode))
+    }
+
+    /// Attaches a shared string cache, see [Dex::with_cache].
+    pub fn with_cache(mut self, cache: StringCache) -> Result<Self> {
+        self.dex.attach_cache(cache)?;
+        Ok(self)
+    }
+
+    /// Calls `f` with the underlying [Dex].
+    pub fn with_dex<T, F>(&mut self, f: F) -> T
+    where
+        F: for<'d> FnOnce(&mut Dex<'d, R>) -> T,
+    {
+        f(&mut self.dex)
+    }
+}
+
+impl<B: AsRef<[u8]> + 'static> OwnedDex<Cursor<B>> {
+    /// Parses a DEX file from any owned byte buffer, e.g. a `Vec<u8>`, a
+    /// shared `Arc<[u8]>` or a memory map.
+    pub fn from_bytes(data: B, verify: bool) -> Result<OwnedDex<Cursor<B>>> {
+        OwnedDex::read(Cursor::new(data), verify)
+    }
+}
+
+/// A region of a byte buffer, e.g. a DEX file within an APK or a memory
+/// dump. The region is checked to be within the buffer when it is created.
+#[derive(Debug, Clone)]
+pub struct Region<B: AsRef<[u8]>> {
+    container: B,
+    offset: usize,
+    len: usize,
+}
+
+impl<B: AsRef<[u8]>> Region<B> {
+    /// Selects `len` bytes starting at `offset` of the given buffer.
+    pub fn new(container: B, offset: usize, len: usize) -> Result<Region<B>> {
+        match offset.checked_add(len) {
+            Some(end) if end <= container.as_ref().len() => Ok(Region {
+                container,
+                offset,
+                len,
+            }),
+            _ => Err(Error::InvalidData(format!(
+                "region {:#x}..{:#x} exceeds the buffer of {:#x} bytes",
+                offset,
+                offset.saturating_add(len),
+                container.as_ref().len()
+            ))),
+        }
+    }
+
+    /// Returns the offset of this region within its buffer.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the whole buffer this region is part of.
+    pub fn container(&self) -> &B {
+        &self.container
+    }
+}
+
+impl<B: AsRef<[u8]>> AsRef<[u8]> for Region<B> {
+    fn as_ref(&self) -> &[u8] {
+        &self.container.as_ref()[self.offset..self.offset + self.len]
+    }
+}
+
+impl<B: AsRef<[u8]> + 'static> OwnedDex<Cursor<Region<B>>> {
+    /// Parses the DEX file stored in `len` bytes at `offset` of the given
+    /// buffer. Offsets within the file are relative to `offset`, so
+    /// references that leave the region are reported like those that leave
+    /// a standalone file.
+    pub fn open_at(
+        container: B,
+        offset: usize,
+        len: usize,
+        verify: bool,
+    ) -> Result<OwnedDex<Cursor<Region<B>>>> {
+        OwnedDex::from_bytes(Region::new(container, offset, len)?, verify)
+    }
+}
+
+/// A DEX file that can be shared between threads. Each thread parses its
+/// own [OwnedDex] from the shared buffer with [SharedDex::view].
+#[derive(Debug, Clone)]
+pub struct SharedDex {
+    data: Arc<[u8]>,
+    mode: ParseMode,
+}
+
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<SharedDex>();
+};
+
+impl SharedDex {
+    /// Parses the given buffer once in the given mode to check that it is
+    /// a DEX file, see [Dex::read_with_mode].
+    pub fn new(data: impl Into<Arc<[u8]>>, mode: ParseMode) -> Result<SharedDex> {
+        let data = data.into();
+        OwnedDex::read_with_mode(Cursor::new(data.clone()), mode)?;
+        Ok(SharedDex { data, mode })
+    }
+
+    /// Returns the shared buffer.
+    pub fn data(&self) -> &Arc<[u8]> {
+        &self.data
+    }
+
+    /// Parses a new view of the file for the calling thread. Views don't
+    /// share their caches, but the buffer is not copied.
+    pub fn view(&self) -> Result<OwnedDex<Cursor<Arc<[u8]>>>> {
+        OwnedDex::read_with_mode(Cursor::new(self.data.clone()), self.mode)
+    }
+}
+
+impl OwnedDex<Cursor<Vec<u8>>> {
+    /// Reads the whole stream into memory and parses it, e.g. for files
+    /// that are received over the network and can't be seeked.
+    pub fn from_reader<S: Read>(mut stream: S, verify: bool) -> Result<Self> {
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data)?;
+        OwnedDex::from_bytes(data, verify)
+    }
+}
+
+impl<R: Read + Seek + 'static> Drop for OwnedDex<R> {
+    fn drop(&mut self) {
+        unsafe {
+            ManuallyDrop::drop(&mut self.dex);
+            drop(Box::from_raw(self.reader.as_ptr()));
+        }
+    }
+}
+
+impl<R: Read + Seek + 'static> IDex for OwnedDex<R> {
+    fn get_header(&self) -> &HeaderItem {
+        &self.dex.header
+    }
+
+    fn get_string(&mut self, index: u32) -> Result<Rc<String>> {
+        self.dex.get_string(index)
+    }
+
+    fn get_proto(&mut self, index: u32) -> Result<Rc<DexPrototype>> {
+        self.dex.get_proto(index)
+    }
+
+    fn get_type(&mut self, index: u32) -> Result<Rc<DexType>> {
+        self.dex.get_type(index)
+    }
+
+    fn get_method_handle(&mut self, index: u32) -> Result<Rc<MethodHandleItem>> {
+        self.dex.get_method_handle(index)
+    }
+
+    fn get_field(&mut self, index: u32) -> Result<Rc<FieldIdItem>> {
+        self.dex.get_field(index)
+    }
+
+    fn get_method(&mut self, index: u32) -> Result<Rc<MethodIdItem>> {
+        self.dex.get_method(index)
+    }
+
+    fn get_call_site(&mut self, index: u32) -> Result<Rc<CallSiteIdItem>> {
+        self.dex.get_call_site(index)
+    }
+
+    fn resolve_call_site(&mut self, index: u32) -> Result<Rc<DexCallSite>> {
+        self.dex.resolve_call_site(index)
+    }
+
+    fn get_class_def(&mut self, index: u32) -> Result<Rc<DexClassDef>> {
+        self.dex.get_class_def(index)
+    }
+
+    fn get_class_def_item(&mut self, index: u32) -> Result<ClassDefItem> {
+        self.dex.get_class_def_item(index)
+    }
+}
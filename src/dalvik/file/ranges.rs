@@ -0,0 +1,203 @@
+//! Byte ranges of items
+//!
+//! [Dex::byte_range_of] locates a single item of a file, e.g. to highlight
+//! it in a hex viewer or to compare two files item by item. Id items have a
+//! fixed size, all other items are read once to find their end.
+//!
+//! ```ignore
+//! let range = dex.byte_range_of(ItemRef::StringData(3))?;
+//! let bytes = dex.read_raw(range)?;
+//! ```
+
+use binrw::BinRead;
+use std::{
+    io::{Read, Seek, SeekFrom},
+    ops::Range,
+};
+
+use crate::dalvik::{
+    dex::*,
+    error::{Error, Result},
+};
+
+use super::Dex;
+
+/// Identifies an item of a file, see [Dex::byte_range_of].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemRef {
+    Header,
+    MapList,
+
+    /// the id items at the given index of their section
+    StringId(u32),
+    TypeId(u32),
+    ProtoId(u32),
+    FieldId(u32),
+    MethodId(u32),
+    ClassDef(u32),
+    CallSiteId(u32),
+    MethodHandle(u32),
+
+    /// the `string_data_item` of the string at the given index
+    StringData(u32),
+
+    /// the `class_data_item` of the class definition at the given index
+    ClassData(u32),
+
+    /// an item of the given type at the given file offset, e.g. a code
+    /// item or an annotation set
+    At(MapListItemType, u32),
+}
+
+impl<R: Read + Seek> Dex<'_, R> {
+    /// Returns the bytes occupied by the given item, excluding the
+    /// alignment padding in front of it.
+    ///
+    /// Items that are referenced by offset are not checked against the map
+    /// list, so any offset within the file is read as the given type.
+    pub fn byte_range_of(&mut self, item: ItemRef) -> Result<Range<u64>> {
+        let header = &self.header;
+        let (type_, offset) = match item {
+            ItemRef::Header => return Ok(0..header.header_size as u64),
+            ItemRef::MapList => (MapListItemType::MapList, header.map_off),
+            ItemRef::StringId(index) => {
+                return id_range(index, 4, header.string_ids_size, header.string_ids_off)
+            }
+            ItemRef::TypeId(index) => {
+                return id_range(index, 4, header.type_ids_size, header.type_ids_off)
+            }
+            ItemRef::ProtoId(index) => {
+                return id_range(index, 12, header.proto_ids_size, header.proto_ids_off)
+            }
+            ItemRef::FieldId(index) => {
+                return id_range(index, 8, header.field_ids_size, header.field_ids_off)
+            }
+            ItemRef::MethodId(index) => {
+                return id_range(index, 8, header.method_ids_size, header.method_ids_off)
+            }
+            ItemRef::ClassDef(index) => {
+                return id_range(index, 32, header.class_defs_size, header.class_defs_off)
+            }
+            ItemRef::CallSiteId(index) => {
+                let section = self.get_map_list().get(MapListItemType::CallSiteIdItem);
+                let (size, offset) = section.map_or((0, 0), |x| (x.size, x.offset));
+                return id_range(index, 4, size, offset);
+            }
+            ItemRef::MethodHandle(index) => {
+                let section = self.get_map_list().get(MapListItemType::MethodHandleItem);
+                let (size, offset) = section.map_or((0, 0), |x| (x.size, x.offset));
+                return id_range(index, 8, size, offset);
+            }
+            ItemRef::StringData(index) => {
+                let id = id_range(index, 4, header.string_ids_size, header.string_ids_off)?;
+                self.fd.seek(SeekFrom::Start(id.start))?;
+                let offset = StringIdItem::read(self.fd)?.offset;
+                (MapListItemType::StringDataItem, offset)
+            }
+            ItemRef::ClassData(index) => {
+                let offset = self.get_class_def_item(index)?.class_data_off;
+                (MapListItemType::ClassDataItem, offset)
+            }
+            ItemRef::At(type_, offset) => (type_, offset),
+        };
+
+        // an offset of zero refers to no item
+        if offset == 0 {
+            return Err(Error::InvalidOffset(0));
+        }
+        self.seeks(offset as u64)?;
+        let end = skip_item(type_, self.fd)?;
+        Ok(offset as u64..end)
+    }
+}
+
+/// Returns the range of a fixed-size id item.
+fn id_range(index: u32, item_size: u64, size: UInt, offset: UInt) -> Result<Range<u64>> {
+    if index >= size {
+        return Err(Error::InvalidIndex(index as usize));
+    }
+    let start = offset as u64 + index as u64 * item_size;
+    Ok(start..start + item_size)
+}
+
+/// Reads a single item of the given type at the current position without
+/// keeping it and returns the position of its end.
+pub(crate) fn skip_item<R: Read + Seek>(type_: MapListItemType, reader: &mut R) -> Result<u64> {
+    macro_rules! skip {
+        ($item:ty) => {{
+            <$item>::read(reader)?;
+        }};
+    }
+
+    match type_ {
+        MapListItemType::HeaderItem => skip!(HeaderItem),
+        MapListItemType::StringIdItem => skip!(StringIdItem),
+        MapListItemType::TypeIdItem => skip!(TypeIdItem),
+        MapListItemType::ProtoIdItem => skip!(ProtoIdItem),
+        MapListItemType::FieldIdItem => skip!(FieldIdItem),
+        MapListItemType::MethodIdItem => skip!(MethodIdItem),
+        MapListItemType::ClassDefItem => skip!(ClassDefItem),
+        MapListItemType::CallSiteIdItem => skip!(CallSiteIdItem),
+        MapListItemType::MethodHandleItem => skip!(MethodHandleItem),
+        MapListItemType::MapList => skip!(MapList),
+        MapListItemType::AnnotationSetRefList => skip!(AnnotationSetRefList),
+        MapListItemType::AnnotationSetItem => skip!(AnnotationSetItem),
+        MapListItemType::ClassDataItem => skip!(ClassDataItem),
+        MapListItemType::CodeItem => skip!(CodeItem),
+        MapListItemType::AnnotationItem => skip!(AnnotationItem),
+        MapListItemType::EncodedArrayItem => skip!(EncodedArrayItem),
+        MapListItemType::AnnotationsDirectoryItem => skip!(AnnotationsDirectoryItem),
+        MapListItemType::StringDataItem => {
+            // MUTF-8 data never contains a null byte besides the terminator
+            ULeb128::read(reader)?;
+            let mut byte = [0xFF];
+            while byte[0] != 0 {
+                reader.read_exact(&mut byte)?;
+            }
+        }
+        MapListItemType::TypeList => {
+            // [TypeList] pads its end to four bytes, which is not part of
+            // the item
+            let size = UInt::read_le(reader)?;
+            reader.seek(SeekFrom::Current(size as i64 * 2))?;
+        }
+        MapListItemType::HiddenApiListClassDataItem => {
+            // the size of this section includes its size field
+            let start = reader.stream_position()?;
+            let size = UInt::read_le(reader)?;
+            reader.seek(SeekFrom::Start(start + size as u64))?;
+        }
+        MapListItemType::DebugInfoItem => {
+            DebugInfoItem::read(reader)?;
+            loop {
+                match UByte::read_le(reader)? {
+                    DebugInfoItem::DBG_END_SEQUENCE => break,
+                    DebugInfoItem::DBG_ADVANCE_PC
+                    | DebugInfoItem::DBG_END_LOCAL
+                    | DebugInfoItem::DBG_RESTART_LOCAL
+                    | DebugInfoItem::DBG_SET_FILE => skip!(ULeb128),
+                    DebugInfoItem::DBG_ADVANCE_LINE => skip!(SLeb128),
+                    DebugInfoItem::DBG_START_LOCAL => {
+                        for _ in 0..3 {
+                            skip!(ULeb128);
+                        }
+                    }
+                    DebugInfoItem::DBG_START_LOCAL_EXTENDED => {
+                        for _ in 0..4 {
+                            skip!(ULeb128);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        MapListItemType::Unknown(x) => {
+            return Err(Error::InvalidData(format!(
+                "item type {:#06x} at {:#x} has no known size",
+                x,
+                reader.stream_position()?
+            )));
+        }
+    }
+    Ok(reader.stream_position()?)
+}
@@ -0,0 +1,72 @@
+use std::{iter::Map, slice, vec};
+
+use crate::dalvik::dex::{UInt, UShort};
+
+/// Index into the `type_ids` list.
+pub type TypeIndex = u32;
+
+/// The entries of a `type_list`, e.g. the parameters of a prototype or the
+/// interfaces of a class, read with [Dex::get_type_list](super::Dex::get_type_list).
+///
+/// The list was checked to be aligned and to lie within the file before
+/// it was read, so a truncated file or a bogus size can't cause an
+/// oversized allocation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TypeListAccessor {
+    offset: UInt,
+    types: Vec<UShort>,
+}
+
+impl TypeListAccessor {
+    pub(super) fn new(offset: UInt, types: Vec<UShort>) -> Self {
+        TypeListAccessor { offset, types }
+    }
+
+    /// Returns the offset of the list within the file, `0` for an absent
+    /// list.
+    pub fn offset(&self) -> UInt {
+        self.offset
+    }
+
+    /// Returns the number of types in this list.
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+
+    /// Returns the type index at the given position of the list.
+    pub fn get(&self, index: usize) -> Option<TypeIndex> {
+        self.types.get(index).map(|x| *x as TypeIndex)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = TypeIndex> + '_ {
+        self.into_iter()
+    }
+}
+
+fn widen(type_idx: UShort) -> TypeIndex {
+    type_idx as TypeIndex
+}
+
+impl IntoIterator for TypeListAccessor {
+    type Item = TypeIndex;
+    type IntoIter = Map<vec::IntoIter<UShort>, fn(UShort) -> TypeIndex>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.types.into_iter().map(widen as fn(UShort) -> TypeIndex)
+    }
+}
+
+impl<'a> IntoIterator for &'a TypeListAccessor {
+    type Item = TypeIndex;
+    type IntoIter = Map<slice::Iter<'a, UShort>, fn(&UShort) -> TypeIndex>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.types
+            .iter()
+            .map((|x: &UShort| widen(*x)) as fn(&UShort) -> TypeIndex)
+    }
+}
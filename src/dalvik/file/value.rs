@@ -1,10 +1,13 @@
 use std::rc::Rc;
 
-use crate::dalvik::{dex::*, error::Result};
+use crate::{
+    dalvik::{dex::*, error::Result},
+    smali::SmaliWrite,
+};
 
 use super::{annotation::DexAnnotation, method::DexPrototype, IDexRef};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum DexValue {
     Byte(i8),
     Short(i16),
@@ -62,4 +65,20 @@ impl DexValue {
             EncodedValue::Enum(v) => Ok(DexValue::Enum(dex.get_field(*v)?)), // _ => unreachable!("unhandled value type"),
         }
     }
+
+    /// Formats this value the way it is written in smali, e.g. `"text"`,
+    /// `Lcom/example/Foo;` or `Lcom/example/Foo;->bar:I`.
+    pub fn pretty(&self, dex: IDexRef<'_>) -> Result<String> {
+        let mut output = Vec::new();
+        output.write_value(self, dex)?;
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+}
+
+impl EncodedValue {
+    /// Resolves all indices stored in this value into the items they
+    /// reference, see [DexValue::from].
+    pub fn resolve(&self, dex: IDexRef<'_>) -> Result<DexValue> {
+        DexValue::from(self, dex)
+    }
 }
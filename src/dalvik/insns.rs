@@ -8,12 +8,16 @@
 //! format, they simply reference their corresponding function to parse the
 //! contents.
 
+pub mod encode;
+pub mod relocate;
+pub mod remap;
+
 use binrw::{
     BinRead, // trait for reading
 };
 use byteorder::{LittleEndian, ReadBytesExt};
 
-use crate::dalvik::error::Result;
+use crate::dalvik::error::{Error, ErrorContext, Result, ResultExt};
 
 use std::fmt::Debug;
 use std::io::{Cursor, Seek};
@@ -22,23 +26,22 @@ use std::rc::Rc;
 
 use super::dex::{
     CallSiteIdItem, CodeItem, DexType, FieldIdItem, FillArrayData, MethodHandleItem, MethodIdItem,
-    PackedSwitch, SparseSwitch,
+    PackedSwitch, SparseSwitch, TryItem,
 };
 use crate::dalvik::file::{method::DexPrototype, IDexRef};
 
 // The function below is important:
 pub fn disasm(item: &CodeItem, dex: IDexRef<'_>) -> Result<Vec<Insn>> {
-    let mut insns = Vec::new();
-    let mut cursor = Cursor::new(item.insns.as_ref());
-    // 1. Fetch information for the next opcode
-    while let Some(raw_opcode) = match cursor.read_u16::<LittleEndian>() {
-        Ok(raw_opcode) => Some(raw_opcode),
-        Err(_) => None,
-    } {
+    let code = item.insns.as_slice();
+    // most instructions take two code units
+    let mut insns = Vec::with_capacity(code.len() / 4);
+    let mut cursor = Cursor::new(code);
+    // 1. Fetch information for the next opcode, the low byte of the next
+    // code unit is read directly instead of through the cursor
+    let mut start = 0;
+    while start + 2 <= code.len() {
         // 2. Decode the opcode and its representation
-        let opcode = &OPCODES[(raw_opcode & 0xFF) as usize];
-        let start = (cursor.position() - 2) as usize;
-
+        let opcode = &OPCODES[code[start] as usize];
         let mut insn = Insn {
             opcode,
             range: start..(start + opcode.length as usize),
@@ -48,26 +51,275 @@ pub fn disasm(item: &CodeItem, dex: IDexRef<'_>) -> Result<Vec<Insn>> {
         // 3. Execute the instruction format and insert the instruction's
         // information into the instruction list
         cursor.set_position(start as u64);
-        let format = match (opcode.format_factory)(&mut cursor, &mut insn, dex) {
-            Ok(format) => format,
-            Err(e) => {
-                return Err(super::error::Error::InvalidData(format!(
-                    "failed to parse instruction: {:?} at {:?}",
-                    e, opcode
-                )));
-            }
-        };
+        let format =
+            (opcode.format_factory)(&mut cursor, &mut insn, dex).context(ErrorContext::Insn {
+                offset: start,
+                name: opcode.name,
+            })?;
 
         insn.format = format;
         // update range if necessary
         if cursor.position() > insn.range.end as u64 {
             insn.range.end = cursor.position() as usize;
         }
+        start = cursor.position() as usize;
         insns.push(insn);
     }
     Ok(insns)
 }
 
+/// Sizes of all instructions in code units, indexed by the opcode. Unused
+/// opcodes take a single code unit, like they do in [disasm]. Payloads
+/// share the opcode of `nop`, their size is returned by [insn_size].
+pub const INSN_SIZES: [u8; 256] = {
+    let mut sizes = [0; 256];
+    let mut i = 0;
+    while i < OPCODES.len() {
+        sizes[OPCODES[i].opcode as usize] = OPCODES[i].length;
+        i += 1;
+    }
+    sizes
+};
+
+/// Reads the instructions of a code item as code units, all at once. A
+/// trailing odd byte is ignored.
+pub fn code_units(code: &[u8]) -> Vec<u16> {
+    code.chunks_exact(2)
+        .map(|x| u16::from_le_bytes([x[0], x[1]]))
+        .collect()
+}
+
+/// Returns the size in code units of the instruction or payload starting
+/// at the code unit `index`, or `None` for opcodes without a size.
+///
+/// Only the size table and the headers of payloads are consulted, so the
+/// returned size may exceed the given code units.
+pub fn insn_size(units: &[u16], index: usize) -> Option<usize> {
+    let unit = |i: usize| units.get(index + i).copied().unwrap_or(0) as usize;
+    match unit(0) {
+        0x0100 => Some(4 + unit(1) * 2),
+        0x0200 => Some(2 + unit(1) * 4),
+        0x0300 => {
+            let count = unit(2) | unit(3) << 16;
+            Some(4 + count.saturating_mul(unit(1)).div_ceil(2))
+        }
+        value => match INSN_SIZES[value & 0xFF] {
+            0 => None,
+            size => Some(size as usize),
+        },
+    }
+}
+
+/// Returns the byte offsets of all instructions and payloads of a code
+/// item without decoding their operands. This is much cheaper than
+/// [disasm] when only the boundaries of the instructions are needed, as no
+/// format function is called and no reference is resolved.
+pub fn insn_offsets(item: &CodeItem) -> Result<Vec<usize>> {
+    let units = code_units(&item.insns);
+    let mut offsets = Vec::with_capacity(units.len() / 2);
+    let mut index = 0;
+    while index < units.len() {
+        let size = match insn_size(&units, index) {
+            Some(size) if index + size <= units.len() => size,
+            Some(_) => {
+                return Err(Error::InvalidData(format!(
+                    "instruction at {:#x} exceeds the instructions",
+                    index * 2
+                )));
+            }
+            None => {
+                return Err(Error::InvalidData(format!(
+                    "unknown opcode {:#04x} at {:#x}",
+                    units[index] & 0xFF,
+                    index * 2
+                )));
+            }
+        };
+        offsets.push(index * 2);
+        index += size;
+    }
+    Ok(offsets)
+}
+
+/// An instruction together with the try blocks covering it, see
+/// [with_tries].
+#[derive(Debug)]
+pub struct TryInsn<'a> {
+    pub insn: &'a Insn,
+
+    /// All try blocks covering this instruction.
+    pub tries: Vec<&'a TryItem>,
+
+    /// Try blocks starting at this instruction.
+    pub begins: Vec<&'a TryItem>,
+
+    /// Try blocks ending right after this instruction.
+    pub ends: Vec<&'a TryItem>,
+}
+
+/// Iterates over the decoded instructions of a code item and annotates each
+/// of them with the try blocks it falls in. Together with
+/// [CodeItem::catch_handler], this is all that is needed to emit `.catch`
+/// directives.
+///
+/// Payloads never begin or end a try block, since they can't throw.
+pub fn with_tries<'a>(item: &'a CodeItem, insns: &'a [Insn]) -> impl Iterator<Item = TryInsn<'a>> {
+    // start of the next instruction that isn't a payload
+    let mut next = vec![usize::MAX; insns.len()];
+    for index in (1..insns.len()).rev() {
+        next[index - 1] = match insns[index].payload {
+            Some(_) => next[index],
+            None => insns[index].range.start,
+        };
+    }
+
+    insns.iter().zip(next).map(move |(insn, next)| {
+        let mut annotated = TryInsn {
+            insn,
+            tries: Vec::new(),
+            begins: Vec::new(),
+            ends: Vec::new(),
+        };
+        if insn.payload.is_some() {
+            return annotated;
+        }
+        for try_item in &item.tries {
+            let range = try_item.range();
+            if !range.contains(&insn.range.start) {
+                continue;
+            }
+            annotated.tries.push(try_item);
+            if range.start == insn.range.start {
+                annotated.begins.push(try_item);
+            }
+            if next >= range.end {
+                annotated.ends.push(try_item);
+            }
+        }
+        annotated
+    })
+}
+
+/// Returns the position of the instruction starting at the given byte
+/// offset. Branch targets and try ranges are given in code units and have
+/// to be multiplied by two first.
+///
+/// The instructions must be sorted by their offset, as returned by
+/// [disasm], so the lookup takes O(log n).
+pub fn insn_at(insns: &[Insn], offset: usize) -> Option<usize> {
+    insns.binary_search_by_key(&offset, |x| x.range.start).ok()
+}
+
+/// Returns the position of the instruction covering the given byte offset,
+/// which may also point into the middle of an instruction or payload.
+pub fn insn_containing(insns: &[Insn], offset: usize) -> Option<usize> {
+    let index = insns.partition_point(|x| x.range.start <= offset);
+    index
+        .checked_sub(1)
+        .filter(|&x| insns[x].range.contains(&offset))
+}
+
+/// The kind of data stored in a [CodeRegion].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Code,
+    PackedSwitch,
+    SparseSwitch,
+    FillArrayData,
+}
+
+/// A contiguous part of the instructions of a method, see [code_regions].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeRegion {
+    pub kind: RegionKind,
+
+    /// Byte range within the instructions.
+    pub range: Range<usize>,
+
+    /// Byte offsets of the instructions referencing this payload, always
+    /// empty for code.
+    pub referrers: Vec<usize>,
+}
+
+/// Splits the instructions of a code item into code and payload regions
+/// without decoding any operand, so that payload data is never read as
+/// instructions. Consecutive instructions form a single code region.
+///
+/// Every `packed-switch`, `sparse-switch` and `fill-array-data` must point
+/// to the start of a payload of the matching kind. Payloads that are not
+/// referenced are returned as well, with an empty list of referrers.
+pub fn code_regions(item: &CodeItem) -> Result<Vec<CodeRegion>> {
+    let code = item.insns.as_slice();
+    let units = code_units(code);
+    let unit = |index: usize| units.get(index).copied().unwrap_or(0) as u64;
+
+    let mut regions: Vec<CodeRegion> = Vec::new();
+    let mut references = Vec::new();
+    let mut index = 0;
+    while index < units.len() {
+        let offset = index * 2;
+        let kind = match units[index] {
+            0x0100 => RegionKind::PackedSwitch,
+            0x0200 => RegionKind::SparseSwitch,
+            0x0300 => RegionKind::FillArrayData,
+            _ => RegionKind::Code,
+        };
+        // sizes are counted in code units
+        let Some(size) = insn_size(&units, index) else {
+            return Err(Error::InvalidData(format!(
+                "unknown opcode {:#04x} at {:#x}",
+                units[index] & 0xFF,
+                offset
+            )));
+        };
+        if kind == RegionKind::Code && matches!(units[index] & 0xFF, 0x26 | 0x2B | 0x2C) {
+            let target = (unit(index + 1) | unit(index + 2) << 16) as i32;
+            references.push((offset, offset as i64 + target as i64 * 2));
+        }
+
+        let end = offset as u64 + size as u64 * 2;
+        if end > code.len() as u64 {
+            return Err(Error::InvalidData(format!(
+                "{:?} at {:#x} exceeds the instructions",
+                kind, offset
+            )));
+        }
+        match regions.last_mut() {
+            Some(last) if kind == RegionKind::Code && last.kind == RegionKind::Code => {
+                last.range.end = end as usize;
+            }
+            _ => regions.push(CodeRegion {
+                kind,
+                range: offset..end as usize,
+                referrers: Vec::new(),
+            }),
+        }
+        index += size;
+    }
+
+    for (referrer, target) in references {
+        let opcode = &OPCODES[code[referrer] as usize];
+        let kind = match opcode.opcode {
+            0x26 => RegionKind::FillArrayData,
+            0x2B => RegionKind::PackedSwitch,
+            _ => RegionKind::SparseSwitch,
+        };
+        let region = regions
+            .iter_mut()
+            .find(|x| x.range.start as i64 == target && x.kind == kind);
+        match region {
+            Some(region) => region.referrers.push(referrer),
+            None => {
+                return Err(Error::InvalidData(format!(
+                    "{} at {:#x} references no {:?} payload",
+                    opcode.name, referrer, kind
+                )));
+            }
+        }
+    }
+    Ok(regions)
+}
+
 // just the implementation for above
 pub enum Index {
     Type(Rc<DexType>),
@@ -75,8 +327,10 @@ pub enum Index {
     MethodHandle(Rc<MethodHandleItem>),
     Proto(Rc<DexPrototype>),
     String(Rc<String>),
-    CallSite(Rc<CallSiteIdItem>),
-    Method(Rc<MethodIdItem>),
+    /// call site together with its index into the `call_site_ids` list
+    CallSite(u32, Rc<CallSiteIdItem>),
+    /// method reference together with its index into the `method_ids` list
+    Method(u32, Rc<MethodIdItem>),
     Unknown(u32),
     Literal(i64),
 }
@@ -206,7 +460,8 @@ pub enum InsnFormat {
         a: u8,
         b: Index,
         c: u16,
-        regs: Range<u16>,
+        /// exclusive range, so it may end at 0x10000
+        regs: Range<u32>,
     },
 
     Format45cc {
@@ -225,7 +480,8 @@ pub enum InsnFormat {
         b: Index,
         c: u16,
         h: Index,
-        regs: Range<u16>,
+        /// exclusive range, so it may end at 0x10000
+        regs: Range<u32>,
     },
     Format51l {
         a: u8,
@@ -266,9 +522,23 @@ pub struct Opcode {
     pub name: &'static str,
     pub registers: u8,
     pub length: u8,
+    /// name of the format function, e.g. `format_21c`
+    pub format: &'static str,
     pub format_factory: &'static IFormatFactory,
 }
 
+impl Opcode {
+    /// Returns the instruction format identifier, e.g. `21c`.
+    pub fn format_id(&self) -> &'static str {
+        self.format.trim_start_matches("format_")
+    }
+}
+
+/// Searches for an opcode by its mnemonic, e.g. `invoke-virtual`.
+pub fn find_opcode(name: &str) -> Option<&'static Opcode> {
+    OPCODES.iter().find(|x| x.name == name)
+}
+
 impl Debug for Opcode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -289,6 +559,7 @@ macro_rules! opcode {
             name: $name,
             registers: $registers,
             length: $length,
+            format: stringify!($func),
             format_factory: &$func,
         }
     };
@@ -298,6 +569,7 @@ macro_rules! opcode {
             name: $name,
             registers: 0,
             length: 0,
+            format: stringify!($func),
             format_factory: &$func,
         }
     };
@@ -308,6 +580,7 @@ macro_rules! opcode {
             name: stringify!($_opcode_),
             registers: 0,
             length: 1,
+            format: "format_10x",
             format_factory: &format_10x,
         }
     };
@@ -391,8 +664,8 @@ pub const OPCODES: &[Opcode] = &[
     opcode! { "goto/16"     := 0x29 impl format_20t[len=2, reg=1] },
     opcode! { "goto/32"     := 0x2A impl format_30t[len=3, reg=1] },
     // branches
-    opcode! { "packed-branch" := 0x2B impl format_31t[len=1, reg=1] },
-    opcode! { "sparse-branch" := 0x2C impl format_31t[len=2, reg=1] },
+    opcode! { "packed-switch" := 0x2B impl format_31t[len=3, reg=1] },
+    opcode! { "sparse-switch" := 0x2C impl format_31t[len=3, reg=1] },
     // comparisons
     opcode! { "cmpl-float"    := 0x2D impl format_23x[len=2, reg=3] },
     opcode! { "cmpg-float"    := 0x2E impl format_23x[len=2, reg=3] },
@@ -616,8 +889,8 @@ pub const OPCODES: &[Opcode] = &[
     opcode!(0xF9),
     opcode! { "invoke-polymorphic"       := 0xFA impl format_45cc[len=4, reg=7] },
     opcode! { "invoke-polymorphic/range" := 0xFB impl format_4rcc[len=4, reg=7] },
-    opcode! { "invoke-custom"            := 0xFC impl format_35c[len=4, reg=7] },
-    opcode! { "invoke-custom/range"      := 0xFD impl format_3rc[len=4, reg=7] },
+    opcode! { "invoke-custom"            := 0xFC impl format_35c[len=3, reg=7] },
+    opcode! { "invoke-custom/range"      := 0xFD impl format_3rc[len=3, reg=7] },
     opcode! { "const-method-handle"      := 0xFE impl format_21c[len=2, reg=2] },
     opcode! { "const-method-type"        := 0xFF impl format_21c[len=2, reg=2] },
 ];
@@ -669,7 +942,7 @@ pub fn format_11n(code: &mut Cursor<&'_ [u8]>, _: &mut Insn, _: IDexRef<'_>) ->
     let value = code.read_u16::<LittleEndian>()?;
     Ok(InsnFormat::Format11n {
         a: ((value & 0x0F00) >> 8) as u8,
-        b: Index::Literal(((value & 0xF000) >> 12) as i64),
+        b: Index::Literal(((value as i16) >> 12) as i64),
     })
 }
 
@@ -748,7 +1021,7 @@ pub fn format_21s(code: &mut Cursor<&'_ [u8]>, _: &mut Insn, _: IDexRef<'_>) ->
     let value = code.read_u16::<LittleEndian>()?;
     Ok(InsnFormat::Format21s {
         a: ((value & 0xFF00) >> 8) as u8,
-        b: Index::Literal(code.read_u16::<LittleEndian>()? as i64),
+        b: Index::Literal(code.read_i16::<LittleEndian>()? as i64),
     })
 }
 
@@ -764,7 +1037,7 @@ pub fn format_21h(code: &mut Cursor<&'_ [u8]>, _: &mut Insn, _: IDexRef<'_>) ->
             0x15 =>
             /* const/high16 */
             {
-                Index::Literal((index_value as i64) << 16)
+                Index::Literal(((index_value as i32) << 16) as i64)
             }
             0x19 =>
             /* const-wide/high16 */
@@ -794,13 +1067,13 @@ pub fn format_21c(
             {
                 Index::String(dex.get_string(index_value)?)
             }
-            0x1C | 0x60..=0x6d =>
-            /* const-class */
+            0x60..=0x6d =>
+            /* sget-kind | sput-kind */
             {
                 Index::Field(dex.get_field(index_value)?)
             }
-            0x1F | 0x22 =>
-            /* check-cast | new-instance */
+            0x1C | 0x1F | 0x22 =>
+            /* const-class | check-cast | new-instance */
             {
                 Index::Type(dex.get_type(index_value)?)
             }
@@ -841,7 +1114,7 @@ pub fn format_22b(code: &mut Cursor<&'_ [u8]>, _: &mut Insn, _: IDexRef<'_>) ->
     Ok(InsnFormat::Format22b {
         a: ((value & 0xFF00) >> 8) as u8,
         b: (next & 0x00FF) as u8,
-        c: Index::Literal(((next & 0xFF00) >> 8) as i64),
+        c: Index::Literal(((next as i16) >> 8) as i64),
     })
 }
 
@@ -867,7 +1140,7 @@ pub fn format_22s(code: &mut Cursor<&'_ [u8]>, _: &mut Insn, _: IDexRef<'_>) ->
     Ok(InsnFormat::Format22s {
         a: ((value & 0x0F00) >> 8) as u8,
         b: ((value & 0xF000) >> 12) as u8,
-        c: Index::Literal(next as i64),
+        c: Index::Literal(next as i16 as i64),
     })
 }
 
@@ -924,7 +1197,7 @@ pub fn format_31i(code: &mut Cursor<&'_ [u8]>, _: &mut Insn, _: IDexRef<'_>) ->
     let value = code.read_u16::<LittleEndian>()?;
     let index = code.read_i32::<LittleEndian>()?;
     Ok(InsnFormat::Format31i {
-        a: ((value & 0xFF) >> 8) as u8,
+        a: ((value & 0xFF00) >> 8) as u8,
         b: Index::Literal(index as i64),
     })
 }
@@ -936,7 +1209,7 @@ pub fn format_31t(code: &mut Cursor<&'_ [u8]>, _: &mut Insn, _: IDexRef<'_>) ->
     let value = code.read_u16::<LittleEndian>()?;
     let b = code.read_i32::<LittleEndian>()?;
     Ok(InsnFormat::Format31t {
-        a: ((value & 0xFF) >> 8) as u8,
+        a: ((value & 0xFF00) >> 8) as u8,
         b,
     })
 }
@@ -952,7 +1225,7 @@ pub fn format_31c(
     let a = code.read_u16::<LittleEndian>()?;
     let index = code.read_u32::<LittleEndian>()?;
     Ok(InsnFormat::Format31c {
-        a: ((a & 0xFF) >> 8) as u8,
+        a: ((a & 0xFF00) >> 8) as u8,
         b: Index::String(dex.get_string(index)?),
     })
 }
@@ -976,10 +1249,10 @@ pub fn format_35c(
                 Index::Type(dex.get_type(second as u32)?)
             },
             0x6E..=0x72 /* invoke-kind */ => {
-                Index::Method(dex.get_method(second as u32)?)
+                Index::Method(second as u32, dex.get_method(second as u32)?)
             },
             0xFC /* invoke-custom */ => {
-                Index::CallSite(dex.get_call_site(second as u32)?)
+                Index::CallSite(second as u32, dex.get_call_site(second as u32)?)
             },
             _ => {
                 Index::Unknown(second as u32)
@@ -1005,7 +1278,7 @@ pub fn format_3rc(
     let b: u16 = code.read_u16::<LittleEndian>()?;
     let c = code.read_u16::<LittleEndian>()?;
 
-    let n = (c + count) - 1;
+    let n = register_range_end(c, count)?;
     Ok(InsnFormat::Format3rc {
         a: count as u8,
         b: match value & 0xFF {
@@ -1013,22 +1286,36 @@ pub fn format_3rc(
                 Index::Type(dex.get_type(b as u32)?)
             },
             0x74..=0x78 /* invoke-kind/range */=> {
-                Index::Method(dex.get_method(b as u32)?)
+                Index::Method(b as u32, dex.get_method(b as u32)?)
             },
             0xFD /* invoke-custom/range */ => {
-                Index::CallSite(dex.get_call_site(b as u32)?)
+                Index::CallSite(b as u32, dex.get_call_site(b as u32)?)
             }
             _ => Index::Unknown(b as u32),
         },
         c,
         /* from AOSP:
         where NNNN = CCCC+AA-1, that is A determines the count 0..255, and C determines
-        the first register.
+        the first register. The range stores NNNN+1 as its exclusive end.
          */
-        regs: c..n,
+        regs: c as u32..n,
     })
 }
 
+/// Returns the exclusive end of a register range starting at `start` that
+/// spans `count` registers. The last register may be v65535, so the end is
+/// computed in 32 bits.
+fn register_range_end(start: u16, count: u16) -> Result<u32> {
+    let end = start as u32 + count as u32;
+    if end > 0x10000 {
+        return Err(Error::InvalidData(format!(
+            "Invalid register range: v{}+{}",
+            start, count
+        )));
+    }
+    Ok(end)
+}
+
 /// ID: 45cc
 /// Syntax: `op {vC, vD, vE, vF, vG}, method@BBBB, prototype@HHHH`
 /// Format: `A|G|op BBBB F|E|D|C HHHH`
@@ -1044,8 +1331,8 @@ pub fn format_45cc(
     Ok(InsnFormat::Format45cc {
         a: ((value & 0xF000) >> 12) as u8,
         g: ((value & 0x0F00) >> 8) as u8,
-        b: Index::Method(dex.get_method(b as u32)?),
-        f: ((v2 & 0xF000) >> 8) as u8,
+        b: Index::Method(b as u32, dex.get_method(b as u32)?),
+        f: ((v2 & 0xF000) >> 12) as u8,
         e: ((v2 & 0x0F00) >> 8) as u8,
         d: ((v2 & 0x00F0) >> 4) as u8,
         c: (v2 & 0x000F) as u8,
@@ -1065,12 +1352,12 @@ pub fn format_4rcc(
     let b = code.read_u16::<LittleEndian>()?;
     let c = code.read_u16::<LittleEndian>()?;
     let h = code.read_u16::<LittleEndian>()?;
-    let n = (c + count) - 1;
+    let n = register_range_end(c, count)?;
     Ok(InsnFormat::Format4rcc {
         a: count as u8,
-        b: Index::Method(dex.get_method(b as u32)?),
+        b: Index::Method(b as u32, dex.get_method(b as u32)?),
         c,
-        regs: c..n,
+        regs: c as u32..n,
         h: Index::Proto(dex.get_proto(h as u32)?),
     })
 }
@@ -1083,7 +1370,7 @@ pub fn format_51l(
     _: &mut Insn,
     _dex: IDexRef<'_>,
 ) -> Result<InsnFormat> {
-    let a = ((code.read_u16::<LittleEndian>()? & 0xF000) >> 12) as u8;
+    let a = ((code.read_u16::<LittleEndian>()? & 0xFF00) >> 8) as u8;
     let b = code.read_i64::<LittleEndian>()?;
     Ok(InsnFormat::Format51l {
         a,
@@ -1118,10 +1405,10 @@ impl Debug for Index {
             Index::String(x) => write!(f, "{}", x),
             Index::Type(x) => write!(f, "{:?}", x),
             Index::Field(x) => write!(f, "{:?}", x),
-            Index::Method(x) => write!(f, "{:?}", x),
+            Index::Method(_, x) => write!(f, "{:?}", x),
             Index::MethodHandle(x) => write!(f, "{:?}", x),
             Index::Proto(x) => write!(f, "{:?}", x),
-            Index::CallSite(x) => write!(f, "{:?}", x),
+            Index::CallSite(_, x) => write!(f, "{:?}", x),
             Index::Literal(x) => write!(f, "{:#x}", x),
         }
     }
@@ -0,0 +1,429 @@
+//! Encoder for Dalvik instructions
+//!
+//! This module is the inverse of [disasm](super::disasm): an opcode together
+//! with its raw [Operands] is serialized into 16-bit code units. Registers,
+//! literals, branch offsets and indices are checked against the bit widths
+//! of the selected instruction format before anything is written.
+//!
+//! ```ignore
+//! let opcode = insns::find_opcode("const/4").unwrap();
+//! let units = encode(opcode, &Operands::Format11n { a: 1, b: -2 })?;
+//! assert_eq!(units, vec![0xE112]);
+//! ```
+
+use std::ops::Range;
+
+use crate::dalvik::{
+    dex::{FillArrayData, PackedSwitch, SparseSwitch},
+    error::{Error, Result},
+};
+
+use super::{Opcode, Payload};
+
+/// Operands of an instruction that is about to be encoded.
+///
+/// The variants follow the naming of [InsnFormat](super::InsnFormat), but
+/// store raw values: registers are register numbers, references are indices
+/// into the corresponding id section and branch targets are offsets in code
+/// units relative to the instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operands {
+    /// `op`
+    Format10x,
+    /// `op vA, vB`
+    Format12x { a: u16, b: u16 },
+    /// `op vA, #+B`
+    Format11n { a: u16, b: i64 },
+    /// `op vAA`
+    Format11x { a: u16 },
+    /// `op +AA`
+    Format10t { a: i32 },
+    /// `op +AAAA`
+    Format20t { a: i32 },
+    /// `op AA, kind@BBBB`
+    Format20bc { a: u16, b: u32 },
+    /// `op vAA, vBBBB`
+    Format22x { a: u16, b: u16 },
+    /// `op vAA, +BBBB`
+    Format21t { a: u16, b: i32 },
+    /// `op vAA, #+BBBB`
+    Format21s { a: u16, b: i64 },
+    /// `op vAA, #+BBBB0000[00000000]`, where `b` is the full literal value
+    /// whose low 16 (or 48) bits must be zero.
+    Format21h { a: u16, b: i64 },
+    /// `op vAA, thing@BBBB`
+    Format21c { a: u16, b: u32 },
+    /// `op vAA, vBB, vCC`
+    Format23x { a: u16, b: u16, c: u16 },
+    /// `op vAA, vBB, #+CC`
+    Format22b { a: u16, b: u16, c: i64 },
+    /// `op vA, vB, +CCCC`
+    Format22t { a: u16, b: u16, c: i32 },
+    /// `op vA, vB, #+CCCC`
+    Format22s { a: u16, b: u16, c: i64 },
+    /// `op vA, vB, thing@CCCC`
+    Format22c { a: u16, b: u16, c: u32 },
+    /// `op +AAAAAAAA`
+    Format30t { a: i32 },
+    /// `op vAAAA, vBBBB`
+    Format32x { a: u16, b: u16 },
+    /// `op vAA, #+BBBBBBBB`
+    Format31i { a: u16, b: i64 },
+    /// `op vAA, +BBBBBBBB`
+    Format31t { a: u16, b: i32 },
+    /// `op vAA, thing@BBBBBBBB`
+    Format31c { a: u16, b: u32 },
+    /// `op {vC, vD, vE, vF, vG}, thing@BBBB`
+    Format35c { b: u32, regs: Vec<u16> },
+    /// `op {vCCCC .. vNNNN}, thing@BBBB`, with an exclusive range like
+    /// the decoded format
+    Format3rc { b: u32, regs: Range<u32> },
+    /// `op {vC, vD, vE, vF, vG}, method@BBBB, proto@HHHH`
+    Format45cc { b: u32, regs: Vec<u16>, h: u32 },
+    /// `op {vCCCC .. vNNNN}, method@BBBB, proto@HHHH`
+    Format4rcc { b: u32, regs: Range<u32>, h: u32 },
+    /// `op vAA, #+BBBBBBBBBBBBBBBB`
+    Format51l { a: u16, b: i64 },
+}
+
+impl Operands {
+    /// Returns the name of the format function that decodes these operands,
+    /// which can be compared against [Opcode::format].
+    pub fn format(&self) -> &'static str {
+        match self {
+            Operands::Format10x => "format_10x",
+            Operands::Format12x { .. } => "format_12x",
+            Operands::Format11n { .. } => "format_11n",
+            Operands::Format11x { .. } => "format_11x",
+            Operands::Format10t { .. } => "format_10t",
+            Operands::Format20t { .. } => "format_20t",
+            Operands::Format20bc { .. } => "format_20bc",
+            Operands::Format22x { .. } => "format_22x",
+            Operands::Format21t { .. } => "format_21t",
+            Operands::Format21s { .. } => "format_21s",
+            Operands::Format21h { .. } => "format_21h",
+            Operands::Format21c { .. } => "format_21c",
+            Operands::Format23x { .. } => "format_23x",
+            Operands::Format22b { .. } => "format_22b",
+            Operands::Format22t { .. } => "format_22t",
+            Operands::Format22s { .. } => "format_22s",
+            Operands::Format22c { .. } => "format_22c",
+            Operands::Format30t { .. } => "format_30t",
+            Operands::Format32x { .. } => "format_32x",
+            Operands::Format31i { .. } => "format_31i",
+            Operands::Format31t { .. } => "format_31t",
+            Operands::Format31c { .. } => "format_31c",
+            Operands::Format35c { .. } => "format_35c",
+            Operands::Format3rc { .. } => "format_3rc",
+            Operands::Format45cc { .. } => "format_45cc",
+            Operands::Format4rcc { .. } => "format_4rcc",
+            Operands::Format51l { .. } => "format_51l",
+        }
+    }
+}
+
+/// Encodes a single instruction into code units.
+///
+/// Fails if the operands don't belong to the format of the given opcode or if
+/// any operand doesn't fit into its slot.
+pub fn encode(opcode: &Opcode, operands: &Operands) -> Result<Vec<u16>> {
+    let mut units = Vec::with_capacity(opcode.length as usize);
+    encode_into(opcode, operands, &mut units)?;
+    Ok(units)
+}
+
+/// Encodes a single instruction and appends its code units to `out`. Nothing
+/// is appended if the instruction can't be encoded.
+pub fn encode_into(opcode: &Opcode, operands: &Operands, out: &mut Vec<u16>) -> Result<()> {
+    if operands.format() != opcode.format {
+        return Err(Error::InvalidData(format!(
+            "{} uses {}, got operands for {}",
+            opcode.name,
+            opcode.format_id(),
+            operands.format().trim_start_matches("format_")
+        )));
+    }
+
+    let op = opcode.opcode as u16;
+    match operands {
+        Operands::Format10x => out.push(op),
+        Operands::Format12x { a, b } => {
+            out.push(op | reg(*a, 4)? << 8 | reg(*b, 4)? << 12);
+        }
+        Operands::Format11n { a, b } => {
+            out.push(op | reg(*a, 4)? << 8 | (literal(*b, 4)? as u16) << 12);
+        }
+        Operands::Format11x { a } => out.push(op | reg(*a, 8)? << 8),
+        Operands::Format10t { a } => {
+            out.push(op | (literal(*a as i64, 8)? as u16) << 8);
+        }
+        Operands::Format20t { a } => {
+            out.extend([op, literal(*a as i64, 16)? as u16]);
+        }
+        Operands::Format20bc { a, b } => {
+            out.extend([op | reg(*a, 8)? << 8, index(*b, 16)? as u16]);
+        }
+        Operands::Format22x { a, b } => {
+            out.extend([op | reg(*a, 8)? << 8, *b]);
+        }
+        Operands::Format21t { a, b } => {
+            out.extend([op | reg(*a, 8)? << 8, literal(*b as i64, 16)? as u16]);
+        }
+        Operands::Format21s { a, b } => {
+            out.extend([op | reg(*a, 8)? << 8, literal(*b, 16)? as u16]);
+        }
+        Operands::Format21h { a, b } => {
+            // const/high16 stores the upper 16 bits of an int, const-wide/high16
+            // the upper 16 bits of a long
+            let shift = if opcode.opcode == 0x19 { 48 } else { 16 };
+            if shift == 16 && *b != *b as i32 as i64 {
+                return Err(Error::InvalidData(format!(
+                    "literal {:#x} does not fit into 32 bits",
+                    b
+                )));
+            }
+            if b & ((1 << shift) - 1) != 0 {
+                return Err(Error::InvalidData(format!(
+                    "literal {:#x} has non-zero low {} bits",
+                    b, shift
+                )));
+            }
+            out.extend([op | reg(*a, 8)? << 8, (*b >> shift) as u16]);
+        }
+        Operands::Format21c { a, b } => {
+            out.extend([op | reg(*a, 8)? << 8, index(*b, 16)? as u16]);
+        }
+        Operands::Format23x { a, b, c } => {
+            out.extend([op | reg(*a, 8)? << 8, reg(*b, 8)? | reg(*c, 8)? << 8]);
+        }
+        Operands::Format22b { a, b, c } => {
+            out.extend([
+                op | reg(*a, 8)? << 8,
+                reg(*b, 8)? | (literal(*c, 8)? as u16) << 8,
+            ]);
+        }
+        Operands::Format22t { a, b, c } => {
+            out.extend([
+                op | reg(*a, 4)? << 8 | reg(*b, 4)? << 12,
+                literal(*c as i64, 16)? as u16,
+            ]);
+        }
+        Operands::Format22s { a, b, c } => {
+            out.extend([
+                op | reg(*a, 4)? << 8 | reg(*b, 4)? << 12,
+                literal(*c, 16)? as u16,
+            ]);
+        }
+        Operands::Format22c { a, b, c } => {
+            out.extend([
+                op | reg(*a, 4)? << 8 | reg(*b, 4)? << 12,
+                index(*c, 16)? as u16,
+            ]);
+        }
+        Operands::Format30t { a } => {
+            out.extend([op, *a as u16, (*a >> 16) as u16]);
+        }
+        Operands::Format32x { a, b } => out.extend([op, *a, *b]),
+        Operands::Format31i { a, b } => {
+            let b = literal(*b, 32)?;
+            out.extend([op | reg(*a, 8)? << 8, b as u16, (b >> 16) as u16]);
+        }
+        Operands::Format31t { a, b } => {
+            out.extend([op | reg(*a, 8)? << 8, *b as u16, (*b >> 16) as u16]);
+        }
+        Operands::Format31c { a, b } => {
+            out.extend([op | reg(*a, 8)? << 8, *b as u16, (*b >> 16) as u16]);
+        }
+        Operands::Format35c { b, regs } => {
+            let (first, args) = register_list(regs)?;
+            out.extend([op | first, index(*b, 16)? as u16, args]);
+        }
+        Operands::Format3rc { b, regs } => {
+            out.extend([
+                op | register_count(regs)? << 8,
+                index(*b, 16)? as u16,
+                regs.start as u16,
+            ]);
+        }
+        Operands::Format45cc { b, regs, h } => {
+            let (first, args) = register_list(regs)?;
+            out.extend([
+                op | first,
+                index(*b, 16)? as u16,
+                args,
+                index(*h, 16)? as u16,
+            ]);
+        }
+        Operands::Format4rcc { b, regs, h } => {
+            out.extend([
+                op | register_count(regs)? << 8,
+                index(*b, 16)? as u16,
+                regs.start as u16,
+                index(*h, 16)? as u16,
+            ]);
+        }
+        Operands::Format51l { a, b } => {
+            out.extend([
+                op | reg(*a, 8)? << 8,
+                *b as u16,
+                (*b >> 16) as u16,
+                (*b >> 32) as u16,
+                (*b >> 48) as u16,
+            ]);
+        }
+    }
+    Ok(())
+}
+
+/// Encodes the payload of a `packed-switch`, `sparse-switch` or
+/// `fill-array-data` instruction, including its identifier.
+pub fn encode_payload(payload: &Payload) -> Result<Vec<u16>> {
+    match payload {
+        Payload::PackedSwitch(x) => encode_packed_switch(x),
+        Payload::SparseSwitch(x) => encode_sparse_switch(x),
+        Payload::FillArrayData(x) => encode_fill_array_data(x),
+    }
+}
+
+/// Converts code units into the little endian byte representation used by
+/// [CodeItem::insns](crate::dalvik::dex::CodeItem::insns).
+pub fn to_bytes(units: &[u16]) -> Vec<u8> {
+    units.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn encode_packed_switch(data: &PackedSwitch) -> Result<Vec<u16>> {
+    if data.targets.len() != data.size as usize {
+        return Err(Error::InvalidData(format!(
+            "packed-switch declares {} targets, got {}",
+            data.size,
+            data.targets.len()
+        )));
+    }
+    let mut units = vec![0x0100, data.size];
+    push_i32(&mut units, data.first_key);
+    data.targets.iter().for_each(|x| push_i32(&mut units, *x));
+    Ok(units)
+}
+
+fn encode_sparse_switch(data: &SparseSwitch) -> Result<Vec<u16>> {
+    if data.keys.len() != data.size as usize || data.targets.len() != data.size as usize {
+        return Err(Error::InvalidData(format!(
+            "sparse-switch declares {} entries, got {} keys and {} targets",
+            data.size,
+            data.keys.len(),
+            data.targets.len()
+        )));
+    }
+    if data.keys.windows(2).any(|x| x[0] >= x[1]) {
+        return Err(Error::InvalidData(
+            "sparse-switch keys must be sorted from low to high".to_string(),
+        ));
+    }
+    let mut units = vec![0x0200, data.size];
+    data.keys.iter().for_each(|x| push_i32(&mut units, *x));
+    data.targets.iter().for_each(|x| push_i32(&mut units, *x));
+    Ok(units)
+}
+
+fn encode_fill_array_data(data: &FillArrayData) -> Result<Vec<u16>> {
+    if !matches!(data.width, 1 | 2 | 4 | 8) {
+        return Err(Error::InvalidData(format!(
+            "invalid element width for fill-array-data: {}",
+            data.width
+        )));
+    }
+    if data.data.len() != data.size as usize * data.width as usize {
+        return Err(Error::InvalidData(format!(
+            "fill-array-data declares {} elements of {} bytes, got {} bytes",
+            data.size,
+            data.width,
+            data.data.len()
+        )));
+    }
+    let mut units = vec![
+        0x0300,
+        data.width,
+        data.size as u16,
+        (data.size >> 16) as u16,
+    ];
+    // an odd number of bytes is padded with a zero byte
+    units.extend(
+        data.data
+            .chunks(2)
+            .map(|x| u16::from_le_bytes([x[0], *x.get(1).unwrap_or(&0)])),
+    );
+    Ok(units)
+}
+
+fn push_i32(units: &mut Vec<u16>, value: i32) {
+    units.extend([value as u16, (value >> 16) as u16]);
+}
+
+/// Checks that the register number fits into `bits` bits.
+fn reg(value: u16, bits: u32) -> Result<u16> {
+    if bits < 16 && value >> bits != 0 {
+        return Err(Error::InvalidData(format!(
+            "register v{} does not fit into {} bits",
+            value, bits
+        )));
+    }
+    Ok(value)
+}
+
+/// Checks that the signed literal fits into `bits` bits and returns its
+/// two's complement representation truncated to these bits.
+fn literal(value: i64, bits: u32) -> Result<u64> {
+    let min = -(1i64 << (bits - 1));
+    let max = (1i64 << (bits - 1)) - 1;
+    if value < min || value > max {
+        return Err(Error::InvalidData(format!(
+            "literal {} does not fit into {} bits",
+            value, bits
+        )));
+    }
+    Ok(value as u64 & ((1u64 << bits) - 1))
+}
+
+/// Checks that the index fits into `bits` bits.
+fn index(value: u32, bits: u32) -> Result<u32> {
+    if bits < 32 && value >> bits != 0 {
+        return Err(Error::InvalidData(format!(
+            "index {:#x} does not fit into {} bits",
+            value, bits
+        )));
+    }
+    Ok(value)
+}
+
+/// Packs up to five 4-bit registers into the `A|G` byte of the first code
+/// unit (already shifted) and the `F|E|D|C` code unit.
+fn register_list(regs: &[u16]) -> Result<(u16, u16)> {
+    if regs.len() > 5 {
+        return Err(Error::InvalidData(format!(
+            "at most 5 registers can be passed, got {}",
+            regs.len()
+        )));
+    }
+    let mut args = 0;
+    for (i, r) in regs.iter().take(4).enumerate() {
+        args |= reg(*r, 4)? << (i * 4);
+    }
+    let g = match regs.get(4) {
+        Some(r) => reg(*r, 4)?,
+        None => 0,
+    };
+    Ok(((regs.len() as u16) << 12 | g << 8, args))
+}
+
+/// Returns the number of registers of a range, which may end at 0x10000
+/// after v65535.
+fn register_count(regs: &Range<u32>) -> Result<u16> {
+    let count = regs.end.checked_sub(regs.start).unwrap_or(u32::MAX);
+    if count > 0xFF || regs.end > 0x10000 {
+        return Err(Error::InvalidData(format!(
+            "invalid register range v{}..v{}",
+            regs.start, regs.end
+        )));
+    }
+    Ok(count as u16)
+}
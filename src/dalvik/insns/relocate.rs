@@ -0,0 +1,512 @@
+//! Relocation of instructions whose size changed
+//!
+//! Replacing an instruction by one of a different size moves all following
+//! instructions. [apply_edits] replaces, inserts and removes instructions of
+//! a code item and fixes up everything that refers to code addresses: the
+//! offsets of `goto`, `if-*` and of the references to payloads, the targets
+//! stored in switch payloads, the try blocks and the addresses of the catch
+//! handlers. Payloads are realigned to even addresses. A branch that no
+//! longer fits into its format is reported as an error and the code item is
+//! left unchanged.
+//!
+//! The debug information is stored outside of the code item. Its
+//! `debug_info_item` is rewritten with [relocate_debug_info], or the
+//! addresses are translated one by one with [Relocation::address].
+//!
+//! ```ignore
+//! // replace `const/4 v0, 0` at address 4 by `const/16 v0, 0x1234`
+//! let opcode = insns::find_opcode("const/16").unwrap();
+//! let units = encode(opcode, &Operands::Format21s { a: 0, b: 0x1234 })?;
+//! let relocation = apply_edits(&mut code, vec![InsnEdit { address: 4, units }])?;
+//! let debug_info = relocate_debug_info(&raw_debug_info, &relocation)?;
+//! ```
+
+use binrw::{BinRead, BinWrite};
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+};
+
+use crate::dalvik::{
+    dex::{
+        CodeItem, DebugInfoItem, EncodedCatchHandler, EncodedCatchHandlerList, EncodedTypeAddrPair,
+        SLeb128, TryItem, UByte, UInt, ULeb128, ULeb128p1, UShort,
+    },
+    error::{Error, Result},
+};
+
+use super::{code_regions, code_units, insn_size, RegionKind, OPCODES};
+
+/// Replaces a single instruction of a code item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InsnEdit {
+    /// address of the replaced instruction in code units
+    pub address: UInt,
+
+    /// The new instructions, or nothing to remove the instruction. Branch
+    /// offsets are relative to `address` in the original code, so a branch
+    /// that is copied unchanged keeps its target.
+    pub units: Vec<UShort>,
+}
+
+/// Translates addresses of the original instructions into the relocated
+/// ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Relocation {
+    /// old and new address of every instruction and payload, sorted by the
+    /// old address and followed by the old and new size of the code
+    moves: Vec<(UInt, UInt)>,
+}
+
+impl Relocation {
+    /// Translates an address of the original instructions, in code units.
+    /// An address within an instruction is translated relative to its
+    /// start, and the address of a removed instruction is that of the
+    /// instruction following it.
+    pub fn address(&self, address: UInt) -> UInt {
+        let index = self.moves.partition_point(|(old, _)| *old <= address);
+        match index.checked_sub(1) {
+            Some(index) => {
+                let (old, new) = self.moves[index];
+                new + (address - old)
+            }
+            None => address,
+        }
+    }
+}
+
+/// An instruction or payload, in code units.
+pub(super) struct Piece {
+    pub kind: RegionKind,
+
+    /// address in the original code
+    pub address: UInt,
+    pub units: Vec<UShort>,
+
+    /// addresses of the instructions referencing a payload
+    pub referrers: Vec<UInt>,
+
+    /// whether the piece follows another one with the same original
+    /// address, i.e. it was inserted by an edit
+    pub inserted: bool,
+}
+
+/// Splits the instructions of a code item into single instructions and
+/// payloads.
+pub(super) fn pieces(item: &CodeItem) -> Result<Vec<Piece>> {
+    let units = code_units(&item.insns);
+    let mut pieces = Vec::new();
+    for region in code_regions(item)? {
+        let range = region.range.start / 2..region.range.end / 2;
+        if region.kind != RegionKind::Code {
+            pieces.push(Piece {
+                kind: region.kind,
+                address: range.start as UInt,
+                units: units[range].to_vec(),
+                referrers: region.referrers.iter().map(|x| (*x / 2) as UInt).collect(),
+                inserted: false,
+            });
+            continue;
+        }
+        let mut address = range.start;
+        while address < range.end {
+            let length = OPCODES[(units[address] & 0xFF) as usize].length as usize;
+            pieces.push(Piece {
+                kind: RegionKind::Code,
+                address: address as UInt,
+                units: units[address..address + length].to_vec(),
+                referrers: Vec::new(),
+                inserted: false,
+            });
+            address += length;
+        }
+    }
+    Ok(pieces)
+}
+
+/// Applies the given edits to a code item and relocates all instructions
+/// that moved. Every edit has to address the start of an instruction and
+/// instructions can only be edited once. Payloads can neither be edited nor
+/// inserted.
+///
+/// A `packed-switch` or `sparse-switch` has to remain the first
+/// instruction of its replacement, as the targets in its payload are
+/// relative to it.
+pub fn apply_edits(item: &mut CodeItem, edits: Vec<InsnEdit>) -> Result<Relocation> {
+    let mut replacements = HashMap::with_capacity(edits.len());
+    for edit in edits {
+        let insns = split(&edit.units, edit.address)?;
+        if replacements.insert(edit.address, insns).is_some() {
+            return Err(Error::InvalidData(format!(
+                "instruction at {:#x} is edited twice",
+                edit.address as u64 * 2
+            )));
+        }
+    }
+
+    let mut edited = Vec::new();
+    for piece in pieces(item)? {
+        let Some(insns) = replacements.remove(&piece.address) else {
+            edited.push(piece);
+            continue;
+        };
+        if piece.kind != RegionKind::Code {
+            return Err(Error::InvalidData(format!(
+                "{:?} at {:#x} can't be edited",
+                piece.kind,
+                piece.address as u64 * 2
+            )));
+        }
+        let opcode = piece.units[0] & 0xFF;
+        let first = insns.first().map(|x| x[0] & 0xFF);
+        if matches!(opcode, 0x2B | 0x2C) && first != Some(opcode) {
+            return Err(Error::InvalidData(format!(
+                "{} at {:#x} has to remain the first instruction of its replacement",
+                OPCODES[opcode as usize].name,
+                piece.address as u64 * 2
+            )));
+        }
+        if insns.is_empty() {
+            // removed instructions keep their place in the relocation
+            edited.push(Piece {
+                units: Vec::new(),
+                ..piece
+            });
+            continue;
+        }
+        for (i, units) in insns.into_iter().enumerate() {
+            edited.push(Piece {
+                kind: RegionKind::Code,
+                address: piece.address,
+                units,
+                referrers: Vec::new(),
+                inserted: i > 0,
+            });
+        }
+    }
+    if let Some(address) = replacements.keys().min() {
+        return Err(Error::InvalidData(format!(
+            "no instruction starts at {:#x}",
+            *address as u64 * 2
+        )));
+    }
+    layout(item, edited)
+}
+
+/// Splits the code units of an edit into single instructions.
+fn split(units: &[UShort], address: UInt) -> Result<Vec<Vec<UShort>>> {
+    let mut insns = Vec::new();
+    let mut index = 0;
+    while index < units.len() {
+        if matches!(units[index], 0x0100 | 0x0200 | 0x0300) {
+            return Err(Error::InvalidData(format!(
+                "edit at {:#x} inserts a payload",
+                address as u64 * 2
+            )));
+        }
+        match insn_size(units, index) {
+            Some(size) if index + size <= units.len() => {
+                insns.push(units[index..index + size].to_vec());
+                index += size;
+            }
+            _ => {
+                return Err(Error::InvalidData(format!(
+                    "edit at {:#x} contains an incomplete or unknown instruction",
+                    address as u64 * 2
+                )));
+            }
+        }
+    }
+    Ok(insns)
+}
+
+/// Places the given pieces one after another, adjusts everything that
+/// refers to their addresses and replaces the instructions of the code
+/// item. Nothing is changed if an error is returned.
+pub(super) fn layout(item: &mut CodeItem, mut pieces: Vec<Piece>) -> Result<Relocation> {
+    // payloads have to start at an even address, which is restored by a
+    // `nop` in front of them if needed
+    let mut next = 0;
+    let mut addresses = Vec::with_capacity(pieces.len());
+    for piece in &pieces {
+        if piece.kind != RegionKind::Code && next % 2 == 1 {
+            next += 1;
+        }
+        addresses.push(next);
+        next += piece.units.len() as UInt;
+    }
+
+    let mut moves: Vec<(UInt, UInt)> = pieces
+        .iter()
+        .zip(addresses.iter().copied())
+        .filter(|(piece, _)| !piece.inserted)
+        .map(|(piece, address)| (piece.address, address))
+        .collect();
+    moves.push(((item.insns.len() / 2) as UInt, next));
+    let relocation = Relocation { moves };
+    for (piece, address) in pieces.iter_mut().zip(&addresses) {
+        relocate(piece, *address, &relocation)?;
+    }
+
+    let mut tries = Vec::with_capacity(item.tries.len());
+    for try_item in &item.tries {
+        let start = relocation.address(try_item.start_addr);
+        let end = relocation.address(try_item.start_addr + try_item.insn_count as UInt);
+        let insn_count = UShort::try_from(end - start).map_err(|_| {
+            Error::InvalidData(format!(
+                "try block at address {:#x} exceeds {} code units",
+                start,
+                UShort::MAX
+            ))
+        })?;
+        tries.push(TryItem {
+            start_addr: start,
+            insn_count,
+            handler_off: try_item.handler_off,
+        });
+    }
+
+    // handler addresses change the size of the encoded handlers, so all
+    // offsets are computed again
+    let mut handler_offsets = HashMap::new();
+    let mut handlers = None;
+    if let Some(old) = &item.handlers {
+        let mut out = Cursor::new(Vec::new());
+        let size = ULeb128(old.size.0);
+        size.write_le(&mut out)?;
+        let mut list = Vec::with_capacity(old.list.len());
+        for handler in &old.list {
+            let offset = UShort::try_from(out.position())
+                .map_err(|_| Error::InvalidData("catch handlers exceed 64 KiB".to_string()))?;
+            handler_offsets.insert(handler.offset, offset);
+            let handler = EncodedCatchHandler {
+                offset,
+                size: SLeb128(handler.size.0),
+                handlers: handler
+                    .handlers
+                    .iter()
+                    .map(|x| EncodedTypeAddrPair {
+                        type_idx: ULeb128(x.type_idx.0),
+                        addr: ULeb128(relocation.address(x.addr.0)),
+                    })
+                    .collect(),
+                catch_all_addr: handler
+                    .catch_all_addr
+                    .as_ref()
+                    .map(|x| ULeb128(relocation.address(x.0))),
+            };
+            handler.write_le(&mut out)?;
+            list.push(handler);
+        }
+        handlers = Some(EncodedCatchHandlerList { size, list });
+    }
+    for try_item in &mut tries {
+        match handler_offsets.get(&try_item.handler_off) {
+            Some(offset) => try_item.handler_off = *offset,
+            None => {
+                return Err(Error::InvalidData(format!(
+                    "try block at address {:#x} refers to no catch handler",
+                    try_item.start_addr
+                )));
+            }
+        }
+    }
+
+    let mut insns = Vec::with_capacity(next as usize * 2);
+    for (piece, address) in pieces.iter().zip(addresses) {
+        if insns.len() < address as usize * 2 {
+            insns.extend_from_slice(&[0, 0]);
+        }
+        insns.extend(piece.units.iter().flat_map(|x| x.to_le_bytes()));
+    }
+    item.set_insns(insns);
+    item.set_tries(tries, handlers);
+    Ok(relocation)
+}
+
+/// Adjusts the branch offsets of an instruction or the targets of a switch
+/// payload that is now placed at `address`.
+fn relocate(piece: &mut Piece, address: UInt, relocation: &Relocation) -> Result<()> {
+    // translates an offset relative to `from` in the original code into
+    // one relative to `to`
+    let target = |from: UInt, to: UInt, offset: i64| -> Result<i64> {
+        match UInt::try_from(from as i64 + offset) {
+            Ok(target) => Ok(relocation.address(target) as i64 - to as i64),
+            Err(_) => Err(Error::InvalidData(format!(
+                "branch at address {:#x} points before the instructions",
+                from
+            ))),
+        }
+    };
+    let units = &mut piece.units;
+    if units.is_empty() {
+        return Ok(());
+    }
+
+    match piece.kind {
+        RegionKind::Code => match units[0] & 0xFF {
+            // goto +AA
+            0x28 => {
+                let offset = target(piece.address, address, (units[0] >> 8) as i8 as i64)?;
+                let offset = i8::try_from(offset).map_err(|_| fits(units, address))?;
+                units[0] = (units[0] & 0xFF) | (offset as u8 as UShort) << 8;
+            }
+            // goto/16 +AAAA, if-test vA, vB, +CCCC, if-testz vAA, +BBBB
+            0x29 | 0x32..=0x3D => {
+                let offset = target(piece.address, address, units[1] as i16 as i64)?;
+                units[1] = i16::try_from(offset).map_err(|_| fits(units, address))? as UShort;
+            }
+            // goto/32 +AAAAAAAA and references to payloads
+            0x2A | 0x26 | 0x2B | 0x2C => {
+                let offset = (units[1] as UInt | (units[2] as UInt) << 16) as i32;
+                let offset = target(piece.address, address, offset as i64)?;
+                let offset = i32::try_from(offset).map_err(|_| fits(units, address))? as UInt;
+                units[1] = offset as UShort;
+                units[2] = (offset >> 16) as UShort;
+            }
+            _ => {}
+        },
+        RegionKind::PackedSwitch | RegionKind::SparseSwitch => {
+            // switch targets are relative to the referring instruction
+            let size = units[1] as usize;
+            let first = match piece.kind {
+                RegionKind::PackedSwitch => 4,
+                _ => 2 + size * 2,
+            };
+            for i in 0..size {
+                let pos = first + i * 2;
+                let offset = (units[pos] as UInt | (units[pos + 1] as UInt) << 16) as i32;
+                let mut new = None;
+                for referrer in &piece.referrers {
+                    let to = relocation.address(*referrer);
+                    let offset = target(*referrer, to, offset as i64)?;
+                    if new.is_some_and(|x| x != offset) {
+                        return Err(Error::InvalidData(format!(
+                            "payload at address {:#x} is shared by switches that moved apart",
+                            piece.address
+                        )));
+                    }
+                    new = Some(offset);
+                }
+                if let Some(offset) = new {
+                    let offset = offset as i32 as UInt;
+                    units[pos] = offset as UShort;
+                    units[pos + 1] = (offset >> 16) as UShort;
+                }
+            }
+        }
+        RegionKind::FillArrayData => {}
+    }
+    Ok(())
+}
+
+fn fits(units: &[UShort], address: UInt) -> Error {
+    Error::InvalidData(format!(
+        "branch of {} at address {:#x} no longer fits into its format",
+        OPCODES[(units[0] & 0xFF) as usize].name,
+        address
+    ))
+}
+
+/// Rewrites the address advances of an encoded `debug_info_item` for the
+/// relocated instructions. Line numbers, locals and all other entries are
+/// kept as they are.
+pub fn relocate_debug_info(data: &[UByte], relocation: &Relocation) -> Result<Vec<UByte>> {
+    let mut reader = Cursor::new(data);
+    DebugInfoItem::read(&mut reader)?;
+    let mut out = data[..reader.position() as usize].to_vec();
+
+    // address registers of the original and of the written sequence
+    let mut old: UInt = 0;
+    let mut new: UInt = 0;
+    // advances the written address to the translated one and returns the
+    // difference that is still to be written
+    let advance = |old: UInt, new: UInt| -> Result<UInt> {
+        relocation.address(old).checked_sub(new).ok_or_else(|| {
+            Error::InvalidData(format!(
+                "debug info address {:#x} is not in increasing order",
+                old
+            ))
+        })
+    };
+
+    loop {
+        let mut opcode = [0u8; 1];
+        if reader.read(&mut opcode)? != 1 {
+            return Err(Error::Custom("Unexpected EOF"));
+        }
+        let opcode = opcode[0];
+        let start = reader.position() as usize;
+        match opcode {
+            DebugInfoItem::DBG_END_SEQUENCE => {
+                out.push(opcode);
+                return Ok(out);
+            }
+            DebugInfoItem::DBG_ADVANCE_PC => {
+                let addr_diff = ULeb128::read(&mut reader)?;
+                old = old
+                    .checked_add(addr_diff.0)
+                    .ok_or_else(|| Error::InvalidData("Debug info address overflow".to_string()))?;
+                // written together with the next entry that needs it
+                continue;
+            }
+            DebugInfoItem::DBG_ADVANCE_LINE => {
+                SLeb128::read(&mut reader)?;
+            }
+            DebugInfoItem::DBG_START_LOCAL => {
+                ULeb128::read(&mut reader)?;
+                ULeb128p1::read(&mut reader)?;
+                ULeb128p1::read(&mut reader)?;
+            }
+            DebugInfoItem::DBG_START_LOCAL_EXTENDED => {
+                ULeb128::read(&mut reader)?;
+                ULeb128p1::read(&mut reader)?;
+                ULeb128p1::read(&mut reader)?;
+                ULeb128p1::read(&mut reader)?;
+            }
+            DebugInfoItem::DBG_END_LOCAL | DebugInfoItem::DBG_RESTART_LOCAL => {
+                ULeb128::read(&mut reader)?;
+            }
+            DebugInfoItem::DBG_SET_FILE => {
+                ULeb128p1::read(&mut reader)?;
+            }
+            DebugInfoItem::DBG_SET_PROLOGUE_END | DebugInfoItem::DBG_SET_EPILOGUE_BEGIN => {}
+            _ => {
+                // special opcodes advance the address and the line at once
+                let adjusted = opcode - DebugInfoItem::DBG_FIRST_SPECIAL;
+                old = old
+                    .checked_add((adjusted / DebugInfoItem::DBG_LINE_RANGE) as UInt)
+                    .ok_or_else(|| Error::InvalidData("Debug info address overflow".to_string()))?;
+                let line = (adjusted % DebugInfoItem::DBG_LINE_RANGE) as UInt;
+                let mut addr_diff = advance(old, new)?;
+                let mut special = DebugInfoItem::DBG_FIRST_SPECIAL as u64
+                    + line as u64
+                    + addr_diff as u64 * DebugInfoItem::DBG_LINE_RANGE as u64;
+                if special > UByte::MAX as u64 {
+                    out.push(DebugInfoItem::DBG_ADVANCE_PC);
+                    leb128::write::unsigned(&mut out, addr_diff as u64)?;
+                    addr_diff = 0;
+                    special = DebugInfoItem::DBG_FIRST_SPECIAL as u64 + line as u64;
+                }
+                out.push(special as UByte);
+                new += addr_diff;
+                continue;
+            }
+        }
+
+        // all other entries take effect at the current address
+        if !matches!(
+            opcode,
+            DebugInfoItem::DBG_ADVANCE_LINE | DebugInfoItem::DBG_SET_FILE
+        ) {
+            let addr_diff = advance(old, new)?;
+            if addr_diff != 0 {
+                out.push(DebugInfoItem::DBG_ADVANCE_PC);
+                leb128::write::unsigned(&mut out, addr_diff as u64)?;
+                new += addr_diff;
+            }
+        }
+        out.push(opcode);
+        out.extend_from_slice(&data[start..reader.position() as usize]);
+    }
+}
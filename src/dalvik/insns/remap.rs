@@ -0,0 +1,219 @@
+//! Rewriting of index operands
+//!
+//! [remap] passes every string, type, field, method, prototype, call site
+//! and method handle index used by a code item through an [IndexMap], which
+//! is what merging files or re-sorting their id sections requires. This
+//! covers the catch types of the try blocks as well, while payloads are
+//! never touched (see [code_regions]).
+//!
+//! A `const-string` whose new string index exceeds 16 bits is widened to
+//! `const-string/jumbo`. As this moves all following instructions, branch
+//! offsets, switch targets, try blocks and catch handlers are adjusted and
+//! payloads are realigned. The debug information is stored outside of the
+//! code item, its addresses can be translated with [Remapped::address].
+//! [widened_const_strings] finds the instructions that would be widened
+//! without changing the code.
+//!
+//! [remap_with] takes a function instead of an [IndexMap], which also gets
+//! the address of the instruction, e.g. to widen a single `const-string`
+//! after its operand has been patched.
+//!
+//! ```ignore
+//! let mut map = IndexMap::default();
+//! map.strings.insert(3, 0x12345);
+//! let remapped = remap(&mut code, &map)?;
+//! assert_eq!(remapped.widened, 1);
+//! ```
+
+use std::collections::HashMap;
+
+use crate::dalvik::{
+    dex::{CodeItem, UInt, UShort},
+    error::{Error, Result},
+};
+
+use super::{
+    code_regions, code_units,
+    relocate::{layout, pieces, Relocation},
+    RegionKind, OPCODES,
+};
+
+/// The kind of id an index operand refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IndexKind {
+    String,
+    Type,
+    Field,
+    Method,
+    Proto,
+    CallSite,
+    MethodHandle,
+}
+
+impl IndexKind {
+    /// Returns the kind of the index stored in the second code unit of
+    /// instructions with the given opcode. `invoke-polymorphic` refers to a
+    /// method and additionally stores a prototype in its fourth code unit.
+    pub fn of(opcode: u8) -> Option<IndexKind> {
+        match opcode {
+            0x1A | 0x1B => Some(IndexKind::String),
+            0x1C | 0x1F | 0x20 | 0x22..=0x25 => Some(IndexKind::Type),
+            0x52..=0x6D => Some(IndexKind::Field),
+            0x6E..=0x72 | 0x74..=0x78 | 0xFA | 0xFB => Some(IndexKind::Method),
+            0xFC | 0xFD => Some(IndexKind::CallSite),
+            0xFE => Some(IndexKind::MethodHandle),
+            0xFF => Some(IndexKind::Proto),
+            _ => None,
+        }
+    }
+}
+
+/// Remapping tables from old to new indices, one per [IndexKind]. Indices
+/// without an entry are left unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct IndexMap {
+    pub strings: HashMap<UInt, UInt>,
+    pub types: HashMap<UInt, UInt>,
+    pub fields: HashMap<UInt, UInt>,
+    pub methods: HashMap<UInt, UInt>,
+    pub protos: HashMap<UInt, UInt>,
+    pub call_sites: HashMap<UInt, UInt>,
+    pub method_handles: HashMap<UInt, UInt>,
+}
+
+impl IndexMap {
+    /// Returns the new value of the given index.
+    pub fn get(&self, kind: IndexKind, index: UInt) -> UInt {
+        let table = match kind {
+            IndexKind::String => &self.strings,
+            IndexKind::Type => &self.types,
+            IndexKind::Field => &self.fields,
+            IndexKind::Method => &self.methods,
+            IndexKind::Proto => &self.protos,
+            IndexKind::CallSite => &self.call_sites,
+            IndexKind::MethodHandle => &self.method_handles,
+        };
+        table.get(&index).copied().unwrap_or(index)
+    }
+}
+
+/// The result of [remap].
+#[derive(Debug, Clone, Default)]
+pub struct Remapped {
+    /// number of `const-string` instructions widened to
+    /// `const-string/jumbo`
+    pub widened: usize,
+
+    /// how the instructions moved, e.g. to rewrite the debug information
+    /// with [relocate_debug_info](super::relocate::relocate_debug_info)
+    pub relocation: Relocation,
+}
+
+impl Remapped {
+    /// Translates an address of the original instructions, in code units,
+    /// into the rewritten ones, e.g. for the entries of the debug
+    /// information.
+    pub fn address(&self, address: UInt) -> UInt {
+        self.relocation.address(address)
+    }
+}
+
+/// Rewrites all index operands of the given code item.
+pub fn remap(item: &mut CodeItem, map: &IndexMap) -> Result<Remapped> {
+    remap_with(item, |kind, _, index| map.get(kind, index))
+}
+
+/// Same as [remap], but the new value of every index is returned by `map`,
+/// which is called with the kind of the index, the address of the
+/// instruction in code units and the current index. Catch types are passed
+/// with the address of their handler.
+pub fn remap_with<F>(item: &mut CodeItem, mut map: F) -> Result<Remapped>
+where
+    F: FnMut(IndexKind, UInt, UInt) -> UInt,
+{
+    let mut remapped = Remapped::default();
+    let mut pieces = pieces(item)?;
+    for piece in pieces.iter_mut().filter(|x| x.kind == RegionKind::Code) {
+        if rewrite(&mut piece.units, piece.address, &mut map)? {
+            remapped.widened += 1;
+        }
+    }
+    if let Some(handlers) = &mut item.handlers {
+        for pair in handlers.list.iter_mut().flat_map(|x| &mut x.handlers) {
+            pair.type_idx.0 = map(IndexKind::Type, pair.addr.0, pair.type_idx.0);
+        }
+    }
+
+    // widened instructions move all following ones
+    remapped.relocation = layout(item, pieces)?;
+    Ok(remapped)
+}
+
+/// Returns the addresses, in code units, of all `const-string` instructions
+/// whose string index exceeds 16 bits after applying the given map, i.e.
+/// those [remap] widens to `const-string/jumbo`. The code item is not
+/// changed.
+pub fn widened_const_strings(item: &CodeItem, map: &IndexMap) -> Result<Vec<UInt>> {
+    let units = code_units(&item.insns);
+    let mut addresses = Vec::new();
+    for region in code_regions(item)? {
+        if region.kind != RegionKind::Code {
+            continue;
+        }
+        let mut address = region.range.start / 2;
+        while address < region.range.end / 2 {
+            let opcode = &OPCODES[(units[address] & 0xFF) as usize];
+            if opcode.opcode == 0x1A {
+                let index = map.get(IndexKind::String, units[address + 1] as UInt);
+                if index > UShort::MAX as UInt {
+                    addresses.push(address as UInt);
+                }
+            }
+            address += opcode.length as usize;
+        }
+    }
+    Ok(addresses)
+}
+
+/// Rewrites the index operands of a single instruction and returns whether
+/// it was widened.
+fn rewrite<F>(insn: &mut Vec<UShort>, address: UInt, map: &mut F) -> Result<bool>
+where
+    F: FnMut(IndexKind, UInt, UInt) -> UInt,
+{
+    let opcode = &OPCODES[(insn[0] & 0xFF) as usize];
+    let Some(kind) = IndexKind::of(opcode.opcode) else {
+        return Ok(false);
+    };
+    let narrow = |kind: IndexKind, index: UInt| -> Result<UShort> {
+        UShort::try_from(index).map_err(|_| {
+            Error::InvalidData(format!(
+                "{:?} index {:#x} of {} at address {:#x} exceeds 16 bits",
+                kind, index, opcode.name, address
+            ))
+        })
+    };
+
+    if opcode.opcode == 0x1B {
+        let index = map(kind, address, insn[1] as UInt | (insn[2] as UInt) << 16);
+        insn[1] = index as UShort;
+        insn[2] = (index >> 16) as UShort;
+        return Ok(false);
+    }
+    let index = map(kind, address, insn[1] as UInt);
+    if opcode.opcode == 0x1A && index > UShort::MAX as UInt {
+        // const-string/jumbo vAA, string@BBBBBBBB
+        *insn = vec![
+            0x1B | (insn[0] & 0xFF00),
+            index as UShort,
+            (index >> 16) as UShort,
+        ];
+        return Ok(true);
+    }
+    insn[1] = narrow(kind, index)?;
+    if matches!(opcode.opcode, 0xFA | 0xFB) {
+        let proto = map(IndexKind::Proto, address, insn[3] as UInt);
+        insn[3] = narrow(IndexKind::Proto, proto)?;
+    }
+    Ok(false)
+}
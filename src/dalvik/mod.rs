@@ -1,4 +1,5 @@
 pub mod dex;
 pub mod error;
 pub mod insns;
-pub mod file;
\ No newline at end of file
+pub mod file;
+pub mod sdk;
@@ -0,0 +1,181 @@
+//! DEX versions and Android API levels
+//!
+//! Every release of the format adds constructs that older runtimes reject,
+//! so the version in the magic determines the lowest API level a file can
+//! be loaded on. [min_api_level] and [max_version] map between both, and
+//! [check_version] finds constructs that are not allowed in the version a
+//! file declares, e.g. an `invoke-polymorphic` in a `035` file that was
+//! repackaged without updating its magic:
+//!
+//! ```ignore
+//! for incompatibility in sdk::check_version(&mut dex)? {
+//!     println!("{:?} requires version {:03}", incompatibility.feature,
+//!         incompatibility.feature.min_version());
+//! }
+//! ```
+
+use std::io::{Read, Seek};
+
+use super::{
+    dex::{AccessFlags, MapListItemType, UInt},
+    error::Result,
+    file::{Dex, IDex},
+};
+
+/// All versions of the format, oldest first.
+pub const VERSIONS: [UInt; 6] = [35, 37, 38, 39, 40, 41];
+
+/// Returns the lowest API level that accepts files of the given version, or
+/// `None` if the version is unknown.
+pub fn min_api_level(version: UInt) -> Option<UInt> {
+    match version {
+        35 => Some(1),
+        37 => Some(24),
+        38 => Some(26),
+        39 => Some(28),
+        40 => Some(29),
+        41 => Some(35),
+        _ => None,
+    }
+}
+
+/// Returns the newest version that can be loaded at the given API level.
+pub fn max_version(api_level: UInt) -> UInt {
+    VERSIONS
+        .iter()
+        .rev()
+        .find(|x| min_api_level(**x).is_some_and(|x| x <= api_level))
+        .copied()
+        .unwrap_or(35)
+}
+
+/// A construct that is only valid from a certain version on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// non-abstract instance and static methods other than `<clinit>` in
+    /// interfaces
+    DefaultMethods,
+
+    /// `invoke-polymorphic` and `invoke-polymorphic/range`
+    InvokePolymorphic,
+
+    /// `invoke-custom` and `invoke-custom/range`
+    InvokeCustom,
+
+    /// a `method_handle_item` section
+    MethodHandles,
+
+    /// a `call_site_id_item` section
+    CallSites,
+
+    /// `const-method-handle`
+    ConstMethodHandle,
+
+    /// `const-method-type`
+    ConstMethodType,
+}
+
+impl Feature {
+    /// Returns the first version that allows this feature.
+    pub fn min_version(&self) -> UInt {
+        match self {
+            Feature::DefaultMethods => 37,
+            Feature::InvokePolymorphic
+            | Feature::InvokeCustom
+            | Feature::MethodHandles
+            | Feature::CallSites => 38,
+            Feature::ConstMethodHandle | Feature::ConstMethodType => 39,
+        }
+    }
+
+    /// Returns the feature an opcode belongs to, if it requires a version
+    /// newer than `035`.
+    pub fn of_opcode(opcode: u8) -> Option<Feature> {
+        match opcode {
+            0xFA | 0xFB => Some(Feature::InvokePolymorphic),
+            0xFC | 0xFD => Some(Feature::InvokeCustom),
+            0xFE => Some(Feature::ConstMethodHandle),
+            0xFF => Some(Feature::ConstMethodType),
+            _ => None,
+        }
+    }
+}
+
+/// A use of a [Feature] that the version of the file doesn't allow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Incompatibility {
+    pub feature: Feature,
+
+    /// index of the class definition using the feature, `None` for
+    /// sections
+    pub class_def: Option<u32>,
+
+    /// index into the `method_ids` list of the method using the feature
+    pub method: Option<u32>,
+
+    /// byte offset of the instruction within the code of the method
+    pub offset: Option<usize>,
+}
+
+/// Finds all constructs that require a newer version than the one declared
+/// in the magic of the file. Files with an unknown version are not checked.
+///
+/// Every class is parsed with [Dex::load_class] and all of its methods are
+/// disassembled. Classes that can't be parsed are skipped, the verifier
+/// reports them separately.
+pub fn check_version<R: Read + Seek>(dex: &mut Dex<'_, R>) -> Result<Vec<Incompatibility>> {
+    let mut found = Vec::new();
+    let Some(version) = dex.get_header().version() else {
+        return Ok(found);
+    };
+    if min_api_level(version).is_none() {
+        return Ok(found);
+    }
+
+    let sections = [
+        (MapListItemType::MethodHandleItem, Feature::MethodHandles),
+        (MapListItemType::CallSiteIdItem, Feature::CallSites),
+    ];
+    for (type_, feature) in sections {
+        if version < feature.min_version() && dex.get_map_list().item_size(type_) > 0 {
+            found.push(Incompatibility {
+                feature,
+                class_def: None,
+                method: None,
+                offset: None,
+            });
+        }
+    }
+
+    for index in 0..dex.get_header().class_defs_size {
+        let Ok(class) = dex.load_class(index) else {
+            continue;
+        };
+        let is_interface = class
+            .flags
+            .as_ref()
+            .is_some_and(|x| x.contains(AccessFlags::INTERFACE));
+        for (_, method) in class.get_methods() {
+            let mut uses = Vec::new();
+            if is_interface && method.code.is_some() && method.name.as_str() != "<clinit>" {
+                uses.push((Feature::DefaultMethods, None));
+            }
+            if let Ok(insns) = method.disasm(dex) {
+                uses.extend(insns.iter().filter_map(|insn| {
+                    Feature::of_opcode(insn.opcode.opcode).map(|x| (x, Some(insn.range.start)))
+                }));
+            }
+            found.extend(
+                uses.into_iter()
+                    .filter(|(feature, _)| version < feature.min_version())
+                    .map(|(feature, offset)| Incompatibility {
+                        feature,
+                        class_def: Some(index),
+                        method: Some(method.identity),
+                        offset,
+                    }),
+            );
+        }
+    }
+    Ok(found)
+}
@@ -0,0 +1,307 @@
+//! Annotated disassembly listings of whole classes
+//!
+//! [write_class_disasm] prints a class the way a reverse engineer reads it:
+//! the class header, all fields with the initial values of static fields,
+//! and every method with its register counts, try blocks, source lines and
+//! instructions, where branch targets are replaced by labels:
+//!
+//! ```text
+//! class public LFoo;
+//!   extends Ljava/lang/Object;
+//!   source "Foo.java"
+//!
+//!   field private static final count:I = 0x5
+//!
+//!   method public static main([Ljava/lang/String;)V
+//!     registers 2, ins 1, outs 1
+//!     try 0x0000..0x0006
+//!       catch Ljava/lang/Exception; -> :L0008
+//!     0x0000  .line 3
+//!     0x0000  if-eqz v1, :L0006
+//!     ...
+//!   :L0006
+//!     0x0006  return-void
+//!   end method
+//! ```
+//!
+//! Types and references are formatted with [PrettyOptions], the parts of
+//! the listing are selected with [DisasmOptions]. Unlike the
+//! [smali writer](crate::smali), the output is not meant to be assembled
+//! again.
+
+use std::io::Write;
+
+use crate::{
+    dalvik::{
+        dex::AccessFlags,
+        error::Result,
+        file::{field::DexField, method::DexMethod, DexClassDef, IDexRef},
+        insns::{self, Insn, Payload},
+    },
+    pretty::{format_field, format_method, pretty_insn, pretty_type, PrettyOptions},
+    smali::{Labels, SmaliWrite},
+};
+
+/// Parts of a listing written by [write_class_disasm].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisasmOptions {
+    /// formatting of types and references
+    pub pretty: PrettyOptions,
+
+    /// Include fields and the initial values of static fields.
+    pub fields: bool,
+
+    /// Include the register, argument and output counts of every method.
+    pub registers: bool,
+
+    /// Include the source lines of the debug info.
+    pub lines: bool,
+
+    /// Replace branch and switch offsets with labels.
+    pub labels: bool,
+
+    /// Include try blocks and their catch handlers.
+    pub tries: bool,
+
+    /// Prefix every instruction with its byte offset within the code.
+    pub offsets: bool,
+}
+
+impl Default for DisasmOptions {
+    fn default() -> Self {
+        DisasmOptions {
+            pretty: PrettyOptions::smali(),
+            fields: true,
+            registers: true,
+            lines: true,
+            labels: true,
+            tries: true,
+            offsets: true,
+        }
+    }
+}
+
+/// Writes the listing of a class, see the [module docs](self).
+pub fn write_class_disasm<W: Write>(
+    dex: IDexRef<'_>,
+    class: &DexClassDef,
+    out: &mut W,
+    options: &DisasmOptions,
+) -> Result<()> {
+    let pretty = &options.pretty;
+    writeln!(
+        out,
+        "class {}{}",
+        flags(&class.flags),
+        pretty_type(&class.type_, pretty)
+    )?;
+    if let Some(superclass) = &class.super_class {
+        writeln!(out, "  extends {}", pretty_type(superclass, pretty))?;
+    }
+    for interface in &class.interfaces {
+        writeln!(out, "  implements {}", pretty_type(interface, pretty))?;
+    }
+    if let Some(source) = &class.source_file {
+        writeln!(out, "  source \"{}\"", source.escape_default())?;
+    }
+
+    if options.fields {
+        for (_, field) in class.get_fields() {
+            writeln!(out)?;
+            write_field(dex, field, out, options)?;
+        }
+    }
+    for (_, method) in class.get_methods() {
+        writeln!(out)?;
+        write_method_disasm(dex, method, out, options)?;
+    }
+    Ok(())
+}
+
+/// Writes the listing of a single method, as part of
+/// [write_class_disasm].
+pub fn write_method_disasm<W: Write>(
+    dex: IDexRef<'_>,
+    method: &DexMethod,
+    out: &mut W,
+    options: &DisasmOptions,
+) -> Result<()> {
+    let declaration = PrettyOptions {
+        qualified: false,
+        ..options.pretty.clone()
+    };
+    writeln!(
+        out,
+        "  method {}{}",
+        flags(&method.access_flags),
+        format_method(&method.class, &method.name, &method.proto, &declaration)
+    )?;
+    let Some(code) = &method.code else {
+        writeln!(out, "  end method")?;
+        return Ok(());
+    };
+
+    if options.registers {
+        writeln!(
+            out,
+            "    registers {}, ins {}, outs {}",
+            code.registers_size, code.ins_size, code.outs_size
+        )?;
+    }
+    let insns = insns::disasm(code, dex)?;
+    let labels = match options.labels {
+        true => Labels::collect(code, &insns),
+        false => Labels::default(),
+    };
+
+    if options.tries {
+        for try_item in &code.tries {
+            let range = try_item.range();
+            writeln!(out, "    try {:#06x}..{:#06x}", range.start, range.end)?;
+            let Some(handler) = code.catch_handler(try_item) else {
+                writeln!(out, "      <missing handler>")?;
+                continue;
+            };
+            for pair in &handler.handlers {
+                let type_ = dex.get_type(pair.type_idx.0)?;
+                let target = labels.name(pair.addr.0 as usize * 2);
+                writeln!(
+                    out,
+                    "      catch {} -> {}",
+                    pretty_type(&type_, &options.pretty),
+                    target
+                )?;
+            }
+            if let Some(addr) = &handler.catch_all_addr {
+                writeln!(
+                    out,
+                    "      catch-all -> {}",
+                    labels.name(addr.0 as usize * 2)
+                )?;
+            }
+        }
+    }
+
+    // source lines in the order of their addresses, debug info counts in
+    // code units
+    let mut lines: Vec<(usize, u64)> = match (&method.debug_info, options.lines) {
+        (Some(debug), true) => debug
+            .lines
+            .iter()
+            .map(|(pc, line)| (*pc as usize * 2, *line))
+            .collect(),
+        _ => Vec::new(),
+    };
+    lines.sort();
+    let mut lines = lines.into_iter().peekable();
+
+    for insn in &insns {
+        let start = insn.range.start;
+        if labels.targets.contains(&start) {
+            writeln!(out, "  {}", labels.name(start))?;
+        }
+        let prefix = match options.offsets {
+            true => format!("    {:#06x}  ", start),
+            false => "    ".to_string(),
+        };
+        while let Some((_, line)) = lines.next_if(|(pc, _)| *pc <= start) {
+            writeln!(out, "{}.line {}", prefix, line)?;
+        }
+        match &insn.payload {
+            Some(payload) => write_payload(insn, payload, &labels, &prefix, out)?,
+            None => writeln!(out, "{}{}", prefix, insn_text(insn, dex, &labels, options)?)?,
+        }
+    }
+    writeln!(out, "  end method")?;
+    Ok(())
+}
+
+fn write_field<W: Write>(
+    dex: IDexRef<'_>,
+    field: &DexField,
+    out: &mut W,
+    options: &DisasmOptions,
+) -> Result<()> {
+    let declaration = PrettyOptions {
+        qualified: false,
+        ..options.pretty.clone()
+    };
+    write!(
+        out,
+        "  field {}{}",
+        flags(&field.access_flags),
+        format_field(&field.class, &field.name, &field.type_, &declaration)
+    )?;
+    if let Some(value) = &field.init_value {
+        write!(out, " = ")?;
+        out.write_value(value, dex)?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Access flags as lowercase names, each followed by a space.
+fn flags(access_flags: &Option<AccessFlags>) -> String {
+    access_flags
+        .iter()
+        .flat_map(|x| x.iter_names())
+        .map(|(name, _)| name.to_lowercase() + " ")
+        .collect()
+}
+
+fn insn_text(
+    insn: &Insn,
+    dex: IDexRef<'_>,
+    labels: &Labels,
+    options: &DisasmOptions,
+) -> Result<String> {
+    Ok(match labels.branch_operands(insn) {
+        Some(operands) => format!("{} {}", insn.opcode.name, operands),
+        // the smali writer leaves a space after instructions without operands
+        None => pretty_insn(insn, dex, &options.pretty)?
+            .trim_end()
+            .to_string(),
+    })
+}
+
+fn write_payload<W: Write>(
+    insn: &Insn,
+    payload: &Payload,
+    labels: &Labels,
+    prefix: &str,
+    out: &mut W,
+) -> Result<()> {
+    let target = |offset: i32| labels.switch_target(insn.range.start, offset);
+    // entries are not prefixed with the offset
+    let indent = " ".repeat(prefix.len());
+    match payload {
+        Payload::PackedSwitch(x) => {
+            writeln!(out, "{}packed-switch-payload", prefix)?;
+            for (i, offset) in x.targets.iter().enumerate() {
+                let key = x.first_key.wrapping_add(i as i32);
+                writeln!(out, "{}    {:#x} -> {}", indent, key, target(*offset))?;
+            }
+        }
+        Payload::SparseSwitch(x) => {
+            writeln!(out, "{}sparse-switch-payload", prefix)?;
+            for (key, offset) in x.keys.iter().zip(&x.targets) {
+                writeln!(out, "{}    {:#x} -> {}", indent, key, target(*offset))?;
+            }
+        }
+        Payload::FillArrayData(x) => {
+            writeln!(
+                out,
+                "{}fill-array-data-payload width={} size={}",
+                prefix, x.width, x.size
+            )?;
+            for element in x.data.chunks(x.width.max(1) as usize) {
+                let value = element
+                    .iter()
+                    .rev()
+                    .fold(0u64, |value, byte| value << 8 | *byte as u64);
+                writeln!(out, "{}    {:#x}", indent, value)?;
+            }
+        }
+    }
+    Ok(())
+}
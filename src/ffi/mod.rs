@@ -0,0 +1,300 @@
+//! C interface for embedding dexrs into non-Rust tools
+//!
+//! Parsed files are exposed as opaque [DexHandle] pointers that are created
+//! by [dexrs_open] and released by [dexrs_free]. Every other function takes
+//! such a handle and returns one of the `DEXRS_*` status codes. Strings are
+//! written into caller provided buffers as NUL-terminated UTF-8; the length
+//! of the full string (without the terminator) is always stored in
+//! `out_len`, so a caller can query the required size by passing an empty
+//! buffer first.
+//!
+//! The message of the last error on the calling thread can be retrieved
+//! with [dexrs_last_error]. The matching C header is `include/dexrs.h`,
+//! which can be regenerated with `cbindgen --config cbindgen.toml --output
+//! include/dexrs.h`.
+//!
+//! Handles are not thread-safe: a handle may only be used by one thread at
+//! a time.
+//!
+//! Panics don't unwind into the caller, which would abort the host process.
+//! They are reported as [DEXRS_PANIC] instead. The handle that was used by
+//! the failed call should only be released afterwards.
+
+use std::{
+    cell::RefCell,
+    ffi::c_char,
+    io::Cursor,
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+};
+
+use crate::{
+    dalvik::{
+        error::Error,
+        file::{IDex, OwnedDex},
+    },
+    smali::SmaliWrite,
+};
+
+/// The call succeeded.
+pub const DEXRS_OK: i32 = 0;
+
+/// A required pointer argument was `NULL`.
+pub const DEXRS_NULL_POINTER: i32 = -1;
+
+/// The file or one of its items could not be parsed.
+pub const DEXRS_PARSE_ERROR: i32 = -2;
+
+/// An index was out of bounds or the requested item does not exist.
+pub const DEXRS_NOT_FOUND: i32 = -3;
+
+/// The output buffer is too small; `out_len` contains the required length
+/// without the NUL terminator.
+pub const DEXRS_BUFFER_TOO_SMALL: i32 = -4;
+
+/// The library panicked, e.g. because of a bug in the parser. The message
+/// is available through [dexrs_last_error].
+pub const DEXRS_PANIC: i32 = -5;
+
+/// Sizes of the id sections of a DEX file.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DexCounts {
+    pub strings: u32,
+    pub types: u32,
+    pub protos: u32,
+    pub fields: u32,
+    pub methods: u32,
+    pub class_defs: u32,
+}
+
+/// A parsed DEX file that owns its contents.
+pub struct DexHandle {
+    dex: OwnedDex<Cursor<Vec<u8>>>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    LAST_ERROR.with(|x| *x.borrow_mut() = Some(message));
+}
+
+/// Maps a parser error to a status code and remembers its message.
+fn status_of(error: Error) -> i32 {
+    let status = match error.root_cause() {
+        Error::InvalidIndex(_) => DEXRS_NOT_FOUND,
+        _ => DEXRS_PARSE_ERROR,
+    };
+    set_last_error(error.to_string());
+    status
+}
+
+/// Runs the body of an exported function and turns a panic into
+/// [DEXRS_PANIC].
+fn guard<F: FnOnce() -> i32>(f: F) -> i32 {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|x| x.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        set_last_error(format!("panicked: {}", message));
+        DEXRS_PANIC
+    })
+}
+
+/// Copies `value` into the caller's buffer and stores its length in
+/// `out_len`.
+unsafe fn write_str(value: &str, buf: *mut c_char, buf_len: usize, out_len: *mut usize) -> i32 {
+    if !out_len.is_null() {
+        unsafe { *out_len = value.len() };
+    }
+    if buf.is_null() || buf_len <= value.len() {
+        return DEXRS_BUFFER_TOO_SMALL;
+    }
+    unsafe {
+        ptr::copy_nonoverlapping(value.as_ptr(), buf as *mut u8, value.len());
+        *buf.add(value.len()) = 0;
+    }
+    DEXRS_OK
+}
+
+/// Parses the DEX file stored in `data`. The bytes are copied, so the
+/// buffer can be released as soon as this function returns.
+///
+/// The file is parsed like by [OwnedDex::read_untrusted], so every offset is
+/// checked against the bounds of its section. If `verify` is set,
+/// checksum, signature and header constraints are checked as well. The
+/// new handle is stored in `out` and must be released with [dexrs_free].
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `out` must be valid for
+/// writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dexrs_open(
+    data: *const u8,
+    len: usize,
+    verify: bool,
+    out: *mut *mut DexHandle,
+) -> i32 {
+    if data.is_null() || out.is_null() {
+        return DEXRS_NULL_POINTER;
+    }
+    guard(|| {
+        let data = unsafe { slice::from_raw_parts(data, len) }.to_vec();
+        let dex = OwnedDex::read_untrusted(Cursor::new(data)).and_then(|mut dex| {
+            if verify {
+                dex.with_dex(|x| x.verify_header())?;
+            }
+            Ok(dex)
+        });
+        match dex {
+            Ok(dex) => {
+                unsafe { *out = Box::into_raw(Box::new(DexHandle { dex })) };
+                DEXRS_OK
+            }
+            Err(e) => status_of(e),
+        }
+    })
+}
+
+/// Releases a handle created by [dexrs_open]. Passing `NULL` does nothing.
+///
+/// # Safety
+///
+/// `handle` must have been returned by [dexrs_open] and must not be used
+/// afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dexrs_free(handle: *mut DexHandle) {
+    if !handle.is_null() {
+        guard(|| {
+            drop(unsafe { Box::from_raw(handle) });
+            DEXRS_OK
+        });
+    }
+}
+
+/// Stores the sizes of all id sections in `out`.
+///
+/// # Safety
+///
+/// `handle` must be a live handle and `out` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dexrs_counts(handle: *const DexHandle, out: *mut DexCounts) -> i32 {
+    if handle.is_null() || out.is_null() {
+        return DEXRS_NULL_POINTER;
+    }
+    guard(|| {
+        let header = unsafe { &*handle }.dex.get_header();
+        unsafe {
+            *out = DexCounts {
+                strings: header.string_ids_size,
+                types: header.type_ids_size,
+                protos: header.proto_ids_size,
+                fields: header.field_ids_size,
+                methods: header.method_ids_size,
+                class_defs: header.class_defs_size,
+            }
+        };
+        DEXRS_OK
+    })
+}
+
+/// Writes the string at `index` into `buf`.
+///
+/// # Safety
+///
+/// `handle` must be a live handle, `buf` must be valid for `buf_len` bytes
+/// (or `NULL` if `buf_len` is zero) and `out_len` must be valid for writes
+/// or `NULL`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dexrs_get_string(
+    handle: *mut DexHandle,
+    index: u32,
+    buf: *mut c_char,
+    buf_len: usize,
+    out_len: *mut usize,
+) -> i32 {
+    if handle.is_null() {
+        return DEXRS_NULL_POINTER;
+    }
+    guard(|| {
+        let dex = &mut unsafe { &mut *handle }.dex;
+        if index >= dex.get_header().string_ids_size {
+            set_last_error(format!("string index {} out of bounds", index));
+            return DEXRS_NOT_FOUND;
+        }
+        match dex.get_string(index) {
+            Ok(value) => unsafe { write_str(&value, buf, buf_len, out_len) },
+            Err(e) => status_of(e),
+        }
+    })
+}
+
+/// Writes the smali disassembly of a method into `buf`. The method is
+/// identified by the index of its class definition and its index into the
+/// `method_ids` list.
+///
+/// # Safety
+///
+/// See [dexrs_get_string].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dexrs_disasm_method(
+    handle: *mut DexHandle,
+    class_def_idx: u32,
+    method_idx: u32,
+    buf: *mut c_char,
+    buf_len: usize,
+    out_len: *mut usize,
+) -> i32 {
+    if handle.is_null() {
+        return DEXRS_NULL_POINTER;
+    }
+    guard(|| {
+        let dex = &mut unsafe { &mut *handle }.dex;
+        if class_def_idx >= dex.get_header().class_defs_size {
+            set_last_error(format!("class_def index {} out of bounds", class_def_idx));
+            return DEXRS_NOT_FOUND;
+        }
+        let class = match dex.get_class_def(class_def_idx) {
+            Ok(class) => class,
+            Err(e) => return status_of(e),
+        };
+        let Some(method) = class.get_method(method_idx) else {
+            set_last_error(format!(
+                "class_def #{} does not define method #{}",
+                class_def_idx, method_idx
+            ));
+            return DEXRS_NOT_FOUND;
+        };
+
+        let mut output = Vec::new();
+        if let Err(e) = output.write_method(method, dex) {
+            return status_of(e);
+        }
+        unsafe { write_str(&String::from_utf8_lossy(&output), buf, buf_len, out_len) }
+    })
+}
+
+/// Writes the message of the last error that occurred on the calling
+/// thread into `buf`. Returns [DEXRS_NOT_FOUND] if there was none.
+///
+/// # Safety
+///
+/// See [dexrs_get_string].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dexrs_last_error(
+    buf: *mut c_char,
+    buf_len: usize,
+    out_len: *mut usize,
+) -> i32 {
+    guard(|| {
+        LAST_ERROR.with(|x| match x.borrow().as_deref() {
+            Some(message) => unsafe { write_str(message, buf, buf_len, out_len) },
+            None => DEXRS_NOT_FOUND,
+        })
+    })
+}
@@ -0,0 +1,168 @@
+//! Frida hook scripts for selected methods
+//!
+//! [hook_script] turns a list of methods, e.g. the result of
+//! [Dex::methods_matching](crate::dalvik::file::Dex::methods_matching) or
+//! a string search, into a script for [Frida](https://frida.re) that logs
+//! every call of these methods. The script can be loaded with `frida -l`
+//! or objection's `import` command:
+//!
+//! ```text
+//! Java.perform(function () {
+//!     var c0 = Java.use("com.example.Foo");
+//!     c0["bar"].overload("int", "[Ljava.lang.String;").implementation = function () {
+//!         console.log("com.example.Foo.bar(" + Array.prototype.join.call(arguments, ", ") + ")");
+//!         var result = this["bar"].apply(this, arguments);
+//!         console.log("com.example.Foo.bar -> " + result);
+//!         return result;
+//!     };
+//! });
+//! ```
+//!
+//! Every method is hooked through its overload, so that methods sharing a
+//! name don't need to be disambiguated by hand. Constructors are hooked as
+//! `$init`, static initializers can't be hooked and are skipped.
+
+use std::rc::Rc;
+
+use crate::{
+    dalvik::{
+        dex::DexType,
+        error::Result,
+        file::{method::DexPrototype, IDexRef},
+    },
+    pretty::{pretty_type, PrettyOptions},
+};
+
+/// Options for [hook_script].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookOptions {
+    /// Log the arguments of every call.
+    pub arguments: bool,
+
+    /// Log the return value of every call.
+    pub return_value: bool,
+
+    /// Log the Java stack trace of every call.
+    pub backtrace: bool,
+}
+
+impl Default for HookOptions {
+    fn default() -> Self {
+        HookOptions {
+            arguments: true,
+            return_value: true,
+            backtrace: false,
+        }
+    }
+}
+
+/// A class and the methods of it that are hooked.
+type HookedClass = (Rc<DexType>, Vec<(Rc<String>, Rc<DexPrototype>)>);
+
+/// Returns a Frida script that hooks the methods at the given indices into
+/// the `method_ids` list, see the [module docs](self).
+///
+/// Classes are hooked in the order in which their first method appears,
+/// duplicate methods are hooked once.
+pub fn hook_script(dex: IDexRef<'_>, methods: &[u32], options: &HookOptions) -> Result<String> {
+    let mut classes: Vec<HookedClass> = Vec::new();
+    for &index in methods {
+        let method = dex.get_method(index)?;
+        let name = dex.get_string(method.name_idx)?;
+        if name.as_str() == "<clinit>" {
+            continue;
+        }
+        let class = dex.get_type(method.class_idx as u32)?;
+        let proto = dex.get_proto(method.proto_idx as u32)?;
+        let position = match classes.iter().position(|(x, _)| *x == class) {
+            Some(position) => position,
+            None => {
+                classes.push((class, Vec::new()));
+                classes.len() - 1
+            }
+        };
+        let hooked = &mut classes[position].1;
+        if !hooked.iter().any(|(x, y)| *x == name && *y == proto) {
+            hooked.push((name, proto));
+        }
+    }
+
+    let mut script = String::from("Java.perform(function () {\n");
+    for (i, (class, hooked)) in classes.iter().enumerate() {
+        let class_name = pretty_type(class, &PrettyOptions::java());
+        script += &format!("    var c{} = Java.use({});\n", i, js_string(&class_name));
+        for (name, proto) in hooked {
+            let member = match name.as_str() {
+                "<init>" => "$init",
+                name => name,
+            };
+            let qualified = format!("{}.{}", class_name, name);
+            let parameters: Vec<String> = proto
+                .parameters
+                .iter()
+                .map(|x| js_string(&frida_type(x)))
+                .collect();
+            script += &format!(
+                "    c{}[{}].overload({}).implementation = function () {{\n",
+                i,
+                js_string(member),
+                parameters.join(", ")
+            );
+            if options.arguments {
+                script += &format!(
+                    "        console.log({} + \"(\" + Array.prototype.join.call(arguments, \", \") + \")\");\n",
+                    js_string(&qualified)
+                );
+            } else {
+                script += &format!("        console.log({});\n", js_string(&qualified));
+            }
+            if options.backtrace {
+                script += "        console.log(Java.use(\"android.util.Log\").getStackTraceString(Java.use(\"java.lang.Exception\").$new()));\n";
+            }
+            script += &format!(
+                "        var result = this[{}].apply(this, arguments);\n",
+                js_string(member)
+            );
+            if options.return_value && proto.return_type.descriptor != "V" {
+                script += &format!(
+                    "        console.log({} + \" -> \" + result);\n",
+                    js_string(&qualified)
+                );
+            }
+            script += "        return result;\n    };\n";
+        }
+    }
+    script += "});\n";
+    Ok(script)
+}
+
+/// Returns the name Frida uses for a type in an overload: the Java name of
+/// primitives and classes, and the descriptor with dots for arrays, e.g.
+/// `[Ljava.lang.String;`.
+fn frida_type(type_: &DexType) -> String {
+    match type_.dim {
+        0 => pretty_type(type_, &PrettyOptions::java()),
+        _ => type_.to_string().replace('/', "."),
+    }
+}
+
+/// Quotes a string as a JavaScript string literal.
+fn js_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_ascii_graphic() || c == ' ' => quoted.push(c),
+            c => {
+                let mut units = [0; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    quoted.push_str(&format!("\\u{:04x}", unit));
+                }
+            }
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -1,4 +1,17 @@
 
 
+pub mod analysis;
+#[cfg(feature = "conformance")]
+pub mod conformance;
 pub mod dalvik;
-pub mod smali;
\ No newline at end of file
+pub mod dump;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod frida;
+pub mod mapping;
+pub mod patch;
+pub mod pretty;
+pub mod scan;
+pub mod smali;
+pub mod verifier;
+pub mod writer;
\ No newline at end of file
@@ -0,0 +1,416 @@
+//! ProGuard and R8 mapping files
+//!
+//! Apps shrunk by ProGuard or R8 ship with a `mapping.txt` that records the
+//! original name of every renamed class and member:
+//!
+//! ```text
+//! com.example.Foo -> a.b:
+//!     int count -> a
+//!     1:4:void bar(java.lang.String) -> b
+//! ```
+//!
+//! [Mapping] loads such a file and translates the obfuscated names of a DEX
+//! file back. [Mapping::pretty_type], [Mapping::pretty_field] and
+//! [Mapping::pretty_method] are the remapped variants of the functions in
+//! [pretty](crate::pretty), and [Mapping::write_class] writes smali that
+//! keeps the obfuscated names, but adds the original ones as comments:
+//!
+//! ```ignore
+//! let mapping = Mapping::parse(&std::fs::read_to_string("mapping.txt")?)?;
+//! let name = mapping.pretty_method(&mut dex, 12, &PrettyOptions::java())?;
+//! mapping.write_class(&mut std::io::stdout(), &class, &mut dex)?;
+//! ```
+//!
+//! [rename](crate::writer::rename) applies a mapping to a whole file and
+//! writes a new one that uses the original names.
+//!
+//! Members are looked up in the class that a reference names. Members that
+//! are inherited from a superclass and referenced through a subclass keep
+//! their obfuscated name.
+
+use std::{collections::HashMap, io::BufRead, io::Write, rc::Rc};
+
+use crate::{
+    dalvik::{
+        dex::DexType,
+        error::{Error, Result},
+        file::{method::DexPrototype, DexClassDef, IDexRef},
+    },
+    pretty::{format_field, format_method, pretty_type, PrettyOptions},
+    smali::SmaliWrite,
+};
+
+/// The original names of a class and its members.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClassMapping {
+    /// descriptor of the original class, e.g. `Lcom/example/Foo;`
+    pub original: String,
+
+    pub fields: Vec<MemberMapping>,
+    pub methods: Vec<MemberMapping>,
+}
+
+/// The original name of a field or method.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemberMapping {
+    pub original: String,
+    pub obfuscated: String,
+
+    /// descriptor of the field's type or the method's prototype, using the
+    /// original names of all types, e.g. `(Ljava/lang/String;)V`
+    pub descriptor: String,
+}
+
+/// A parsed mapping file.
+#[derive(Debug, Clone, Default)]
+pub struct Mapping {
+    /// all renamed classes by their obfuscated descriptor
+    pub classes: HashMap<String, ClassMapping>,
+}
+
+impl Mapping {
+    /// Parses the contents of a mapping file.
+    pub fn parse(text: &str) -> Result<Mapping> {
+        Mapping::read(text.as_bytes())
+    }
+
+    /// Reads a mapping file line by line. Comments, including the metadata
+    /// written by R8, are ignored.
+    pub fn read<R: BufRead>(reader: R) -> Result<Mapping> {
+        let mut mapping = Mapping::default();
+        let mut class: Option<&mut ClassMapping> = None;
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let text = line.trim();
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+            let invalid = || Error::InvalidData(format!("line {}: {}", number + 1, text));
+
+            let (original, obfuscated) = text.rsplit_once(" -> ").ok_or_else(invalid)?;
+            if !line.starts_with(char::is_whitespace) {
+                let obfuscated = obfuscated.strip_suffix(':').ok_or_else(invalid)?;
+                class = Some(
+                    mapping
+                        .classes
+                        .entry(descriptor_of(obfuscated.trim()))
+                        .or_insert_with(|| ClassMapping {
+                            original: descriptor_of(original),
+                            ..Default::default()
+                        }),
+                );
+                continue;
+            }
+
+            let class = class.as_mut().ok_or_else(invalid)?;
+            // methods may start with the range of their line numbers
+            let member = original.trim_start_matches(|c: char| c.is_ascii_digit() || c == ':');
+            let (type_, name) = member.split_once(' ').ok_or_else(invalid)?;
+            let obfuscated = obfuscated.to_string();
+            match name.split_once('(') {
+                Some((name, rest)) => {
+                    // qualified names belong to methods inlined from other
+                    // classes
+                    if name.contains('.') {
+                        continue;
+                    }
+                    let (parameters, _) = rest.split_once(')').ok_or_else(invalid)?;
+                    let parameters: String = parameters
+                        .split(',')
+                        .filter(|x| !x.is_empty())
+                        .map(descriptor_of)
+                        .collect();
+                    class.methods.push(MemberMapping {
+                        original: name.to_string(),
+                        obfuscated,
+                        descriptor: format!("({}){}", parameters, descriptor_of(type_)),
+                    });
+                }
+                None => class.fields.push(MemberMapping {
+                    original: name.to_string(),
+                    obfuscated,
+                    descriptor: descriptor_of(type_),
+                }),
+            }
+        }
+        Ok(mapping)
+    }
+
+    /// Returns the mapping of the class with the given obfuscated
+    /// descriptor.
+    pub fn class(&self, descriptor: &str) -> Option<&ClassMapping> {
+        self.classes.get(descriptor)
+    }
+
+    /// Returns the original form of a type descriptor, e.g.
+    /// `[Lcom/example/Foo;` for `[La/b;`. Unknown types are returned as is.
+    pub fn original_descriptor(&self, descriptor: &str) -> String {
+        let element = descriptor.trim_start_matches('[');
+        match self.classes.get(element) {
+            Some(class) => {
+                descriptor[..descriptor.len() - element.len()].to_string() + &class.original
+            }
+            None => descriptor.to_string(),
+        }
+    }
+
+    /// Returns the original form of a prototype descriptor, e.g.
+    /// `(Lcom/example/Foo;)V` for `(La/b;)V`.
+    pub fn original_proto(&self, proto: &str) -> Option<String> {
+        let (parameters, return_type) = proto.strip_prefix('(')?.split_once(')')?;
+        let parameters: String = split_descriptors(parameters)?
+            .into_iter()
+            .map(|x| self.original_descriptor(x))
+            .collect();
+        Some(format!(
+            "({}){}",
+            parameters,
+            self.original_descriptor(return_type)
+        ))
+    }
+
+    /// Returns the mapping of a field, which is given by the obfuscated
+    /// descriptors of its class and type. If no field has that type, the
+    /// first field with the given name is returned.
+    pub fn field(&self, class: &str, name: &str, type_: &str) -> Option<&MemberMapping> {
+        self.exact_field(class, name, type_).or_else(|| {
+            let fields = &self.classes.get(class)?.fields;
+            fields.iter().find(|x| x.obfuscated == name)
+        })
+    }
+
+    /// Returns the mapping of a field like [Mapping::field], but only if
+    /// its type matches as well.
+    pub fn exact_field(&self, class: &str, name: &str, type_: &str) -> Option<&MemberMapping> {
+        let fields = &self.classes.get(class)?.fields;
+        let type_ = self.original_descriptor(type_);
+        fields
+            .iter()
+            .find(|x| x.obfuscated == name && x.descriptor == type_)
+    }
+
+    /// Returns the mapping of a method, which is given by the obfuscated
+    /// descriptors of its class and prototype. Overloads are told apart
+    /// by their prototype. If no method has that prototype, the first
+    /// method with the given name is returned.
+    pub fn method(&self, class: &str, name: &str, proto: &str) -> Option<&MemberMapping> {
+        self.exact_method(class, name, proto).or_else(|| {
+            let methods = &self.classes.get(class)?.methods;
+            methods.iter().find(|x| x.obfuscated == name)
+        })
+    }
+
+    /// Returns the mapping of a method like [Mapping::method], but only if
+    /// its prototype matches as well.
+    pub fn exact_method(&self, class: &str, name: &str, proto: &str) -> Option<&MemberMapping> {
+        let methods = &self.classes.get(class)?.methods;
+        let proto = self.original_proto(proto)?;
+        methods
+            .iter()
+            .find(|x| x.obfuscated == name && x.descriptor == proto)
+    }
+
+    /// Formats a type using its original name, see
+    /// [pretty_type](crate::pretty::pretty_type).
+    pub fn pretty_type(&self, type_: &DexType, options: &PrettyOptions) -> String {
+        pretty_type(&self.original_type(type_), options)
+    }
+
+    /// Formats the field at the given index into the `field_ids` list using
+    /// its original name, see [pretty_field](crate::pretty::pretty_field).
+    pub fn pretty_field(
+        &self,
+        dex: IDexRef<'_>,
+        field_idx: u32,
+        options: &PrettyOptions,
+    ) -> Result<String> {
+        let field = dex.get_field(field_idx)?;
+        let name = dex.get_string(field.name_idx)?;
+        let class = dex.get_type(field.class_idx as u32)?;
+        let type_ = dex.get_type(field.type_idx as u32)?;
+
+        let name = self
+            .field(&class.to_string(), &name, &type_.to_string())
+            .map_or(name.as_str(), |x| &x.original);
+        Ok(format_field(
+            &self.original_type(&class),
+            name,
+            &self.original_type(&type_),
+            options,
+        ))
+    }
+
+    /// Formats the method at the given index into the `method_ids` list
+    /// using its original name, see
+    /// [pretty_method](crate::pretty::pretty_method).
+    pub fn pretty_method(
+        &self,
+        dex: IDexRef<'_>,
+        method_idx: u32,
+        options: &PrettyOptions,
+    ) -> Result<String> {
+        let method = dex.get_method(method_idx)?;
+        let name = dex.get_string(method.name_idx)?;
+        let class = dex.get_type(method.class_idx as u32)?;
+        let proto = dex.get_proto(method.proto_idx as u32)?;
+
+        let name = self
+            .method(&class.to_string(), &name, &proto.to_string())
+            .map_or(name.as_str(), |x| &x.original);
+        let proto = DexPrototype {
+            shorty: proto.shorty.clone(),
+            return_type: Rc::new(self.original_type(&proto.return_type)),
+            parameters: proto
+                .parameters
+                .iter()
+                .map(|x| Rc::new(self.original_type(x)))
+                .collect(),
+        };
+        Ok(format_method(
+            &self.original_type(&class),
+            name,
+            &proto,
+            options,
+        ))
+    }
+
+    /// Writes a class as smali, see
+    /// [SmaliWrite::write_class](crate::smali::SmaliWrite::write_class).
+    /// The original names of the class, its members and all referenced
+    /// items that were renamed are added as comments at the end of a line.
+    pub fn write_class<W: Write>(
+        &self,
+        out: &mut W,
+        class: &DexClassDef,
+        dex: IDexRef<'_>,
+    ) -> Result<()> {
+        let mut smali = Vec::new();
+        smali.write_class(class, dex)?;
+        let descriptor = class.type_.to_string();
+        for line in String::from_utf8_lossy(&smali).split_inclusive('\n') {
+            let (text, newline) = match line.strip_suffix('\n') {
+                Some(text) => (text, "\n"),
+                None => (line, ""),
+            };
+            match self.comment(&descriptor, text.trim_start()) {
+                Some(comment) => write!(out, "{}  # {}{}", text, comment, newline)?,
+                None => write!(out, "{}", line)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn original_type(&self, type_: &DexType) -> DexType {
+        let descriptor = self.original_descriptor(&type_.to_string());
+        DexType::read(&Rc::new(descriptor)).unwrap_or_else(|_| DexType {
+            descriptor: type_.descriptor.clone(),
+            ..*type_
+        })
+    }
+
+    /// Returns the original names of everything a smali line refers to, or
+    /// `None` if nothing was renamed.
+    fn comment(&self, class: &str, line: &str) -> Option<String> {
+        let (keyword, operands) = line.split_once(' ')?;
+        let refs: Vec<String> = match keyword {
+            ".class" | ".extends" | ".implements" => {
+                vec![self.original_ref(operands.rsplit(' ').next()?)?]
+            }
+            ".field" => {
+                // .field <flags> name:type [= value]
+                let (declaration, _) = operands.split_once(" = ").unwrap_or((operands, ""));
+                let member = declaration.rsplit(' ').next()?;
+                vec![self.original_ref(&format!("{}->{}", class, member))?]
+            }
+            ".method" => {
+                let member = operands.rsplit(' ').next()?;
+                vec![self.original_ref(&format!("{}->{}", class, member))?]
+            }
+            // directives, labels and string literals
+            _ if keyword.starts_with(['.', '#']) || keyword.starts_with("const-string") => {
+                return None;
+            }
+            _ => operands
+                .split(", ")
+                .filter_map(|x| self.original_ref(x))
+                .collect(),
+        };
+        (!refs.is_empty()).then(|| refs.join(", "))
+    }
+
+    /// Returns the original form of a type, field or method reference in
+    /// smali syntax, or `None` if nothing was renamed.
+    fn original_ref(&self, text: &str) -> Option<String> {
+        let original = match text.split_once("->") {
+            Some((class, member)) => {
+                let original_class = self.original_descriptor(class);
+                match member.split_once('(') {
+                    Some((name, proto)) => {
+                        let proto = format!("({}", proto);
+                        let name = self
+                            .method(class, name, &proto)
+                            .map_or(name, |x| &x.original);
+                        format!(
+                            "{}->{}{}",
+                            original_class,
+                            name,
+                            self.original_proto(&proto)?
+                        )
+                    }
+                    None => {
+                        let (name, type_) = member.split_once(':')?;
+                        let name = self.field(class, name, type_).map_or(name, |x| &x.original);
+                        format!(
+                            "{}->{}:{}",
+                            original_class,
+                            name,
+                            self.original_descriptor(type_)
+                        )
+                    }
+                }
+            }
+            None if text.starts_with('(') => self.original_proto(text)?,
+            None if text.starts_with(['L', '[']) && text.ends_with(';') => {
+                self.original_descriptor(text)
+            }
+            None => return None,
+        };
+        (original != text).then_some(original)
+    }
+}
+
+/// Converts a Java type name to a descriptor, e.g. `java.lang.String[]` to
+/// `[Ljava/lang/String;`.
+fn descriptor_of(name: &str) -> String {
+    let element = name.trim_end_matches("[]");
+    let dims = "[".repeat((name.len() - element.len()) / 2);
+    let element = match element {
+        "void" => "V",
+        "boolean" => "Z",
+        "byte" => "B",
+        "short" => "S",
+        "char" => "C",
+        "int" => "I",
+        "long" => "J",
+        "float" => "F",
+        "double" => "D",
+        class => return format!("{}L{};", dims, class.replace('.', "/")),
+    };
+    dims + element
+}
+
+/// Splits concatenated type descriptors, e.g. the parameters of a
+/// prototype.
+fn split_descriptors(mut text: &str) -> Option<Vec<&str>> {
+    let mut descriptors = Vec::new();
+    while !text.is_empty() {
+        let dims = text.len() - text.trim_start_matches('[').len();
+        let end = match text[dims..].chars().next()? {
+            'L' => dims + text[dims..].find(';')? + 1,
+            _ => dims + 1,
+        };
+        descriptors.push(&text[..end]);
+        text = &text[end..];
+    }
+    Some(descriptors)
+}
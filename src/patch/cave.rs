@@ -0,0 +1,296 @@
+//! Code caves
+//!
+//! A code cave is a run of zeroed bytes in the data section that no item
+//! claims, e.g. left over by a dex tool that removed items without
+//! compacting the file. If the buffer of a session is larger than
+//! `file_size` (a memory map of a file that was extended beforehand), the
+//! bytes after the data section can be claimed as well, which grows the
+//! file when the session is applied.
+//!
+//! Items moved into a cave have to stay within the section of their type,
+//! as verifiers reject offsets of items that the map list doesn't cover.
+//! Relocated items are therefore placed into the cave that directly
+//! follows their section, and the map list counts them.
+//!
+//! ```ignore
+//! let mut code = session.read_code(class_def, method_idx)?;
+//! // ... insert instructions and update insns_size ...
+//! let offset = session.relocate_code(class_def, method_idx, &code)?;
+//! ```
+
+use std::{io::Cursor, ops::Range};
+
+use crate::{
+    dalvik::{
+        dex::MapListItemType,
+        error::{Error, Result},
+        file::{skip_item, Dex},
+    },
+    verifier::sections::check_sections,
+    writer::rewrite::is_aligned,
+};
+
+use super::{
+    class_data::{ClassData, Value},
+    Edit, PatchSession,
+};
+
+/// A free region within the data section, see
+/// [PatchSession::find_code_caves].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeCave {
+    /// offset from the start of the file, aligned to four bytes
+    pub offset: usize,
+
+    /// number of usable bytes starting at `offset`
+    pub size: usize,
+
+    /// whether the cave lies after `file_size`, so that using it grows the
+    /// file
+    pub tail: bool,
+}
+
+impl<B> PatchSession<B>
+where
+    B: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Returns all code caves with at least `min_size` bytes, sorted by
+    /// their offset. Regions that were already claimed by an edit of this
+    /// session are left out.
+    ///
+    /// The sections are measured through [check_sections], so regions that
+    /// contain data aren't considered to be free, even if no item refers to
+    /// them.
+    pub fn find_code_caves(&self, min_size: usize) -> Result<Vec<CodeCave>> {
+        let mut caves: Vec<CodeCave> = self
+            .free_ranges()?
+            .into_iter()
+            .filter_map(|x| {
+                let offset = x.start.next_multiple_of(4);
+                let size = x.end.saturating_sub(offset);
+                (size >= min_size).then_some(CodeCave {
+                    offset,
+                    size,
+                    tail: false,
+                })
+            })
+            .collect();
+        if let Some(cave) = self.tail_cave().filter(|x| x.size >= min_size) {
+            caves.push(cave);
+        }
+        Ok(caves)
+    }
+
+    /// Returns the code cave with at least `size` bytes that is closest to
+    /// the given offset. Caves within the file are preferred over the tail
+    /// of the buffer.
+    pub fn find_code_cave_near(&self, offset: usize, size: usize) -> Result<Option<CodeCave>> {
+        let caves = self.find_code_caves(size)?;
+        Ok(caves
+            .into_iter()
+            .min_by_key(|x| (x.tail, x.offset.abs_diff(offset))))
+    }
+
+    /// Returns the code item of a method of the given class definition,
+    /// e.g. to insert instructions before passing it to
+    /// [PatchSession::relocate_code].
+    pub fn read_code(&self, class_def_index: u32, method_idx: u32) -> Result<Vec<u8>> {
+        let code_off = self.find_code_off(class_def_index, method_idx)?.1;
+        if code_off.value == 0 {
+            return Err(Error::InvalidData(format!(
+                "method {} has no code",
+                method_idx
+            )));
+        }
+        let offset = code_off.value as usize;
+        let mut reader = Cursor::new(self.data_at(offset)?);
+        let size = skip_item(MapListItemType::CodeItem, &mut reader)? as usize;
+        self.read_patched(offset, size)
+    }
+
+    /// Places the given `code_item` after the last code item of the file
+    /// and changes the `code_off` in the `class_data_item` to point to it.
+    /// Returns the offset of the new code item.
+    ///
+    /// The old code item stays where it is. Offsets within the new code
+    /// item (`debug_info_off`) are written as they are. If the new offset
+    /// doesn't fit into the ULEB128 value that held the old one, the
+    /// `class_data_item` is moved as well. Fails if there is no code cave
+    /// directly after the code items, see [PatchSession::add_item].
+    pub fn relocate_code(
+        &mut self,
+        class_def_index: u32,
+        method_idx: u32,
+        code: &[u8],
+    ) -> Result<usize> {
+        let (class_data, code_off) = self.find_code_off(class_def_index, method_idx)?;
+        self.atomic(|session| {
+            let offset = session.add_item(MapListItemType::CodeItem, code.to_vec(), false)?;
+            session.set_class_data_value(class_def_index, &class_data, code_off, offset as u32)?;
+            Ok(offset)
+        })
+    }
+
+    /// Records a new item of the given type directly after the last item
+    /// of its section and counts it in the map list. Returns the offset of
+    /// the new item.
+    ///
+    /// The items of a type have to form a single section that is listed in
+    /// the map list; verifiers like ART's `DexFileVerifier` reject offsets
+    /// of items outside of it. So the item is placed into the code cave
+    /// that starts at the end of the section or, if the section ends the
+    /// file, into the tail of the buffer. If neither has room and `append`
+    /// is set, an item of a section that ends the file is appended to it,
+    /// see [PatchSession::apply_to_vec].
+    pub(super) fn add_item(
+        &mut self,
+        type_: MapListItemType,
+        data: Vec<u8>,
+        append: bool,
+    ) -> Result<usize> {
+        let (entry, size, end) = self.section_end(type_)?;
+        let offset = if is_aligned(type_) {
+            end.next_multiple_of(4)
+        } else {
+            end
+        };
+        let item_end = offset + data.len();
+        let in_file = self
+            .free_ranges()?
+            .iter()
+            .any(|x| x.start <= end && item_end <= x.end);
+        let in_tail = end == self.end()
+            && self.appended.is_empty()
+            && self.data_end() == self.end()
+            && item_end <= self.data.as_ref().len();
+        let appended = append && end == self.end() + self.appended.len();
+        if !in_file && !in_tail && !appended {
+            return Err(Error::InvalidData(format!(
+                "no code cave with {} bytes after the {:?} section",
+                data.len(),
+                type_
+            )));
+        }
+
+        let mut item = vec![0; offset - end];
+        item.extend(data);
+        self.atomic(|session| {
+            if in_file {
+                session.record(Edit {
+                    offset: end,
+                    data: item,
+                })?;
+            } else if in_tail {
+                session.grown = item_end - session.header.file_size as usize;
+                session.record(Edit {
+                    offset: end,
+                    data: item,
+                })?;
+            } else {
+                session.appended.extend(item);
+            }
+            // the size of the entry follows its type and an unused field
+            session.record_patch(Edit {
+                offset: entry + 4,
+                data: (size + 1).to_le_bytes().to_vec(),
+            })?;
+            Ok(offset)
+        })
+    }
+
+    /* private impl */
+
+    /// Returns all zeroed regions within the data section that no section
+    /// and no edit of this session claims.
+    fn free_ranges(&self) -> Result<Vec<Range<usize>>> {
+        let file_size = self.header.file_size as usize;
+        let mut reader = Cursor::new(&self.data.as_ref()[..file_size]);
+        let mut dex = Dex::read(&mut reader, false)?;
+        let report = check_sections(&mut dex)?;
+
+        let data = self.data.as_ref();
+        let data_range = self.header.data_off as usize..self.data_end();
+        let mut free: Vec<Range<usize>> = report
+            .slack
+            .iter()
+            .map(|x| x.start as usize..x.end as usize)
+            .map(|x| x.start.max(data_range.start)..x.end.min(data_range.end))
+            .filter(|x| !x.is_empty() && data[x.clone()].iter().all(|b| *b == 0))
+            .collect();
+        for edit in &self.edits {
+            let edit = edit.offset..edit.offset + edit.data.len();
+            free = free
+                .into_iter()
+                .flat_map(|x| [x.start..x.end.min(edit.start), x.start.max(edit.end)..x.end])
+                .filter(|x| !x.is_empty())
+                .collect();
+        }
+        Ok(free)
+    }
+
+    /// Returns the offset of the map list entry of the given type, the
+    /// number of items it lists and the end of its last item, including
+    /// the items added by this session.
+    fn section_end(&self, type_: MapListItemType) -> Result<(usize, u32, usize)> {
+        let map_off = self.header.map_off as usize;
+        let x = self.read_patched(map_off, 4)?;
+        let count = u32::from_le_bytes([x[0], x[1], x[2], x[3]]) as usize;
+        let entries = self.read_patched(map_off + 4, count * 12)?;
+        for (i, x) in entries.chunks_exact(12).enumerate() {
+            if MapListItemType::from(u16::from_le_bytes([x[0], x[1]])) != type_ {
+                continue;
+            }
+            let size = u32::from_le_bytes([x[4], x[5], x[6], x[7]]);
+            let mut end = u32::from_le_bytes([x[8], x[9], x[10], x[11]]) as usize;
+            for _ in 0..size {
+                if is_aligned(type_) {
+                    end = end.next_multiple_of(4);
+                }
+                let mut reader = Cursor::new(self.data_at(end)?);
+                end += skip_item(type_, &mut reader)? as usize;
+            }
+            return Ok((map_off + 4 + i * 12, size, end));
+        }
+        Err(Error::InvalidData(format!(
+            "the map list has no {:?} section",
+            type_
+        )))
+    }
+
+    /// Returns the bytes of the buffer after the data section, if the data
+    /// section ends the file.
+    fn tail_cave(&self) -> Option<CodeCave> {
+        if !self.appended.is_empty() || self.data_end() != self.end() {
+            return None;
+        }
+        let offset = self.end().next_multiple_of(4);
+        let size = self.data.as_ref().len().checked_sub(offset)?;
+        Some(CodeCave {
+            offset,
+            size,
+            tail: true,
+        })
+    }
+
+    /// Returns the class data of the given class definition together with
+    /// the `code_off` of the given method.
+    pub(super) fn find_code_off(
+        &self,
+        class_def_index: u32,
+        method_idx: u32,
+    ) -> Result<(ClassData, Value)> {
+        let class_data = match self.read_class_data(class_def_index)? {
+            Some(x) => x,
+            None => return Err(Error::MethodNotFound(method_idx as usize)),
+        };
+        let code_off = class_data
+            .members
+            .iter()
+            .find(|x| x.is_method && x.index == method_idx)
+            .and_then(|x| x.code_off);
+        match code_off {
+            Some(x) => Ok((class_data, x)),
+            None => Err(Error::MethodNotFound(method_idx as usize)),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Positions of the values within a `class_data_item`
+//!
+//! Access flags and code offsets are ULEB128 values, so they can only be
+//! patched in place if the new value fits into the bytes of the old one.
+//! Otherwise the whole item is re-encoded and placed into the code cave
+//! after the class data section.
+
+use binrw::BinRead;
+use std::io::Cursor;
+
+use crate::dalvik::{
+    dex::{MapListItemType, ULeb128},
+    error::{Error, Result},
+};
+
+use super::{uleb128_size, write_uleb128, Edit, PatchSession};
+
+/// A ULEB128 value within a `class_data_item`.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Value {
+    /// offset from the start of the item
+    pub offset: usize,
+
+    /// number of bytes of the encoded value
+    pub width: usize,
+
+    pub value: u32,
+}
+
+/// An `encoded_field` or `encoded_method`.
+#[derive(Debug, Clone)]
+pub(super) struct Member {
+    pub is_method: bool,
+
+    /// whether the member is listed as a direct method
+    pub is_direct: bool,
+
+    /// index into `field_ids` or `method_ids`
+    pub index: u32,
+
+    pub access_flags: Value,
+
+    /// only present for methods
+    pub code_off: Option<Value>,
+}
+
+/// The current contents of a `class_data_item`, including all edits of a
+/// session.
+pub(super) struct ClassData {
+    /// offset from the start of the file
+    pub offset: usize,
+
+    pub bytes: Vec<u8>,
+
+    pub members: Vec<Member>,
+}
+
+/// Parses a `class_data_item` and returns its members as well as its size.
+fn parse(data: &[u8]) -> Result<(Vec<Member>, usize)> {
+    let mut reader = Cursor::new(data);
+    let mut read = || -> Result<Value> {
+        let offset = reader.position() as usize;
+        let value = ULeb128::read(&mut reader)?.0;
+        Ok(Value {
+            offset,
+            width: reader.position() as usize - offset,
+            value,
+        })
+    };
+
+    let mut sizes = [0; 4];
+    for size in &mut sizes {
+        *size = read()?.value;
+    }
+    let mut members = Vec::new();
+    for (i, count) in sizes.into_iter().enumerate() {
+        let is_method = i >= 2;
+        // indices are encoded as differences, starting anew for each list
+        let mut index = 0u32;
+        for _ in 0..count {
+            index = index.wrapping_add(read()?.value);
+            let access_flags = read()?;
+            let code_off = if is_method { Some(read()?) } else { None };
+            members.push(Member {
+                is_method,
+                is_direct: i == 2,
+                index,
+                access_flags,
+                code_off,
+            });
+        }
+    }
+    Ok((members, reader.position() as usize))
+}
+
+impl<B> PatchSession<B>
+where
+    B: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Reads the `class_data_item` of the given class definition as it will
+    /// be written, i.e. at its relocated offset and with all edits of this
+    /// session. Returns `None` for classes without class data.
+    pub(super) fn read_class_data(&self, class_def_index: u32) -> Result<Option<ClassData>> {
+        let x = self.read_patched(self.class_data_off_offset(class_def_index)?, 4)?;
+        let offset = u32::from_le_bytes([x[0], x[1], x[2], x[3]]);
+        if offset == 0 {
+            return Ok(None);
+        }
+
+        // the size doesn't change through edits, as in-place edits keep
+        // the width of their values
+        let offset = offset as usize;
+        let (_, size) = parse(self.data_at(offset)?)?;
+        let bytes = self.read_patched(offset, size)?;
+        let (members, _) = parse(&bytes)?;
+        Ok(Some(ClassData {
+            offset,
+            bytes,
+            members,
+        }))
+    }
+
+    /// Changes a value of the given `class_data_item`. If the new value
+    /// doesn't fit into the bytes of the old one, the item is re-encoded
+    /// and added after the last `class_data_item` of the file, see
+    /// [PatchSession::add_item].
+    pub(super) fn set_class_data_value(
+        &mut self,
+        class_def_index: u32,
+        class_data: &ClassData,
+        value: Value,
+        new_value: u32,
+    ) -> Result<()> {
+        if uleb128_size(new_value) <= value.width {
+            return self.record_patch(Edit {
+                offset: class_data.offset + value.offset,
+                data: write_uleb128(new_value, value.width),
+            });
+        }
+
+        let mut bytes = class_data.bytes.clone();
+        bytes.splice(
+            value.offset..value.offset + value.width,
+            write_uleb128(new_value, uleb128_size(new_value)),
+        );
+        let offset = self.class_data_off_offset(class_def_index)?;
+        self.atomic(|session| {
+            let new_offset = session.add_item(MapListItemType::ClassDataItem, bytes, false)?;
+            session.record_patch(Edit {
+                offset,
+                data: (new_offset as u32).to_le_bytes().to_vec(),
+            })
+        })
+    }
+
+    /* private impl */
+
+    /// Returns the file offset of the `class_data_off` of the given class
+    /// definition.
+    fn class_data_off_offset(&self, class_def_index: u32) -> Result<usize> {
+        if class_def_index >= self.header.class_defs_size {
+            return Err(Error::InvalidIndex(class_def_index as usize));
+        }
+        // class_idx, access_flags, superclass_idx, interfaces_off,
+        // source_file_idx and annotations_off precede class_data_off
+        Ok(self.header.class_defs_off as usize + class_def_index as usize * 32 + 24)
+    }
+}
@@ -0,0 +1,118 @@
+//! Access flags of members
+//!
+//! The access flags of fields and methods are stored in the
+//! `class_data_item` of their class, see [PatchSession::set_method_access_flags].
+//! [PatchSession::make_public] covers the common case of opening up a whole
+//! class, e.g. to call into it from instrumentation code.
+
+use crate::dalvik::{
+    dex::AccessFlags,
+    error::{Error, Result},
+};
+
+use super::PatchSession;
+
+impl<B> PatchSession<B>
+where
+    B: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Changes the access flags of a method of the given class definition.
+    ///
+    /// The flags are patched in place if they fit into the bytes of the old
+    /// ones, otherwise the `class_data_item` is moved into a code cave, see
+    /// [PatchSession::find_code_caves].
+    pub fn set_method_access_flags(
+        &mut self,
+        class_def_index: u32,
+        method_idx: u32,
+        flags: u32,
+    ) -> Result<()> {
+        self.set_member_access_flags(class_def_index, true, method_idx, flags)
+    }
+
+    /// Changes the access flags of a field of the given class definition,
+    /// see [PatchSession::set_method_access_flags].
+    pub fn set_field_access_flags(
+        &mut self,
+        class_def_index: u32,
+        field_idx: u32,
+        flags: u32,
+    ) -> Result<()> {
+        self.set_member_access_flags(class_def_index, false, field_idx, flags)
+    }
+
+    /// Makes the class definition at the given index and all of its members
+    /// public and removes their `final` flag.
+    ///
+    /// Private instance methods are left as they are: they are stored as
+    /// direct methods, which must not be public unless they are static or
+    /// constructors.
+    pub fn make_public(&mut self, class_def_index: u32) -> Result<()> {
+        if class_def_index >= self.header.class_defs_size {
+            return Err(Error::InvalidIndex(class_def_index as usize));
+        }
+        let offset = self.header.class_defs_off as usize + class_def_index as usize * 32 + 4;
+        let flags = self.read_patched(offset, 4)?;
+        let flags = u32::from_le_bytes([flags[0], flags[1], flags[2], flags[3]]);
+        self.set_class_access_flags(class_def_index, open_up(flags))?;
+
+        let members = match self.read_class_data(class_def_index)? {
+            Some(x) => x.members,
+            None => return Ok(()),
+        };
+        for member in members {
+            let flags = AccessFlags::from_bits_retain(member.access_flags.value);
+            if member.is_direct && !flags.intersects(AccessFlags::STATIC | AccessFlags::CONSTRUCTOR)
+            {
+                continue;
+            }
+            let new_flags = open_up(flags.bits());
+            if new_flags != flags.bits() {
+                self.set_member_access_flags(
+                    class_def_index,
+                    member.is_method,
+                    member.index,
+                    new_flags,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /* private impl */
+
+    fn set_member_access_flags(
+        &mut self,
+        class_def_index: u32,
+        is_method: bool,
+        index: u32,
+        flags: u32,
+    ) -> Result<()> {
+        let not_found = || match is_method {
+            true => Error::MethodNotFound(index as usize),
+            false => Error::FieldNotFound(index as usize),
+        };
+        // the class data is read again for every member, as it may have
+        // been moved by a previous change
+        let class_data = self
+            .read_class_data(class_def_index)?
+            .ok_or_else(not_found)?;
+        let value = class_data
+            .members
+            .iter()
+            .find(|x| x.is_method == is_method && x.index == index)
+            .map(|x| x.access_flags)
+            .ok_or_else(not_found)?;
+        self.set_class_data_value(class_def_index, &class_data, value, flags)
+    }
+}
+
+/// Replaces the visibility of the given flags by `public` and removes
+/// `final`.
+fn open_up(flags: u32) -> u32 {
+    let flags = AccessFlags::from_bits_retain(flags)
+        - AccessFlags::PRIVATE
+        - AccessFlags::PROTECTED
+        - AccessFlags::FINAL;
+    (flags | AccessFlags::PUBLIC).bits()
+}
@@ -0,0 +1,416 @@
+//! In-place patching of DEX files
+//!
+//! A [PatchSession] operates directly on the bytes of a DEX file, which can
+//! be a `Vec<u8>` as well as a writable memory map (anything that implements
+//! `AsRef<[u8]>` and `AsMut<[u8]>`). Edits are validated against the current
+//! contents of the file when they are recorded, so that nothing is written
+//! unless every edit fits into the space it replaces. Applying the session
+//! writes all edits and updates the checksum and signature of the file.
+//!
+//! Strings that outgrow their data are the exception: if the string data
+//! ends the file, they are appended to it, so such a session has to be
+//! applied with [PatchSession::apply_to_vec].
+//!
+//! Code items that outgrow their place can be moved into a code cave, see
+//! [PatchSession::relocate_code].
+//!
+//! ```ignore
+//! let data = std::fs::read("classes.dex")?;
+//! let mut session = PatchSession::new(data)?;
+//! session.replace_string(12, "patched")?;
+//! session.set_class_access_flags(0, AccessFlags::PUBLIC.bits())?;
+//! std::fs::write("classes-patched.dex", session.apply()?)?;
+//! ```
+
+use binrw::BinRead;
+use std::io::Cursor;
+
+mod cave;
+mod class_data;
+mod flags;
+pub use cave::CodeCave;
+
+use crate::dalvik::{
+    dex::{mutf8, HeaderItem, MapListItemType, UInt},
+    error::{Error, Result},
+    insns::{
+        self,
+        encode::{self, Operands},
+        OPCODES,
+    },
+};
+
+/// A single write into the underlying file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    /// offset from the start of the file
+    pub offset: usize,
+
+    /// bytes that will be written at `offset`
+    pub data: Vec<u8>,
+}
+
+/// Records edits to a DEX file and applies them in place.
+pub struct PatchSession<B> {
+    data: B,
+    header: HeaderItem,
+    edits: Vec<Edit>,
+
+    /// number of bytes after `file_size` that were claimed within the
+    /// buffer, see [CodeCave::tail]
+    grown: usize,
+
+    /// data that will be placed after `file_size` and the claimed bytes
+    appended: Vec<u8>,
+}
+
+impl<B> PatchSession<B>
+where
+    B: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Starts a new session on the given file contents. The header must
+    /// describe sections that lie within the buffer.
+    pub fn new(data: B) -> Result<Self> {
+        let header = HeaderItem::read(&mut Cursor::new(data.as_ref()))?;
+        header.verify_bounds(data.as_ref().len() as u64)?;
+        Ok(PatchSession {
+            data,
+            header,
+            edits: Vec::new(),
+            grown: 0,
+            appended: Vec::new(),
+        })
+    }
+
+    /// Returns the header of the file as it was when the session started.
+    pub fn header(&self) -> &HeaderItem {
+        &self.header
+    }
+
+    /// Returns all edits recorded so far.
+    pub fn edits(&self) -> &[Edit] {
+        &self.edits
+    }
+
+    /// Returns the data that will be appended to the file.
+    pub fn appended(&self) -> &[u8] {
+        &self.appended
+    }
+
+    /// Replaces the contents of the string at the given index.
+    ///
+    /// If the new value fits into the existing `string_data_item`, it is
+    /// patched in place. Shorter values are padded by extending the length
+    /// prefix (a ULEB128 value may use up to five bytes) and by zero bytes
+    /// after the terminator. Otherwise a new `string_data_item` is added
+    /// after the last one of the file and the string id is changed to point
+    /// to it, see [PatchSession::add_item]. If the string data ends the
+    /// file, the item is appended, which requires the session to be applied
+    /// with [PatchSession::apply_to_vec].
+    ///
+    /// Note that string ids must be sorted by their contents, so changing a
+    /// string may produce a file that is rejected by a strict verifier.
+    pub fn replace_string(&mut self, index: u32, value: &str) -> Result<()> {
+        if index >= self.header.string_ids_size {
+            return Err(Error::InvalidIndex(index as usize));
+        }
+        let id_offset = self.header.string_ids_off as usize + index as usize * 4;
+        let data_offset = self.read_u32(id_offset)? as usize;
+
+        let old_size = self.string_data_size(data_offset)?;
+        let (utf16_size, bytes) = mutf8::encode(value);
+        let prefix_size = uleb128_size(utf16_size);
+        let new_size = prefix_size + bytes.len() + 1;
+        if new_size > old_size {
+            let mut item = write_uleb128(utf16_size, prefix_size);
+            item.extend(bytes);
+            item.push(0);
+            return self.atomic(|session| {
+                let offset = session.add_item(MapListItemType::StringDataItem, item, true)?;
+                let offset = UInt::try_from(offset)
+                    .map_err(|_| Error::InvalidData(format!("string {} can't be moved", index)))?;
+                session.record(Edit {
+                    offset: id_offset,
+                    data: offset.to_le_bytes().to_vec(),
+                })
+            });
+        }
+
+        let slack = old_size - new_size;
+        let mut data = write_uleb128(utf16_size, (prefix_size + slack).min(5));
+        data.extend(bytes);
+        data.resize(old_size, 0);
+        self.record(Edit {
+            offset: data_offset,
+            data,
+        })
+    }
+
+    /// Changes the literal of the `const*` instruction at the given address
+    /// (in code units) of a method of the given class definition. The
+    /// instruction keeps its opcode and register, so the value has to fit
+    /// into the literal of the existing format.
+    ///
+    /// The code item is read with all edits of this session, so it may
+    /// have been relocated before. The address has to be the start of an
+    /// instruction.
+    pub fn patch_const(
+        &mut self,
+        class_def_index: u32,
+        method_idx: u32,
+        address: usize,
+        value: i64,
+    ) -> Result<()> {
+        let code = self.read_code(class_def_index, method_idx)?;
+        let code_off = self.find_code_off(class_def_index, method_idx)?.1.value as usize;
+        // the instructions follow the 16 bytes of the code item's header
+        let insns_size = u32::from_le_bytes([code[12], code[13], code[14], code[15]]) as usize;
+        let units = insns::code_units(&code[16..16 + insns_size * 2]);
+        let mut index = 0;
+        while index < address
+            && let Some(size) = insns::insn_size(&units, index)
+        {
+            index += size;
+        }
+        if index != address || address >= units.len() {
+            return Err(Error::InvalidData(format!(
+                "no instruction starts at {:#x} in method {}",
+                address, method_idx
+            )));
+        }
+        let offset = code_off + 16 + address * 2;
+
+        let unit = units[address];
+        let opcode = &OPCODES[(unit & 0xFF) as usize];
+        let a = unit >> 8;
+        let operands = match opcode.format_id() {
+            "11n" => Operands::Format11n {
+                a: a & 0x0F,
+                b: value,
+            },
+            "21s" => Operands::Format21s { a, b: value },
+            "21h" => Operands::Format21h { a, b: value },
+            "31i" => Operands::Format31i { a, b: value },
+            "51l" => Operands::Format51l { a, b: value },
+            _ => {
+                return Err(Error::InvalidData(format!(
+                    "expected a const instruction at {:#x}, got {}",
+                    address, opcode.name
+                )));
+            }
+        };
+
+        let data = encode::to_bytes(&encode::encode(opcode, &operands)?);
+        self.record_patch(Edit { offset, data })
+    }
+
+    /// Changes the access flags of the class definition at the given index.
+    pub fn set_class_access_flags(&mut self, index: u32, flags: UInt) -> Result<()> {
+        if index >= self.header.class_defs_size {
+            return Err(Error::InvalidIndex(index as usize));
+        }
+        // access_flags directly follow class_idx
+        let offset = self.header.class_defs_off as usize + index as usize * 32 + 4;
+        self.record_patch(Edit {
+            offset,
+            data: flags.to_le_bytes().to_vec(),
+        })
+    }
+
+    /// Writes all recorded edits and updates the signature and checksum in
+    /// the header. Returns the patched buffer.
+    ///
+    /// Fails if data has to be appended, see [PatchSession::apply_to_vec].
+    pub fn apply(mut self) -> Result<B> {
+        if !self.appended.is_empty() {
+            return Err(Error::InvalidData(format!(
+                "{} bytes have to be appended, which requires apply_to_vec",
+                self.appended.len()
+            )));
+        }
+        let file_size = self.end();
+        let data_size = self
+            .data_end()
+            .saturating_sub(self.header.data_off as usize);
+        let data = self.data.as_mut();
+        for edit in &self.edits {
+            data[edit.offset..edit.offset + edit.data.len()].copy_from_slice(&edit.data);
+        }
+        if self.grown != 0 {
+            data[32..36].copy_from_slice(&(file_size as UInt).to_le_bytes());
+            data[104..108].copy_from_slice(&(data_size as UInt).to_le_bytes());
+        }
+        update_digests(&mut data[..file_size]);
+        Ok(self.data)
+    }
+
+    /// Like [PatchSession::apply], but copies the file into a new buffer
+    /// that is extended by all appended data. `file_size` and the data
+    /// section are grown accordingly; anything stored after the old
+    /// `file_size` is dropped.
+    pub fn apply_to_vec(self) -> Result<Vec<u8>> {
+        let file_size = self.end();
+        let mut data = self.data.as_ref()[..file_size].to_vec();
+        for edit in &self.edits {
+            data[edit.offset..edit.offset + edit.data.len()].copy_from_slice(&edit.data);
+        }
+        data.extend_from_slice(&self.appended);
+
+        let new_size = data.len() as UInt;
+        let data_size = new_size
+            .saturating_sub(self.header.data_off)
+            .max(self.header.data_size);
+        data[32..36].copy_from_slice(&new_size.to_le_bytes());
+        data[104..108].copy_from_slice(&data_size.to_le_bytes());
+        update_digests(&mut data);
+        Ok(data)
+    }
+
+    /* private impl */
+
+    /// Returns the end of the file including all claimed bytes after
+    /// `file_size`.
+    fn end(&self) -> usize {
+        self.header.file_size as usize + self.grown
+    }
+
+    /// Returns the end of the data section including all claimed bytes
+    /// after `file_size`.
+    fn data_end(&self) -> usize {
+        self.header.data_off as usize + self.header.data_size as usize + self.grown
+    }
+
+    /// Runs the given function and drops everything it recorded if it
+    /// fails, so that a failed edit leaves the session unchanged.
+    fn atomic<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let (edits, grown, appended) = (self.edits.clone(), self.grown, self.appended.len());
+        let result = f(self);
+        if result.is_err() {
+            self.edits = edits;
+            self.grown = grown;
+            self.appended.truncate(appended);
+        }
+        result
+    }
+
+    fn record(&mut self, edit: Edit) -> Result<()> {
+        let range = edit.offset..edit.offset + edit.data.len();
+        if range.end > self.end() {
+            return Err(Error::InvalidOffset(edit.offset as isize));
+        }
+        if let Some(other) = self
+            .edits
+            .iter()
+            .find(|x| x.offset < range.end && range.start < x.offset + x.data.len())
+        {
+            return Err(Error::InvalidData(format!(
+                "edit at {:#x} overlaps a previous edit at {:#x}",
+                edit.offset, other.offset
+            )));
+        }
+        self.edits.push(edit);
+        Ok(())
+    }
+
+    /// Like [PatchSession::record], but an edit that lies within a previous
+    /// edit changes that one instead, e.g. to patch a value of a relocated
+    /// item.
+    fn record_patch(&mut self, edit: Edit) -> Result<()> {
+        let end = edit.offset + edit.data.len();
+        let other = self
+            .edits
+            .iter_mut()
+            .find(|x| x.offset <= edit.offset && end <= x.offset + x.data.len());
+        match other {
+            Some(other) => {
+                let start = edit.offset - other.offset;
+                other.data[start..start + edit.data.len()].copy_from_slice(&edit.data);
+                Ok(())
+            }
+            None => self.record(edit),
+        }
+    }
+
+    /// Returns the bytes from the given offset up to the end of the file
+    /// (including appended data) or, if the offset lies within an edit, up
+    /// to the end of that edit.
+    /// Other edits are not applied, see [PatchSession::read_patched].
+    fn data_at(&self, offset: usize) -> Result<&[u8]> {
+        let edit = self
+            .edits
+            .iter()
+            .find(|x| x.offset <= offset && offset < x.offset + x.data.len());
+        let data = match edit {
+            Some(edit) => Some(&edit.data[offset - edit.offset..]),
+            None if offset >= self.end() => self.appended.get(offset - self.end()..),
+            None => self.data.as_ref().get(offset..self.end()),
+        };
+        data.ok_or(Error::InvalidOffset(offset as isize))
+    }
+
+    /// Returns `len` bytes at the given offset as they will be written,
+    /// i.e. with all recorded edits applied.
+    fn read_patched(&self, offset: usize, len: usize) -> Result<Vec<u8>> {
+        let range = offset..offset + len;
+        let mut data = match self.data.as_ref().get(range.clone()) {
+            Some(x) if range.end <= self.end() => x.to_vec(),
+            _ => return Err(Error::InvalidOffset(offset as isize)),
+        };
+        for edit in &self.edits {
+            let start = edit.offset.max(range.start);
+            let end = (edit.offset + edit.data.len()).min(range.end);
+            if start < end {
+                data[start - offset..end - offset]
+                    .copy_from_slice(&edit.data[start - edit.offset..end - edit.offset]);
+            }
+        }
+        Ok(data)
+    }
+
+    fn read_u32(&self, offset: usize) -> Result<u32> {
+        match self.data.as_ref().get(offset..offset + 4) {
+            Some(x) => Ok(u32::from_le_bytes([x[0], x[1], x[2], x[3]])),
+            None => Err(Error::InvalidOffset(offset as isize)),
+        }
+    }
+
+    /// Returns the size of the string_data_item at the given offset,
+    /// including the length prefix and the terminating null byte. The item
+    /// has to be well-formed and end within the data section.
+    fn string_data_size(&self, offset: usize) -> Result<usize> {
+        let end = self.header.data_off as usize + self.header.data_size as usize;
+        let data = match self.data.as_ref().get(offset..end) {
+            Some(x) => x,
+            None => return Err(Error::InvalidOffset(offset as isize)),
+        };
+        let mut cursor = Cursor::new(data);
+        mutf8::read(&mut cursor)?;
+        Ok(cursor.position() as usize)
+    }
+}
+
+/// Updates the signature and checksum in the header of the given file.
+fn update_digests(data: &mut [u8]) {
+    // the checksum covers the signature, so it has to be computed last
+    let signature = HeaderItem::compute_signature(data);
+    data[12..32].copy_from_slice(&signature);
+    let checksum = HeaderItem::compute_checksum(data);
+    data[8..12].copy_from_slice(&checksum.to_le_bytes());
+}
+
+/// Returns the number of bytes of the shortest ULEB128 encoding of `value`.
+fn uleb128_size(value: u32) -> usize {
+    (32 - value.leading_zeros() as usize).max(1).div_ceil(7)
+}
+
+/// Encodes `value` as ULEB128 using exactly `width` bytes, which must be at
+/// least [uleb128_size] of the value.
+fn write_uleb128(mut value: u32, width: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(width);
+    for i in 0..width {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        out.push(if i + 1 < width { byte | 0x80 } else { byte });
+    }
+    out
+}
@@ -0,0 +1,428 @@
+//! Human readable names of types, fields, methods and instructions
+//!
+//! Two styles are supported:
+//!
+//! - [Style::Smali] uses descriptors as written by baksmali and apktool,
+//!   e.g. `Lcom/example/Foo;->bar(I)V` or `Lcom/example/Foo;->count:I`,
+//! - [Style::Java] uses source-like names, e.g. `void com.example.Foo.bar(int)`
+//!   or `int com.example.Foo.count`.
+//!
+//! Which parts of a member are printed is controlled by [PrettyOptions].
+//! For compact listings, [Packages] shortens or drops the package of class
+//! names, e.g. `String` instead of `java.lang.String`.
+//! [pretty_class] formats the declaration of a class, e.g.
+//! `public final class com.example.Foo extends android.app.Activity`.
+//! The smali writer uses the same functions to format the operands of
+//! instructions, so [pretty_insn] with the default options produces the
+//! same text as [SmaliWrite::write_insn].
+
+use crate::{
+    dalvik::{
+        dex::{AccessFlags, DexType, FieldIdItem, MethodHandleItem, MethodIdItem},
+        error::Result,
+        file::{method::DexPrototype, DexClassDef, IDexRef},
+        insns::{Index, Insn},
+    },
+    smali::SmaliWrite,
+};
+
+/// Naming style of types and member references.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Style {
+    /// type descriptors, e.g. `Lcom/example/Foo;->bar(I)V`
+    #[default]
+    Smali,
+
+    /// Java source names, e.g. `void com.example.Foo.bar(int)`
+    Java,
+}
+
+/// How the package of a class name is printed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Packages {
+    /// the full name, e.g. `Ljava/lang/String;` or `java.lang.String`
+    #[default]
+    Full,
+
+    /// the package replaced by an ellipsis, e.g. `L…/String;` or `….String`
+    Ellipsis,
+
+    /// the simple name only, e.g. `LString;` or `String`
+    Simple,
+}
+
+/// Options for formatting types and member references.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrettyOptions {
+    pub style: Style,
+
+    /// Prefix fields and methods with their declaring class.
+    pub qualified: bool,
+
+    /// Include the type of fields and the prototype of methods.
+    pub signature: bool,
+
+    /// How packages of class names are printed. Shortened names can't be
+    /// assembled again.
+    pub packages: Packages,
+}
+
+impl Default for PrettyOptions {
+    fn default() -> Self {
+        PrettyOptions::smali()
+    }
+}
+
+impl PrettyOptions {
+    /// Fully qualified references in smali style.
+    pub fn smali() -> Self {
+        PrettyOptions {
+            style: Style::Smali,
+            qualified: true,
+            signature: true,
+            packages: Packages::Full,
+        }
+    }
+
+    /// Fully qualified references in Java style.
+    pub fn java() -> Self {
+        PrettyOptions {
+            style: Style::Java,
+            ..PrettyOptions::smali()
+        }
+    }
+}
+
+/// Formats a type, e.g. `[[Ljava/lang/String;` or `java.lang.String[][]`.
+pub fn pretty_type(type_: &DexType, options: &PrettyOptions) -> String {
+    let descriptor = short_descriptor(&type_.descriptor, options.packages);
+    match options.style {
+        Style::Smali => "[".repeat(type_.dim) + &descriptor,
+        Style::Java => {
+            let name = match descriptor.as_str() {
+                "V" => "void".to_string(),
+                "Z" => "boolean".to_string(),
+                "B" => "byte".to_string(),
+                "S" => "short".to_string(),
+                "C" => "char".to_string(),
+                "I" => "int".to_string(),
+                "J" => "long".to_string(),
+                "F" => "float".to_string(),
+                "D" => "double".to_string(),
+                descriptor => descriptor
+                    .strip_prefix('L')
+                    .and_then(|x| x.strip_suffix(';'))
+                    .unwrap_or(descriptor)
+                    .replace('/', "."),
+            };
+            name + &"[]".repeat(type_.dim)
+        }
+    }
+}
+
+/// Shortens the package of a class descriptor, e.g. `Ljava/lang/String;`
+/// to `L…/String;`. Primitive types are returned as they are.
+fn short_descriptor(descriptor: &str, packages: Packages) -> String {
+    let class = descriptor
+        .strip_prefix('L')
+        .and_then(|x| x.strip_suffix(';'));
+    let Some((_, name)) = class.and_then(|x| x.rsplit_once('/')) else {
+        return descriptor.to_string();
+    };
+    match packages {
+        Packages::Full => descriptor.to_string(),
+        Packages::Ellipsis => format!("L…/{};", name),
+        Packages::Simple => format!("L{};", name),
+    }
+}
+
+/// Formats a prototype, e.g. `(ILjava/lang/String;)V` or
+/// `void (int, java.lang.String)`.
+pub fn pretty_proto(proto: &DexPrototype, options: &PrettyOptions) -> String {
+    match options.style {
+        Style::Smali if options.packages == Packages::Full => proto.to_string(),
+        Style::Smali => {
+            let parameters: String = proto
+                .parameters
+                .iter()
+                .map(|x| pretty_type(x, options))
+                .collect();
+            format!(
+                "({}){}",
+                parameters,
+                pretty_type(&proto.return_type, options)
+            )
+        }
+        Style::Java => format!(
+            "{} ({})",
+            pretty_type(&proto.return_type, options),
+            java_parameters(proto, options)
+        ),
+    }
+}
+
+fn java_parameters(proto: &DexPrototype, options: &PrettyOptions) -> String {
+    proto
+        .parameters
+        .iter()
+        .map(|x| pretty_type(x, options))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Formats a field reference, e.g. `Lcom/example/Foo;->count:I` or
+/// `int com.example.Foo.count`.
+pub fn pretty_field_ref(
+    field: &FieldIdItem,
+    dex: IDexRef<'_>,
+    options: &PrettyOptions,
+) -> Result<String> {
+    let name = dex.get_string(field.name_idx)?;
+    let class = dex.get_type(field.class_idx as u32)?;
+    let type_ = dex.get_type(field.type_idx as u32)?;
+    Ok(format_field(&class, &name, &type_, options))
+}
+
+/// Formats a field given by its parts, see [pretty_field_ref].
+pub(crate) fn format_field(
+    class: &DexType,
+    name: &str,
+    type_: &DexType,
+    options: &PrettyOptions,
+) -> String {
+    let mut text = String::new();
+    match options.style {
+        Style::Smali => {
+            if options.qualified {
+                text += &format!("{}->", pretty_type(class, options));
+            }
+            text += name;
+            if options.signature {
+                text += &format!(":{}", pretty_type(type_, options));
+            }
+        }
+        Style::Java => {
+            if options.signature {
+                text += &format!("{} ", pretty_type(type_, options));
+            }
+            if options.qualified {
+                text += &format!("{}.", pretty_type(class, options));
+            }
+            text += name;
+        }
+    }
+    text
+}
+
+/// Formats a method reference, e.g. `Lcom/example/Foo;->bar(I)V` or
+/// `void com.example.Foo.bar(int)`.
+pub fn pretty_method_ref(
+    method: &MethodIdItem,
+    dex: IDexRef<'_>,
+    options: &PrettyOptions,
+) -> Result<String> {
+    let name = dex.get_string(method.name_idx)?;
+    let class = dex.get_type(method.class_idx as u32)?;
+    let proto = dex.get_proto(method.proto_idx as u32)?;
+    Ok(format_method(&class, &name, &proto, options))
+}
+
+/// Formats a method given by its parts, see [pretty_method_ref].
+pub(crate) fn format_method(
+    class: &DexType,
+    name: &str,
+    proto: &DexPrototype,
+    options: &PrettyOptions,
+) -> String {
+    let mut text = String::new();
+    match options.style {
+        Style::Smali => {
+            if options.qualified {
+                text += &format!("{}->", pretty_type(class, options));
+            }
+            text += name;
+            if options.signature {
+                text += &pretty_proto(proto, options);
+            }
+        }
+        Style::Java => {
+            if options.signature {
+                text += &format!("{} ", pretty_type(&proto.return_type, options));
+            }
+            if options.qualified {
+                text += &format!("{}.", pretty_type(class, options));
+            }
+            text += name;
+            if options.signature {
+                text += &format!("({})", java_parameters(proto, options));
+            }
+        }
+    }
+    text
+}
+
+/// Formats the field at the given index into the `field_ids` list.
+pub fn pretty_field(dex: IDexRef<'_>, field_idx: u32, options: &PrettyOptions) -> Result<String> {
+    let field = dex.get_field(field_idx)?;
+    pretty_field_ref(&field, dex, options)
+}
+
+/// Formats the method at the given index into the `method_ids` list.
+pub fn pretty_method(dex: IDexRef<'_>, method_idx: u32, options: &PrettyOptions) -> Result<String> {
+    let method = dex.get_method(method_idx)?;
+    pretty_method_ref(&method, dex, options)
+}
+
+/// Formats the declaration of a class with its modifiers, superclass and
+/// interfaces, e.g.
+/// `public final class com.example.Foo extends android.app.Activity implements java.lang.Runnable`.
+///
+/// Modifiers implied by the kind of the class are left out, like `abstract`
+/// for interfaces or `final` for enums, and so are the implied superclasses
+/// `java.lang.Object` and `java.lang.Enum`. Types are formatted in the
+/// given style.
+pub fn pretty_class_def(class: &DexClassDef, options: &PrettyOptions) -> String {
+    let flags = class.flags.as_ref().map_or(0, |x| x.bits());
+    let has = |flag: AccessFlags| flags & flag.bits() != 0;
+    let (kind, implied) = if has(AccessFlags::ANNOTATION) {
+        ("@interface", AccessFlags::INTERFACE | AccessFlags::ABSTRACT)
+    } else if has(AccessFlags::INTERFACE) {
+        ("interface", AccessFlags::INTERFACE | AccessFlags::ABSTRACT)
+    } else if has(AccessFlags::ENUM) {
+        ("enum", AccessFlags::FINAL)
+    } else {
+        ("class", AccessFlags::empty())
+    };
+
+    let mut text = String::new();
+    let modifiers = [
+        (AccessFlags::PUBLIC, "public"),
+        (AccessFlags::PRIVATE, "private"),
+        (AccessFlags::PROTECTED, "protected"),
+        (AccessFlags::STATIC, "static"),
+        (AccessFlags::ABSTRACT, "abstract"),
+        (AccessFlags::FINAL, "final"),
+    ];
+    for (flag, name) in modifiers {
+        if implied.bits() & flag.bits() == 0 && has(flag) {
+            text += name;
+            text.push(' ');
+        }
+    }
+    text += &format!("{} {}", kind, pretty_type(&class.type_, options));
+
+    // interfaces have java.lang.Object as their superclass as well, but
+    // can't extend a class
+    let implied_super = match kind {
+        "class" => Some("Ljava/lang/Object;"),
+        "enum" => Some("Ljava/lang/Enum;"),
+        _ => None,
+    };
+    let super_class = class
+        .super_class
+        .as_ref()
+        .filter(|x| implied_super.is_some_and(|y| x.to_string() != y));
+    if let Some(super_class) = super_class {
+        text += &format!(" extends {}", pretty_type(super_class, options));
+    }
+    // annotations always implement java.lang.annotation.Annotation
+    let interfaces: Vec<String> = match kind {
+        "@interface" => Vec::new(),
+        _ => class
+            .interfaces
+            .iter()
+            .map(|x| pretty_type(x, options))
+            .collect(),
+    };
+    if !interfaces.is_empty() {
+        // interfaces extend other interfaces
+        let keyword = match kind {
+            "interface" => "extends",
+            _ => "implements",
+        };
+        text += &format!(" {} {}", keyword, interfaces.join(", "));
+    }
+    text
+}
+
+/// Formats the declaration of the class at the given index into the
+/// `class_defs` list, see [pretty_class_def].
+pub fn pretty_class(
+    dex: IDexRef<'_>,
+    class_def_idx: u32,
+    options: &PrettyOptions,
+) -> Result<String> {
+    let class = dex.get_class_def(class_def_idx)?;
+    Ok(pretty_class_def(&class, options))
+}
+
+/// Formats a method handle as its kind and the referenced member, e.g.
+/// `invoke-static@Lcom/example/Foo;->bar(I)V` or
+/// `static-get@int com.example.Foo.count`.
+pub fn pretty_method_handle_ref(
+    handle: &MethodHandleItem,
+    dex: IDexRef<'_>,
+    options: &PrettyOptions,
+) -> Result<String> {
+    let kind = &handle.method_handle_type;
+    let index = handle.field_or_method_id as u32;
+    let member = match kind.is_field_accessor() {
+        true => pretty_field(dex, index, options)?,
+        false => pretty_method(dex, index, options)?,
+    };
+    Ok(format!("{}@{}", kind.name(), member))
+}
+
+/// Formats the method handle at the given index into the `method_handles`
+/// list.
+pub fn pretty_method_handle(
+    dex: IDexRef<'_>,
+    handle_idx: u32,
+    options: &PrettyOptions,
+) -> Result<String> {
+    let handle = dex.get_method_handle(handle_idx)?;
+    pretty_method_handle_ref(&handle, dex, options)
+}
+
+/// Formats the name and type of the method a call site links and the
+/// bootstrap method that links it, e.g.
+/// `"apply" (II)I, bootstrap invoke-static@Lcom/example/Foo;->bootstrap(...)`.
+pub fn pretty_call_site(
+    dex: IDexRef<'_>,
+    call_site_idx: u32,
+    options: &PrettyOptions,
+) -> Result<String> {
+    let call_site = dex.resolve_call_site(call_site_idx)?;
+    Ok(format!(
+        "\"{}\" {}, bootstrap {}",
+        call_site.name.escape_default(),
+        pretty_proto(&call_site.method_type, options),
+        pretty_method_handle_ref(&call_site.bootstrap, dex, options)?
+    ))
+}
+
+/// Formats the index operand of an instruction.
+pub fn pretty_index(index: &Index, dex: IDexRef<'_>, options: &PrettyOptions) -> Result<String> {
+    Ok(match index {
+        Index::Literal(x) => format!("{:#x}", x),
+        Index::Field(x) => pretty_field_ref(x, dex, options)?,
+        Index::Method(_, x) => pretty_method_ref(x, dex, options)?,
+        Index::MethodHandle(x) => pretty_method_handle_ref(x, dex, options)?,
+        // the call site is always the last operand
+        Index::CallSite(x, _) => {
+            format!("call_site@{}  # {}", x, pretty_call_site(dex, *x, options)?)
+        }
+        Index::Proto(x) => pretty_proto(x, options),
+        Index::Type(x) => pretty_type(x, options),
+        Index::String(x) => format!("\"{}\"", x.escape_default()),
+        _ => format!("{:?}", index),
+    })
+}
+
+/// Formats a single instruction including its operands, e.g.
+/// `invoke-virtual {v0, v1}, void java.io.PrintStream.println(java.lang.String)`.
+pub fn pretty_insn(insn: &Insn, dex: IDexRef<'_>, options: &PrettyOptions) -> Result<String> {
+    let mut output = Vec::new();
+    output.write_insn_with(insn, dex, 0, options)?;
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
@@ -0,0 +1,320 @@
+//! Batch scanning of DEX files and APKs
+//!
+//! Tools that run over a whole corpus of samples all need the same driver:
+//! find the files, open every DEX file within them, run an analysis and
+//! keep going when a single file is broken. [scan] does this for a list of
+//! files and directories, calling a [DexScanner] for every DEX file on a
+//! pool of threads.
+//!
+//! Directories are searched recursively for `.dex` files and, with the
+//! `apk` feature, for `.apk` and `.jar` files. All `classes*.dex` entries of
+//! such an archive are scanned in the order of their multidex index.
+//! Entries larger than [MAX_ENTRY_SIZE] are reported as errors.
+//!
+//! Every DEX file gets its own [ScanResult]. Errors, including panics of
+//! the parser or the scanner, only affect the result of the file they
+//! occurred in:
+//!
+//! ```ignore
+//! struct CountClasses;
+//!
+//! impl DexScanner for CountClasses {
+//!     type Output = u32;
+//!
+//!     fn scan(&self, _: &Source, dex: &mut ScanDex<'_>) -> Result<u32> {
+//!         Ok(dex.header.class_defs_size)
+//!     }
+//! }
+//!
+//! for result in scan(&CountClasses, &["samples/"], &ScanOptions::default()) {
+//!     println!("{}: {:?}", result.source, result.output);
+//! }
+//! ```
+
+use std::{
+    fmt::Display,
+    io::Cursor,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use crate::dalvik::{
+    error::{Error, Result},
+    file::Dex,
+};
+
+/// Maximum number of bytes read from a single archive entry. The size
+/// stored in an archive is not trusted, so a compressed entry can't make
+/// the scanner allocate more memory than this.
+pub const MAX_ENTRY_SIZE: u64 = 1 << 30;
+
+/// A DEX file as passed to a [DexScanner].
+pub type ScanDex<'a> = Dex<'a, Cursor<Vec<u8>>>;
+
+/// Where a scanned DEX file comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub path: PathBuf,
+
+    /// name of the DEX file within an archive, e.g. `classes2.dex`
+    pub entry: Option<String>,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(entry) = &self.entry {
+            write!(f, "!{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of scanning a single DEX file.
+#[derive(Debug)]
+pub struct ScanResult<T> {
+    pub source: Source,
+    pub output: Result<T>,
+}
+
+/// Options for [scan].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanOptions {
+    /// number of worker threads, or zero to use one per CPU
+    pub threads: usize,
+
+    /// Parse files with [Dex::read_untrusted] instead of verifying their
+    /// checksum and signature.
+    pub untrusted: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            threads: 0,
+            untrusted: true,
+        }
+    }
+}
+
+/// An analysis that is run on every DEX file of a scan.
+///
+/// The same scanner is shared by all worker threads.
+pub trait DexScanner: Sync {
+    type Output: Send;
+
+    fn scan(&self, source: &Source, dex: &mut ScanDex<'_>) -> Result<Self::Output>;
+}
+
+/// Scans all DEX files found at the given paths, see the [module
+/// docs](self). Results are returned in the order of the paths, files
+/// within a directory are sorted by name.
+///
+/// Panics are caught, but still reported by the panic hook, which prints
+/// them to stderr by default.
+pub fn scan<S, P>(scanner: &S, paths: &[P], options: &ScanOptions) -> Vec<ScanResult<S::Output>>
+where
+    S: DexScanner,
+    P: AsRef<Path>,
+{
+    let mut files = Vec::new();
+    let mut results = Vec::new();
+    for path in paths {
+        find_files(path.as_ref(), true, &mut files, &mut results);
+    }
+
+    let threads = match options.threads {
+        0 => thread::available_parallelism().map_or(1, |x| x.get()),
+        threads => threads,
+    };
+    let next = AtomicUsize::new(0);
+    let done = Mutex::new(Vec::with_capacity(files.len()));
+    thread::scope(|scope| {
+        for _ in 0..threads.min(files.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = files.get(index) else {
+                    break;
+                };
+                let output = scan_file(scanner, path, options);
+                done.lock().unwrap().push((index, output));
+            });
+        }
+    });
+
+    let mut done = done.into_inner().unwrap();
+    done.sort_by_key(|(index, _)| *index);
+    results.extend(done.into_iter().flat_map(|(_, x)| x));
+    results
+}
+
+/// Collects the files to scan. Explicitly given files are always taken,
+/// files within directories only if their extension is known.
+///
+/// Symbolic links to directories are only followed if they are given
+/// explicitly, so that a link to a parent directory can't make the search
+/// recurse endlessly.
+fn find_files<T>(
+    path: &Path,
+    explicit: bool,
+    files: &mut Vec<PathBuf>,
+    errors: &mut Vec<ScanResult<T>>,
+) {
+    let is_link = path
+        .symlink_metadata()
+        .is_ok_and(|x| x.file_type().is_symlink());
+    if is_link && !explicit && path.is_dir() {
+        return;
+    }
+    if !path.is_dir() {
+        let extension = path.extension().and_then(|x| x.to_str());
+        if explicit || extension.is_some_and(is_known_extension) {
+            files.push(path.to_path_buf());
+        }
+        return;
+    }
+
+    let entries = std::fs::read_dir(path).and_then(|x| x.collect::<std::io::Result<Vec<_>>>());
+    match entries {
+        Ok(entries) => {
+            let mut paths: Vec<_> = entries.into_iter().map(|x| x.path()).collect();
+            paths.sort();
+            for path in paths {
+                find_files(&path, false, files, errors);
+            }
+        }
+        Err(e) => errors.push(ScanResult {
+            source: Source {
+                path: path.to_path_buf(),
+                entry: None,
+            },
+            output: Err(e.into()),
+        }),
+    }
+}
+
+fn is_known_extension(extension: &str) -> bool {
+    extension == "dex" || (cfg!(feature = "apk") && matches!(extension, "apk" | "jar"))
+}
+
+/// Scans a DEX file or all DEX files of an archive.
+fn scan_file<S: DexScanner>(
+    scanner: &S,
+    path: &Path,
+    options: &ScanOptions,
+) -> Vec<ScanResult<S::Output>> {
+    let source = |entry| Source {
+        path: path.to_path_buf(),
+        entry,
+    };
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            return vec![ScanResult {
+                source: source(None),
+                output: Err(e.into()),
+            }]
+        }
+    };
+    if !data.starts_with(b"PK\x03\x04") {
+        return vec![run(scanner, source(None), data, options)];
+    }
+
+    match read_archive(data) {
+        Ok(entries) => entries
+            .into_iter()
+            .map(|(name, data)| {
+                let source = source(Some(name));
+                match data {
+                    Ok(data) => run(scanner, source, data, options),
+                    Err(e) => ScanResult {
+                        source,
+                        output: Err(e),
+                    },
+                }
+            })
+            .collect(),
+        Err(e) => vec![ScanResult {
+            source: source(None),
+            output: Err(e),
+        }],
+    }
+}
+
+/// Parses a single DEX file and runs the scanner on it.
+fn run<S: DexScanner>(
+    scanner: &S,
+    source: Source,
+    data: Vec<u8>,
+    options: &ScanOptions,
+) -> ScanResult<S::Output> {
+    let output = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut reader = Cursor::new(data);
+        let mut dex = if options.untrusted {
+            Dex::read_untrusted(&mut reader)?
+        } else {
+            Dex::read(&mut reader, true)?
+        };
+        scanner.scan(&source, &mut dex)
+    }));
+    let output = output.unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|x| x.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(Error::InvalidData(format!("panicked: {}", message)))
+    });
+    ScanResult { source, output }
+}
+
+/// Returns the multidex index of an archive entry, i.e. 1 for
+/// `classes.dex` and N for `classesN.dex`.
+#[cfg(feature = "apk")]
+fn multidex_index(name: &str) -> Option<u32> {
+    match name.strip_prefix("classes")?.strip_suffix(".dex")? {
+        "" => Some(1),
+        index => index.parse().ok().filter(|x| *x > 1),
+    }
+}
+
+/// Reads all `classes*.dex` entries of a ZIP archive.
+#[cfg(feature = "apk")]
+pub(crate) fn read_archive(data: Vec<u8>) -> Result<Vec<(String, Result<Vec<u8>>)>> {
+    use std::io::Read;
+
+    let invalid = |e: zip::result::ZipError| Error::InvalidData(format!("invalid archive: {}", e));
+    let mut archive = zip::ZipArchive::new(Cursor::new(data)).map_err(invalid)?;
+    let mut names: Vec<(u32, String)> = archive
+        .file_names()
+        .filter_map(|x| Some((multidex_index(x)?, x.to_string())))
+        .collect();
+    names.sort();
+
+    let mut entries = Vec::with_capacity(names.len());
+    for (_, name) in names {
+        let data = archive.by_name(&name).map_err(invalid).and_then(|file| {
+            let mut data = Vec::new();
+            file.take(MAX_ENTRY_SIZE + 1).read_to_end(&mut data)?;
+            if data.len() as u64 > MAX_ENTRY_SIZE {
+                return Err(Error::InvalidData(format!(
+                    "{} is larger than {} bytes",
+                    name, MAX_ENTRY_SIZE
+                )));
+            }
+            Ok(data)
+        });
+        entries.push((name, data));
+    }
+    Ok(entries)
+}
+
+#[cfg(not(feature = "apk"))]
+pub(crate) fn read_archive(_: Vec<u8>) -> Result<Vec<(String, Result<Vec<u8>>)>> {
+    Err(Error::Custom("reading archives requires the `apk` feature"))
+}
@@ -0,0 +1,270 @@
+//! Export of whole files as smali projects
+//!
+//! [export_project] writes every class of a DEX file or APK to its own
+//! `.smali` file, in directories derived from the package of the class,
+//! the way `baksmali` lays out its output:
+//!
+//! ```text
+//! out/com/example/MainActivity.smali
+//! out/com/example/MainActivity$1.smali
+//! ```
+//!
+//! The DEX files of an archive go to separate directories named like the
+//! ones of `apktool`: `smali` for `classes.dex` and `smali_classesN` for
+//! `classesN.dex`.
+//!
+//! Descriptors may contain characters that are not allowed in file names
+//! on some platforms, and two classes may differ in case only. Such
+//! characters are escaped as `#xx` with their hexadecimal value, long names
+//! are shortened and colliding names get a counter, e.g. `Foo.1.smali`.
+//! The names only depend on the order of the classes, not on the number of
+//! threads.
+
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{BufWriter, Cursor, Read, Seek, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use crate::{
+    dalvik::{
+        error::{Error, Result},
+        file::{Dex, IDex},
+    },
+    scan::read_archive,
+};
+
+use super::SmaliWrite;
+
+/// Longest file name without the counter and extension, in bytes.
+const MAX_NAME_LEN: usize = 200;
+
+/// Options for [export_project].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportOptions {
+    /// number of worker threads, or zero to use one per CPU
+    pub threads: usize,
+
+    /// Parse files with [Dex::read_untrusted] instead of verifying their
+    /// checksum and signature.
+    pub untrusted: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            threads: 1,
+            untrusted: true,
+        }
+    }
+}
+
+/// The outcome of writing a single class.
+#[derive(Debug)]
+pub struct ExportedClass {
+    /// name of the DEX file within an archive, e.g. `classes2.dex`
+    pub entry: Option<String>,
+
+    /// Index into the `class_defs` list.
+    pub class_def: u32,
+
+    /// the written file
+    pub path: PathBuf,
+
+    pub result: Result<()>,
+}
+
+/// Writes all classes of the DEX file or archive at `input` to `out_dir`,
+/// see the [module docs](self).
+///
+/// Classes that can't be parsed or written are reported in their
+/// [ExportedClass] and don't stop the export. Errors that affect a whole
+/// DEX file are returned.
+pub fn export_project<P, Q>(
+    input: P,
+    out_dir: Q,
+    options: &ExportOptions,
+) -> Result<Vec<ExportedClass>>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let data = fs::read(input)?;
+    let out_dir = out_dir.as_ref();
+    if !data.starts_with(b"PK\x03\x04") {
+        return export_dex(&data, out_dir, options);
+    }
+
+    let mut classes = Vec::new();
+    for (name, data) in read_archive(data)? {
+        let dir = match name.trim_end_matches(".dex") {
+            "classes" => "smali".to_string(),
+            stem => format!("smali_{}", stem),
+        };
+        let mut exported = export_dex(&data?, &out_dir.join(dir), options)?;
+        for class in &mut exported {
+            class.entry = Some(name.clone());
+        }
+        classes.extend(exported);
+    }
+    Ok(classes)
+}
+
+/// Writes all classes of a single DEX file to `out_dir`. The results are
+/// in the order of the `class_defs` list.
+pub fn export_dex(
+    data: &[u8],
+    out_dir: &Path,
+    options: &ExportOptions,
+) -> Result<Vec<ExportedClass>> {
+    let mut reader = Cursor::new(data);
+    let mut dex = open(&mut reader, options)?;
+    let mut names = FileNames::default();
+    let mut paths = Vec::with_capacity(dex.header.class_defs_size as usize);
+    for index in 0..dex.header.class_defs_size {
+        let item = dex.get_class_def_item(index)?;
+        let type_ = dex.get_type(item.class_idx)?;
+        paths.push(out_dir.join(names.assign(&type_.descriptor)));
+    }
+
+    let threads = match options.threads {
+        0 => thread::available_parallelism().map_or(1, |x| x.get()),
+        threads => threads,
+    };
+    let next = AtomicUsize::new(0);
+    let done = Mutex::new(Vec::with_capacity(paths.len()));
+    thread::scope(|scope| {
+        for _ in 0..threads.min(paths.len()) {
+            scope.spawn(|| {
+                // parsed files can't be shared between threads, so every
+                // thread parses its own
+                let mut reader = Cursor::new(data);
+                let mut dex = open(&mut reader, options);
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = paths.get(index) else {
+                        break;
+                    };
+                    let result = match &mut dex {
+                        Ok(dex) => write_class_file(dex, index as u32, path),
+                        Err(e) => Err(Error::InvalidData(e.to_string())),
+                    };
+                    done.lock().unwrap().push(ExportedClass {
+                        entry: None,
+                        class_def: index as u32,
+                        path: path.clone(),
+                        result,
+                    });
+                }
+            });
+        }
+    });
+
+    let mut done = done.into_inner().unwrap();
+    done.sort_by_key(|x| x.class_def);
+    Ok(done)
+}
+
+fn open<'a, 'b>(
+    reader: &'a mut Cursor<&'b [u8]>,
+    options: &ExportOptions,
+) -> Result<Dex<'a, Cursor<&'b [u8]>>> {
+    match options.untrusted {
+        true => Dex::read_untrusted(reader),
+        false => Dex::read(reader, true),
+    }
+}
+
+fn write_class_file<R: Read + Seek>(dex: &mut Dex<'_, R>, index: u32, path: &Path) -> Result<()> {
+    let class = dex.load_class(index)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_class(&class, dex)?;
+    writeln!(out)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Assigns unique relative file names to class descriptors.
+#[derive(Debug, Default)]
+struct FileNames {
+    /// lowercase names that were assigned, so that names which only differ
+    /// in case don't overwrite each other on case-insensitive file systems
+    taken: HashSet<String>,
+}
+
+impl FileNames {
+    fn assign(&mut self, descriptor: &str) -> PathBuf {
+        let name = descriptor
+            .strip_prefix('L')
+            .and_then(|x| x.strip_suffix(';'))
+            .unwrap_or(descriptor);
+        let mut path: PathBuf = name.split('/').map(escape_component).collect();
+        let stem = path
+            .file_name()
+            .map(|x| x.to_os_string())
+            .unwrap_or_default();
+
+        let mut counter = 0;
+        loop {
+            let mut file_name = stem.clone();
+            if counter > 0 {
+                file_name.push(format!(".{}", counter));
+            }
+            file_name.push(".smali");
+            path.set_file_name(file_name);
+            if self.taken.insert(path.to_string_lossy().to_lowercase()) {
+                return path;
+            }
+            counter += 1;
+        }
+    }
+}
+
+/// Escapes a single path component of a descriptor, see the [module
+/// docs](self).
+fn escape_component(component: &str) -> String {
+    let mut escaped = String::with_capacity(component.len());
+    for (i, c) in component.char_indices() {
+        // trailing dots and spaces are dropped on Windows
+        let trailing = i + c.len_utf8() == component.len() && matches!(c, '.' | ' ');
+        if trailing || c.is_ascii_control() || "<>:\"\\|?*#".contains(c) {
+            escaped.push_str(&format!("#{:02x}", c as u32));
+        } else {
+            escaped.push(c);
+        }
+    }
+    if escaped.is_empty() || is_reserved(&escaped) {
+        escaped.push('#');
+    }
+    if escaped.len() > MAX_NAME_LEN {
+        let mut end = MAX_NAME_LEN;
+        while !escaped.is_char_boundary(end) {
+            end -= 1;
+        }
+        escaped.truncate(end);
+    }
+    escaped
+}
+
+/// Whether the name is a device name on Windows, which can't be used as a
+/// file name even with an extension.
+fn is_reserved(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).to_ascii_uppercase();
+    match stem.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        _ => {
+            (stem.starts_with("COM") || stem.starts_with("LPT"))
+                && stem.len() == 4
+                && stem.as_bytes()[3].is_ascii_digit()
+        }
+    }
+}
@@ -1,14 +1,19 @@
+use std::collections::BTreeMap;
 use std::io::Write;
 use std::rc::Rc;
 
 use crate::dalvik::dex::{AccessFlags, DexType, FieldIdItem, MethodIdItem};
 use crate::dalvik::error::Result;
 use crate::dalvik::file::annotation::DexAnnotation;
+use crate::dalvik::file::debug::LocalVariable;
 use crate::dalvik::file::field::DexField;
 use crate::dalvik::file::method::DexMethod;
 use crate::dalvik::file::DexClassDef;
 use crate::dalvik::file::{method::DexPrototype, DexValue, IDexRef};
 use crate::dalvik::insns::{self, Index, Insn, InsnFormat, Payload};
+use crate::pretty::{pretty_index, pretty_method_handle_ref, PrettyOptions};
+
+use super::Labels;
 
 // A small hack to implement write_* operations for all
 // `Write` types.
@@ -58,12 +63,7 @@ pub trait SmaliWrite: Write {
 
     fn write_proto(&mut self, proto: &DexPrototype) -> Result<()> {
         // (param_types) return_type
-        write!(self, "(")?;
-        for param in proto.parameters.iter() {
-            self.write_type(param)?;
-        }
-        write!(self, ")")?;
-        self.write_type(&proto.return_type)?;
+        write!(self, "{}", proto)?;
         Ok(())
     }
 
@@ -74,6 +74,10 @@ pub trait SmaliWrite: Write {
             DexValue::FieldRef(v) => self.write_field_ref(v, dex)?,
             DexValue::MethodRef(.., v) => self.write_method_ref(v, dex)?,
             DexValue::MethodType(v) => self.write_proto(v)?,
+            DexValue::MethodHandle(v) => {
+                let handle = pretty_method_handle_ref(v, dex, &PrettyOptions::smali())?;
+                write!(self, "{}", handle)?
+            }
             DexValue::Int(v) => write!(self, "{:#x}", v)?,
             DexValue::Float(v) => write!(self, "{}", v)?,
             DexValue::Long(v) => write!(self, "{:#x}", v)?,
@@ -95,11 +99,10 @@ pub trait SmaliWrite: Write {
             DexValue::Char(v) => write!(self, "'{}'", v.escape_default())?,
             DexValue::Short(v) => write!(self, "{:#x}", v)?,
             DexValue::Byte(v) => write!(self, "{:#x}", v)?,
-            // DexValue::Annotation(v) => self.w,
+            DexValue::Annotation(v) => self.write_annotation(v, dex, 0, true)?,
             DexValue::Enum(v) => {
                 self.write_field_ref(v, dex)?;
             }
-            _ => write!(self, "{:?}", value)?,
         }
         Ok(())
     }
@@ -110,7 +113,7 @@ pub trait SmaliWrite: Write {
             Index::Field(a) => {
                 self.write_field_ref(a, dex)?;
             }
-            Index::Method(a) => {
+            Index::Method(_, a) => {
                 self.write_method_ref(a, dex)?;
             }
             Index::Proto(a) => {
@@ -124,6 +127,15 @@ pub trait SmaliWrite: Write {
             Index::String(a) => {
                 write!(self, "\"{}\"", a.escape_default())?;
             }
+            Index::CallSite(..) => {
+                let call_site = pretty_index(index, dex, &PrettyOptions::smali())?;
+                write!(self, "{}", call_site)?;
+            }
+            Index::MethodHandle(a) => {
+                // kind@member
+                let handle = pretty_method_handle_ref(a, dex, &PrettyOptions::smali())?;
+                write!(self, "{}", handle)?;
+            }
             _ => {
                 // TODO
                 write!(self, "{:?}", index)?;
@@ -133,17 +145,29 @@ pub trait SmaliWrite: Write {
     }
 
     fn write_insn(&mut self, insn: &Insn, dex: IDexRef<'_>, indent: usize) -> Result<()> {
+        self.write_insn_with(insn, dex, indent, &PrettyOptions::smali())
+    }
+
+    /// Writes an instruction whose references are formatted with the given
+    /// options, see [pretty](crate::pretty).
+    fn write_insn_with(
+        &mut self,
+        insn: &Insn,
+        dex: IDexRef<'_>,
+        indent: usize,
+        options: &PrettyOptions,
+    ) -> Result<()> {
         let indent_val = "    ".repeat(indent);
         write!(self, "{}", indent_val)?;
         if let Some(payload) = &insn.payload {
             let indent2 = "    ".repeat(indent + 1);
             match payload {
                 Payload::FillArrayData(data) => {
-                    write!(self, ".array-data {:#x} {:#x}", data.width, data.size)?;
+                    writeln!(self, ".array-data {:#x} {:#x}", data.width, data.size)?;
                     for v in data.data.iter() {
                         writeln!(self, "{}{:#x}", indent2, v)?;
                     }
-                    write!(self, ".end array-data")?;
+                    write!(self, "{}.end array-data", indent_val)?;
                 }
                 Payload::PackedSwitch(pswitch) => {
                     writeln!(self, ".packed-switch {:#x}", pswitch.first_key)?;
@@ -196,33 +220,33 @@ pub trait SmaliWrite: Write {
                 }
                 InsnFormat::Format21s { a, b } => {
                     write!(self, "v{}, ", a)?; // op vAA, +BBBB
-                    self.write_index(b, dex)?;
+                    write!(self, "{}", pretty_index(b, dex, options)?)?;
                 }
                 InsnFormat::Format21h { a, b } => {
                     write!(self, "v{}, ", a)?; // op vAA, +BBBB0000
-                    self.write_index(b, dex)?;
+                    write!(self, "{}", pretty_index(b, dex, options)?)?;
                 }
                 InsnFormat::Format21c { a, b } => {
                     write!(self, "v{}, ", a)?; // op vAA, kind@BBBB
-                    self.write_index(b, dex)?;
+                    write!(self, "{}", pretty_index(b, dex, options)?)?;
                 }
                 InsnFormat::Format23x { a, b, c } => {
                     write!(self, "v{}, v{}, v{}", a, b, c)?; // op vAA, vBB, vCC
                 }
                 InsnFormat::Format22b { a, b, c } => {
                     write!(self, "v{}, v{}, ", a, b)?; // op vAA, vBB, #+CC
-                    self.write_index(c, dex)?;
+                    write!(self, "{}", pretty_index(c, dex, options)?)?;
                 }
                 InsnFormat::Format22t { a, b, c } => {
                     write!(self, "v{}, v{}, {}", a, b, c)?; // op vAA, vBB, +CCCC
                 }
                 InsnFormat::Format22s { a, b, c } => {
                     write!(self, "v{}, v{}, ", a, b)?; // op vAA, vBB, +CCCC
-                    self.write_index(c, dex)?;
+                    write!(self, "{}", pretty_index(c, dex, options)?)?;
                 }
                 InsnFormat::Format22c { a, b, c } => {
                     write!(self, "v{}, v{}, ", a, b)?; // op vAA, vBB, kind@CCCC
-                    self.write_index(c, dex)?;
+                    write!(self, "{}", pretty_index(c, dex, options)?)?;
                 }
                 InsnFormat::Format30t { a } => {
                     write!(self, "{}", a)?; // op +AAAAAAAA
@@ -232,14 +256,14 @@ pub trait SmaliWrite: Write {
                 }
                 InsnFormat::Format31i { a, b } => {
                     write!(self, "v{}, ", a)?; // op vAA, #+BBBBBBBB
-                    self.write_index(b, dex)?;
+                    write!(self, "{}", pretty_index(b, dex, options)?)?;
                 }
                 InsnFormat::Format31t { a, b } => {
                     write!(self, "v{}, {}", a, b)?; // op vAAAA, +BBBB
                 }
                 InsnFormat::Format31c { a, b } => {
                     write!(self, "v{}, ", a)?; // op vAAAA, kind@BBBB
-                    self.write_index(b, dex)?;
+                    write!(self, "{}", pretty_index(b, dex, options)?)?;
                 }
 
                 InsnFormat::Format35c {
@@ -262,7 +286,7 @@ pub trait SmaliWrite: Write {
                         _ => {}
                     }
                     write!(self, "}}, ")?;
-                    self.write_index(b, dex)?;
+                    write!(self, "{}", pretty_index(b, dex, options)?)?;
                 }
 
                 InsnFormat::Format3rc {
@@ -275,12 +299,12 @@ pub trait SmaliWrite: Write {
                     write!(self, "{{")?;
                     for i in regs.start..regs.end {
                         write!(self, "v{}", i)?;
-                        if i != regs.end {
+                        if i + 1 != regs.end {
                             write!(self, ", ")?;
                         }
                     }
                     write!(self, "}}, ")?;
-                    self.write_index(b, dex)?;
+                    write!(self, "{}", pretty_index(b, dex, options)?)?;
                 }
 
                 InsnFormat::Format45cc {
@@ -304,9 +328,9 @@ pub trait SmaliWrite: Write {
                         _ => {}
                     }
                     write!(self, "}}, ")?;
-                    self.write_index(b, dex)?;
+                    write!(self, "{}", pretty_index(b, dex, options)?)?;
                     write!(self, ", ")?;
-                    self.write_index(h, dex)?;
+                    write!(self, "{}", pretty_index(h, dex, options)?)?;
                 }
 
                 InsnFormat::Format4rcc {
@@ -320,19 +344,19 @@ pub trait SmaliWrite: Write {
                     write!(self, "{{")?;
                     for i in regs.start..regs.end {
                         write!(self, "v{}", i)?;
-                        if i != regs.end {
+                        if i + 1 != regs.end {
                             write!(self, ", ")?;
                         }
                     }
                     write!(self, "}}, ")?;
-                    self.write_index(b, dex)?;
+                    write!(self, "{}", pretty_index(b, dex, options)?)?;
                     write!(self, ", ")?;
-                    self.write_index(h, dex)?;
+                    write!(self, "{}", pretty_index(h, dex, options)?)?;
                 }
 
                 InsnFormat::Format51l { a, b } => {
                     write!(self, "v{}, ", a)?; // op vAA, +BBBBBBBB
-                    self.write_index(b, dex)?;
+                    write!(self, "{}", pretty_index(b, dex, options)?)?;
                 }
 
                 _ => {
@@ -424,6 +448,26 @@ pub trait SmaliWrite: Write {
         Ok(())
     }
 
+    /// Writes a `.local` directive for the given variable, e.g.
+    /// `.local v0, "count":I`. Unknown names and types are written as
+    /// `null` and `V`.
+    fn write_local(&mut self, var: &LocalVariable, indent: &str) -> Result<()> {
+        write!(self, "{}.local v{}, ", indent, var.register_num)?;
+        match &var.name {
+            Some(name) => write!(self, "\"{}\":", name.escape_default())?,
+            None => write!(self, "null:")?,
+        }
+        match &var.type_ {
+            Some(type_) => self.write_type(type_)?,
+            None => write!(self, "V")?,
+        }
+        if let Some(signature) = &var.signature {
+            write!(self, ", \"{}\"", signature.escape_default())?;
+        }
+        writeln!(self)?;
+        Ok(())
+    }
+
     /// Dex method representation for smali
     fn write_method(&mut self, method: &DexMethod, dex: IDexRef<'_>) -> Result<()> {
         write!(self, ".method ")?;
@@ -437,6 +481,30 @@ pub trait SmaliWrite: Write {
             let indent = "    ";
             writeln!(self, "\n{}.registers {}", indent, code.registers_size)?;
 
+            // parameter registers start with `this` for instance methods
+            let is_static = method
+                .access_flags
+                .as_ref()
+                .is_some_and(|x| x.contains(AccessFlags::STATIC));
+            let mut register = if is_static { 0 } else { 1 };
+            for parameter in &method.parameters {
+                if let Some(name) = &parameter.name {
+                    write!(
+                        self,
+                        "{}.param p{}, \"{}\"    # ",
+                        indent,
+                        register,
+                        name.escape_default()
+                    )?;
+                    self.write_type(&parameter.type_)?;
+                    writeln!(self)?;
+                }
+                register += match parameter.type_.shorty() {
+                    'J' | 'D' => 2,
+                    _ => 1,
+                };
+            }
+
             if !method.annotations.is_empty() {
                 writeln!(self)?;
                 for annotation in &method.annotations {
@@ -445,14 +513,110 @@ pub trait SmaliWrite: Write {
                 writeln!(self)?;
             }
 
-            for instruction in insns::disasm(code, dex)? {
-                write!(self, "\n{:#06x}:\n", instruction.range.start)?;
-                if let Some(debug) = &method.debug_info {
-                    if let Some(line) = debug.lines.get(&(instruction.range.start as u32)) {
-                        writeln!(self, "{}.line {}", indent, line)?;
+            // local variables by the address in code units at which they
+            // start or end, parameters are covered by .param
+            let mut starts: BTreeMap<u32, Vec<&LocalVariable>> = BTreeMap::new();
+            let mut ends: BTreeMap<u32, Vec<&LocalVariable>> = BTreeMap::new();
+            if let Some(debug) = &method.debug_info {
+                for var in debug.local_variables.iter().filter(|x| !x.parameter) {
+                    starts.entry(var.start_pc).or_default().push(var);
+                    ends.entry(var.end_pc).or_default().push(var);
+                }
+            }
+
+            let insns = insns::disasm(code, dex)?;
+            let mut labels = Labels::collect(code, &insns);
+
+            // try blocks start and end at labels, their catch directives
+            // follow the end of the block
+            let mut catches: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+            for try_item in &code.tries {
+                let range = try_item.range();
+                labels.targets.insert(range.start);
+                labels.targets.insert(range.end);
+                let Some(handler) = code.catch_handler(try_item) else {
+                    continue;
+                };
+                let block = format!(
+                    "{{{} .. {}}}",
+                    labels.name(range.start),
+                    labels.name(range.end)
+                );
+                let directives = catches.entry(range.end).or_default();
+                for pair in &handler.handlers {
+                    let type_ = dex.get_type(pair.type_idx.0)?;
+                    let target = labels.name(pair.addr.0 as usize * 2);
+                    directives.push(format!(".catch {} {} {}", type_.descriptor, block, target));
+                }
+                if let Some(addr) = &handler.catch_all_addr {
+                    let target = labels.name(addr.0 as usize * 2);
+                    directives.push(format!(".catchall {} {}", block, target));
+                }
+            }
+
+            // source lines in the order of their addresses, debug info
+            // counts in code units
+            let mut lines: Vec<(usize, u64)> = method
+                .debug_info
+                .iter()
+                .flat_map(|x| &x.lines)
+                .map(|(pc, line)| (*pc as usize * 2, *line))
+                .collect();
+            lines.sort();
+            let mut lines = lines.into_iter().peekable();
+
+            // a try block may end with the code, which needs a label after
+            // the last instruction
+            let end = code.insns_size as usize * 2;
+            let offsets = insns.iter().map(|x| (x.range.start, Some(x)));
+            for (start, insn) in offsets.chain([(end, None)]) {
+                if labels.targets.contains(&start) {
+                    writeln!(self, "\n{}{}", indent, labels.name(start))?;
+                }
+                for directive in catches.get(&start).into_iter().flatten() {
+                    writeln!(self, "{}{}", indent, directive)?;
+                }
+                let Some(insn) = insn else {
+                    break;
+                };
+                while let Some((_, line)) = lines.next_if(|(pc, _)| *pc <= start) {
+                    writeln!(self, "{}.line {}", indent, line)?;
+                }
+                let pc = (start / 2) as u32;
+                for var in ends.get(&pc).into_iter().flatten() {
+                    writeln!(self, "{}.end local v{}", indent, var.register_num)?;
+                }
+                for var in starts.get(&pc).into_iter().flatten() {
+                    self.write_local(var, indent)?;
+                }
+
+                match &insn.payload {
+                    Some(Payload::PackedSwitch(x)) => {
+                        writeln!(self, "{}.packed-switch {:#x}", indent, x.first_key)?;
+                        for target in &x.targets {
+                            let label = labels.switch_target(start, *target);
+                            writeln!(self, "{}    {}", indent, label)?;
+                        }
+                        writeln!(self, "{}.end packed-switch", indent)?;
                     }
+                    Some(Payload::SparseSwitch(x)) => {
+                        writeln!(self, "{}.sparse-switch", indent)?;
+                        for (key, target) in x.keys.iter().zip(&x.targets) {
+                            let label = labels.switch_target(start, *target);
+                            writeln!(self, "{}    {:#x} -> {}", indent, key, label)?;
+                        }
+                        writeln!(self, "{}.end sparse-switch", indent)?;
+                    }
+                    _ => match labels.branch_operands(insn) {
+                        Some(operands) => {
+                            writeln!(self, "{}{} {}", indent, insn.opcode.name, operands)?
+                        }
+                        None => {
+                            self.write_insn(insn, dex, 1)?;
+                            writeln!(self)?;
+                        }
+                    },
                 }
-                self.write_insn(&instruction, dex, 1)?;
             }
         }
         writeln!(self, "\n.end method")?;
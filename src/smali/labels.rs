@@ -0,0 +1,127 @@
+//! Labels of branch, switch and exception handler targets
+//!
+//! Both the [smali writer](super::SmaliWrite) and the
+//! [disassembly listings](crate::dump) replace the relative offsets of
+//! branches with labels named after the byte offset of their target, e.g.
+//! `:L0006`.
+
+use std::collections::BTreeSet;
+
+use crate::dalvik::{
+    dex::CodeItem,
+    insns::{Insn, InsnFormat, Payload},
+};
+
+/// Branch targets of a code item and the switch instructions that refer to
+/// each payload.
+#[derive(Debug, Default)]
+pub(crate) struct Labels {
+    pub(crate) targets: BTreeSet<usize>,
+
+    /// (payload offset, switch offset)
+    switches: Vec<(usize, usize)>,
+}
+
+impl Labels {
+    pub(crate) fn collect(code: &CodeItem, insns: &[Insn]) -> Labels {
+        let mut labels = Labels::default();
+        for insn in insns {
+            let start = insn.range.start;
+            let offset = match &insn.format {
+                InsnFormat::Format10t { a } => *a as i64,
+                InsnFormat::Format20t { a } => *a as i64,
+                InsnFormat::Format30t { a } => *a as i64,
+                InsnFormat::Format21t { b, .. } => *b as i64,
+                InsnFormat::Format22t { c, .. } => *c as i64,
+                InsnFormat::Format31t { b, .. } => {
+                    let payload = labels.add(start, *b as i64);
+                    labels.switches.push((payload, start));
+                    continue;
+                }
+                _ => continue,
+            };
+            labels.add(start, offset);
+        }
+        // switch targets are relative to the switch, not to the payload
+        for insn in insns {
+            let targets = match &insn.payload {
+                Some(Payload::PackedSwitch(x)) => &x.targets,
+                Some(Payload::SparseSwitch(x)) => &x.targets,
+                _ => continue,
+            };
+            if let Some(switch) = labels.switch_of(insn.range.start) {
+                for target in targets {
+                    labels.add(switch, *target as i64);
+                }
+            }
+        }
+        for try_item in &code.tries {
+            if let Some(handler) = code.catch_handler(try_item) {
+                let handlers = handler.handlers.iter().map(|x| &x.addr);
+                for addr in handlers.chain(&handler.catch_all_addr) {
+                    labels.targets.insert(addr.0 as usize * 2);
+                }
+            }
+        }
+        labels
+    }
+
+    /// Adds the target of a branch at `start` with an offset in code units.
+    fn add(&mut self, start: usize, offset: i64) -> usize {
+        let target = (start as i64 + offset * 2).max(0) as usize;
+        self.targets.insert(target);
+        target
+    }
+
+    fn switch_of(&self, payload: usize) -> Option<usize> {
+        self.switches
+            .iter()
+            .find(|(x, _)| *x == payload)
+            .map(|(_, x)| *x)
+    }
+
+    /// Returns the label of an offset, or the offset itself if labels are
+    /// disabled.
+    pub(crate) fn name(&self, offset: usize) -> String {
+        match self.targets.contains(&offset) {
+            true => format!(":L{:04x}", offset),
+            false => format!("{:#06x}", offset),
+        }
+    }
+
+    /// Formats a branch operand given in code units relative to `start`.
+    pub(crate) fn branch(&self, start: usize, offset: i64) -> String {
+        let target = start as i64 + offset * 2;
+        match usize::try_from(target) {
+            Ok(target) if self.targets.contains(&target) => self.name(target),
+            _ => format!("{:+}", offset),
+        }
+    }
+
+    /// Formats a target of the switch payload at `payload`, which is
+    /// relative to the switch instruction that refers to the payload.
+    pub(crate) fn switch_target(&self, payload: usize, offset: i32) -> String {
+        match self.switch_of(payload) {
+            Some(switch) => self.branch(switch, offset as i64),
+            None => format!("{:+}", offset),
+        }
+    }
+
+    /// Formats the operands of a branch or switch instruction with its
+    /// target replaced by a label. Returns `None` for all other
+    /// instructions.
+    pub(crate) fn branch_operands(&self, insn: &Insn) -> Option<String> {
+        let start = insn.range.start;
+        Some(match &insn.format {
+            InsnFormat::Format10t { a } => self.branch(start, *a as i64),
+            InsnFormat::Format20t { a } => self.branch(start, *a as i64),
+            InsnFormat::Format30t { a } => self.branch(start, *a as i64),
+            InsnFormat::Format21t { a, b } => format!("v{}, {}", a, self.branch(start, *b as i64)),
+            InsnFormat::Format22t { a, b, c } => {
+                format!("v{}, v{}, {}", a, b, self.branch(start, *c as i64))
+            }
+            InsnFormat::Format31t { a, b } => format!("v{}, {}", a, self.branch(start, *b as i64)),
+            _ => return None,
+        })
+    }
+}
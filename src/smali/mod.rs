@@ -1,2 +1,11 @@
 pub mod io;
 pub use io::*;
+
+pub mod export;
+pub use export::*;
+
+pub mod refs;
+pub use refs::*;
+
+mod labels;
+pub(crate) use labels::Labels;
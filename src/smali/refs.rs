@@ -0,0 +1,30 @@
+//! Canonical smali references of fields and methods
+//!
+//! Hook configurations, Frida scripts and `apktool` refer to members by
+//! their smali reference, e.g. `Lcom/example/Foo;->count:I` or
+//! `Lcom/example/Foo;->bar(I)V`. Unlike the [pretty](crate::pretty)
+//! functions, these don't depend on any options and always produce the
+//! text the [smali writer](super::SmaliWrite) uses for the operands of
+//! instructions.
+
+use crate::dalvik::{error::Result, file::IDexRef};
+
+use super::SmaliWrite;
+
+/// Returns the smali reference of the field at the given index into the
+/// `field_ids` list, e.g. `Lcom/example/Foo;->count:I`.
+pub fn smali_field_ref(dex: IDexRef<'_>, field_idx: u32) -> Result<String> {
+    let field = dex.get_field(field_idx)?;
+    let mut text = Vec::new();
+    text.write_field_ref(&field, dex)?;
+    Ok(String::from_utf8_lossy(&text).into_owned())
+}
+
+/// Returns the smali reference of the method at the given index into the
+/// `method_ids` list, e.g. `Lcom/example/Foo;->bar(I)V`.
+pub fn smali_method_ref(dex: IDexRef<'_>, method_idx: u32) -> Result<String> {
+    let method = dex.get_method(method_idx)?;
+    let mut text = Vec::new();
+    text.write_method_ref(&method, dex)?;
+    Ok(String::from_utf8_lossy(&text).into_owned())
+}
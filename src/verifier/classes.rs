@@ -0,0 +1,268 @@
+//! Consistency checks of the class hierarchy
+//!
+//! Parsing a class only resolves the types it references, so a class that
+//! extends itself, a superclass index pointing to a primitive type or two
+//! definitions of the same class go unnoticed until a tool walks the
+//! hierarchy and loops forever or picks the wrong definition.
+//! [check_classes] reads the raw class definitions and reports all of these
+//! problems at once:
+//!
+//! ```ignore
+//! let report = check_classes(&mut dex)?;
+//! for (index, findings) in &report.findings {
+//!     for finding in findings {
+//!         println!("class_def #{}: {}", index, finding);
+//!     }
+//! }
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    io::{Read, Seek},
+};
+
+use crate::dalvik::{
+    dex::NO_INDEX,
+    error::Result,
+    file::{Dex, IDex},
+};
+
+/// Index into the `class_defs` list.
+pub type ClassDefIndex = u32;
+
+/// A problem of a single class definition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClassFinding {
+    /// `class_idx` is not a valid index into the `type_ids` list
+    BadClassIndex(u32),
+
+    /// the defined type is not a class, i.e. its descriptor doesn't start
+    /// with `L`
+    NotAClass(String),
+
+    /// `superclass_idx` is neither `NO_INDEX` nor a valid type index
+    BadSuperclassIndex(u32),
+
+    /// the superclass is not a class type
+    SuperclassNotAClass(String),
+
+    /// a class other than `java.lang.Object` has no superclass
+    MissingSuperclass,
+
+    /// the list of interfaces can't be read
+    BadInterfaceList(String),
+
+    /// an entry of the interface list is not a valid type index
+    BadInterfaceIndex(u32),
+
+    /// an implemented interface is not a class type
+    InterfaceNotAClass(String),
+
+    /// an interface is listed more than once
+    DuplicateInterface(String),
+
+    /// the type was already defined by the class definition at `first`
+    DuplicateDefinition { first: ClassDefIndex },
+
+    /// The class is part of an inheritance cycle through its superclass or
+    /// interfaces. The cycle lists all classes involved, in the order of
+    /// the inheritance edges.
+    InheritanceCycle(Vec<ClassDefIndex>),
+}
+
+impl fmt::Display for ClassFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClassFinding::BadClassIndex(x) => write!(f, "invalid class_idx {}", x),
+            ClassFinding::NotAClass(x) => write!(f, "{} is not a class type", x),
+            ClassFinding::BadSuperclassIndex(x) => write!(f, "invalid superclass_idx {}", x),
+            ClassFinding::SuperclassNotAClass(x) => {
+                write!(f, "superclass {} is not a class type", x)
+            }
+            ClassFinding::MissingSuperclass => write!(f, "missing superclass"),
+            ClassFinding::BadInterfaceList(x) => write!(f, "bad interface list: {}", x),
+            ClassFinding::BadInterfaceIndex(x) => write!(f, "invalid interface index {}", x),
+            ClassFinding::InterfaceNotAClass(x) => {
+                write!(f, "interface {} is not a class type", x)
+            }
+            ClassFinding::DuplicateInterface(x) => write!(f, "interface {} is listed twice", x),
+            ClassFinding::DuplicateDefinition { first } => {
+                write!(f, "class is already defined by class_def #{}", first)
+            }
+            ClassFinding::InheritanceCycle(cycle) => {
+                write!(f, "inheritance cycle through class_defs {:?}", cycle)
+            }
+        }
+    }
+}
+
+/// The result of [check_classes].
+#[derive(Debug, Default)]
+pub struct ClassReport {
+    /// findings by the index of the affected class definition; classes
+    /// without findings are not listed
+    pub findings: BTreeMap<ClassDefIndex, Vec<ClassFinding>>,
+}
+
+impl ClassReport {
+    pub fn is_empty(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    fn add(&mut self, index: ClassDefIndex, finding: ClassFinding) {
+        self.findings.entry(index).or_default().push(finding);
+    }
+}
+
+/// Checks the type references of all class definitions and the hierarchy
+/// they form:
+///
+/// - `class_idx`, `superclass_idx` and all interfaces must be valid type
+///   indices that refer to class types,
+/// - only `java.lang.Object` may have no superclass,
+/// - an interface must not be listed twice,
+/// - a type must not be defined by more than one class definition,
+/// - the superclasses and interfaces defined in the file must not form a
+///   cycle.
+///
+/// Errors are only returned if a class definition item itself can't be
+/// read.
+pub fn check_classes<R: Read + Seek>(dex: &mut Dex<'_, R>) -> Result<ClassReport> {
+    let mut report = ClassReport::default();
+    let count = dex.get_header().class_defs_size;
+
+    // type index of every class definition and the types it inherits from
+    let mut defined: HashMap<u32, ClassDefIndex> = HashMap::new();
+    let mut parents: Vec<Vec<u32>> = Vec::with_capacity(count as usize);
+    for index in 0..count {
+        let item = dex.get_class_def_item(index)?;
+        let mut inherited = Vec::new();
+
+        match descriptor(dex, item.class_idx) {
+            None => report.add(index, ClassFinding::BadClassIndex(item.class_idx)),
+            Some(x) if !is_class(&x) => report.add(index, ClassFinding::NotAClass(x)),
+            Some(x) => {
+                if item.superclass_idx == NO_INDEX && x != "Ljava/lang/Object;" {
+                    report.add(index, ClassFinding::MissingSuperclass);
+                }
+            }
+        }
+        match defined.get(&item.class_idx) {
+            Some(&first) => report.add(index, ClassFinding::DuplicateDefinition { first }),
+            None => {
+                defined.insert(item.class_idx, index);
+            }
+        }
+
+        if item.superclass_idx != NO_INDEX {
+            match descriptor(dex, item.superclass_idx) {
+                None => report.add(index, ClassFinding::BadSuperclassIndex(item.superclass_idx)),
+                Some(x) if !is_class(&x) => report.add(index, ClassFinding::SuperclassNotAClass(x)),
+                Some(_) => inherited.push(item.superclass_idx),
+            }
+        }
+
+        match dex.get_class_interfaces(index) {
+            Ok(interfaces) => {
+                let mut seen = HashSet::new();
+                for interface in interfaces {
+                    match descriptor(dex, interface) {
+                        None => report.add(index, ClassFinding::BadInterfaceIndex(interface)),
+                        Some(x) if !is_class(&x) => {
+                            report.add(index, ClassFinding::InterfaceNotAClass(x))
+                        }
+                        Some(x) if !seen.insert(interface) => {
+                            report.add(index, ClassFinding::DuplicateInterface(x))
+                        }
+                        Some(_) => inherited.push(interface),
+                    }
+                }
+            }
+            Err(e) => report.add(index, ClassFinding::BadInterfaceList(e.to_string())),
+        }
+        parents.push(inherited);
+    }
+
+    // edges to the classes defined in this file, duplicates resolve to the
+    // first definition
+    let edges: Vec<Vec<usize>> = parents
+        .iter()
+        .map(|x| {
+            x.iter()
+                .filter_map(|x| defined.get(x).map(|x| *x as usize))
+                .collect()
+        })
+        .collect();
+    for cycle in find_cycles(&edges) {
+        let cycle: Vec<ClassDefIndex> = cycle.into_iter().map(|x| x as u32).collect();
+        for index in &cycle {
+            report.add(*index, ClassFinding::InheritanceCycle(cycle.clone()));
+        }
+    }
+    Ok(report)
+}
+
+/// Returns the descriptor of the type at the given index, or `None` if the
+/// index or its string is invalid.
+fn descriptor<R: Read + Seek>(dex: &mut Dex<'_, R>, type_idx: u32) -> Option<String> {
+    let header = dex.get_header();
+    if type_idx >= header.type_ids_size {
+        return None;
+    }
+    // the raw string is needed, parsing the type would reject malformed
+    // descriptors
+    let offset = header.type_ids_off as u64 + type_idx as u64 * 4;
+    let raw = dex.read_raw(offset..offset + 4).ok()?;
+    let descriptor_idx = u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+    dex.get_string(descriptor_idx).ok().map(|x| x.to_string())
+}
+
+fn is_class(descriptor: &str) -> bool {
+    descriptor.len() > 2 && descriptor.starts_with('L') && descriptor.ends_with(';')
+}
+
+/// Finds the cycles of a graph with a depth-first search, without
+/// recursion since hostile files may contain very long chains. Every node
+/// is part of at most one returned cycle.
+fn find_cycles(edges: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    const NEW: u8 = 0;
+    const ACTIVE: u8 = 1;
+    const DONE: u8 = 2;
+
+    let mut cycles = Vec::new();
+    let mut state = vec![NEW; edges.len()];
+    let mut in_cycle = vec![false; edges.len()];
+    for start in 0..edges.len() {
+        if state[start] != NEW {
+            continue;
+        }
+        state[start] = ACTIVE;
+        // nodes of the current path and the next edge to follow
+        let mut path = vec![(start, 0)];
+        while let Some(&(node, edge)) = path.last() {
+            let Some(&next) = edges[node].get(edge) else {
+                state[node] = DONE;
+                path.pop();
+                continue;
+            };
+            path.last_mut().unwrap().1 += 1;
+            match state[next] {
+                NEW => {
+                    state[next] = ACTIVE;
+                    path.push((next, 0));
+                }
+                ACTIVE => {
+                    let position = path.iter().position(|(x, _)| *x == next).unwrap();
+                    let cycle: Vec<usize> = path[position..].iter().map(|(x, _)| *x).collect();
+                    if cycle.iter().all(|x| !in_cycle[*x]) {
+                        cycle.iter().for_each(|x| in_cycle[*x] = true);
+                        cycles.push(cycle);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    cycles
+}
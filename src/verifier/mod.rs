@@ -0,0 +1,289 @@
+//! Incremental verification of whole DEX files
+//!
+//! [verify_streaming] checks a file step by step and reports every finding
+//! through a callback as soon as it is known, instead of stopping at the
+//! first error. Large files can therefore be verified in the background
+//! while progress is shown, and the verification can be aborted through a
+//! [CancelToken] between two steps.
+//!
+//! ```ignore
+//! let cancel = CancelToken::new();
+//! let outcome = verify_streaming(&mut dex, &cancel, |event| match event {
+//!     Event::Progress { stage, done, total } => println!("{:?}: {}/{}", stage, done, total),
+//!     Event::Finding(finding) => println!("{}", finding.error),
+//! });
+//! ```
+
+pub mod classes;
+pub mod orphans;
+pub mod sections;
+
+use std::{
+    io::{Read, Seek},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    dalvik::{
+        error::{ConstraintError, Error, ErrorContext, Result, ResultExt},
+        file::{Dex, IDex},
+        sdk,
+    },
+    writer::layout,
+};
+
+/// The steps of a verification, in the order they are run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// checksum, signature and the global header constraints
+    Header,
+
+    /// sizes and offsets declared by the header and the map list against
+    /// the measured sections, see [sections::check_sections]
+    Sections,
+
+    /// structure of the map list
+    MapList,
+
+    /// canonical ordering of the id sections and class definitions
+    Layout,
+
+    /// shorty descriptors of all prototypes
+    Protos,
+
+    /// type references and inheritance of the class definitions, see
+    /// [classes::check_classes]
+    Hierarchy,
+
+    /// constructs that the version in the magic doesn't allow, see
+    /// [sdk::check_version]
+    Version,
+
+    /// class definitions including their members, code and annotations
+    Classes,
+}
+
+/// A problem found during verification.
+#[derive(Debug)]
+pub struct Finding {
+    pub stage: Stage,
+
+    /// Index of the affected item within the section checked by `stage`,
+    /// e.g. the class definition index.
+    pub index: Option<u32>,
+
+    pub error: Error,
+}
+
+/// Notifications passed to the callback of [verify_streaming].
+#[derive(Debug)]
+pub enum Event {
+    /// `done` out of `total` items of the given stage have been checked.
+    Progress {
+        stage: Stage,
+        done: u32,
+        total: u32,
+    },
+
+    Finding(Finding),
+}
+
+/// How a verification ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// All stages have been run; `findings` is the number of reported
+    /// findings.
+    Completed { findings: usize },
+
+    /// The verification was cancelled through its [CancelToken].
+    Cancelled,
+}
+
+/// A flag to abort a running verification from the callback or from
+/// another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Verifies the given file and reports findings and progress through
+/// `callback`.
+///
+/// The header, the sections, the map list, the layout and the class
+/// hierarchy are checked as a whole, while prototypes and class definitions are checked one by one.
+/// Classes are parsed with [Dex::load_class], so they are not cached, and
+/// the code of every method is disassembled. If the map list is broken, the remaining
+/// stages are skipped, since they rely on it.
+///
+/// The token is checked before every step. Errors of the underlying reader
+/// are reported as findings as well.
+pub fn verify_streaming<R, F>(
+    dex: &mut Dex<'_, R>,
+    cancel: &CancelToken,
+    mut callback: F,
+) -> Outcome
+where
+    R: Read + Seek,
+    F: FnMut(Event),
+{
+    let mut findings = 0;
+    macro_rules! report {
+        ($stage:expr, $index:expr, $error:expr) => {{
+            findings += 1;
+            callback(Event::Finding(Finding {
+                stage: $stage,
+                index: $index,
+                error: $error,
+            }));
+        }};
+    }
+    macro_rules! progress {
+        ($stage:expr, $done:expr, $total:expr) => {
+            callback(Event::Progress {
+                stage: $stage,
+                done: $done,
+                total: $total,
+            })
+        };
+    }
+    macro_rules! check_cancel {
+        () => {
+            if cancel.is_cancelled() {
+                return Outcome::Cancelled;
+            }
+        };
+    }
+
+    check_cancel!();
+    if let Err(e) = dex.verify_header() {
+        report!(Stage::Header, None, Error::Validation(e));
+    }
+    progress!(Stage::Header, 1, 1);
+
+    check_cancel!();
+    match sections::check_sections(dex) {
+        Ok(report) => {
+            for violation in report.violations {
+                report!(Stage::Sections, None, Error::Validation(violation));
+            }
+        }
+        Err(e) => report!(Stage::Sections, None, e),
+    }
+    progress!(Stage::Sections, 1, 1);
+
+    check_cancel!();
+    if let Err(e) = dex.validate_map_list() {
+        report!(Stage::MapList, None, Error::Validation(e));
+        return Outcome::Completed { findings };
+    }
+    progress!(Stage::MapList, 1, 1);
+
+    check_cancel!();
+    match layout::validate(dex) {
+        Ok(violations) => {
+            for violation in violations {
+                let error = Error::Validation(ConstraintError {
+                    identifier: "layout",
+                    description: format!("{:?}: {}", violation.section, violation.description),
+                });
+                report!(Stage::Layout, Some(violation.index), error);
+            }
+        }
+        Err(e) => report!(Stage::Layout, None, e),
+    }
+    progress!(Stage::Layout, 1, 1);
+
+    let protos = dex.get_header().proto_ids_size;
+    for index in 0..protos {
+        check_cancel!();
+        let result = dex
+            .get_proto(index)
+            .and_then(|proto| proto.validate_shorty().map_err(Error::from));
+        if let Err(e) = result {
+            report!(
+                Stage::Protos,
+                Some(index),
+                e.context(ErrorContext::Proto(index))
+            );
+        }
+        progress!(Stage::Protos, index + 1, protos);
+    }
+
+    check_cancel!();
+    match classes::check_classes(dex) {
+        Ok(report) => {
+            for (index, findings) in report.findings {
+                for finding in findings {
+                    let error = Error::Validation(ConstraintError {
+                        identifier: "class_def",
+                        description: finding.to_string(),
+                    });
+                    report!(Stage::Hierarchy, Some(index), error);
+                }
+            }
+        }
+        Err(e) => report!(Stage::Hierarchy, None, e),
+    }
+    progress!(Stage::Hierarchy, 1, 1);
+
+    check_cancel!();
+    match sdk::check_version(dex) {
+        Ok(incompatibilities) => {
+            for x in incompatibilities {
+                let mut description = format!(
+                    "{:?} requires version {:03}, the file has {:03}",
+                    x.feature,
+                    x.feature.min_version(),
+                    dex.header.version().unwrap_or_default()
+                );
+                if let Some(offset) = x.offset {
+                    description += &format!(" (instruction at {:#x})", offset);
+                }
+                let mut error = Error::Validation(ConstraintError {
+                    identifier: "version",
+                    description,
+                });
+                if let Some(method) = x.method {
+                    error = error.context(ErrorContext::Method(method));
+                }
+                report!(Stage::Version, x.class_def, error);
+            }
+        }
+        Err(e) => report!(Stage::Version, None, e),
+    }
+    progress!(Stage::Version, 1, 1);
+
+    let classes = dex.get_header().class_defs_size;
+    for index in 0..classes {
+        check_cancel!();
+        if let Err(e) = verify_class(dex, index) {
+            report!(Stage::Classes, Some(index), e);
+        }
+        progress!(Stage::Classes, index + 1, classes);
+    }
+    Outcome::Completed { findings }
+}
+
+/// Parses a class definition and disassembles all of its methods.
+fn verify_class<R: Read + Seek>(dex: &mut Dex<'_, R>, index: u32) -> Result<()> {
+    let class = dex.load_class(index)?;
+    for (_, method) in class.get_methods() {
+        method.disasm(dex).context(ErrorContext::ClassDef(index))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,196 @@
+//! Detection of orphaned items
+//!
+//! Every item of the data section is supposed to be referenced, either by
+//! an id item, a class definition or another data item. Items that are
+//! listed by the map list but reached by none of these references are
+//! orphans: left behind by a tool that removed their users, or a payload
+//! that is stored in plain sight of the map list. [find_orphans]
+//! complements [carve_unclaimed_regions](crate::dalvik::file::Dex::carve_unclaimed_regions),
+//! which finds data that isn't listed at all.
+//!
+//! ```ignore
+//! for orphan in find_orphans(&mut dex)? {
+//!     println!("{:?} at {:#x}", orphan.type_, orphan.range.start);
+//! }
+//! ```
+
+use binrw::BinRead;
+use std::{
+    collections::HashSet,
+    io::{Cursor, Read, Seek},
+    ops::Range,
+};
+
+use crate::{
+    dalvik::{
+        dex::*,
+        error::{ErrorContext, Result, ResultExt},
+        file::{skip_item, Dex},
+    },
+    writer::rewrite,
+};
+
+/// An item of the data section that no other item refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Orphan {
+    pub type_: MapListItemType,
+
+    /// bytes occupied by the item, excluding its alignment padding
+    pub range: Range<u64>,
+}
+
+/// Collects the offsets of all referenced items of the data section and
+/// returns the listed items that are not among them, sorted by their
+/// offset.
+///
+/// The hidden API section is referenced by the map list only and is never
+/// reported. The whole file is read into memory.
+pub fn find_orphans<R: Read + Seek>(dex: &mut Dex<'_, R>) -> Result<Vec<Orphan>> {
+    let file_len = dex.stream_len()?.min(dex.header.file_size as u64);
+    let input = dex.read_raw(0..file_len)?;
+    let header = &dex.header;
+    let mut reader = Cursor::new(input.as_slice());
+    let mut refs: HashSet<u64> = HashSet::new();
+    let mut add = |offset: UInt| {
+        if offset != 0 {
+            refs.insert(offset as u64);
+        }
+    };
+
+    macro_rules! read_at {
+        ($item:ty, $offset:expr, $context:expr) => {{
+            reader.set_position($offset as u64);
+            <$item>::read(&mut reader).context($context)?
+        }};
+    }
+
+    // references of the id sections
+    for index in 0..header.string_ids_size {
+        let offset = header.string_ids_off as u64 + index as u64 * 4;
+        add(read_at!(StringIdItem, offset, ErrorContext::String(index)).offset);
+    }
+    for index in 0..header.proto_ids_size {
+        let offset = header.proto_ids_off as u64 + index as u64 * 12;
+        add(read_at!(ProtoIdItem, offset, ErrorContext::Proto(index)).parameters_off);
+    }
+    let call_sites = dex.get_map_list().get(MapListItemType::CallSiteIdItem);
+    let (size, offset) = call_sites.map_or((0, 0), |x| (x.size, x.offset));
+    for index in 0..size {
+        reader.set_position(offset as u64 + index as u64 * 4);
+        add(CallSiteIdItem::read(&mut reader)?.call_side_off);
+    }
+
+    // class definitions and the items only they refer to
+    let mut class_data = Vec::new();
+    let mut directories = Vec::new();
+    for index in 0..header.class_defs_size {
+        let offset = header.class_defs_off as u64 + index as u64 * 32;
+        let def = read_at!(ClassDefItem, offset, ErrorContext::ClassDef(index));
+        add(def.interfaces_off);
+        add(def.annotations_off);
+        add(def.class_data_off);
+        add(def.static_values_off);
+        if def.class_data_off != 0 {
+            class_data.push(def.class_data_off);
+        }
+        if def.annotations_off != 0 {
+            directories.push(def.annotations_off);
+        }
+    }
+
+    let mut code_items = Vec::new();
+    for offset in class_data {
+        let context = ErrorContext::ClassData(offset as u64);
+        let item = read_at!(ClassDataItem, offset, context);
+        for method in item.direct_methods.iter().chain(&item.virtual_methods) {
+            add(method.code_off.0);
+            if method.code_off.0 != 0 {
+                code_items.push(method.code_off.0);
+            }
+        }
+    }
+    for offset in code_items {
+        let context = ErrorContext::CodeItem(offset as u64);
+        add(read_at!(CodeItem, offset, context).debug_info_off);
+    }
+
+    let mut sets = Vec::new();
+    let mut ref_lists = Vec::new();
+    for offset in directories {
+        let context = ErrorContext::Annotations(offset as u64);
+        let directory = read_at!(AnnotationsDirectoryItem, offset, context);
+        sets.push(directory.class_annotations_off);
+        for field in &directory.field_annotations {
+            sets.push(field.annotations_off);
+        }
+        for method in &directory.method_annotations {
+            sets.push(method.annotations_off);
+        }
+        for parameters in &directory.parameter_annotations {
+            ref_lists.push(parameters.annotations_off);
+        }
+    }
+    for offset in ref_lists.into_iter().filter(|x| *x != 0) {
+        add(offset);
+        let context = ErrorContext::Annotations(offset as u64);
+        let list = read_at!(AnnotationSetRefList, offset, context);
+        sets.extend(list.list.iter().map(|x| x.annotations_off));
+    }
+    for offset in sets.into_iter().filter(|x| *x != 0) {
+        add(offset);
+        let context = ErrorContext::Annotations(offset as u64);
+        let set = read_at!(AnnotationSetItem, offset, context);
+        for item in &set.list {
+            add(item.annotation_off);
+        }
+    }
+
+    // every listed item of the data section
+    let mut sections: Vec<(MapListItemType, UInt, u64)> = dex
+        .get_map_list()
+        .items()
+        .iter()
+        .map(|x| (x.item_type(), x.size, x.offset as u64))
+        .filter(|(type_, _, _)| is_referenced_data(*type_))
+        .collect();
+    sections.sort_by_key(|(_, _, offset)| *offset);
+
+    let mut orphans = Vec::new();
+    for (type_, size, offset) in sections {
+        reader.set_position(offset);
+        for _ in 0..size {
+            if rewrite::is_aligned(type_) {
+                reader.set_position(reader.position().next_multiple_of(4));
+            }
+            let start = reader.position();
+            let end = skip_item(type_, &mut reader)?;
+            if !refs.contains(&start) {
+                orphans.push(Orphan {
+                    type_,
+                    range: start..end,
+                });
+            }
+        }
+    }
+    Ok(orphans)
+}
+
+/// Whether items of the given type are referenced by offset from another
+/// item.
+fn is_referenced_data(type_: MapListItemType) -> bool {
+    !matches!(
+        type_,
+        MapListItemType::HeaderItem
+            | MapListItemType::StringIdItem
+            | MapListItemType::TypeIdItem
+            | MapListItemType::ProtoIdItem
+            | MapListItemType::FieldIdItem
+            | MapListItemType::MethodIdItem
+            | MapListItemType::ClassDefItem
+            | MapListItemType::CallSiteIdItem
+            | MapListItemType::MethodHandleItem
+            | MapListItemType::MapList
+            | MapListItemType::HiddenApiListClassDataItem
+            | MapListItemType::Unknown(_)
+    )
+}
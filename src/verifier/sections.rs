@@ -0,0 +1,369 @@
+//! Cross-checks of the declared layout against the actual sections
+//!
+//! Tampered files often declare sizes and offsets in the header that differ
+//! from the map list or from where the items really end, let sections
+//! overlap, or hide data in the gaps between them. A parser that trusts
+//! only one of these sources either breaks or never sees the hidden bytes.
+//! [check_sections] measures every section of the map list by reading its
+//! items and reports all inconsistencies at once, together with the slack
+//! regions that are not claimed by any section.
+//!
+//! [Dex::carve_unclaimed_regions] returns these regions together with data
+//! appended after `file_size`, so that payloads hidden in a file can be
+//! extracted:
+//!
+//! ```ignore
+//! for region in dex.carve_unclaimed_regions()? {
+//!     if !region.zeroed {
+//!         let data = dex.read_raw(region.range.clone())?;
+//!     }
+//! }
+//! ```
+
+use std::{
+    io::{Cursor, Read, Seek},
+    ops::Range,
+};
+
+use crate::{
+    dalvik::{
+        dex::{MapListItemType, UByte, UInt},
+        error::{ConstraintError, Result},
+        file::{skip_item, Dex},
+    },
+    writer::rewrite,
+};
+
+/// The bytes actually occupied by a section of the map list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extent {
+    pub type_: MapListItemType,
+
+    /// number of items as declared by the map list
+    pub size: UInt,
+
+    /// from the start of the first item to the end of the last one
+    pub range: Range<u64>,
+}
+
+/// The result of [check_sections].
+#[derive(Debug, Default)]
+pub struct SectionReport {
+    /// all sections that could be measured, sorted by their offset
+    pub extents: Vec<Extent>,
+
+    /// regions within `file_size` that are neither claimed by a section nor
+    /// the alignment padding in front of one
+    pub slack: Vec<Range<u64>>,
+
+    pub violations: Vec<ConstraintError>,
+}
+
+/// A region of a file that doesn't belong to any section, see
+/// [Dex::carve_unclaimed_regions].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnclaimedRegion {
+    pub range: Range<u64>,
+
+    /// whether the region lies after `file_size`, i.e. was appended to the
+    /// file
+    pub overlay: bool,
+
+    /// whether the region only contains zeros
+    pub zeroed: bool,
+}
+
+/// Checks the header, the map list and the measured sections against each
+/// other:
+///
+/// - `file_size` must match the length of the file,
+/// - every section declared by the header must be listed in the map list
+///   with the same size and offset, and vice versa,
+/// - all items must be readable and end before `file_size`,
+/// - all sections except the header and the id sections must lie within
+///   the data section,
+/// - sections must not overlap,
+/// - slack regions and alignment padding must only contain zeros.
+///
+/// Unlike [MapList::validate](crate::dalvik::dex::MapList::validate), this
+/// check doesn't stop at the first violation. The whole file is read into
+/// memory.
+pub fn check_sections<R: Read + Seek>(dex: &mut Dex<'_, R>) -> Result<SectionReport> {
+    let file_len = dex.stream_len()?;
+    let input = dex.read_raw(0..file_len)?;
+    let header = &dex.header;
+    let mut report = SectionReport::default();
+    macro_rules! violation {
+        ($identifier:expr, $($arg:tt)*) => {
+            report.violations.push(ConstraintError {
+                identifier: $identifier,
+                description: format!($($arg)*),
+            })
+        };
+    }
+
+    let file_size = header.file_size as u64;
+    if file_size != file_len {
+        violation!(
+            "file_size",
+            "file_size is {:#x}, but the file has {:#x} bytes",
+            file_size,
+            file_len
+        );
+    }
+
+    // sections declared by the header and the size of their items
+    let map_list = dex.get_map_list();
+    let declared = [
+        (MapListItemType::MapList, 1, header.map_off, 0),
+        (
+            MapListItemType::StringIdItem,
+            header.string_ids_size,
+            header.string_ids_off,
+            4,
+        ),
+        (
+            MapListItemType::TypeIdItem,
+            header.type_ids_size,
+            header.type_ids_off,
+            4,
+        ),
+        (
+            MapListItemType::ProtoIdItem,
+            header.proto_ids_size,
+            header.proto_ids_off,
+            12,
+        ),
+        (
+            MapListItemType::FieldIdItem,
+            header.field_ids_size,
+            header.field_ids_off,
+            8,
+        ),
+        (
+            MapListItemType::MethodIdItem,
+            header.method_ids_size,
+            header.method_ids_off,
+            8,
+        ),
+        (
+            MapListItemType::ClassDefItem,
+            header.class_defs_size,
+            header.class_defs_off,
+            32,
+        ),
+    ];
+    for (type_, size, offset, _) in declared {
+        match map_list.get(type_) {
+            Some(item) if item.size == size && (size == 0 || item.offset == offset) => {}
+            Some(item) => violation!(
+                "map_section",
+                "{:?}: the header declares size {} at {:#x}, the map list size {} at {:#x}",
+                type_,
+                size,
+                offset,
+                item.size,
+                item.offset
+            ),
+            None if size == 0 => {}
+            None => violation!(
+                "map_section",
+                "{:?}: the header declares size {} at {:#x}, but the map list has no entry",
+                type_,
+                size,
+                offset
+            ),
+        }
+    }
+
+    // measure every section by reading its items
+    let mut items: Vec<_> = map_list
+        .items()
+        .iter()
+        .map(|x| (x.item_type(), x.size, x.offset as u64))
+        .collect();
+    items.sort_by_key(|(_, _, offset)| *offset);
+    let mut reader = Cursor::new(input.as_slice());
+    let mut padding = Vec::new();
+    for (type_, size, offset) in items {
+        if offset >= file_len {
+            violation!(
+                "extent",
+                "{:?} at {:#x} starts after the end of the file",
+                type_,
+                offset
+            );
+            continue;
+        }
+        match measure(type_, size, offset, &mut reader, &mut padding) {
+            Ok(range) => report.extents.push(Extent { type_, size, range }),
+            Err(e) => violation!(
+                "extent",
+                "{:?} at {:#x} can't be read: {}",
+                type_,
+                offset,
+                e
+            ),
+        }
+    }
+
+    let data = header.data_off as u64..header.data_off as u64 + header.data_size as u64;
+    for extent in &report.extents {
+        if extent.range.end > file_size {
+            violation!(
+                "extent",
+                "{:?} ends at {:#x}, after file_size ({:#x})",
+                extent.type_,
+                extent.range.end,
+                file_size
+            );
+        }
+        let is_data = !matches!(
+            extent.type_,
+            MapListItemType::HeaderItem
+                | MapListItemType::StringIdItem
+                | MapListItemType::TypeIdItem
+                | MapListItemType::ProtoIdItem
+                | MapListItemType::FieldIdItem
+                | MapListItemType::MethodIdItem
+                | MapListItemType::ClassDefItem
+        );
+        if is_data && (extent.range.start < data.start || extent.range.end > data.end) {
+            violation!(
+                "data_section",
+                "{:?} at {:#x}..{:#x} is outside of the data section {:#x}..{:#x}",
+                extent.type_,
+                extent.range.start,
+                extent.range.end,
+                data.start,
+                data.end
+            );
+        }
+    }
+
+    // the link section isn't listed in the map list
+    let mut claimed: Vec<(String, Range<u64>)> = report
+        .extents
+        .iter()
+        .map(|x| (format!("{:?}", x.type_), x.range.clone()))
+        .collect();
+    if header.link_size != 0 {
+        let start = header.link_off as u64;
+        claimed.push(("link".to_string(), start..start + header.link_size as u64));
+    }
+    claimed.sort_by_key(|(_, range)| (range.start, range.end));
+
+    // a truncated file ends before file_size
+    let limit = file_size.min(file_len);
+    let is_zero = |range: &Range<u64>| {
+        input
+            .get(range.start as usize..range.end as usize)
+            .is_none_or(|x| x.iter().all(|x| *x == 0))
+    };
+    let mut end = 0;
+    let mut last = "";
+    for (name, range) in &claimed {
+        if range.start < end {
+            violation!(
+                "overlap",
+                "{} at {:#x} overlaps {}, which ends at {:#x}",
+                name,
+                range.start,
+                last,
+                end
+            );
+        }
+        if range.end > end {
+            end = range.end;
+            last = name;
+        }
+    }
+
+    // id sections that are only declared by the header still claim their
+    // bytes
+    let mut ranges: Vec<Range<u64>> = claimed.into_iter().map(|(_, x)| x).collect();
+    for (_, size, offset, item_size) in declared {
+        if size != 0 && item_size != 0 {
+            ranges.push(offset as u64..offset as u64 + size as u64 * item_size);
+        }
+    }
+    ranges.sort_by_key(|x| (x.start, x.end));
+    let mut end = 0;
+    for range in ranges {
+        let gap = end..range.start.min(limit).max(end);
+        let is_padding = gap.end - gap.start < 4 && gap.end % 4 == 0 && is_zero(&gap);
+        if !gap.is_empty() && !is_padding {
+            report.slack.push(gap);
+        }
+        end = end.max(range.end);
+    }
+    if end < limit {
+        report.slack.push(end..limit);
+    }
+
+    for range in report.slack.iter().chain(&padding) {
+        if !is_zero(range) {
+            report.violations.push(ConstraintError {
+                identifier: "hidden_data",
+                description: format!(
+                    "{:#x}..{:#x} is not claimed by any item, but contains data",
+                    range.start, range.end
+                ),
+            });
+        }
+    }
+    Ok(report)
+}
+
+/// Reads all items of a section and returns the range they occupy. The
+/// alignment padding in front of each item is collected in `padding`.
+fn measure(
+    type_: MapListItemType,
+    size: UInt,
+    offset: u64,
+    reader: &mut Cursor<&[UByte]>,
+    padding: &mut Vec<Range<u64>>,
+) -> Result<Range<u64>> {
+    reader.set_position(offset);
+    for i in 0..size {
+        let start = reader.position();
+        if rewrite::is_aligned(type_) {
+            reader.set_position(start.next_multiple_of(4));
+            // the padding in front of the first item is part of the gap
+            // between the sections
+            if i > 0 && reader.position() > start {
+                padding.push(start..reader.position());
+            }
+        }
+        skip_item(type_, reader)?;
+    }
+    Ok(offset..reader.position())
+}
+
+impl<R: Read + Seek> Dex<'_, R> {
+    /// Returns all byte ranges that are neither claimed by a section of the
+    /// map list, an id section declared by the header or the link section,
+    /// nor are alignment padding. Data after `file_size` is returned as a
+    /// single overlay region.
+    ///
+    /// The slack regions are the ones of [check_sections], so the whole file
+    /// is read into memory.
+    pub fn carve_unclaimed_regions(&mut self) -> Result<Vec<UnclaimedRegion>> {
+        let slack = check_sections(self)?.slack;
+        let file_size = self.header.file_size as u64;
+        let file_len = self.stream_len()?;
+
+        let mut regions = Vec::with_capacity(slack.len() + 1);
+        let ranges = slack.into_iter().map(|x| (x, false));
+        let overlay = (file_len > file_size).then_some((file_size..file_len, true));
+        for (range, overlay) in ranges.chain(overlay) {
+            let data = self.read_raw(range.clone())?;
+            regions.push(UnclaimedRegion {
+                range,
+                overlay,
+                zeroed: data.iter().all(|x| *x == 0),
+            });
+        }
+        Ok(regions)
+    }
+}
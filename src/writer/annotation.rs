@@ -0,0 +1,255 @@
+//! Annotations of classes that are being written
+//!
+//! Annotations are written in four sections: the `annotation_item`s, the
+//! `annotation_set_item`s grouping them, the `annotation_set_ref_list`s of
+//! parameters and one `annotations_directory_item` per annotated class.
+//! Identical annotations and sets are only written once.
+
+use binrw::BinWrite;
+use std::{
+    collections::HashMap,
+    io::{Cursor, Write},
+};
+
+use crate::dalvik::{
+    dex::{
+        AnnotationItem, AnnotationOffItem, AnnotationSetItem, AnnotationSetRefItem,
+        AnnotationSetRefList, AnnotationVisibility, AnnotationsDirectoryItem, FieldAnnotation,
+        MapListItemType, MethodAnnotation, ParameterAnnotation, UByte, UInt,
+    },
+    error::{Error, Result},
+};
+
+use super::{align, pool::Pools, ClassDef, Value};
+
+/// An annotation, either attached to an item or nested in a [Value].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    /// descriptor of the annotation type
+    pub type_: String,
+
+    /// all elements as `(name, value)` pairs
+    pub elements: Vec<(String, Value)>,
+}
+
+impl Annotation {
+    pub fn new(type_: &str) -> Annotation {
+        Annotation {
+            type_: type_.to_string(),
+            elements: Vec::new(),
+        }
+    }
+
+    /// Adds an element to this annotation.
+    pub fn with(mut self, name: &str, value: Value) -> Annotation {
+        self.elements.push((name.to_string(), value));
+        self
+    }
+}
+
+/// An annotation of a class, field, method or parameter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnnotationDef {
+    pub visibility: AnnotationVisibility,
+    pub annotation: Annotation,
+}
+
+/// Offsets of the annotation sections written by [write].
+pub(super) struct Annotations {
+    /// `annotations_off` of every class, `0` for classes without
+    /// annotations
+    pub directories: Vec<UInt>,
+
+    /// type, size and offset of every non-empty section
+    pub sections: Vec<(MapListItemType, usize, u64)>,
+}
+
+/// Annotation sets of a class, referring to annotation items by offset.
+#[derive(Default)]
+struct ClassSets {
+    class: Vec<UInt>,
+    fields: Vec<(UInt, Vec<UInt>)>,
+    methods: Vec<(UInt, Vec<UInt>)>,
+    parameters: Vec<(UInt, Vec<Vec<UInt>>)>,
+}
+
+/// Writes the annotations of all classes.
+pub(super) fn write(
+    out: &mut Cursor<Vec<UByte>>,
+    classes: &[&ClassDef],
+    pools: &Pools,
+) -> Result<Annotations> {
+    let mut sections = Vec::new();
+
+    // annotation items
+    let start = out.position();
+    let mut items: HashMap<Vec<UByte>, UInt> = HashMap::new();
+    let mut item_set =
+        |out: &mut Cursor<Vec<UByte>>, defs: &[AnnotationDef]| -> Result<Vec<UInt>> {
+            let mut set = Vec::with_capacity(defs.len());
+            for def in defs {
+                let type_idx = pools.type_idx(&def.annotation.type_)?;
+                if set.iter().any(|(x, _)| *x == type_idx) {
+                    return Err(Error::InvalidData(format!(
+                        "annotation {} is applied twice",
+                        def.annotation.type_
+                    )));
+                }
+                let bytes = encode(def, pools)?;
+                let offset = match items.get(&bytes) {
+                    Some(offset) => *offset,
+                    None => {
+                        let offset = out.position() as UInt;
+                        out.write_all(&bytes)?;
+                        items.insert(bytes, offset);
+                        offset
+                    }
+                };
+                set.push((type_idx, offset));
+            }
+            // sets are sorted by the type of their annotations
+            set.sort_by_key(|(type_idx, _)| *type_idx);
+            Ok(set.into_iter().map(|(_, offset)| offset).collect())
+        };
+    let mut class_sets = Vec::with_capacity(classes.len());
+    for class in classes {
+        let mut sets = ClassSets {
+            class: item_set(out, &class.annotations)?,
+            ..Default::default()
+        };
+        for field in class.fields().filter(|x| !x.annotations.is_empty()) {
+            let set = item_set(out, &field.annotations)?;
+            sets.fields.push((pools.field_idx(&field.field)?, set));
+        }
+        for method in class.methods() {
+            let method_idx = pools.method_idx(&method.method)?;
+            if !method.annotations.is_empty() {
+                sets.methods
+                    .push((method_idx, item_set(out, &method.annotations)?));
+            }
+            if method.parameter_annotations.iter().any(|x| !x.is_empty()) {
+                let mut parameters = Vec::with_capacity(method.parameter_annotations.len());
+                for defs in &method.parameter_annotations {
+                    parameters.push(item_set(out, defs)?);
+                }
+                sets.parameters.push((method_idx, parameters));
+            }
+        }
+        class_sets.push(sets);
+    }
+    sections.push((MapListItemType::AnnotationItem, items.len(), start));
+
+    // annotation sets, an empty set is referred to by offset 0
+    align(out, 4)?;
+    let start = out.position();
+    let mut sets: HashMap<Vec<UInt>, UInt> = HashMap::new();
+    let mut set_off = |out: &mut Cursor<Vec<UByte>>, set: &[UInt]| -> Result<UInt> {
+        if set.is_empty() {
+            return Ok(0);
+        }
+        if let Some(offset) = sets.get(set) {
+            return Ok(*offset);
+        }
+        let offset = out.position() as UInt;
+        AnnotationSetItem {
+            list: set
+                .iter()
+                .map(|x| AnnotationOffItem { annotation_off: *x })
+                .collect(),
+        }
+        .write(out)?;
+        sets.insert(set.to_vec(), offset);
+        Ok(offset)
+    };
+    let mut directories = Vec::with_capacity(classes.len());
+    let mut parameter_lists = Vec::with_capacity(classes.len());
+    for class in &class_sets {
+        let mut directory = AnnotationsDirectoryItem {
+            class_annotations_off: set_off(out, &class.class)?,
+            field_annotations: Vec::with_capacity(class.fields.len()),
+            method_annotations: Vec::with_capacity(class.methods.len()),
+            parameter_annotations: Vec::new(),
+        };
+        for (field_idx, set) in &class.fields {
+            directory.field_annotations.push(FieldAnnotation {
+                field_idx: *field_idx,
+                annotations_off: set_off(out, set)?,
+            });
+        }
+        for (method_idx, set) in &class.methods {
+            directory.method_annotations.push(MethodAnnotation {
+                method_idx: *method_idx,
+                annotations_off: set_off(out, set)?,
+            });
+        }
+        let mut lists = Vec::with_capacity(class.parameters.len());
+        for (method_idx, parameters) in &class.parameters {
+            let mut list = Vec::with_capacity(parameters.len());
+            for set in parameters {
+                list.push(AnnotationSetRefItem {
+                    annotations_off: set_off(out, set)?,
+                });
+            }
+            lists.push((*method_idx, AnnotationSetRefList { list }));
+        }
+        directories.push(directory);
+        parameter_lists.push(lists);
+    }
+    sections.push((MapListItemType::AnnotationSetItem, sets.len(), start));
+
+    // parameter annotations
+    align(out, 4)?;
+    let start = out.position();
+    let mut count = 0;
+    for (directory, lists) in directories.iter_mut().zip(parameter_lists) {
+        for (method_idx, list) in lists {
+            directory.parameter_annotations.push(ParameterAnnotation {
+                method_idx,
+                annotations_off: out.position() as UInt,
+            });
+            list.write(out)?;
+            count += 1;
+        }
+    }
+    sections.push((MapListItemType::AnnotationSetRefList, count, start));
+
+    // directories, with all entries sorted by their index
+    align(out, 4)?;
+    let start = out.position();
+    let mut offsets = Vec::with_capacity(directories.len());
+    for mut directory in directories {
+        if directory.class_annotations_off == 0
+            && directory.field_annotations.is_empty()
+            && directory.method_annotations.is_empty()
+            && directory.parameter_annotations.is_empty()
+        {
+            offsets.push(0);
+            continue;
+        }
+        directory.field_annotations.sort_by_key(|x| x.field_idx);
+        directory.method_annotations.sort_by_key(|x| x.method_idx);
+        directory
+            .parameter_annotations
+            .sort_by_key(|x| x.method_idx);
+        offsets.push(out.position() as UInt);
+        directory.write(out)?;
+    }
+    let count = offsets.iter().filter(|x| **x != 0).count();
+    sections.push((MapListItemType::AnnotationsDirectoryItem, count, start));
+
+    Ok(Annotations {
+        directories: offsets,
+        sections,
+    })
+}
+
+/// Encodes an annotation into an `annotation_item`.
+fn encode(def: &AnnotationDef, pools: &Pools) -> Result<Vec<UByte>> {
+    let mut out = Cursor::new(Vec::new());
+    AnnotationItem {
+        visibility: def.visibility,
+        annotation: pools.encode_annotation(&def.annotation)?,
+    }
+    .write(&mut out)?;
+    Ok(out.into_inner())
+}
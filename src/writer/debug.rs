@@ -0,0 +1,281 @@
+//! Debug information of methods that are being written
+//!
+//! The debug information of a method is described by a list of
+//! [DebugEvent]s, ordered by their address. When the file is built, the
+//! events are encoded into the `DBG_*` state machine of a
+//! `debug_info_item`: line changes use special opcodes whenever possible
+//! and the address and line registers are only advanced explicitly if a
+//! special opcode can't cover the difference.
+
+use binrw::BinWrite;
+use std::io::{Cursor, Write};
+
+use crate::dalvik::{
+    dex::{DebugInfoItem, SLeb128, UByte, ULeb128, ULeb128p1},
+    error::{Error, Result},
+};
+
+use super::pool::Pools;
+
+/// An entry of the debug information of a method. Addresses are given in
+/// 16-bit code units.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugEvent {
+    /// The instruction at `address` is the first one of source line `line`.
+    Line {
+        address: u32,
+        line: u32,
+    },
+
+    /// A local variable is stored in `register` from `address` on. Each of
+    /// the name, type descriptor and generic signature may be unknown.
+    StartLocal {
+        address: u32,
+        register: u32,
+        name: Option<String>,
+        type_: Option<String>,
+        signature: Option<String>,
+    },
+
+    /// The local variable in `register` goes out of scope.
+    EndLocal {
+        address: u32,
+        register: u32,
+    },
+
+    /// The last local variable of `register` is live again.
+    RestartLocal {
+        address: u32,
+        register: u32,
+    },
+
+    PrologueEnd {
+        address: u32,
+    },
+
+    EpilogueBegin {
+        address: u32,
+    },
+
+    /// All following lines refer to the given source file instead of the
+    /// one of the class.
+    SetFile {
+        address: u32,
+        name: Option<String>,
+    },
+}
+
+impl DebugEvent {
+    pub fn address(&self) -> u32 {
+        match self {
+            DebugEvent::Line { address, .. }
+            | DebugEvent::StartLocal { address, .. }
+            | DebugEvent::EndLocal { address, .. }
+            | DebugEvent::RestartLocal { address, .. }
+            | DebugEvent::PrologueEnd { address }
+            | DebugEvent::EpilogueBegin { address }
+            | DebugEvent::SetFile { address, .. } => *address,
+        }
+    }
+
+    fn address_mut(&mut self) -> &mut u32 {
+        match self {
+            DebugEvent::Line { address, .. }
+            | DebugEvent::StartLocal { address, .. }
+            | DebugEvent::EndLocal { address, .. }
+            | DebugEvent::RestartLocal { address, .. }
+            | DebugEvent::PrologueEnd { address }
+            | DebugEvent::EpilogueBegin { address }
+            | DebugEvent::SetFile { address, .. } => address,
+        }
+    }
+}
+
+/// The debug information of a method.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugInfoDef {
+    /// names of all parameters, excluding `this`
+    pub parameter_names: Vec<Option<String>>,
+
+    /// all events in increasing order of their address
+    pub events: Vec<DebugEvent>,
+}
+
+impl DebugInfoDef {
+    /// Creates debug information that only maps addresses to lines, e.g.
+    /// from a list of `(address, line)` pairs.
+    pub fn from_lines(lines: impl IntoIterator<Item = (u32, u32)>) -> DebugInfoDef {
+        let mut events: Vec<DebugEvent> = lines
+            .into_iter()
+            .map(|(address, line)| DebugEvent::Line { address, line })
+            .collect();
+        events.sort_by_key(|x| x.address());
+        DebugInfoDef {
+            parameter_names: Vec::new(),
+            events,
+        }
+    }
+
+    /// Translates the addresses of all events, e.g. after instructions were
+    /// moved by [remap](crate::dalvik::insns::remap::remap). The translation
+    /// must keep the order of the addresses.
+    pub fn map_addresses(&mut self, mut f: impl FnMut(u32) -> u32) {
+        for event in &mut self.events {
+            let address = event.address_mut();
+            *address = f(*address);
+        }
+    }
+
+    /// Returns all strings referenced by this debug information.
+    pub fn strings(&self) -> impl Iterator<Item = &str> {
+        let names = self.parameter_names.iter().flatten();
+        let events = self.events.iter().flat_map(|event| match event {
+            DebugEvent::StartLocal {
+                name, signature, ..
+            } => vec![name, signature],
+            DebugEvent::SetFile { name, .. } => vec![name],
+            _ => Vec::new(),
+        });
+        names.chain(events.flatten()).map(|x| x.as_str())
+    }
+
+    /// Returns the descriptors of all types referenced by this debug
+    /// information.
+    pub fn types(&self) -> impl Iterator<Item = &str> {
+        self.events.iter().filter_map(|event| match event {
+            DebugEvent::StartLocal {
+                type_: Some(type_), ..
+            } => Some(type_.as_str()),
+            _ => None,
+        })
+    }
+}
+
+/// Encodes the given debug information into a `debug_info_item`.
+pub(super) fn encode(debug_info: &DebugInfoDef, pools: &Pools) -> Result<Vec<UByte>> {
+    let string = |value: &Option<String>| -> Result<ULeb128p1> {
+        Ok(match value {
+            Some(x) => ULeb128p1::Pos(pools.string_idx(x)?),
+            None => ULeb128p1::Neg,
+        })
+    };
+    let type_ = |value: &Option<String>| -> Result<ULeb128p1> {
+        Ok(match value {
+            Some(x) => ULeb128p1::Pos(pools.type_idx(x)?),
+            None => ULeb128p1::Neg,
+        })
+    };
+
+    let line_start = debug_info
+        .events
+        .iter()
+        .find_map(|x| match x {
+            DebugEvent::Line { line, .. } => Some(*line),
+            _ => None,
+        })
+        .unwrap_or_default();
+    let mut parameter_names = Vec::with_capacity(debug_info.parameter_names.len());
+    for name in &debug_info.parameter_names {
+        parameter_names.push(string(name)?);
+    }
+
+    let mut out = Cursor::new(Vec::new());
+    DebugInfoItem {
+        line_start: ULeb128(line_start),
+        parameter_names,
+    }
+    .write_le(&mut out)?;
+
+    let mut address = 0u32;
+    let mut line = line_start as i64;
+    for event in &debug_info.events {
+        let Some(addr_diff) = event.address().checked_sub(address) else {
+            return Err(Error::InvalidData(format!(
+                "debug event at {:#x} is not ordered by address",
+                event.address()
+            )));
+        };
+        address = event.address();
+
+        let is_line = matches!(event, DebugEvent::Line { .. });
+        if !is_line && addr_diff > 0 {
+            out.write_all(&[DebugInfoItem::DBG_ADVANCE_PC])?;
+            ULeb128(addr_diff).write_le(&mut out)?;
+        }
+        match event {
+            DebugEvent::Line { line: next, .. } => {
+                let mut line_diff = *next as i64 - line;
+                line = *next as i64;
+                if !(DebugInfoItem::DBG_LINE_BASE as i64..max_line_diff()).contains(&line_diff) {
+                    out.write_all(&[DebugInfoItem::DBG_ADVANCE_LINE])?;
+                    SLeb128(line_diff as i32).write_le(&mut out)?;
+                    line_diff = 0;
+                }
+                let opcode = match special_opcode(line_diff, addr_diff) {
+                    Some(opcode) => opcode,
+                    None => {
+                        out.write_all(&[DebugInfoItem::DBG_ADVANCE_PC])?;
+                        ULeb128(addr_diff).write_le(&mut out)?;
+                        special_opcode(line_diff, 0).unwrap_or_default()
+                    }
+                };
+                out.write_all(&[opcode])?;
+            }
+            DebugEvent::StartLocal {
+                register,
+                name,
+                type_: local_type,
+                signature,
+                ..
+            } => {
+                let extended = signature.is_some();
+                out.write_all(&[if extended {
+                    DebugInfoItem::DBG_START_LOCAL_EXTENDED
+                } else {
+                    DebugInfoItem::DBG_START_LOCAL
+                }])?;
+                ULeb128(*register).write_le(&mut out)?;
+                string(name)?.write_le(&mut out)?;
+                type_(local_type)?.write_le(&mut out)?;
+                if extended {
+                    string(signature)?.write_le(&mut out)?;
+                }
+            }
+            DebugEvent::EndLocal { register, .. } => {
+                out.write_all(&[DebugInfoItem::DBG_END_LOCAL])?;
+                ULeb128(*register).write_le(&mut out)?;
+            }
+            DebugEvent::RestartLocal { register, .. } => {
+                out.write_all(&[DebugInfoItem::DBG_RESTART_LOCAL])?;
+                ULeb128(*register).write_le(&mut out)?;
+            }
+            DebugEvent::PrologueEnd { .. } => {
+                out.write_all(&[DebugInfoItem::DBG_SET_PROLOGUE_END])?
+            }
+            DebugEvent::EpilogueBegin { .. } => {
+                out.write_all(&[DebugInfoItem::DBG_SET_EPILOGUE_BEGIN])?
+            }
+            DebugEvent::SetFile { name, .. } => {
+                out.write_all(&[DebugInfoItem::DBG_SET_FILE])?;
+                string(name)?.write_le(&mut out)?;
+            }
+        }
+    }
+    out.write_all(&[DebugInfoItem::DBG_END_SEQUENCE])?;
+    Ok(out.into_inner())
+}
+
+/// Exclusive upper bound of the line differences a special opcode can
+/// encode.
+fn max_line_diff() -> i64 {
+    DebugInfoItem::DBG_LINE_BASE as i64 + DebugInfoItem::DBG_LINE_RANGE as i64
+}
+
+/// Returns the special opcode that advances the line and address registers
+/// by the given amounts, if there is one.
+fn special_opcode(line_diff: i64, addr_diff: u32) -> Option<UByte> {
+    let opcode = (line_diff - DebugInfoItem::DBG_LINE_BASE as i64)
+        + addr_diff as i64 * DebugInfoItem::DBG_LINE_RANGE as i64
+        + DebugInfoItem::DBG_FIRST_SPECIAL as i64;
+    UByte::try_from(opcode).ok()
+}
@@ -0,0 +1,297 @@
+//! Conversion of parsed classes into the writer model
+
+use crate::dalvik::{
+    dex::{AccessFlags, CodeItem, FieldIdItem, MethodIdItem, UInt},
+    error::{Error, Result},
+    file::{
+        annotation::DexAnnotation, field::DexField, method::DexMethod, method::DexPrototype,
+        DexClassDef, DexValue, IDexRef,
+    },
+    insns::{Index, InsnFormat},
+};
+
+use super::{
+    Annotation, AnnotationDef, ClassDef, CodeDef, CodeRef, DebugInfoDef, FieldDef, FieldRef,
+    ItemRef, MethodDef, MethodRef, ProtoRef, TryDef, Value,
+};
+
+pub(super) fn class_def(dex: IDexRef<'_>, class: &DexClassDef) -> Result<ClassDef> {
+    let descriptor = class.type_.to_string();
+    let mut static_fields = Vec::new();
+    for field in class.get_static_fields() {
+        static_fields.push(field_def(dex, field)?);
+    }
+    let mut instance_fields = Vec::new();
+    for field in class.get_instance_fields() {
+        instance_fields.push(field_def(dex, field)?);
+    }
+    let mut direct_methods = Vec::new();
+    for method in class.get_direct_methods() {
+        direct_methods.push(method_def(dex, method)?);
+    }
+    let mut virtual_methods = Vec::new();
+    for method in class.get_virtual_methods() {
+        virtual_methods.push(method_def(dex, method)?);
+    }
+
+    Ok(ClassDef {
+        access_flags: access_flags(&class.flags, &descriptor)?,
+        superclass: class.super_class.as_ref().map(|x| x.to_string()),
+        interfaces: class.interfaces.iter().map(|x| x.to_string()).collect(),
+        source_file: class.source_file.as_ref().map(|x| x.to_string()),
+        descriptor,
+        static_fields,
+        instance_fields,
+        direct_methods,
+        virtual_methods,
+        annotations: annotation_defs(dex, &class.annotations)?,
+    })
+}
+
+fn field_def(dex: IDexRef<'_>, field: &DexField) -> Result<FieldDef> {
+    let field_ref = FieldRef {
+        class: field.class.to_string(),
+        name: field.name.to_string(),
+        type_: field.type_.to_string(),
+    };
+    Ok(FieldDef {
+        access_flags: access_flags(&field.access_flags, &field_ref.name)?,
+        init_value: match &field.init_value {
+            Some(value) => Some(value_of(dex, value)?),
+            None => None,
+        },
+        annotations: annotation_defs(dex, &field.annotations)?,
+        field: field_ref,
+    })
+}
+
+fn method_def(dex: IDexRef<'_>, method: &DexMethod) -> Result<MethodDef> {
+    let method_ref = MethodRef {
+        class: method.class.to_string(),
+        name: method.name.to_string(),
+        proto: proto_ref(&method.proto),
+    };
+    Ok(MethodDef {
+        access_flags: access_flags(&method.access_flags, &method_ref.name)?,
+        code: match &method.code {
+            Some(code) => Some(code_def(dex, method, code)?),
+            None => None,
+        },
+        annotations: annotation_defs(dex, &method.annotations)?,
+        parameter_annotations: parameter_annotations(dex, method)?,
+        method: method_ref,
+    })
+}
+
+/// Copies the bytecode of a method and records all index operands.
+fn code_def(dex: IDexRef<'_>, method: &DexMethod, code: &CodeItem) -> Result<CodeDef> {
+    let mut refs = Vec::new();
+    for insn in method.disasm(dex)? {
+        // byte offsets of the index operands relative to the instruction
+        let (index, offset, wide, proto) = match &insn.format {
+            InsnFormat::Format21c { b, .. }
+            | InsnFormat::Format35c { b, .. }
+            | InsnFormat::Format3rc { b, .. } => (b, 2, false, None),
+            InsnFormat::Format22c { c, .. } => (c, 2, false, None),
+            InsnFormat::Format31c { b, .. } => (b, 2, true, None),
+            InsnFormat::Format45cc { b, h, .. } | InsnFormat::Format4rcc { b, h, .. } => {
+                (b, 2, false, Some(h))
+            }
+            _ => continue,
+        };
+        refs.push(CodeRef {
+            offset: insn.range.start + offset,
+            wide,
+            item: item_ref(dex, index, insn.range.start)?,
+        });
+        if let Some(proto) = proto {
+            refs.push(CodeRef {
+                offset: insn.range.start + 6,
+                wide: false,
+                item: item_ref(dex, proto, insn.range.start)?,
+            });
+        }
+    }
+
+    Ok(CodeDef {
+        registers_size: code.registers_size,
+        ins_size: code.ins_size,
+        outs_size: code.outs_size,
+        insns: code.insns.clone(),
+        refs,
+        debug_info: debug_info_def(method),
+        tries: try_defs(dex, code)?,
+    })
+}
+
+/// Converts the try blocks of a code item and resolves the caught types.
+fn try_defs(dex: IDexRef<'_>, code: &CodeItem) -> Result<Vec<TryDef>> {
+    let mut defs = Vec::with_capacity(code.tries.len());
+    for try_item in &code.tries {
+        let Some(handler) = code.catch_handler(try_item) else {
+            return Err(Error::InvalidData(format!(
+                "try block at {:#x} has no catch handler",
+                try_item.range().start
+            )));
+        };
+        let mut handlers = Vec::with_capacity(handler.handlers.len());
+        for pair in &handler.handlers {
+            handlers.push((dex.get_type(pair.type_idx.0)?.to_string(), pair.addr.0));
+        }
+        defs.push(TryDef {
+            start_addr: try_item.start_addr,
+            insn_count: try_item.insn_count,
+            handlers,
+            catch_all_addr: handler.catch_all_addr.as_ref().map(|x| x.0),
+        });
+    }
+    Ok(defs)
+}
+
+/// Converts the line table and parameter names of a method. Local
+/// variables are not carried over.
+fn debug_info_def(method: &DexMethod) -> Option<DebugInfoDef> {
+    let debug_info = method.debug_info.as_ref()?;
+    let lines = debug_info
+        .lines
+        .iter()
+        .map(|(address, line)| (*address, *line as u32));
+    let mut def = DebugInfoDef::from_lines(lines);
+    def.parameter_names = method
+        .parameters
+        .iter()
+        .map(|x| x.name.as_ref().map(|x| x.to_string()))
+        .collect();
+    Some(def)
+}
+
+/// Returns the annotations of all parameters, omitting trailing parameters
+/// without annotations.
+fn parameter_annotations(dex: IDexRef<'_>, method: &DexMethod) -> Result<Vec<Vec<AnnotationDef>>> {
+    let count = method
+        .parameters
+        .iter()
+        .rposition(|x| !x.annotations.is_empty())
+        .map_or(0, |x| x + 1);
+    let mut defs = Vec::with_capacity(count);
+    for parameter in &method.parameters[..count] {
+        defs.push(annotation_defs(dex, &parameter.annotations)?);
+    }
+    Ok(defs)
+}
+
+fn annotation_defs(dex: IDexRef<'_>, annotations: &[DexAnnotation]) -> Result<Vec<AnnotationDef>> {
+    let mut defs = Vec::with_capacity(annotations.len());
+    for annotation in annotations {
+        let Some(visibility) = annotation.visibility else {
+            return Err(Error::InvalidData(format!(
+                "annotation {} has no visibility",
+                annotation.type_
+            )));
+        };
+        defs.push(AnnotationDef {
+            visibility,
+            annotation: annotation_of(dex, annotation)?,
+        });
+    }
+    Ok(defs)
+}
+
+/// Element order is not preserved by the parsed model; elements are sorted
+/// by name when the file is written.
+fn annotation_of(dex: IDexRef<'_>, annotation: &DexAnnotation) -> Result<Annotation> {
+    let mut elements = Vec::with_capacity(annotation.values.len());
+    for (name, value) in &annotation.values {
+        elements.push((name.to_string(), value_of(dex, value)?));
+    }
+    Ok(Annotation {
+        type_: annotation.type_.to_string(),
+        elements,
+    })
+}
+
+fn item_ref(dex: IDexRef<'_>, index: &Index, offset: usize) -> Result<ItemRef> {
+    Ok(match index {
+        Index::String(x) => ItemRef::String(x.to_string()),
+        Index::Type(x) => ItemRef::Type(x.to_string()),
+        Index::Field(x) => ItemRef::Field(field_ref(dex, x)?),
+        Index::Method(_, x) => ItemRef::Method(method_ref(dex, x)?),
+        Index::Proto(x) => ItemRef::Proto(proto_ref(x)),
+        _ => {
+            return Err(Error::InvalidData(format!(
+                "unsupported index operand in instruction at {:#x}",
+                offset
+            )));
+        }
+    })
+}
+
+fn value_of(dex: IDexRef<'_>, value: &DexValue) -> Result<Value> {
+    Ok(match value {
+        DexValue::Byte(x) => Value::Byte(*x),
+        DexValue::Short(x) => Value::Short(*x),
+        DexValue::Char(x) => Value::Char(*x),
+        DexValue::Int(x) => Value::Int(*x),
+        DexValue::Long(x) => Value::Long(*x),
+        DexValue::Float(x) => Value::Float(*x),
+        DexValue::Double(x) => Value::Double(*x),
+        DexValue::String(x) => Value::String(x.to_string()),
+        DexValue::Type(x) => Value::Type(x.to_string()),
+        DexValue::MethodType(x) => Value::MethodType(proto_ref(x)),
+        DexValue::MethodRef(_, x) => Value::Method(method_ref(dex, x)?),
+        DexValue::FieldRef(x) => Value::Field(field_ref(dex, x)?),
+        DexValue::Enum(x) => Value::Enum(field_ref(dex, x)?),
+        DexValue::Array(values) => {
+            let mut converted = Vec::with_capacity(values.len());
+            for value in values {
+                converted.push(value_of(dex, value)?);
+            }
+            Value::Array(converted)
+        }
+        DexValue::True => Value::Boolean(true),
+        DexValue::False => Value::Boolean(false),
+        DexValue::Null => Value::Null,
+        DexValue::Annotation(x) => Value::Annotation(annotation_of(dex, x)?),
+        _ => {
+            return Err(Error::InvalidData(format!(
+                "unsupported value: {:?}",
+                value
+            )));
+        }
+    })
+}
+
+pub(super) fn proto_ref(proto: &DexPrototype) -> ProtoRef {
+    ProtoRef {
+        return_type: proto.return_type.to_string(),
+        parameters: proto.parameters.iter().map(|x| x.to_string()).collect(),
+    }
+}
+
+fn field_ref(dex: IDexRef<'_>, field: &FieldIdItem) -> Result<FieldRef> {
+    Ok(FieldRef {
+        class: dex.get_type(field.class_idx as u32)?.to_string(),
+        name: dex.get_string(field.name_idx)?.to_string(),
+        type_: dex.get_type(field.type_idx as u32)?.to_string(),
+    })
+}
+
+fn method_ref(dex: IDexRef<'_>, method: &MethodIdItem) -> Result<MethodRef> {
+    Ok(MethodRef {
+        class: dex.get_type(method.class_idx as u32)?.to_string(),
+        name: dex.get_string(method.name_idx)?.to_string(),
+        proto: proto_ref(dex.get_proto(method.proto_idx as u32)?.as_ref()),
+    })
+}
+
+/// The parsed model drops access flags with unknown bits, which can't be
+/// restored.
+fn access_flags(flags: &Option<AccessFlags>, name: &str) -> Result<UInt> {
+    match flags {
+        Some(flags) => Ok(flags.bits()),
+        None => Err(Error::InvalidData(format!(
+            "unknown access flags of {}",
+            name
+        ))),
+    }
+}
@@ -0,0 +1,234 @@
+//! Canonical ordering of DEX sections
+//!
+//! The DEX format requires the id sections to be sorted:
+//!
+//! - `string_ids` by the UTF-16 code units of their contents,
+//! - `type_ids` by the index of their descriptor string,
+//! - `proto_ids` by return type, then by their parameter types,
+//! - `field_ids` by declaring class, name and type,
+//! - `method_ids` by declaring class, name and prototype,
+//! - `class_defs` so that superclasses and implemented interfaces that are
+//!   defined in the same file come before their subclasses.
+//!
+//! The writer uses these rules to lay out new files, [validate] checks them
+//! against existing files.
+
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+};
+
+use crate::dalvik::{
+    dex::MapListItemType,
+    error::{Error, Result},
+    file::IDexRef,
+};
+
+use super::{extract::proto_ref, ClassDef, FieldRef, MethodRef, ProtoRef};
+
+/// Compares two strings by their UTF-16 code units.
+pub fn compare_strings(a: &str, b: &str) -> Ordering {
+    a.encode_utf16().cmp(b.encode_utf16())
+}
+
+/// Compares two lists of type descriptors element-wise, a shorter list
+/// that is a prefix of the other comes first.
+fn compare_type_lists(a: &[String], b: &[String]) -> Ordering {
+    for (x, y) in a.iter().zip(b) {
+        match compare_strings(x, y) {
+            Ordering::Equal => continue,
+            ordering => return ordering,
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+pub fn compare_protos(a: &ProtoRef, b: &ProtoRef) -> Ordering {
+    compare_strings(&a.return_type, &b.return_type)
+        .then_with(|| compare_type_lists(&a.parameters, &b.parameters))
+}
+
+pub fn compare_fields(a: &FieldRef, b: &FieldRef) -> Ordering {
+    compare_strings(&a.class, &b.class)
+        .then_with(|| compare_strings(&a.name, &b.name))
+        .then_with(|| compare_strings(&a.type_, &b.type_))
+}
+
+pub fn compare_methods(a: &MethodRef, b: &MethodRef) -> Ordering {
+    compare_strings(&a.class, &b.class)
+        .then_with(|| compare_strings(&a.name, &b.name))
+        .then_with(|| compare_protos(&a.proto, &b.proto))
+}
+
+/// Returns all classes ordered so that superclasses and interfaces defined
+/// in the same set precede their subclasses. Apart from that, the order of
+/// the given classes is kept.
+pub fn sort_classes(classes: &[ClassDef]) -> Result<Vec<&ClassDef>> {
+    let all: HashMap<&str, &ClassDef> =
+        classes.iter().map(|x| (x.descriptor.as_str(), x)).collect();
+    let mut sorted = Vec::with_capacity(classes.len());
+    let mut done = HashSet::new();
+    let mut visiting = HashSet::new();
+    // classes whose parents are being visited, together with the number of
+    // parents that were visited so far; inheritance chains can be longer
+    // than the call stack allows
+    let mut stack: Vec<(&ClassDef, usize)> = Vec::new();
+    for class in classes {
+        if done.contains(class.descriptor.as_str()) {
+            continue;
+        }
+        visiting.insert(class.descriptor.as_str());
+        stack.push((class, 0));
+        while let Some((class, next)) = stack.last_mut() {
+            let class: &ClassDef = class;
+            let parent = class.superclass.iter().chain(&class.interfaces).nth(*next);
+            *next += 1;
+            let Some(parent) = parent else {
+                stack.pop();
+                done.insert(class.descriptor.as_str());
+                sorted.push(class);
+                continue;
+            };
+            let Some(parent) = all.get(parent.as_str()) else {
+                continue;
+            };
+            if done.contains(parent.descriptor.as_str()) {
+                continue;
+            }
+            if !visiting.insert(parent.descriptor.as_str()) {
+                return Err(Error::InvalidData(format!(
+                    "class {} inherits from itself",
+                    parent.descriptor
+                )));
+            }
+            stack.push((parent, 0));
+        }
+    }
+    Ok(sorted)
+}
+
+/// An item that is not in canonical order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// the section containing the item
+    pub section: MapListItemType,
+
+    /// index of the item within its section
+    pub index: u32,
+
+    pub description: String,
+}
+
+/// Checks that the id sections and class definitions of an existing file
+/// are sorted as described in the [module documentation](self). Ids must
+/// also be unique, so equal neighbours are reported as well.
+///
+/// Types and prototypes are compared by the values they reference. This is
+/// equivalent to comparing indices as long as the string ids are sorted.
+pub fn validate(dex: IDexRef<'_>) -> Result<Vec<Violation>> {
+    let header = dex.get_header();
+    let (strings, types, protos, fields, methods, classes) = (
+        header.string_ids_size,
+        header.type_ids_size,
+        header.proto_ids_size,
+        header.field_ids_size,
+        header.method_ids_size,
+        header.class_defs_size,
+    );
+
+    let mut violations = Vec::new();
+    let mut check = |section, index: u32, ordering: Ordering, item: &dyn std::fmt::Debug| {
+        if index > 0 && ordering != Ordering::Less {
+            violations.push(Violation {
+                section,
+                index,
+                description: format!("{:?} is not greater than the previous item", item),
+            });
+        }
+    };
+
+    let mut prev = String::new();
+    for index in 0..strings {
+        let value = dex.get_string(index)?;
+        check(
+            MapListItemType::StringIdItem,
+            index,
+            compare_strings(&prev, &value),
+            &value,
+        );
+        prev = value.to_string();
+    }
+
+    let mut prev = String::new();
+    for index in 0..types {
+        let descriptor = dex.get_type(index)?.to_string();
+        let ordering = compare_strings(&prev, &descriptor);
+        check(MapListItemType::TypeIdItem, index, ordering, &descriptor);
+        prev = descriptor;
+    }
+
+    let mut prev: Option<ProtoRef> = None;
+    for index in 0..protos {
+        let proto = proto_ref(dex.get_proto(index)?.as_ref());
+        let ordering = prev
+            .as_ref()
+            .map_or(Ordering::Less, |x| compare_protos(x, &proto));
+        check(MapListItemType::ProtoIdItem, index, ordering, &proto);
+        prev = Some(proto);
+    }
+
+    // field and method ids store indices, which can be compared directly
+    let mut prev = (0, 0, 0);
+    for index in 0..fields {
+        let field = dex.get_field(index)?;
+        let key = (
+            field.class_idx as u32,
+            field.name_idx,
+            field.type_idx as u32,
+        );
+        check(MapListItemType::FieldIdItem, index, prev.cmp(&key), &field);
+        prev = key;
+    }
+
+    let mut prev = (0, 0, 0);
+    for index in 0..methods {
+        let method = dex.get_method(index)?;
+        let key = (method.class_idx, method.name_idx, method.proto_idx);
+        check(
+            MapListItemType::MethodIdItem,
+            index,
+            prev.cmp(&key),
+            &method,
+        );
+        prev = key;
+    }
+
+    // superclasses and interfaces must be defined before their subclasses
+    let mut defined: HashMap<String, u32> = HashMap::new();
+    let mut parents = Vec::with_capacity(classes as usize);
+    for index in 0..classes {
+        let class = dex.get_class_def(index)?;
+        defined.insert(class.type_.to_string(), index);
+        let names: Vec<String> = class
+            .super_class
+            .iter()
+            .chain(&class.interfaces)
+            .map(|x| x.to_string())
+            .collect();
+        parents.push(names);
+    }
+    for (index, names) in parents.iter().enumerate() {
+        for name in names {
+            if let Some(parent) = defined.get(name)
+                && *parent as usize >= index
+            {
+                violations.push(Violation {
+                    section: MapListItemType::ClassDefItem,
+                    index: index as u32,
+                    description: format!("{} is defined after its subclass", name),
+                });
+            }
+        }
+    }
+    Ok(violations)
+}
@@ -0,0 +1,74 @@
+//! Merging of multiple DEX files
+//!
+//! All classes of the input files are converted into the writer model, so
+//! strings, types, prototypes, fields and methods are deduplicated by value
+//! and all index operands are rewritten when the merged file is built. The
+//! limitations of [ClassDef::from_dex] apply to every merged class.
+//!
+//! ```ignore
+//! let merged = merge::merge([&mut dex1 as IDexRef, &mut dex2], Duplicates::Fail)?;
+//! std::fs::write("merged.dex", merged)?;
+//! ```
+
+use crate::dalvik::{
+    error::{Error, Result},
+    file::IDexRef,
+};
+
+use super::{ClassDef, DexBuilder};
+
+/// Specifies how classes defined by more than one input are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Duplicates {
+    /// Merging fails with an error.
+    #[default]
+    Fail,
+
+    /// The definition of the first input is used, later ones are ignored.
+    KeepFirst,
+}
+
+/// Merges all given DEX files into a single file.
+///
+/// Fails if the merged file would reference more than [MAX_IDS](super::MAX_IDS)
+/// types, prototypes, fields or methods.
+pub fn merge<'a, I>(inputs: I, duplicates: Duplicates) -> Result<Vec<u8>>
+where
+    I: IntoIterator<Item = IDexRef<'a>>,
+{
+    let mut builder = DexBuilder::new();
+    for dex in inputs {
+        merge_into(&mut builder, dex, duplicates)?;
+    }
+    builder.build()
+}
+
+/// Adds all classes of a DEX file to the given builder. The version of the
+/// builder is raised to the version of the file if necessary.
+pub fn merge_into(
+    builder: &mut DexBuilder,
+    dex: IDexRef<'_>,
+    duplicates: Duplicates,
+) -> Result<()> {
+    if let Ok(version) = dex.get_header().magic.version_num() {
+        builder.set_version(builder.version().max(version));
+    }
+
+    for index in 0..dex.get_header().class_defs_size {
+        let class = dex.get_class_def(index)?;
+        let descriptor = class.type_.to_string();
+        if builder.contains_class(&descriptor) {
+            match duplicates {
+                Duplicates::Fail => {
+                    return Err(Error::InvalidData(format!(
+                        "class {} is defined by more than one input",
+                        descriptor
+                    )));
+                }
+                Duplicates::KeepFirst => continue,
+            }
+        }
+        builder.add_class(ClassDef::from_dex(dex, &class)?)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,918 @@
+//! Writing DEX files
+//!
+//! The writer works on an owned model of classes that is independent of any
+//! input file. All references are stored by value (descriptors, names and
+//! prototypes) and are only turned into indices when the file is built, which
+//! makes it possible to combine classes from different sources.
+//!
+//! [DexBuilder::build] collects all referenced strings, types, prototypes,
+//! fields and methods, sorts them as required by the DEX format and lays out
+//! a new file including the map list, checksum and signature. A
+//! `const-string` whose string index no longer fits into 16 bits, e.g. after
+//! merging files with many strings, is widened to `const-string/jumbo` and
+//! the branches, try blocks and debug information of its method are
+//! adjusted.
+//!
+//! ```ignore
+//! let class = dex.get_class_def(0)?;
+//! let builder = DexBuilder::from_class(&mut dex, &class)?;
+//! std::fs::write("extracted.dex", builder.build()?)?;
+//! ```
+//!
+//! The writer does not support method handles and call sites yet.
+
+use binrw::BinWrite;
+use std::{
+    collections::HashMap,
+    io::{Cursor, Seek, SeekFrom, Write},
+};
+
+use crate::dalvik::{
+    dex::*,
+    error::{Error, Result},
+    file::{DexClassDef, IDexRef},
+    insns::{
+        insn_offsets,
+        remap::{remap_with, IndexKind, Remapped},
+    },
+};
+
+pub mod annotation;
+pub mod debug;
+mod extract;
+pub mod layout;
+pub mod merge;
+mod pool;
+pub mod rename;
+pub mod rewrite;
+pub mod split;
+
+pub use annotation::{Annotation, AnnotationDef};
+pub use debug::{DebugEvent, DebugInfoDef};
+use pool::{Pools, PoolsBuilder};
+
+/// Maximum number of types, prototypes, fields and methods of a single
+/// file, as their indices are stored in 16 bits.
+pub const MAX_IDS: usize = 0x10000;
+
+/// A method prototype, stored by the descriptors of its types.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ProtoRef {
+    /// descriptor of the return type
+    pub return_type: String,
+
+    /// descriptors of all parameter types
+    pub parameters: Vec<String>,
+}
+
+impl ProtoRef {
+    /// Returns the shorty descriptor of this prototype, e.g. `VL` for
+    /// `(Ljava/lang/String;)V`.
+    pub fn shorty(&self) -> String {
+        std::iter::once(&self.return_type)
+            .chain(&self.parameters)
+            .map(|x| match x.as_bytes().first() {
+                Some(b'[') | Some(b'L') => 'L',
+                Some(c) => *c as char,
+                None => 'V',
+            })
+            .collect()
+    }
+}
+
+/// A field reference, stored by the descriptors of its types.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FieldRef {
+    /// descriptor of the declaring class
+    pub class: String,
+
+    /// name of the field
+    pub name: String,
+
+    /// descriptor of the field's type
+    pub type_: String,
+}
+
+/// A method reference, stored by the descriptors of its types.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MethodRef {
+    /// descriptor of the declaring class
+    pub class: String,
+
+    /// name of the method
+    pub name: String,
+
+    /// prototype of the method
+    pub proto: ProtoRef,
+}
+
+/// An item referenced from an instruction.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ItemRef {
+    String(String),
+    Type(String),
+    Field(FieldRef),
+    Method(MethodRef),
+    Proto(ProtoRef),
+}
+
+/// A constant value, e.g. the initial value of a static field.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Byte(i8),
+    Short(i16),
+    Char(char),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    Type(String),
+    Field(FieldRef),
+    Method(MethodRef),
+    Enum(FieldRef),
+    MethodType(ProtoRef),
+    Array(Vec<Value>),
+    Annotation(Annotation),
+    Boolean(bool),
+    Null,
+}
+
+impl Value {
+    /// Returns the default value of a field with the given type descriptor.
+    pub fn default_for(descriptor: &str) -> Value {
+        match descriptor {
+            "Z" => Value::Boolean(false),
+            "B" => Value::Byte(0),
+            "S" => Value::Short(0),
+            "C" => Value::Char('\0'),
+            "I" => Value::Int(0),
+            "J" => Value::Long(0),
+            "F" => Value::Float(0.0),
+            "D" => Value::Double(0.0),
+            _ => Value::Null,
+        }
+    }
+}
+
+/// An index operand within the bytecode of a method.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeRef {
+    /// byte offset of the index within the instructions
+    pub offset: usize,
+
+    /// whether the index uses 32 bits (`const-string/jumbo`) instead of 16
+    pub wide: bool,
+
+    /// the referenced item
+    pub item: ItemRef,
+}
+
+/// The code of a method.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeDef {
+    /// the number of registers used by this code
+    pub registers_size: UShort,
+
+    /// the number of words of incoming arguments
+    pub ins_size: UShort,
+
+    /// the number of words of outgoing argument space
+    pub outs_size: UShort,
+
+    /// raw bytecode, index operands are overwritten when the file is built
+    pub insns: Vec<UByte>,
+
+    /// all index operands within `insns`
+    pub refs: Vec<CodeRef>,
+
+    /// line numbers and local variables
+    pub debug_info: Option<DebugInfoDef>,
+
+    /// try blocks sorted by their start address
+    pub tries: Vec<TryDef>,
+}
+
+/// A try block and its exception handlers. All addresses are counted in
+/// 16-bit code units, like those of a [TryItem].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TryDef {
+    /// address of the first covered instruction
+    pub start_addr: UInt,
+
+    /// number of covered code units
+    pub insn_count: UShort,
+
+    /// descriptors of the caught exception types and the addresses of
+    /// their handlers, in the order the types are tested
+    pub handlers: Vec<(String, UInt)>,
+
+    /// address of the handler for all other exceptions
+    pub catch_all_addr: Option<UInt>,
+}
+
+/// A field defined by a class.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldDef {
+    pub field: FieldRef,
+    pub access_flags: UInt,
+
+    /// initial value of a static field
+    pub init_value: Option<Value>,
+    pub annotations: Vec<AnnotationDef>,
+}
+
+/// A method defined by a class.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodDef {
+    pub method: MethodRef,
+    pub access_flags: UInt,
+    pub code: Option<CodeDef>,
+    pub annotations: Vec<AnnotationDef>,
+
+    /// annotations of each parameter, in the order of the prototype's
+    /// parameters
+    pub parameter_annotations: Vec<Vec<AnnotationDef>>,
+}
+
+/// A class definition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassDef {
+    /// descriptor of this class
+    pub descriptor: String,
+    pub access_flags: UInt,
+
+    /// descriptor of the superclass, `None` for `java.lang.Object`
+    pub superclass: Option<String>,
+    pub interfaces: Vec<String>,
+    pub source_file: Option<String>,
+    pub static_fields: Vec<FieldDef>,
+    pub instance_fields: Vec<FieldDef>,
+    pub direct_methods: Vec<MethodDef>,
+    pub virtual_methods: Vec<MethodDef>,
+    pub annotations: Vec<AnnotationDef>,
+}
+
+impl ClassDef {
+    /// Converts a parsed class definition into the writer model, resolving
+    /// all references through the given DEX file.
+    ///
+    /// Local variables are not carried over. Methods with instructions
+    /// referencing method handles or call sites are rejected.
+    pub fn from_dex(dex: IDexRef<'_>, class: &DexClassDef) -> Result<ClassDef> {
+        extract::class_def(dex, class)
+    }
+
+    /// Returns all fields defined by this class.
+    pub fn fields(&self) -> impl Iterator<Item = &FieldDef> {
+        self.static_fields.iter().chain(&self.instance_fields)
+    }
+
+    /// Returns all methods defined by this class.
+    pub fn methods(&self) -> impl Iterator<Item = &MethodDef> {
+        self.direct_methods.iter().chain(&self.virtual_methods)
+    }
+}
+
+/// Builds a new DEX file from a set of classes.
+#[derive(Debug, Clone)]
+pub struct DexBuilder {
+    version: UInt,
+    classes: Vec<ClassDef>,
+}
+
+impl Default for DexBuilder {
+    fn default() -> Self {
+        DexBuilder {
+            version: 35,
+            classes: Vec::new(),
+        }
+    }
+}
+
+impl DexBuilder {
+    /// Creates an empty builder for version `035` files.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Extracts a single class into a new builder, which can then be used
+    /// to write a standalone DEX file. The builder uses the version of the
+    /// source file.
+    ///
+    /// See [ClassDef::from_dex] for the parts of a class that are dropped.
+    pub fn from_class(dex: IDexRef<'_>, class: &DexClassDef) -> Result<DexBuilder> {
+        let mut builder = DexBuilder::new();
+        if let Ok(version) = dex.get_header().magic.version_num() {
+            builder.set_version(version);
+        }
+        builder.add_class(ClassDef::from_dex(dex, class)?)?;
+        Ok(builder)
+    }
+
+    /// Returns the version written to the magic of the file.
+    pub fn version(&self) -> UInt {
+        self.version
+    }
+
+    pub fn set_version(&mut self, version: UInt) {
+        self.version = version;
+    }
+
+    /// Returns all classes added so far.
+    pub fn classes(&self) -> &[ClassDef] {
+        &self.classes
+    }
+
+    /// Removes all classes from this builder and returns them.
+    pub fn take_classes(&mut self) -> Vec<ClassDef> {
+        std::mem::take(&mut self.classes)
+    }
+
+    /// Returns whether a class with the given descriptor has been added.
+    pub fn contains_class(&self, descriptor: &str) -> bool {
+        self.classes.iter().any(|x| x.descriptor == descriptor)
+    }
+
+    /// Adds a class to this builder. Every class may only be defined once.
+    pub fn add_class(&mut self, class: ClassDef) -> Result<()> {
+        if self
+            .classes
+            .iter()
+            .any(|x| x.descriptor == class.descriptor)
+        {
+            return Err(Error::InvalidData(format!(
+                "class {} is already defined",
+                class.descriptor
+            )));
+        }
+        self.classes.push(class);
+        Ok(())
+    }
+
+    /// Lays out and writes a new DEX file storing all classes of this
+    /// builder.
+    pub fn build(&self) -> Result<Vec<UByte>> {
+        let mut pools = PoolsBuilder::default();
+        for class in &self.classes {
+            pools.add_class(class);
+        }
+        let pools = pools.finish()?;
+        let classes = layout::sort_classes(&self.classes)?;
+
+        // all id sections directly follow the header, the data section
+        // starts after them
+        let string_ids_off = HEADER_SIZE as UInt;
+        let type_ids_off = string_ids_off + pools.strings.len() as UInt * 4;
+        let proto_ids_off = type_ids_off + pools.types.len() as UInt * 4;
+        let field_ids_off = proto_ids_off + pools.protos.len() as UInt * 12;
+        let method_ids_off = field_ids_off + pools.fields.len() as UInt * 8;
+        let class_defs_off = method_ids_off + pools.methods.len() as UInt * 8;
+        let data_off = class_defs_off + classes.len() as UInt * 32;
+
+        let mut out = Cursor::new(vec![0; data_off as usize]);
+        out.seek(SeekFrom::End(0))?;
+        let mut map = Vec::new();
+        let mut section = |out: &mut Cursor<Vec<UByte>>, type_, size: usize, start: u64| {
+            if size > 0 {
+                map.push(MapListItem {
+                    type_: UShort::from(type_),
+                    size: size as UInt,
+                    offset: start as UInt,
+                });
+            }
+            out.position()
+        };
+
+        // code items
+        let start = out.position();
+        let mut code_offsets = Vec::new();
+        let mut relocations = Vec::new();
+        for class in &classes {
+            for method in class.methods() {
+                code_offsets.push(match &method.code {
+                    Some(code) => {
+                        align(&mut out, 4)?;
+                        let offset = out.position() as UInt;
+                        relocations.push(write_code(&mut out, code, &pools)?);
+                        offset
+                    }
+                    None => {
+                        relocations.push(None);
+                        0
+                    }
+                });
+            }
+        }
+        let count = code_offsets.iter().filter(|x| **x != 0).count();
+        section(&mut out, MapListItemType::CodeItem, count, start);
+
+        // type lists of prototypes and interfaces
+        align(&mut out, 4)?;
+        let start = out.position();
+        let mut type_lists: Vec<(Vec<String>, UInt)> = Vec::new();
+        let mut type_list = |out: &mut Cursor<Vec<UByte>>, types: &[String]| -> Result<UInt> {
+            if types.is_empty() {
+                return Ok(0);
+            }
+            if let Some((_, offset)) = type_lists.iter().find(|(x, _)| x == types) {
+                return Ok(*offset);
+            }
+            let offset = out.position() as UInt;
+            let mut list = Vec::with_capacity(types.len());
+            for type_ in types {
+                list.push(TypeItem {
+                    type_idx: pools.type_idx_u16(type_)?,
+                });
+            }
+            TypeList {
+                size: list.len() as UInt,
+                list,
+            }
+            .write(out)?;
+            type_lists.push((types.to_vec(), offset));
+            Ok(offset)
+        };
+        let mut parameters_offsets = Vec::with_capacity(pools.protos.len());
+        for proto in &pools.protos {
+            parameters_offsets.push(type_list(&mut out, &proto.parameters)?);
+        }
+        let mut interfaces_offsets = Vec::with_capacity(classes.len());
+        for class in &classes {
+            interfaces_offsets.push(type_list(&mut out, &class.interfaces)?);
+        }
+        let count = type_lists.len();
+        section(&mut out, MapListItemType::TypeList, count, start);
+
+        // string data
+        let start = out.position();
+        let mut string_offsets = Vec::with_capacity(pools.strings.len());
+        for value in &pools.strings {
+            string_offsets.push(out.position() as UInt);
+            let (utf16_size, bytes) = mutf8::encode(value);
+            ULeb128(utf16_size).write_le(&mut out)?;
+            out.write_all(&bytes)?;
+            out.write_all(&[0])?;
+        }
+        section(
+            &mut out,
+            MapListItemType::StringDataItem,
+            pools.strings.len(),
+            start,
+        );
+
+        // debug info, referenced by the code items written above
+        let start = out.position();
+        let mut count = 0;
+        let methods = classes.iter().flat_map(|x| x.methods());
+        for ((method, code_off), remapped) in methods.zip(&code_offsets).zip(&relocations) {
+            let Some(debug_info) = method.code.as_ref().and_then(|x| x.debug_info.as_ref()) else {
+                continue;
+            };
+            let offset = out.position() as UInt;
+            let encoded = match remapped {
+                // widened instructions moved the ones following them
+                Some(remapped) => {
+                    let mut debug_info = debug_info.clone();
+                    debug_info.map_addresses(|x| remapped.address(x));
+                    debug::encode(&debug_info, &pools)?
+                }
+                None => debug::encode(debug_info, &pools)?,
+            };
+            out.write_all(&encoded)?;
+            // debug_info_off follows the four 16-bit sizes of the code item
+            let end = out.position();
+            out.seek(SeekFrom::Start(*code_off as u64 + 8))?;
+            out.write_all(&offset.to_le_bytes())?;
+            out.seek(SeekFrom::Start(end))?;
+            count += 1;
+        }
+        section(&mut out, MapListItemType::DebugInfoItem, count, start);
+
+        // class data
+        let start = out.position();
+        let mut class_data_offsets = Vec::with_capacity(classes.len());
+        let mut code_offsets = code_offsets.into_iter();
+        for class in &classes {
+            if class.fields().next().is_none() && class.methods().next().is_none() {
+                class_data_offsets.push(0);
+                continue;
+            }
+            class_data_offsets.push(out.position() as UInt);
+            let item = ClassDataItem {
+                static_fields: encode_fields(&class.static_fields, &pools)?,
+                instance_fields: encode_fields(&class.instance_fields, &pools)?,
+                direct_methods: encode_methods(&class.direct_methods, &mut code_offsets, &pools)?,
+                virtual_methods: encode_methods(&class.virtual_methods, &mut code_offsets, &pools)?,
+            };
+            item.write(&mut out)?;
+        }
+        let count = class_data_offsets.iter().filter(|x| **x != 0).count();
+        section(&mut out, MapListItemType::ClassDataItem, count, start);
+
+        // static values
+        let start = out.position();
+        let mut static_values_offsets = Vec::with_capacity(classes.len());
+        for class in &classes {
+            match static_values(class, &pools)? {
+                Some(values) => {
+                    static_values_offsets.push(out.position() as UInt);
+                    values.write(&mut out)?;
+                }
+                None => static_values_offsets.push(0),
+            }
+        }
+        let count = static_values_offsets.iter().filter(|x| **x != 0).count();
+        section(&mut out, MapListItemType::EncodedArrayItem, count, start);
+
+        // annotations
+        let annotations = annotation::write(&mut out, &classes, &pools)?;
+        for (type_, size, start) in annotations.sections {
+            section(&mut out, type_, size, start);
+        }
+
+        // the map list closes the data section
+        align(&mut out, 4)?;
+        let map_off = out.position() as UInt;
+        let mut items = vec![MapListItem {
+            type_: UShort::from(MapListItemType::HeaderItem),
+            size: 1,
+            offset: 0,
+        }];
+        for (type_, size, offset) in [
+            (
+                MapListItemType::StringIdItem,
+                pools.strings.len(),
+                string_ids_off,
+            ),
+            (MapListItemType::TypeIdItem, pools.types.len(), type_ids_off),
+            (
+                MapListItemType::ProtoIdItem,
+                pools.protos.len(),
+                proto_ids_off,
+            ),
+            (
+                MapListItemType::FieldIdItem,
+                pools.fields.len(),
+                field_ids_off,
+            ),
+            (
+                MapListItemType::MethodIdItem,
+                pools.methods.len(),
+                method_ids_off,
+            ),
+            (MapListItemType::ClassDefItem, classes.len(), class_defs_off),
+        ] {
+            if size > 0 {
+                items.push(MapListItem {
+                    type_: UShort::from(type_),
+                    size: size as UInt,
+                    offset,
+                });
+            }
+        }
+        items.extend(map);
+        items.push(MapListItem {
+            type_: UShort::from(MapListItemType::MapList),
+            size: 1,
+            offset: map_off,
+        });
+        MapList::new(items).write(&mut out)?;
+        let file_size = out.position() as UInt;
+
+        // id sections
+        out.seek(SeekFrom::Start(string_ids_off as u64))?;
+        for offset in string_offsets {
+            StringIdItem { offset }.write(&mut out)?;
+        }
+        for type_ in &pools.types {
+            TypeIdItem {
+                descriptor_idx: pools.string_idx(type_)?,
+            }
+            .write(&mut out)?;
+        }
+        for (proto, parameters_off) in pools.protos.iter().zip(parameters_offsets) {
+            ProtoIdItem {
+                shorty_idx: pools.string_idx(&proto.shorty())?,
+                return_type_idx: pools.type_idx(&proto.return_type)?,
+                parameters_off,
+            }
+            .write(&mut out)?;
+        }
+        for field in &pools.fields {
+            FieldIdItem {
+                class_idx: pools.type_idx_u16(&field.class)?,
+                type_idx: pools.type_idx_u16(&field.type_)?,
+                name_idx: pools.string_idx(&field.name)?,
+            }
+            .write(&mut out)?;
+        }
+        for method in &pools.methods {
+            MethodIdItem {
+                class_idx: pools.type_idx_u16(&method.class)?,
+                proto_idx: pools.proto_idx_u16(&method.proto)?,
+                name_idx: pools.string_idx(&method.name)?,
+            }
+            .write(&mut out)?;
+        }
+        for (i, class) in classes.iter().enumerate() {
+            ClassDefItem {
+                class_idx: pools.type_idx(&class.descriptor)?,
+                access_flags: class.access_flags,
+                superclass_idx: match &class.superclass {
+                    Some(x) => pools.type_idx(x)?,
+                    None => NO_INDEX,
+                },
+                interfaces_off: interfaces_offsets[i],
+                source_file_idx: match &class.source_file {
+                    Some(x) => pools.string_idx(x)?,
+                    None => NO_INDEX,
+                },
+                annotations_off: annotations.directories[i],
+                class_data_off: class_data_offsets[i],
+                static_values_off: static_values_offsets[i],
+            }
+            .write(&mut out)?;
+        }
+
+        // header
+        out.seek(SeekFrom::Start(0))?;
+        let section_off = |size: usize, offset: UInt| if size > 0 { offset } else { 0 };
+        HeaderItem {
+            magic: Magic::new(self.version),
+            checksum: 0,
+            signature: [0; SIGNATURE_SIZE],
+            file_size,
+            header_size: HEADER_SIZE as UInt,
+            endian_tag: ENDIAN_CONSTANT,
+            link_size: 0,
+            link_off: 0,
+            map_off,
+            string_ids_size: pools.strings.len() as UInt,
+            string_ids_off: section_off(pools.strings.len(), string_ids_off),
+            type_ids_size: pools.types.len() as UInt,
+            type_ids_off: section_off(pools.types.len(), type_ids_off),
+            proto_ids_size: pools.protos.len() as UInt,
+            proto_ids_off: section_off(pools.protos.len(), proto_ids_off),
+            field_ids_size: pools.fields.len() as UInt,
+            field_ids_off: section_off(pools.fields.len(), field_ids_off),
+            method_ids_size: pools.methods.len() as UInt,
+            method_ids_off: section_off(pools.methods.len(), method_ids_off),
+            class_defs_size: classes.len() as UInt,
+            class_defs_off: section_off(classes.len(), class_defs_off),
+            data_size: file_size - data_off,
+            data_off,
+        }
+        .write(&mut out)?;
+
+        // the checksum covers the signature, so it has to be computed last
+        let mut data = out.into_inner();
+        let signature = HeaderItem::compute_signature(&data);
+        data[12..32].copy_from_slice(&signature);
+        let checksum = HeaderItem::compute_checksum(&data);
+        data[8..12].copy_from_slice(&checksum.to_le_bytes());
+        Ok(data)
+    }
+}
+
+/// Pads the output with zero bytes to the given alignment.
+fn align(out: &mut Cursor<Vec<UByte>>, alignment: u64) -> Result<()> {
+    let padding = (alignment - out.position() % alignment) % alignment;
+    out.write_all(&vec![0; padding as usize])?;
+    Ok(())
+}
+
+/// Writes a code item and returns how its instructions were moved if a
+/// `const-string` had to be widened to `const-string/jumbo`.
+fn write_code(
+    out: &mut Cursor<Vec<UByte>>,
+    code: &CodeDef,
+    pools: &Pools,
+) -> Result<Option<Remapped>> {
+    let mut insns = code.insns.clone();
+    // string indices of the instructions to widen, by their address
+    let mut jumbo = HashMap::new();
+    for code_ref in &code.refs {
+        let index = pools.item_idx(&code_ref.item)?;
+        let width = if code_ref.wide { 4 } else { 2 };
+        let opcode = code_ref.offset.checked_sub(2).and_then(|x| insns.get(x));
+        if !code_ref.wide && index > UShort::MAX as UInt && opcode == Some(&0x1A) {
+            jumbo.insert((code_ref.offset / 2 - 1) as UInt, index);
+            continue;
+        }
+        if !code_ref.wide && index > UShort::MAX as UInt {
+            return Err(Error::InvalidData(format!(
+                "index {} of {:?} does not fit into the instruction at {:#x}",
+                index, code_ref.item, code_ref.offset
+            )));
+        }
+        match insns.get_mut(code_ref.offset..code_ref.offset + width) {
+            Some(bytes) => bytes.copy_from_slice(&index.to_le_bytes()[..width]),
+            None => return Err(Error::InvalidOffset(code_ref.offset as isize)),
+        }
+    }
+    let mut item = CodeItem::new(code.registers_size, code.ins_size, code.outs_size, insns);
+    if !code.tries.is_empty() {
+        let (tries, handlers) = encode_tries(&item, &code.tries, pools)?;
+        item.set_tries(tries, Some(handlers));
+    }
+    let remapped = match jumbo.is_empty() {
+        true => None,
+        false => Some(remap_with(&mut item, |kind, address, index| match kind {
+            IndexKind::String => jumbo.get(&address).copied().unwrap_or(index),
+            _ => index,
+        })?),
+    };
+    item.write(out)?;
+    Ok(remapped)
+}
+
+/// The typed handlers and the catch-all address of a try block.
+type HandlerKey<'a> = (&'a [(String, UInt)], Option<UInt>);
+
+/// Encodes the try blocks of a code item. Try blocks with the same
+/// handlers share a single entry of the handler list.
+///
+/// All addresses must point to the start of an instruction, except for the
+/// end of a try block, which may also be the end of the code.
+fn encode_tries(
+    item: &CodeItem,
+    tries: &[TryDef],
+    pools: &Pools,
+) -> Result<(Vec<TryItem>, EncodedCatchHandlerList)> {
+    let offsets = insn_offsets(item)?;
+    let check = |addr: UInt, what: &str| -> Result<()> {
+        let offset = addr as usize * 2;
+        let is_end = what == "end" && offset == item.insns.len();
+        if !is_end && offsets.binary_search(&offset).is_err() {
+            return Err(Error::InvalidData(format!(
+                "{} of try block at {:#x} is not an instruction boundary",
+                what, offset
+            )));
+        }
+        Ok(())
+    };
+
+    let mut unique: Vec<HandlerKey> = Vec::new();
+    let mut indices = Vec::with_capacity(tries.len());
+    let mut prev_end = 0;
+    for try_def in tries {
+        let end = try_def.start_addr as u64 + try_def.insn_count as u64;
+        if try_def.insn_count == 0 || (try_def.start_addr as u64) < prev_end {
+            return Err(Error::InvalidData(format!(
+                "try block at {:#x} is empty, overlaps or is out of order",
+                try_def.start_addr as u64 * 2
+            )));
+        }
+        check(try_def.start_addr, "start")?;
+        check(end as UInt, "end")?;
+        for addr in try_def
+            .handlers
+            .iter()
+            .map(|x| x.1)
+            .chain(try_def.catch_all_addr)
+        {
+            check(addr, "handler")?;
+        }
+        prev_end = end;
+
+        let key = (try_def.handlers.as_slice(), try_def.catch_all_addr);
+        let index = match unique.iter().position(|x| *x == key) {
+            Some(index) => index,
+            None => {
+                unique.push(key);
+                unique.len() - 1
+            }
+        };
+        indices.push(index);
+    }
+
+    // handler offsets are relative to the start of the list, including
+    // its size
+    let size = ULeb128(unique.len() as UInt);
+    let mut buf = Cursor::new(Vec::new());
+    size.write_le(&mut buf)?;
+    let mut list = Vec::with_capacity(unique.len());
+    let mut handler_offsets = Vec::with_capacity(unique.len());
+    for (handlers, catch_all_addr) in unique {
+        let mut pairs = Vec::with_capacity(handlers.len());
+        for (type_, addr) in handlers {
+            pairs.push(EncodedTypeAddrPair {
+                type_idx: ULeb128(pools.type_idx(type_)?),
+                addr: ULeb128(*addr),
+            });
+        }
+        // a catch-all is indicated by a non-positive size
+        let count = pairs.len() as i32;
+        let count = match catch_all_addr {
+            Some(_) => -count,
+            None => count,
+        };
+        let handler = EncodedCatchHandler {
+            offset: UShort::try_from(buf.position())
+                .map_err(|_| Error::InvalidData("catch handlers exceed 64 KiB".to_string()))?,
+            size: SLeb128(count),
+            handlers: pairs,
+            catch_all_addr: catch_all_addr.map(ULeb128),
+        };
+        handler.write_le(&mut buf)?;
+        handler_offsets.push(handler.offset);
+        list.push(handler);
+    }
+
+    let tries = tries
+        .iter()
+        .zip(indices)
+        .map(|(try_def, index)| TryItem {
+            start_addr: try_def.start_addr,
+            insn_count: try_def.insn_count,
+            handler_off: handler_offsets[index],
+        })
+        .collect();
+    Ok((tries, EncodedCatchHandlerList { size, list }))
+}
+
+/// Returns the encoded fields sorted by their index.
+fn encode_fields(fields: &[FieldDef], pools: &Pools) -> Result<Vec<EncodedField>> {
+    let mut indices = Vec::with_capacity(fields.len());
+    for field in fields {
+        indices.push((pools.field_idx(&field.field)?, field.access_flags));
+    }
+    indices.sort_by_key(|(index, _)| *index);
+
+    let mut prev = 0;
+    let mut encoded = Vec::with_capacity(indices.len());
+    for (i, (index, access_flags)) in indices.into_iter().enumerate() {
+        if i > 0 && index == prev {
+            return Err(Error::InvalidData(format!(
+                "field {} is defined twice",
+                index
+            )));
+        }
+        encoded.push(EncodedField {
+            field_idx_diff: ULeb128(index - prev),
+            access_flags: ULeb128(access_flags),
+        });
+        prev = index;
+    }
+    Ok(encoded)
+}
+
+/// Returns the encoded methods sorted by their index. Code offsets are
+/// taken from `code_offsets` in the order the methods are defined.
+fn encode_methods(
+    methods: &[MethodDef],
+    code_offsets: &mut impl Iterator<Item = UInt>,
+    pools: &Pools,
+) -> Result<Vec<EncodedMethod>> {
+    let mut indices = Vec::with_capacity(methods.len());
+    for method in methods {
+        let code_off = code_offsets.next().unwrap_or_default();
+        indices.push((
+            pools.method_idx(&method.method)?,
+            method.access_flags,
+            code_off,
+        ));
+    }
+    indices.sort_by_key(|(index, _, _)| *index);
+
+    let mut prev = 0;
+    let mut encoded = Vec::with_capacity(indices.len());
+    for (i, (index, access_flags, code_off)) in indices.into_iter().enumerate() {
+        if i > 0 && index == prev {
+            return Err(Error::InvalidData(format!(
+                "method {} is defined twice",
+                index
+            )));
+        }
+        encoded.push(EncodedMethod {
+            method_idx_diff: ULeb128(index - prev),
+            access_flags: ULeb128(access_flags),
+            code_off: ULeb128(code_off),
+        });
+        prev = index;
+    }
+    Ok(encoded)
+}
+
+/// Returns the initial values of all static fields in the order of their
+/// field index. Trailing default values are omitted.
+fn static_values(class: &ClassDef, pools: &Pools) -> Result<Option<EncodedArray>> {
+    let mut fields = Vec::with_capacity(class.static_fields.len());
+    for field in &class.static_fields {
+        fields.push((pools.field_idx(&field.field)?, field));
+    }
+    fields.sort_by_key(|(index, _)| *index);
+
+    let Some(last) = fields.iter().rposition(|(_, x)| x.init_value.is_some()) else {
+        return Ok(None);
+    };
+    let mut values = Vec::with_capacity(last + 1);
+    for (_, field) in &fields[..=last] {
+        values.push(match &field.init_value {
+            Some(value) => pools.encode_value(value)?,
+            None => pools.encode_value(&Value::default_for(&field.field.type_))?,
+        });
+    }
+    Ok(Some(EncodedArray { values }))
+}
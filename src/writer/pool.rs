@@ -0,0 +1,339 @@
+//! Id pools of a DEX file that is being written
+//!
+//! Pools are sorted according to the rules in [layout](super::layout).
+
+use std::collections::{HashMap, HashSet};
+
+use crate::dalvik::{
+    dex::{
+        AnnotationElement, EncodedAnnotation, EncodedArray, EncodedValue, UInt, ULeb128, UShort,
+    },
+    error::{Error, Result},
+};
+
+use super::{
+    layout::{compare_fields, compare_methods, compare_protos, compare_strings},
+    Annotation, ClassDef, FieldRef, ItemRef, MethodRef, ProtoRef, Value, MAX_IDS,
+};
+
+/// Collects all items referenced by a set of classes.
+#[derive(Default)]
+pub(super) struct PoolsBuilder {
+    strings: HashSet<String>,
+    types: HashSet<String>,
+    protos: HashSet<ProtoRef>,
+    fields: HashSet<FieldRef>,
+    methods: HashSet<MethodRef>,
+}
+
+impl PoolsBuilder {
+    pub fn add_class(&mut self, class: &ClassDef) {
+        self.add_type(&class.descriptor);
+        for type_ in class.superclass.iter().chain(&class.interfaces) {
+            self.add_type(type_);
+        }
+        if let Some(source_file) = &class.source_file {
+            self.add_string(source_file);
+        }
+        for def in &class.annotations {
+            self.add_annotation(&def.annotation);
+        }
+        for field in class.fields() {
+            self.add_field(&field.field);
+            if let Some(value) = &field.init_value {
+                self.add_value(value);
+            }
+            for def in &field.annotations {
+                self.add_annotation(&def.annotation);
+            }
+        }
+        for method in class.methods() {
+            self.add_method(&method.method);
+            let parameters = method.parameter_annotations.iter().flatten();
+            for def in method.annotations.iter().chain(parameters) {
+                self.add_annotation(&def.annotation);
+            }
+            for code_ref in method.code.iter().flat_map(|x| &x.refs) {
+                self.add_item(&code_ref.item);
+            }
+            let tries = method.code.iter().flat_map(|x| &x.tries);
+            for (type_, _) in tries.flat_map(|x| &x.handlers) {
+                self.add_type(type_);
+            }
+            if let Some(debug_info) = method.code.as_ref().and_then(|x| x.debug_info.as_ref()) {
+                debug_info.strings().for_each(|x| self.add_string(x));
+                debug_info.types().for_each(|x| self.add_type(x));
+            }
+        }
+    }
+
+    pub fn add_string(&mut self, value: &str) {
+        if !self.strings.contains(value) {
+            self.strings.insert(value.to_string());
+        }
+    }
+
+    pub fn add_type(&mut self, descriptor: &str) {
+        self.add_string(descriptor);
+        if !self.types.contains(descriptor) {
+            self.types.insert(descriptor.to_string());
+        }
+    }
+
+    pub fn add_proto(&mut self, proto: &ProtoRef) {
+        self.add_string(&proto.shorty());
+        self.add_type(&proto.return_type);
+        for parameter in &proto.parameters {
+            self.add_type(parameter);
+        }
+        if !self.protos.contains(proto) {
+            self.protos.insert(proto.clone());
+        }
+    }
+
+    pub fn add_field(&mut self, field: &FieldRef) {
+        self.add_type(&field.class);
+        self.add_type(&field.type_);
+        self.add_string(&field.name);
+        if !self.fields.contains(field) {
+            self.fields.insert(field.clone());
+        }
+    }
+
+    pub fn add_method(&mut self, method: &MethodRef) {
+        self.add_type(&method.class);
+        self.add_proto(&method.proto);
+        self.add_string(&method.name);
+        if !self.methods.contains(method) {
+            self.methods.insert(method.clone());
+        }
+    }
+
+    pub fn add_item(&mut self, item: &ItemRef) {
+        match item {
+            ItemRef::String(x) => self.add_string(x),
+            ItemRef::Type(x) => self.add_type(x),
+            ItemRef::Field(x) => self.add_field(x),
+            ItemRef::Method(x) => self.add_method(x),
+            ItemRef::Proto(x) => self.add_proto(x),
+        }
+    }
+
+    pub fn add_value(&mut self, value: &Value) {
+        match value {
+            Value::String(x) => self.add_string(x),
+            Value::Type(x) => self.add_type(x),
+            Value::Field(x) | Value::Enum(x) => self.add_field(x),
+            Value::Method(x) => self.add_method(x),
+            Value::MethodType(x) => self.add_proto(x),
+            Value::Array(values) => values.iter().for_each(|x| self.add_value(x)),
+            Value::Annotation(x) => self.add_annotation(x),
+            _ => {}
+        }
+    }
+
+    pub fn add_annotation(&mut self, annotation: &Annotation) {
+        self.add_type(&annotation.type_);
+        for (name, value) in &annotation.elements {
+            self.add_string(name);
+            self.add_value(value);
+        }
+    }
+
+    /// Returns the number of collected types, prototypes, fields and
+    /// methods, which are limited to [MAX_IDS] each.
+    pub fn id_counts(&self) -> [usize; 4] {
+        [
+            self.types.len(),
+            self.protos.len(),
+            self.fields.len(),
+            self.methods.len(),
+        ]
+    }
+
+    /// Returns the number of types, prototypes, fields and methods
+    /// collected by `other` that this builder doesn't contain yet.
+    pub fn missing_id_counts(&self, other: &PoolsBuilder) -> [usize; 4] {
+        [
+            other.types.difference(&self.types).count(),
+            other.protos.difference(&self.protos).count(),
+            other.fields.difference(&self.fields).count(),
+            other.methods.difference(&self.methods).count(),
+        ]
+    }
+
+    /// Adds all items collected by `other`.
+    pub fn extend(&mut self, other: PoolsBuilder) {
+        self.strings.extend(other.strings);
+        self.types.extend(other.types);
+        self.protos.extend(other.protos);
+        self.fields.extend(other.fields);
+        self.methods.extend(other.methods);
+    }
+
+    /// Sorts all collected items and assigns their indices.
+    pub fn finish(self) -> Result<Pools> {
+        let mut strings: Vec<String> = self.strings.into_iter().collect();
+        strings.sort_by(|a, b| compare_strings(a, b));
+        let mut types: Vec<String> = self.types.into_iter().collect();
+        types.sort_by(|a, b| compare_strings(a, b));
+        let mut protos: Vec<ProtoRef> = self.protos.into_iter().collect();
+        protos.sort_by(compare_protos);
+        let mut fields: Vec<FieldRef> = self.fields.into_iter().collect();
+        fields.sort_by(compare_fields);
+        let mut methods: Vec<MethodRef> = self.methods.into_iter().collect();
+        methods.sort_by(compare_methods);
+
+        // field and method ids store 16-bit type and proto indices, and
+        // instructions only address 16-bit field and method indices
+        for (kind, size) in [
+            ("types", types.len()),
+            ("prototypes", protos.len()),
+            ("fields", fields.len()),
+            ("methods", methods.len()),
+        ] {
+            if size > MAX_IDS {
+                return Err(Error::InvalidData(format!(
+                    "too many {}: {} (at most {} can be referenced)",
+                    kind, size, MAX_IDS
+                )));
+            }
+        }
+
+        Ok(Pools {
+            string_ids: index_map(&strings),
+            type_ids: index_map(&types),
+            proto_ids: index_map(&protos),
+            field_ids: index_map(&fields),
+            method_ids: index_map(&methods),
+            strings,
+            types,
+            protos,
+            fields,
+            methods,
+        })
+    }
+}
+
+/// Sorted id pools of a DEX file.
+pub(super) struct Pools {
+    pub strings: Vec<String>,
+    pub types: Vec<String>,
+    pub protos: Vec<ProtoRef>,
+    pub fields: Vec<FieldRef>,
+    pub methods: Vec<MethodRef>,
+    string_ids: HashMap<String, UInt>,
+    type_ids: HashMap<String, UInt>,
+    proto_ids: HashMap<ProtoRef, UInt>,
+    field_ids: HashMap<FieldRef, UInt>,
+    method_ids: HashMap<MethodRef, UInt>,
+}
+
+macro_rules! lookup {
+    ($name:ident, $map:ident, $type:ty, $kind:literal) => {
+        pub fn $name(&self, value: &$type) -> Result<UInt> {
+            match self.$map.get(value) {
+                Some(index) => Ok(*index),
+                None => Err(Error::InvalidData(format!(
+                    "{} {:?} is not part of the pool",
+                    $kind, value
+                ))),
+            }
+        }
+    };
+}
+
+impl Pools {
+    lookup!(string_idx, string_ids, str, "string");
+    lookup!(type_idx, type_ids, str, "type");
+    lookup!(proto_idx, proto_ids, ProtoRef, "prototype");
+    lookup!(field_idx, field_ids, FieldRef, "field");
+    lookup!(method_idx, method_ids, MethodRef, "method");
+
+    /// Same as [Pools::type_idx], but the pool size has already been
+    /// checked to fit into 16 bits.
+    pub fn type_idx_u16(&self, descriptor: &str) -> Result<UShort> {
+        Ok(self.type_idx(descriptor)? as UShort)
+    }
+
+    pub fn proto_idx_u16(&self, proto: &ProtoRef) -> Result<UShort> {
+        Ok(self.proto_idx(proto)? as UShort)
+    }
+
+    pub fn item_idx(&self, item: &ItemRef) -> Result<UInt> {
+        match item {
+            ItemRef::String(x) => self.string_idx(x),
+            ItemRef::Type(x) => self.type_idx(x),
+            ItemRef::Field(x) => self.field_idx(x),
+            ItemRef::Method(x) => self.method_idx(x),
+            ItemRef::Proto(x) => self.proto_idx(x),
+        }
+    }
+
+    /// Converts a value into its encoded form.
+    pub fn encode_value(&self, value: &Value) -> Result<EncodedValue> {
+        Ok(match value {
+            Value::Byte(x) => EncodedValue::Byte(*x),
+            Value::Short(x) => EncodedValue::Short(*x),
+            Value::Char(x) => EncodedValue::Char(*x),
+            Value::Int(x) => EncodedValue::Int(*x),
+            Value::Long(x) => EncodedValue::Long(*x),
+            Value::Float(x) => EncodedValue::Float(*x),
+            Value::Double(x) => EncodedValue::Double(*x),
+            Value::String(x) => EncodedValue::String(self.string_idx(x)?),
+            Value::Type(x) => EncodedValue::Type(self.type_idx(x)?),
+            Value::Field(x) => EncodedValue::Field(self.field_idx(x)?),
+            Value::Method(x) => EncodedValue::Method(self.method_idx(x)?),
+            Value::Enum(x) => EncodedValue::Enum(self.field_idx(x)?),
+            Value::MethodType(x) => EncodedValue::MethodType(self.proto_idx(x)?),
+            Value::Array(values) => {
+                let mut encoded = Vec::with_capacity(values.len());
+                for value in values {
+                    encoded.push(self.encode_value(value)?);
+                }
+                EncodedValue::Array(EncodedArray { values: encoded })
+            }
+            Value::Annotation(x) => EncodedValue::Annotation(self.encode_annotation(x)?),
+            Value::Boolean(true) => EncodedValue::True,
+            Value::Boolean(false) => EncodedValue::False,
+            Value::Null => EncodedValue::Null,
+        })
+    }
+}
+
+impl Pools {
+    /// Converts an annotation into its encoded form, with its elements
+    /// sorted by name.
+    pub fn encode_annotation(&self, annotation: &Annotation) -> Result<EncodedAnnotation> {
+        let mut elements = Vec::with_capacity(annotation.elements.len());
+        for (name, value) in &annotation.elements {
+            let name_idx = self.string_idx(name)?;
+            if elements
+                .iter()
+                .any(|x: &AnnotationElement| x.name_idx.0 == name_idx)
+            {
+                return Err(Error::InvalidData(format!(
+                    "element {} of annotation {} is defined twice",
+                    name, annotation.type_
+                )));
+            }
+            elements.push(AnnotationElement {
+                name_idx: ULeb128(name_idx),
+                value: self.encode_value(value)?,
+            });
+        }
+        elements.sort_by_key(|x| x.name_idx.0);
+        Ok(EncodedAnnotation {
+            type_idx: ULeb128(self.type_idx(&annotation.type_)?),
+            elements,
+        })
+    }
+}
+
+fn index_map<T: Clone + Eq + std::hash::Hash>(values: &[T]) -> HashMap<T, UInt> {
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (x.clone(), i as UInt))
+        .collect()
+}
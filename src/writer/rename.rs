@@ -0,0 +1,521 @@
+//! Renaming of classes and members
+//!
+//! A [Renamer] decides on the new names of classes, fields and methods. All
+//! classes of a file are converted into the writer model and every
+//! reference to a renamed class is rewritten: descriptors in class
+//! definitions, prototypes, instructions, static values, annotations and
+//! debug information, the generic signatures stored in
+//! `dalvik.annotation.Signature`, the simple names of inner classes and the
+//! source files of renamed classes. The limitations of [ClassDef::from_dex]
+//! apply to every renamed class.
+//!
+//! A reference to a field or method may name a subclass of the class that
+//! declares it. Such references are resolved through the superclasses and
+//! interfaces of the renamed classes first, so that the [Renamer] is
+//! always asked for the declared member. Members of classes outside of the
+//! file, e.g. of the Android framework, keep their names.
+//!
+//! A [Mapping] renames everything back to its original name, which
+//! normalizes obfuscated samples before they are compared:
+//!
+//! ```ignore
+//! let mapping = Mapping::parse(&std::fs::read_to_string("mapping.txt")?)?;
+//! std::fs::write("deobfuscated.dex", rename::rename(&mut dex, &mapping)?)?;
+//! ```
+//!
+//! Strings that name classes or members, e.g. for reflection, and the
+//! element names of annotations are kept.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    dalvik::{error::Result, file::IDexRef},
+    mapping::Mapping,
+};
+
+use super::{
+    merge::{merge_into, Duplicates},
+    Annotation, AnnotationDef, ClassDef, CodeDef, DebugEvent, DexBuilder, FieldRef, ItemRef,
+    MethodRef, ProtoRef, Value,
+};
+
+/// Descriptor of the annotation storing generic signatures.
+const SIGNATURE: &str = "Ldalvik/annotation/Signature;";
+
+/// Descriptor of the annotation storing the simple name of an inner class.
+const INNER_CLASS: &str = "Ldalvik/annotation/InnerClass;";
+
+/// Decides on the new names of classes and members. All arguments use the
+/// names before renaming.
+pub trait Renamer {
+    /// Returns the new descriptor of a class, e.g. `Lcom/example/Foo;` for
+    /// `La/b;`, or `None` if the class keeps its name. Array types are
+    /// renamed through their element type.
+    fn class(&self, descriptor: &str) -> Option<String>;
+
+    /// Returns the new name of a field, or `None` if it keeps its name.
+    /// The class of the field is the class that declares it.
+    fn field(&self, _field: &FieldRef) -> Option<String> {
+        None
+    }
+
+    /// Returns the new name of a method, or `None` if it keeps its name.
+    /// The class of the method is the class that declares it.
+    fn method(&self, _method: &MethodRef) -> Option<String> {
+        None
+    }
+
+    /// Returns the new source file of a class that was renamed from
+    /// `descriptor` to `renamed`. By default, the file is named after the
+    /// new top-level class and keeps the extension of the old file, e.g.
+    /// `Foo.java` for `Lcom/example/Foo$Bar;`.
+    fn source_file(&self, _descriptor: &str, renamed: &str, source_file: &str) -> Option<String> {
+        let name = renamed.trim_end_matches(';');
+        let name = name.rsplit('/').next().unwrap_or(name);
+        let name = name.split('$').next().unwrap_or(name);
+        let extension = match source_file.rsplit_once('.') {
+            Some((_, extension)) => extension,
+            None => "java",
+        };
+        Some(format!("{}.{}", name, extension))
+    }
+}
+
+/// Renames classes by their descriptors, members keep their names.
+impl Renamer for HashMap<String, String> {
+    fn class(&self, descriptor: &str) -> Option<String> {
+        self.get(descriptor).cloned()
+    }
+}
+
+/// Renames classes and members back to their original names. Members are
+/// only renamed if their type or prototype matches the mapping, so that
+/// overloads keep their own names.
+impl Renamer for Mapping {
+    fn class(&self, descriptor: &str) -> Option<String> {
+        Mapping::class(self, descriptor).map(|x| x.original.clone())
+    }
+
+    fn field(&self, field: &FieldRef) -> Option<String> {
+        Mapping::exact_field(self, &field.class, &field.name, &field.type_)
+            .map(|x| x.original.clone())
+    }
+
+    fn method(&self, method: &MethodRef) -> Option<String> {
+        let proto = format!(
+            "({}){}",
+            method.proto.parameters.concat(),
+            method.proto.return_type
+        );
+        Mapping::exact_method(self, &method.class, &method.name, &proto).map(|x| x.original.clone())
+    }
+}
+
+/// Renames all classes of the given DEX file and builds a new file of the
+/// same version.
+pub fn rename<R: Renamer>(dex: IDexRef<'_>, renamer: &R) -> Result<Vec<u8>> {
+    let mut builder = DexBuilder::new();
+    merge_into(&mut builder, dex, Duplicates::Fail)?;
+    rename_classes(&mut builder, renamer)?;
+    builder.build()
+}
+
+/// Renames all classes of the given builder. Fails if two classes end up
+/// with the same name.
+pub fn rename_classes<R: Renamer>(builder: &mut DexBuilder, renamer: &R) -> Result<()> {
+    let declarations = Declarations::new(builder.classes());
+    for mut class in builder.take_classes() {
+        rename_with(&mut class, renamer, &declarations);
+        builder.add_class(class)?;
+    }
+    Ok(())
+}
+
+/// Renames a single class and all references within it. Inherited members
+/// can't be resolved without the other classes, so references to them are
+/// renamed as if the referenced class declared them; use [rename_classes]
+/// to resolve them.
+pub fn rename_class<R: Renamer>(class: &mut ClassDef, renamer: &R) {
+    let declarations = Declarations::new(std::slice::from_ref(class));
+    rename_with(class, renamer, &declarations);
+}
+
+fn rename_with<R: Renamer>(class: &mut ClassDef, renamer: &R, declarations: &Declarations) {
+    let renaming = Renaming {
+        renamer,
+        declarations,
+    };
+    let descriptor = renaming.type_(&class.descriptor);
+    if descriptor != class.descriptor {
+        if let Some(source_file) = class.source_file.take() {
+            class.source_file = Some(
+                renamer
+                    .source_file(&class.descriptor, &descriptor, &source_file)
+                    .unwrap_or(source_file),
+            );
+        }
+        // the simple name of an inner class is part of its annotation
+        let name = descriptor.trim_end_matches(';');
+        let name = name.rsplit(['/', '$']).next().unwrap_or(name);
+        for annotation in &mut class.annotations {
+            if annotation.annotation.type_ != INNER_CLASS {
+                continue;
+            }
+            for (key, value) in &mut annotation.annotation.elements {
+                if key == "name" && matches!(value, Value::String(_)) {
+                    *value = Value::String(name.to_string());
+                }
+            }
+        }
+    }
+
+    class.descriptor = descriptor;
+    class.superclass = class.superclass.as_deref().map(|x| renaming.type_(x));
+    for interface in &mut class.interfaces {
+        *interface = renaming.type_(interface);
+    }
+    renaming.annotations(&mut class.annotations);
+
+    for field in class
+        .static_fields
+        .iter_mut()
+        .chain(&mut class.instance_fields)
+    {
+        field.field = renaming.field(&field.field);
+        if let Some(value) = &mut field.init_value {
+            renaming.value(value);
+        }
+        renaming.annotations(&mut field.annotations);
+    }
+    for method in class
+        .direct_methods
+        .iter_mut()
+        .chain(&mut class.virtual_methods)
+    {
+        method.method = renaming.method(&method.method);
+        if let Some(code) = &mut method.code {
+            renaming.code(code);
+        }
+        renaming.annotations(&mut method.annotations);
+        for annotations in &mut method.parameter_annotations {
+            renaming.annotations(annotations);
+        }
+    }
+}
+
+/// The members declared by the renamed classes.
+struct Declarations {
+    classes: HashMap<String, Declared>,
+}
+
+/// The supertypes and members of a single class.
+struct Declared {
+    superclass: Option<String>,
+    interfaces: Vec<String>,
+    fields: HashSet<(String, String)>,
+    methods: HashSet<(String, ProtoRef)>,
+}
+
+impl Declarations {
+    fn new(classes: &[ClassDef]) -> Self {
+        let classes = classes
+            .iter()
+            .map(|class| {
+                let declared = Declared {
+                    superclass: class.superclass.clone(),
+                    interfaces: class.interfaces.clone(),
+                    fields: (class.static_fields.iter())
+                        .chain(&class.instance_fields)
+                        .map(|x| (x.field.name.clone(), x.field.type_.clone()))
+                        .collect(),
+                    methods: (class.direct_methods.iter())
+                        .chain(&class.virtual_methods)
+                        .map(|x| (x.method.name.clone(), x.method.proto.clone()))
+                        .collect(),
+                };
+                (class.descriptor.clone(), declared)
+            })
+            .collect();
+        Declarations { classes }
+    }
+
+    /// Returns the class that declares the given field, or `None` if it
+    /// isn't declared by any of the classes.
+    fn field_owner(&self, field: &FieldRef) -> Option<&str> {
+        self.owner(&field.class, |x| {
+            x.fields
+                .contains(&(field.name.clone(), field.type_.clone()))
+        })
+    }
+
+    /// Returns the class that declares the given method, or `None` if it
+    /// isn't declared by any of the classes.
+    fn method_owner(&self, method: &MethodRef) -> Option<&str> {
+        self.owner(&method.class, |x| {
+            x.methods
+                .contains(&(method.name.clone(), method.proto.clone()))
+        })
+    }
+
+    /// Searches the given class, then its superclasses and then the
+    /// interfaces of all of them for a class matching `declares`.
+    fn owner(&self, class: &str, declares: impl Fn(&Declared) -> bool) -> Option<&str> {
+        // malformed files may contain cycles
+        let mut visited = HashSet::new();
+        let mut interfaces = Vec::new();
+        let mut current = Some(class);
+        while let Some(class) = current
+            && visited.insert(class)
+        {
+            // superclasses outside of the file end the chain
+            let Some((descriptor, declared)) = self.classes.get_key_value(class) else {
+                break;
+            };
+            if declares(declared) {
+                return Some(descriptor);
+            }
+            interfaces.extend(declared.interfaces.iter().map(String::as_str));
+            current = declared.superclass.as_deref();
+        }
+        interfaces.reverse();
+        while let Some(class) = interfaces.pop() {
+            if !visited.insert(class) {
+                continue;
+            }
+            let Some((descriptor, declared)) = self.classes.get_key_value(class) else {
+                continue;
+            };
+            if declares(declared) {
+                return Some(descriptor);
+            }
+            interfaces.extend(declared.interfaces.iter().rev().map(String::as_str));
+        }
+        None
+    }
+}
+
+/// Applies a [Renamer] to references.
+struct Renaming<'a, R> {
+    renamer: &'a R,
+    declarations: &'a Declarations,
+}
+
+impl<R: Renamer> Renaming<'_, R> {
+    fn type_(&self, descriptor: &str) -> String {
+        let element = descriptor.trim_start_matches('[');
+        match self.renamer.class(element) {
+            Some(renamed) => descriptor[..descriptor.len() - element.len()].to_string() + &renamed,
+            None => descriptor.to_string(),
+        }
+    }
+
+    fn proto(&self, proto: &ProtoRef) -> ProtoRef {
+        ProtoRef {
+            return_type: self.type_(&proto.return_type),
+            parameters: proto.parameters.iter().map(|x| self.type_(x)).collect(),
+        }
+    }
+
+    fn field(&self, field: &FieldRef) -> FieldRef {
+        let declared = FieldRef {
+            class: (self.declarations.field_owner(field))
+                .unwrap_or(&field.class)
+                .to_string(),
+            ..field.clone()
+        };
+        FieldRef {
+            class: self.type_(&field.class),
+            name: self
+                .renamer
+                .field(&declared)
+                .unwrap_or_else(|| field.name.clone()),
+            type_: self.type_(&field.type_),
+        }
+    }
+
+    fn method(&self, method: &MethodRef) -> MethodRef {
+        let declared = MethodRef {
+            class: (self.declarations.method_owner(method))
+                .unwrap_or(&method.class)
+                .to_string(),
+            ..method.clone()
+        };
+        MethodRef {
+            class: self.type_(&method.class),
+            name: self
+                .renamer
+                .method(&declared)
+                .unwrap_or_else(|| method.name.clone()),
+            proto: self.proto(&method.proto),
+        }
+    }
+
+    fn code(&self, code: &mut CodeDef) {
+        for code_ref in &mut code.refs {
+            code_ref.item = match &code_ref.item {
+                ItemRef::String(x) => ItemRef::String(x.clone()),
+                ItemRef::Type(x) => ItemRef::Type(self.type_(x)),
+                ItemRef::Field(x) => ItemRef::Field(self.field(x)),
+                ItemRef::Method(x) => ItemRef::Method(self.method(x)),
+                ItemRef::Proto(x) => ItemRef::Proto(self.proto(x)),
+            };
+        }
+        for try_ in &mut code.tries {
+            for (type_, _) in &mut try_.handlers {
+                *type_ = self.type_(type_);
+            }
+        }
+        let Some(debug_info) = &mut code.debug_info else {
+            return;
+        };
+        for event in &mut debug_info.events {
+            if let DebugEvent::StartLocal {
+                type_, signature, ..
+            } = event
+            {
+                *type_ = type_.as_deref().map(|x| self.type_(x));
+                *signature = signature.as_deref().map(|x| self.signature(x).concat());
+            }
+        }
+    }
+
+    fn annotations(&self, annotations: &mut [AnnotationDef]) {
+        for annotation in annotations {
+            if annotation.annotation.type_ == SIGNATURE {
+                self.signature_annotation(&mut annotation.annotation);
+            } else {
+                self.annotation(&mut annotation.annotation);
+            }
+        }
+    }
+
+    fn annotation(&self, annotation: &mut Annotation) {
+        annotation.type_ = self.type_(&annotation.type_);
+        for (_, value) in &mut annotation.elements {
+            self.value(value);
+        }
+    }
+
+    fn value(&self, value: &mut Value) {
+        match value {
+            Value::Type(x) => *x = self.type_(x),
+            Value::Field(x) | Value::Enum(x) => *x = self.field(x),
+            Value::Method(x) => *x = self.method(x),
+            Value::MethodType(x) => *x = self.proto(x),
+            Value::Array(values) => values.iter_mut().for_each(|x| self.value(x)),
+            Value::Annotation(x) => self.annotation(x),
+            _ => {}
+        }
+    }
+
+    /// Rewrites the signature stored in the `value` array of a
+    /// `dalvik.annotation.Signature`. The signature is split again, so that
+    /// every class name is a string of its own.
+    fn signature_annotation(&self, annotation: &mut Annotation) {
+        for (key, value) in &mut annotation.elements {
+            let Value::Array(parts) = value else {
+                continue;
+            };
+            if key != "value" {
+                continue;
+            }
+            let mut signature = String::new();
+            for part in parts.iter() {
+                match part {
+                    Value::String(x) => signature.push_str(x),
+                    // not a signature written by a compiler
+                    _ => return,
+                }
+            }
+            *parts = self
+                .signature(&signature)
+                .into_iter()
+                .map(Value::String)
+                .collect();
+        }
+    }
+
+    /// Renames all classes within a generic signature, e.g.
+    /// `Ljava/util/List<La/b;>;`, and returns it split in front of and
+    /// after every class name.
+    fn signature(&self, signature: &str) -> Vec<String> {
+        let mut parts = vec![String::new()];
+        // the names before and after renaming of all classes whose type
+        // arguments or inner classes follow
+        let mut outer: Vec<(String, String)> = Vec::new();
+        let mut rest = signature;
+        while let Some(c) = rest.chars().next() {
+            let end = rest.find([';', '<', '.', ':', '>']).unwrap_or(rest.len());
+            let (run, terminator) = (&rest[..end], rest[end..].chars().next());
+            match c {
+                // the identifier of a type parameter
+                _ if end > 0 && terminator == Some(':') => {
+                    parts.last_mut().unwrap().push_str(run);
+                    rest = &rest[end..];
+                }
+                'L' => {
+                    let renamed = self.type_(&format!("{};", run));
+                    let renamed = renamed.trim_end_matches(';');
+                    if !parts.last().unwrap().is_empty() {
+                        parts.push(String::new());
+                    }
+                    let part = parts.last_mut().unwrap();
+                    part.push_str(renamed);
+                    match terminator {
+                        Some(terminator @ (';' | '<')) => {
+                            part.push(terminator);
+                            parts.push(String::new());
+                            if terminator == '<' {
+                                outer.push((run.to_string(), renamed.to_string()));
+                            }
+                            rest = &rest[end + 1..];
+                        }
+                        // an inner class follows
+                        _ => {
+                            outer.push((run.to_string(), renamed.to_string()));
+                            rest = &rest[end..];
+                        }
+                    }
+                }
+                // a type variable
+                'T' => {
+                    let end = rest.find(';').map_or(rest.len(), |x| x + 1);
+                    parts.last_mut().unwrap().push_str(&rest[..end]);
+                    rest = &rest[end..];
+                }
+                // the end of a class type with type arguments or inner
+                // classes
+                ';' => {
+                    outer.pop();
+                    parts.last_mut().unwrap().push(c);
+                    rest = &rest[1..];
+                }
+                '.' if !outer.is_empty() => {
+                    let end = rest[1..]
+                        .find([';', '<', '.'])
+                        .map_or(rest.len(), |x| x + 1);
+                    let inner = &rest[1..end];
+                    let (original, renamed) = outer.last_mut().unwrap();
+                    *original = format!("{}${}", original, inner);
+                    let name = self.type_(&format!("{};", original));
+                    let name = name.trim_end_matches(';');
+                    let simple = match name.strip_prefix(&format!("{}$", renamed)) {
+                        Some(simple) => simple,
+                        None => name.rsplit(['/', '$']).next().unwrap_or(name),
+                    };
+                    let part = parts.last_mut().unwrap();
+                    part.push('.');
+                    part.push_str(simple);
+                    *renamed = name.to_string();
+                    rest = &rest[end..];
+                }
+                _ => {
+                    parts.last_mut().unwrap().push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+        parts.retain(|x| !x.is_empty());
+        parts
+    }
+}
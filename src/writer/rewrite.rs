@@ -0,0 +1,169 @@
+//! Re-serialization of existing DEX files
+//!
+//! [roundtrip] reads every item listed in the map list of a file, encodes it
+//! again and places it at its original offset. Padding and bytes that are
+//! not covered by the map list are copied as they are, so an unmodified file
+//! is reproduced byte for byte, including the original section order. An
+//! item whose encoding differs from the input (e.g. because it uses
+//! non-minimal LEB128 values) is reported as an error, which makes this a
+//! check of the encoders as well as the base for edits that leave the rest
+//! of a file untouched.
+//!
+//! ```ignore
+//! let data = rewrite::roundtrip(&mut dex)?;
+//! assert_eq!(data, std::fs::read("classes.dex")?);
+//! ```
+
+use binrw::{BinRead, BinWrite};
+use std::io::{Cursor, Read, Seek, Write};
+
+use crate::dalvik::{
+    dex::*,
+    error::{Error, Result},
+    file::Dex,
+};
+
+/// Re-serializes the given file item by item.
+pub fn roundtrip<R: Read + Seek>(dex: &mut Dex<'_, R>) -> Result<Vec<UByte>> {
+    let input = dex.read_raw(0..dex.header.file_size as u64)?;
+    let mut sections: Vec<(MapListItemType, UInt, usize)> = dex
+        .get_map_list()
+        .items()
+        .iter()
+        .map(|x| (x.item_type(), x.size, x.offset as usize))
+        .collect();
+    sections.sort_by_key(|(_, _, offset)| *offset);
+
+    let mut reader = Cursor::new(input.as_slice());
+    let mut out = Vec::with_capacity(input.len());
+    for (type_, size, offset) in sections {
+        if offset < out.len() || offset > input.len() {
+            return Err(Error::InvalidData(format!(
+                "{:?} at {:#x} overlaps the previous section",
+                type_, offset
+            )));
+        }
+        reader.set_position(offset as u64);
+        for _ in 0..size {
+            if is_aligned(type_) {
+                reader.set_position(reader.position().next_multiple_of(4));
+            }
+            // padding before the item
+            let start = reader.position() as usize;
+            match input.get(out.len()..start) {
+                Some(padding) => out.extend_from_slice(padding),
+                None => return Err(Error::InvalidOffset(start as isize)),
+            }
+
+            let item = encode_item(type_, &mut reader)?;
+            let end = reader.position() as usize;
+            if input[start..end] != item[..] {
+                return Err(Error::InvalidData(format!(
+                    "{:?} at {:#x} is not reproduced by its encoder",
+                    type_, start
+                )));
+            }
+            out.extend_from_slice(&item);
+        }
+    }
+    out.extend_from_slice(&input[out.len()..]);
+    Ok(out)
+}
+
+/// Whether items of the given type start at 4-byte boundaries.
+pub(crate) fn is_aligned(type_: MapListItemType) -> bool {
+    !matches!(
+        type_,
+        MapListItemType::ClassDataItem
+            | MapListItemType::StringDataItem
+            | MapListItemType::DebugInfoItem
+            | MapListItemType::AnnotationItem
+            | MapListItemType::EncodedArrayItem
+            | MapListItemType::HiddenApiListClassDataItem
+    )
+}
+
+/// Reads a single item at the current position and encodes it again.
+fn encode_item(type_: MapListItemType, reader: &mut Cursor<&[UByte]>) -> Result<Vec<UByte>> {
+    let mut out = Cursor::new(Vec::new());
+    macro_rules! reencode {
+        ($item:ty) => {
+            <$item>::read(reader)?.write(&mut out)?
+        };
+    }
+
+    match type_ {
+        MapListItemType::HeaderItem => reencode!(HeaderItem),
+        MapListItemType::StringIdItem => reencode!(StringIdItem),
+        MapListItemType::TypeIdItem => reencode!(TypeIdItem),
+        MapListItemType::ProtoIdItem => reencode!(ProtoIdItem),
+        MapListItemType::FieldIdItem => reencode!(FieldIdItem),
+        MapListItemType::MethodIdItem => reencode!(MethodIdItem),
+        MapListItemType::ClassDefItem => reencode!(ClassDefItem),
+        MapListItemType::CallSiteIdItem => reencode!(CallSiteIdItem),
+        MapListItemType::MethodHandleItem => reencode!(MethodHandleItem),
+        MapListItemType::MapList => reencode!(MapList),
+        MapListItemType::AnnotationSetRefList => reencode!(AnnotationSetRefList),
+        MapListItemType::AnnotationSetItem => reencode!(AnnotationSetItem),
+        MapListItemType::ClassDataItem => reencode!(ClassDataItem),
+        MapListItemType::CodeItem => reencode!(CodeItem),
+        MapListItemType::AnnotationItem => reencode!(AnnotationItem),
+        MapListItemType::EncodedArrayItem => reencode!(EncodedArrayItem),
+        MapListItemType::AnnotationsDirectoryItem => reencode!(AnnotationsDirectoryItem),
+        MapListItemType::HiddenApiListClassDataItem => reencode!(HiddenAPIClassDataItem),
+        MapListItemType::StringDataItem => {
+            let value = StringDataItem::read(reader)?.data.unwrap_or_default();
+            let (utf16_size, bytes) = mutf8::encode(&value);
+            ULeb128(utf16_size).write_le(&mut out)?;
+            out.write_all(&bytes)?;
+            out.write_all(&[0])?;
+        }
+        MapListItemType::TypeList => {
+            // [TypeList] pads its end to four bytes, which is not part of
+            // the item if an unaligned section follows directly
+            let size = UInt::read_le(reader)?;
+            size.write_le(&mut out)?;
+            for _ in 0..size {
+                reencode!(TypeItem);
+            }
+        }
+        MapListItemType::DebugInfoItem => encode_debug_info(reader, &mut out)?,
+        MapListItemType::Unknown(x) => {
+            return Err(Error::InvalidData(format!(
+                "item type {:#06x} at {:#x} can't be encoded",
+                x,
+                reader.position()
+            )));
+        }
+    }
+    Ok(out.into_inner())
+}
+
+/// Copies a `debug_info_item` including its state machine bytecode, which
+/// is not part of [DebugInfoItem].
+fn encode_debug_info(reader: &mut Cursor<&[UByte]>, out: &mut Cursor<Vec<UByte>>) -> Result<()> {
+    DebugInfoItem::read(reader)?.write(out)?;
+    loop {
+        let opcode = UByte::read_le(reader)?;
+        out.write_all(&[opcode])?;
+        match opcode {
+            DebugInfoItem::DBG_END_SEQUENCE => return Ok(()),
+            DebugInfoItem::DBG_ADVANCE_PC
+            | DebugInfoItem::DBG_END_LOCAL
+            | DebugInfoItem::DBG_RESTART_LOCAL => ULeb128::read_le(reader)?.write_le(out)?,
+            DebugInfoItem::DBG_ADVANCE_LINE => SLeb128::read_le(reader)?.write_le(out)?,
+            DebugInfoItem::DBG_START_LOCAL | DebugInfoItem::DBG_START_LOCAL_EXTENDED => {
+                ULeb128::read_le(reader)?.write_le(out)?;
+                ULeb128p1::read_le(reader)?.write_le(out)?;
+                ULeb128p1::read_le(reader)?.write_le(out)?;
+                if opcode == DebugInfoItem::DBG_START_LOCAL_EXTENDED {
+                    ULeb128p1::read_le(reader)?.write_le(out)?;
+                }
+            }
+            DebugInfoItem::DBG_SET_FILE => ULeb128p1::read_le(reader)?.write_le(out)?,
+            // special opcodes and the prologue and epilogue markers don't
+            // take any arguments
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Splitting of classes into multiple DEX files
+//!
+//! A single DEX file can reference at most [MAX_IDS] types, prototypes,
+//! fields and methods. The splitter partitions a set of classes into as many
+//! builders as needed to stay below these limits. Classes are first grouped
+//! by a [Grouping] strategy; all classes of a group are placed into the same
+//! output, groups are distributed in the order they first appear.
+//!
+//! ```ignore
+//! let mut builder = DexBuilder::new();
+//! merge::merge_into(&mut builder, &mut dex, Duplicates::Fail)?;
+//! for (i, part) in split::split(builder, &ByPackage)?.iter().enumerate() {
+//!     std::fs::write(format!("classes{}.dex", i + 1), part.build()?)?;
+//! }
+//! ```
+
+use std::collections::HashMap;
+
+use crate::dalvik::error::{Error, Result};
+
+use super::{pool::PoolsBuilder, ClassDef, DexBuilder, MAX_IDS};
+
+/// Decides which classes have to be placed into the same output file.
+pub trait Grouping {
+    /// Returns the group of the given class.
+    fn group(&self, class: &ClassDef) -> String;
+}
+
+impl<F> Grouping for F
+where
+    F: Fn(&ClassDef) -> String,
+{
+    fn group(&self, class: &ClassDef) -> String {
+        self(class)
+    }
+}
+
+/// Keeps all classes of a package together.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ByPackage;
+
+impl Grouping for ByPackage {
+    fn group(&self, class: &ClassDef) -> String {
+        match class.descriptor.rfind('/') {
+            Some(pos) => class.descriptor[..pos].to_string(),
+            None => String::new(),
+        }
+    }
+}
+
+/// Places every class on its own, which produces the fewest outputs.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ByClass;
+
+impl Grouping for ByClass {
+    fn group(&self, class: &ClassDef) -> String {
+        class.descriptor.clone()
+    }
+}
+
+/// Splits the classes of a builder into builders that stay within the id
+/// limits of the DEX format. All outputs use the version of the input.
+pub fn split<G: Grouping>(builder: DexBuilder, grouping: &G) -> Result<Vec<DexBuilder>> {
+    split_with_limit(builder, grouping, MAX_IDS)
+}
+
+/// Same as [split], but uses a custom limit for the number of types,
+/// prototypes, fields and methods referenced by each output.
+pub fn split_with_limit<G: Grouping>(
+    mut builder: DexBuilder,
+    grouping: &G,
+    limit: usize,
+) -> Result<Vec<DexBuilder>> {
+    // group classes while keeping the order of their first appearance
+    let mut groups: Vec<Vec<ClassDef>> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for class in builder.take_classes() {
+        let key = grouping.group(&class);
+        let position = *positions.entry(key).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[position].push(class);
+    }
+
+    let mut outputs = Vec::new();
+    let mut current = DexBuilder::new();
+    let mut pools = PoolsBuilder::default();
+    for group in groups {
+        // only the ids of the group are collected, the ids it adds to the
+        // current output are counted against the output's pools
+        let mut added = PoolsBuilder::default();
+        for class in &group {
+            added.add_class(class);
+        }
+        if added.id_counts().iter().any(|x| *x > limit) {
+            return Err(Error::InvalidData(format!(
+                "the group of {} exceeds the id limit of {} on its own",
+                group[0].descriptor, limit
+            )));
+        }
+
+        // start a new output if the group doesn't fit into the current one
+        let counts = pools.id_counts();
+        let missing = pools.missing_id_counts(&added);
+        if counts.iter().zip(missing).any(|(x, y)| x + y > limit) {
+            outputs.push(std::mem::take(&mut current));
+            pools = PoolsBuilder::default();
+        }
+
+        for class in group {
+            current.add_class(class)?;
+        }
+        pools.extend(added);
+    }
+    if !current.classes().is_empty() {
+        outputs.push(current);
+    }
+
+    for output in &mut outputs {
+        output.set_version(builder.version());
+    }
+    Ok(outputs)
+}
@@ -0,0 +1,35 @@
+//! Compares the conformance dumps of the test files with the snapshots in
+//! `*.conformance.txt`. The snapshots were written by `conformance::dump`;
+//! `tests/dexdump-baselines` replaces them by the output of `dexdump`.
+
+use std::{fs, io::Cursor, path::Path};
+
+use dexrs::{conformance, dalvik::file::Dex};
+
+const FIXTURES: &[&str] = &["fibonacci/fib", "prime/prime"];
+
+#[test]
+fn dump_matches_dexdump() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+    for fixture in FIXTURES {
+        let data = fs::read(root.join(format!("{}.dex", fixture))).unwrap();
+        let mut reader = Cursor::new(data);
+        let mut dex = Dex::read(&mut reader, false).unwrap();
+        let mut output = Vec::new();
+        conformance::dump(&mut dex, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let baseline = root.join(format!("{}.conformance.txt", fixture));
+        let expected = fs::read_to_string(baseline).unwrap();
+        // report the first line that differs instead of both dumps
+        for (i, (actual, expected)) in output.lines().zip(expected.lines()).enumerate() {
+            assert_eq!(actual, expected, "{}: line {} differs", fixture, i + 1);
+        }
+        assert_eq!(
+            output.lines().count(),
+            expected.lines().count(),
+            "{}: number of lines differs",
+            fixture
+        );
+    }
+}
@@ -0,0 +1,92 @@
+//! Checks that `apply_edits` fixes up branches, switches and try blocks
+//! when an instruction grows.
+
+use dexrs::dalvik::{
+    dex::{CodeItem, EncodedCatchHandler, EncodedCatchHandlerList, SLeb128, TryItem, ULeb128},
+    insns::relocate::{apply_edits, InsnEdit},
+};
+
+fn code_item(units: &[u16]) -> CodeItem {
+    let insns = units.iter().flat_map(|x| x.to_le_bytes()).collect();
+    let mut item = CodeItem::new(2, 0, 0, insns);
+    let handler = EncodedCatchHandler {
+        offset: 1,
+        size: SLeb128(0),
+        handlers: Vec::new(),
+        catch_all_addr: Some(ULeb128(7)),
+    };
+    let handlers = EncodedCatchHandlerList {
+        size: ULeb128(1),
+        list: vec![handler],
+    };
+    let try_item = TryItem {
+        start_addr: 4,
+        insn_count: 3,
+        handler_off: 1,
+    };
+    item.set_tries(vec![try_item], Some(handlers));
+    item
+}
+
+fn units(item: &CodeItem) -> Vec<u16> {
+    item.insns
+        .chunks(2)
+        .map(|x| u16::from_le_bytes([x[0], x[1]]))
+        .collect()
+}
+
+#[test]
+fn grow_instruction() {
+    let mut item = code_item(&[
+        0x0012, // 0: const/4 v0, 0
+        0x002B, 0x0007, 0x0000, // 1: packed-switch v0, +7
+        0x0038, 0x0003, // 4: if-eqz v0, +3
+        0x1112, // 6: const/4 v1, 1
+        0x000E, // 7: return-void
+        0x0100, 0x0001, 0x0000, 0x0000, 0x0006, 0x0000, // 8: packed-switch-payload
+    ]);
+    let edit = InsnEdit {
+        address: 6,
+        // const/16 v1, 0x1234
+        units: vec![0x0113, 0x1234],
+    };
+    let relocation = apply_edits(&mut item, vec![edit]).unwrap();
+
+    assert_eq!(
+        units(&item),
+        [
+            0x0012, // 0: const/4 v0, 0
+            0x002B, 0x0009, 0x0000, // 1: packed-switch v0, +9
+            0x0038, 0x0004, // 4: if-eqz v0, +4
+            0x0113, 0x1234, // 6: const/16 v1, 0x1234
+            0x000E, // 8: return-void
+            0x0000, // 9: nop
+            0x0100, 0x0001, 0x0000, 0x0000, 0x0007, 0x0000, // 10: packed-switch-payload
+        ]
+    );
+    assert_eq!(item.insns_size, 16);
+    assert_eq!(relocation.address(7), 8);
+    assert_eq!(relocation.address(8), 10);
+
+    assert_eq!(item.tries_size, 1);
+    assert_eq!(item.tries[0].start_addr, 4);
+    assert_eq!(item.tries[0].insn_count, 4);
+    let handler = item.catch_handler(&item.tries[0]).unwrap();
+    assert_eq!(handler.catch_all_addr.as_ref().unwrap().0, 8);
+}
+
+#[test]
+fn reject_edit_inside_instruction() {
+    // 0: if-eqz v0, +2; 2: return-void
+    let insns = [0x0038u16, 0x0002, 0x000E]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut item = CodeItem::new(1, 0, 0, insns);
+    let edit = InsnEdit {
+        address: 1,
+        units: vec![0x0000],
+    };
+    assert!(apply_edits(&mut item, vec![edit]).is_err());
+    assert_eq!(units(&item), [0x0038, 0x0002, 0x000E]);
+}
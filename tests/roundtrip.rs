@@ -0,0 +1,20 @@
+//! Checks that the test files are reproduced byte for byte by
+//! `rewrite::roundtrip`.
+
+use std::{fs, io::Cursor, path::Path};
+
+use dexrs::{dalvik::file::Dex, writer::rewrite};
+
+const FIXTURES: &[&str] = &["fibonacci/fib.dex", "prime/prime.dex"];
+
+#[test]
+fn roundtrip_reproduces_fixtures() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+    for fixture in FIXTURES {
+        let data = fs::read(root.join(fixture)).unwrap();
+        let mut reader = Cursor::new(data.clone());
+        let mut dex = Dex::read(&mut reader, true).unwrap();
+        let output = rewrite::roundtrip(&mut dex).unwrap();
+        assert!(output == data, "{} is not reproduced", fixture);
+    }
+}
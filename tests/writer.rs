@@ -0,0 +1,92 @@
+//! Builds new files from the test files and checks that they are parsed
+//! again with all checks enabled.
+
+use std::{collections::HashMap, fs, io::Cursor, path::Path};
+
+use dexrs::{
+    dalvik::file::{Dex, IDex},
+    writer::{
+        merge::{self, Duplicates},
+        rename, DexBuilder,
+    },
+};
+
+fn fixture(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join(name);
+    fs::read(path).unwrap()
+}
+
+/// Returns the descriptors of all classes defined by the given file.
+fn classes(data: Vec<u8>) -> Vec<String> {
+    let mut reader = Cursor::new(data);
+    let mut dex = Dex::read(&mut reader, true).unwrap();
+    (0..dex.get_header().class_defs_size)
+        .map(|index| dex.get_class_def(index).unwrap().type_.to_string())
+        .collect()
+}
+
+#[test]
+fn build_from_class() {
+    let mut reader = Cursor::new(fixture("fibonacci/fib.dex"));
+    let mut dex = Dex::read(&mut reader, true).unwrap();
+    let class = dex.get_class_def(0).unwrap();
+    let data = DexBuilder::from_class(&mut dex, &class)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let mut reader = Cursor::new(data);
+    let mut built = Dex::read(&mut reader, true).unwrap();
+    let rebuilt = built.get_class_def(0).unwrap();
+    assert_eq!(rebuilt.type_.to_string(), class.type_.to_string());
+    assert_eq!(rebuilt.get_methods().count(), class.get_methods().count());
+}
+
+#[test]
+fn merge_fixtures() {
+    let mut fib = Cursor::new(fixture("fibonacci/fib.dex"));
+    let mut prime = Cursor::new(fixture("prime/prime.dex"));
+    let mut fib = Dex::read(&mut fib, true).unwrap();
+    let mut prime = Dex::read(&mut prime, true).unwrap();
+    let inputs: Vec<&mut dyn IDex> = vec![&mut fib, &mut prime];
+    let data = merge::merge(inputs, Duplicates::Fail).unwrap();
+
+    let mut merged = classes(data);
+    merged.sort();
+    assert_eq!(merged, ["Lfibonacci/fib;", "Lprime/prime;"]);
+}
+
+#[test]
+fn merge_rejects_duplicates() {
+    let mut first = Cursor::new(fixture("fibonacci/fib.dex"));
+    let mut second = Cursor::new(fixture("fibonacci/fib.dex"));
+    let mut first = Dex::read(&mut first, true).unwrap();
+    let mut second = Dex::read(&mut second, true).unwrap();
+    let inputs: Vec<&mut dyn IDex> = vec![&mut first, &mut second];
+    assert!(merge::merge(inputs, Duplicates::Fail).is_err());
+}
+
+#[test]
+fn rename_class() {
+    let mut reader = Cursor::new(fixture("fibonacci/fib.dex"));
+    let mut dex = Dex::read(&mut reader, true).unwrap();
+    let renamer = HashMap::from([(
+        "Lfibonacci/fib;".to_string(),
+        "Lcom/example/Fibonacci;".to_string(),
+    )]);
+    let data = rename::rename(&mut dex, &renamer).unwrap();
+
+    let mut reader = Cursor::new(data);
+    let mut renamed = Dex::read(&mut reader, true).unwrap();
+    let class = renamed.get_class_def(0).unwrap();
+    assert_eq!(class.type_.to_string(), "Lcom/example/Fibonacci;");
+    // no reference to the old name is left
+    for index in 0..renamed.get_header().type_ids_size {
+        assert_ne!(
+            renamed.get_type(index).unwrap().to_string(),
+            "Lfibonacci/fib;"
+        );
+    }
+}